@@ -0,0 +1,144 @@
+//! Splitting a portfolio into the asset classes cFIREsim simulates, or by the
+//! type of its denominations.
+
+use denomination::Denomination;
+use rust_decimal::prelude::*;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AssetClass {
+    Equities,
+    Bonds,
+    Cash,
+    Gold,
+}
+
+/// Precious metals have ISO 4217 codes too: gold, silver, platinum, palladium.
+const METAL_CODES: [&str; 4] = ["XAU", "XAG", "XPT", "XPD"];
+
+pub fn asset_class(denomination: &Denomination) -> AssetClass {
+    match denomination {
+        Denomination::Currency { currency } if METAL_CODES.contains(&currency.as_str()) => {
+            AssetClass::Gold
+        }
+        Denomination::Currency { .. } => AssetClass::Cash,
+        // cFIREsim has nothing closer for cryptocurrencies or derivatives.
+        Denomination::Cryptocurrency { .. }
+        | Denomination::Stock { .. }
+        | Denomination::Derivative { .. } => AssetClass::Equities,
+    }
+}
+
+/// Whole percentages of each asset class, summing to 100.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub equities: u32,
+    pub bonds: u32,
+    pub cash: u32,
+    pub gold: u32,
+}
+
+/// Computes the allocation of assets given by their values in a common
+/// currency. An empty portfolio is treated as 100% equities.
+///
+/// Percentages are rounded down, and the points lost to rounding go to the
+/// classes with the largest remainders.
+pub fn allocation(values: &[(Denomination, Decimal)]) -> Allocation {
+    const CLASSES: [AssetClass; 4] = [
+        AssetClass::Equities,
+        AssetClass::Bonds,
+        AssetClass::Cash,
+        AssetClass::Gold,
+    ];
+    let mut totals = [Decimal::ZERO; 4];
+    for (denomination, value) in values {
+        let class = asset_class(denomination);
+        let i = CLASSES.iter().position(|c| *c == class).unwrap();
+        totals[i] += value;
+    }
+    let total: Decimal = totals.iter().sum();
+    if total <= Decimal::ZERO {
+        return Allocation {
+            equities: 100,
+            bonds: 0,
+            cash: 0,
+            gold: 0,
+        };
+    }
+
+    let exact: Vec<Decimal> = totals
+        .iter()
+        .map(|t| (t * Decimal::ONE_HUNDRED / total).max(Decimal::ZERO))
+        .collect();
+    let mut percents: Vec<u32> = exact.iter().map(|p| p.floor().to_u32().unwrap()).collect();
+    let mut by_remainder: Vec<usize> = (0..CLASSES.len()).collect();
+    by_remainder.sort_by(|&a, &b| exact[b].fract().cmp(&exact[a].fract()));
+    let missing = 100u32.saturating_sub(percents.iter().sum());
+    for &i in by_remainder.iter().cycle().take(missing as usize) {
+        percents[i] += 1;
+    }
+
+    Allocation {
+        equities: percents[0],
+        bonds: percents[1],
+        cash: percents[2],
+        gold: percents[3],
+    }
+}
+
+/// What an asset is denominated in, for the split of net worth shown with the
+/// model.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord)]
+pub enum DenominationType {
+    Currency,
+    Stock,
+    Crypto,
+    Metal,
+    Derivative,
+}
+
+impl DenominationType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DenominationType::Currency => "currency",
+            DenominationType::Stock => "stock",
+            DenominationType::Crypto => "crypto",
+            DenominationType::Metal => "metal",
+            DenominationType::Derivative => "derivative",
+        }
+    }
+}
+
+pub fn denomination_type(denomination: &Denomination) -> DenominationType {
+    match denomination {
+        Denomination::Currency { currency } if METAL_CODES.contains(&currency.as_str()) => {
+            DenominationType::Metal
+        }
+        Denomination::Currency { .. } => DenominationType::Currency,
+        Denomination::Cryptocurrency { .. } => DenominationType::Crypto,
+        Denomination::Stock { .. } => DenominationType::Stock,
+        Denomination::Derivative { .. } => DenominationType::Derivative,
+    }
+}
+
+/// Percentage of the total held in each type of denomination, given the
+/// values of assets in a common currency, ordered by type. Types not held are
+/// left out, and so is everything if the total isn't positive.
+pub fn total_by_type(values: &[(Denomination, Decimal)]) -> Vec<(DenominationType, Decimal)> {
+    let mut totals: Vec<(DenominationType, Decimal)> = Vec::new();
+    for (denomination, value) in values {
+        let denomination_type = denomination_type(denomination);
+        match totals.iter_mut().find(|(t, _)| *t == denomination_type) {
+            Some((_, total)) => *total += value,
+            None => totals.push((denomination_type, *value)),
+        }
+    }
+    let total: Decimal = totals.iter().map(|(_, value)| value).sum();
+    if total <= Decimal::ZERO {
+        return Vec::new();
+    }
+    totals.sort_by_key(|(denomination_type, _)| *denomination_type);
+    totals
+        .into_iter()
+        .map(|(denomination_type, value)| (denomination_type, value * Decimal::ONE_HUNDRED / total))
+        .collect()
+}
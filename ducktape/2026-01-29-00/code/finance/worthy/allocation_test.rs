@@ -0,0 +1,93 @@
+use allocation::{
+    Allocation, AssetClass, DenominationType, allocation, asset_class, total_by_type,
+};
+use denomination::Denomination;
+use rust_decimal_macros::*;
+use test_util::currency;
+
+fn stock(symbol: &str) -> Denomination {
+    Denomination::Stock {
+        stock: symbol.to_string(),
+        isin: None,
+    }
+}
+
+#[test]
+fn asset_classes() {
+    assert_eq!(asset_class(&stock("GOOG")), AssetClass::Equities);
+    assert_eq!(asset_class(&currency("CZK")), AssetClass::Cash);
+    assert_eq!(asset_class(&currency("XAU")), AssetClass::Gold);
+}
+
+#[test]
+fn mixed_portfolio() {
+    let values = vec![
+        (stock("GOOG"), dec!(5000)),
+        (stock("VTI"), dec!(2000)),
+        (currency("USD"), dec!(2500)),
+        (currency("XAU"), dec!(500)),
+    ];
+    assert_eq!(
+        allocation(&values),
+        Allocation {
+            equities: 70,
+            bonds: 0,
+            cash: 25,
+            gold: 5,
+        }
+    );
+}
+
+#[test]
+fn rounds_to_sum_to_100() {
+    // 1/3 each: 33.33% rounds down everywhere, and the missing point goes to
+    // the first class on a tie.
+    let values = vec![
+        (stock("GOOG"), dec!(1)),
+        (currency("USD"), dec!(1)),
+        (currency("XAU"), dec!(1)),
+    ];
+    let a = allocation(&values);
+    assert_eq!(a.equities + a.bonds + a.cash + a.gold, 100);
+    assert_eq!((a.equities, a.cash, a.gold), (34, 33, 33));
+}
+
+#[test]
+fn empty_portfolio_is_all_equities() {
+    assert_eq!(
+        allocation(&[]),
+        Allocation {
+            equities: 100,
+            bonds: 0,
+            cash: 0,
+            gold: 0,
+        }
+    );
+}
+
+#[test]
+fn split_by_denomination_type() {
+    let values = vec![
+        (stock("GOOG"), dec!(5000)),
+        (currency("USD"), dec!(2500)),
+        (
+            Denomination::Cryptocurrency {
+                symbol: "BTC".to_string(),
+            },
+            dec!(1500),
+        ),
+        (stock("VTI"), dec!(500)),
+        (currency("XAU"), dec!(500)),
+        (currency("EUR"), dec!(0)),
+    ];
+    assert_eq!(
+        total_by_type(&values),
+        vec![
+            (DenominationType::Currency, dec!(25)),
+            (DenominationType::Stock, dec!(55)),
+            (DenominationType::Crypto, dec!(15)),
+            (DenominationType::Metal, dec!(5)),
+        ]
+    );
+    assert_eq!(total_by_type(&[]), vec![]);
+}
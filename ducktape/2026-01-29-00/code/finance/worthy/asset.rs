@@ -1,10 +1,89 @@
 use denomination::Denomination;
 use rust_decimal::prelude::Decimal;
 use serde::Deserialize;
+use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct Asset {
     pub amount: Decimal,
     #[serde(flatten)]
     pub denomination: Denomination,
+    /// What was paid for the asset, if its source reports it.
+    #[serde(default)]
+    pub cost_basis: Option<CostBasis>,
+}
+
+/// Cost of a position as reported by its source, both amounts in `currency`.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct CostBasis {
+    pub currency: String,
+    /// Total paid for the position, i.e. its weighted-average price times
+    /// its size.
+    pub money: Decimal,
+    /// Gain (or, if negative, loss) not yet realized, going by FIFO.
+    pub unrealized_pnl: Decimal,
+}
+
+impl Asset {
+    /// Sum of `self` and `other`, or `None` if they are in different
+    /// denominations or the sum overflows. The cost basis is kept only if both
+    /// have one in the same currency, and it adds up without overflowing.
+    pub fn checked_add(&self, other: &Asset) -> Option<Asset> {
+        if self.denomination != other.denomination {
+            return None;
+        }
+        let cost_basis = match (&self.cost_basis, &other.cost_basis) {
+            (Some(a), Some(b)) if a.currency == b.currency => {
+                match (
+                    a.money.checked_add(b.money),
+                    a.unrealized_pnl.checked_add(b.unrealized_pnl),
+                ) {
+                    (Some(money), Some(unrealized_pnl)) => Some(CostBasis {
+                        currency: a.currency.clone(),
+                        money,
+                        unrealized_pnl,
+                    }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        Some(Asset {
+            amount: self.amount.checked_add(other.amount)?,
+            denomination: self.denomination.clone(),
+            cost_basis,
+        })
+    }
+
+    /// Value of `self` in `base`, given the price in `base` of one unit of each
+    /// denomination, or `None` if its denomination has no price or the value
+    /// overflows.
+    pub fn in_base(
+        &self,
+        base: &Denomination,
+        rates: &HashMap<Denomination, Decimal>,
+    ) -> Option<Asset> {
+        Some(Asset {
+            amount: self.amount.checked_mul(*rates.get(&self.denomination)?)?,
+            denomination: base.clone(),
+            cost_basis: None,
+        })
+    }
+}
+
+/// `assets` added up per denomination, ordered by denomination, or `None` if
+/// a sum overflows.
+pub fn sum_by_denomination<'a>(assets: impl IntoIterator<Item = &'a Asset>) -> Option<Vec<Asset>> {
+    let mut sums: HashMap<&Denomination, Asset> = HashMap::new();
+    for asset in assets {
+        match sums.get_mut(&asset.denomination) {
+            Some(sum) => *sum = sum.checked_add(asset)?,
+            None => {
+                sums.insert(&asset.denomination, asset.clone());
+            }
+        }
+    }
+    let mut sums: Vec<Asset> = sums.into_values().collect();
+    sums.sort_by_key(|asset| asset.denomination.to_string());
+    Some(sums)
 }
@@ -0,0 +1,101 @@
+use asset::{Asset, CostBasis, sum_by_denomination};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::HashMap;
+use test_util::currency;
+
+fn asset(amount: Decimal, denomination: &str) -> Asset {
+    Asset {
+        amount,
+        denomination: currency(denomination),
+        cost_basis: None,
+    }
+}
+
+fn with_cost_basis(asset: Asset, currency: &str, money: Decimal) -> Asset {
+    Asset {
+        cost_basis: Some(CostBasis {
+            currency: currency.to_string(),
+            money,
+            unrealized_pnl: dec!(1),
+        }),
+        ..asset
+    }
+}
+
+#[test]
+fn checked_add_same_denomination() {
+    assert_eq!(
+        asset(dec!(1.5), "USD").checked_add(&asset(dec!(2), "USD")),
+        Some(asset(dec!(3.5), "USD"))
+    );
+}
+
+#[test]
+fn checked_add_mismatched_denomination() {
+    assert_eq!(
+        asset(dec!(1), "USD").checked_add(&asset(dec!(2), "EUR")),
+        None
+    );
+}
+
+#[test]
+fn checked_add_cost_basis() {
+    let usd = with_cost_basis(asset(dec!(1), "GOOG"), "USD", dec!(10));
+    assert_eq!(
+        usd.checked_add(&with_cost_basis(asset(dec!(2), "GOOG"), "USD", dec!(20))),
+        Some(Asset {
+            cost_basis: Some(CostBasis {
+                currency: "USD".to_string(),
+                money: dec!(30),
+                unrealized_pnl: dec!(2),
+            }),
+            ..asset(dec!(3), "GOOG")
+        })
+    );
+    // Cost bases that can't be added up are dropped.
+    assert_eq!(
+        usd.checked_add(&with_cost_basis(asset(dec!(2), "GOOG"), "EUR", dec!(20))),
+        Some(asset(dec!(3), "GOOG"))
+    );
+    assert_eq!(
+        usd.checked_add(&asset(dec!(2), "GOOG")),
+        Some(asset(dec!(3), "GOOG"))
+    );
+}
+
+#[test]
+fn in_base() {
+    let rates = HashMap::from([(currency("EUR"), dec!(2))]);
+    assert_eq!(
+        asset(dec!(3), "EUR").in_base(&currency("USD"), &rates),
+        Some(asset(dec!(6), "USD"))
+    );
+    assert_eq!(
+        asset(dec!(3), "CHF").in_base(&currency("USD"), &rates),
+        None
+    );
+}
+
+#[test]
+fn sums_by_denomination() {
+    let assets = [
+        asset(dec!(1), "USD"),
+        asset(dec!(2), "EUR"),
+        asset(dec!(3), "USD"),
+    ];
+    assert_eq!(
+        sum_by_denomination(&assets),
+        Some(vec![asset(dec!(2), "EUR"), asset(dec!(4), "USD")])
+    );
+    assert_eq!(sum_by_denomination(&[]), Some(vec![]));
+}
+
+#[test]
+fn overflow() {
+    let max = asset(Decimal::MAX, "USD");
+    assert_eq!(max.checked_add(&asset(dec!(1), "USD")), None);
+    assert_eq!(sum_by_denomination(&[max.clone(), max.clone()]), None);
+    let rates = HashMap::from([(currency("USD"), dec!(2))]);
+    assert_eq!(max.in_base(&currency("EUR"), &rates), None);
+}
@@ -0,0 +1,119 @@
+//! Stocks priced as a fixed mix of other denominations, e.g. a fund as a
+//! basket of what it holds, for when there is no direct price for them.
+
+use chrono::Utc;
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use log::warn;
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use worthy_error::WorthyError;
+
+#[derive(Debug, Deserialize)]
+pub struct Component {
+    #[serde(flatten)]
+    pub denomination: Denomination,
+    /// Share of the basket. The weights of a basket add up to 1.
+    pub weight: Decimal,
+}
+
+/// One unit of `stock` is worth the weighted mix of one unit of each of
+/// `components`.
+#[derive(Debug, Deserialize)]
+pub struct Basket {
+    /// Ticker of the stock, matched whatever its ISIN.
+    pub stock: String,
+    pub components: Vec<Component>,
+}
+
+impl Basket {
+    pub fn validate(&self) -> Result<(), WorthyError> {
+        if self.components.is_empty() {
+            return Err(WorthyError::Config(format!(
+                "basket {} has no components",
+                self.stock
+            )));
+        }
+        if let Some(component) = self.components.iter().find(|c| c.weight <= Decimal::ZERO) {
+            return Err(WorthyError::Config(format!(
+                "basket {}: weight of {} must be positive, not {}",
+                self.stock, component.denomination, component.weight
+            )));
+        }
+        let sum: Decimal = self.components.iter().map(|c| c.weight).sum();
+        if sum != Decimal::ONE {
+            return Err(WorthyError::Config(format!(
+                "weights of basket {} add up to {}, not 1",
+                self.stock, sum
+            )));
+        }
+        Ok(())
+    }
+
+    /// Price of one unit, given the prices of its components, or the first
+    /// component without a price.
+    pub fn price<'a>(
+        &'a self,
+        prices: &HashMap<Denomination, Decimal>,
+    ) -> Result<Decimal, &'a Denomination> {
+        let mut price = Decimal::ZERO;
+        for component in &self.components {
+            match prices.get(&component.denomination) {
+                Some(component_price) => price += component.weight * component_price,
+                None => return Err(&component.denomination),
+            }
+        }
+        Ok(price)
+    }
+}
+
+/// Rates into `base` of each of the `held` denominations that is a basket,
+/// priced through its components over `conversions`. A basket with an unpriced
+/// component gets no rate, so it stays unpriced too. The rates are taken to be
+/// as old as the oldest of `conversions`.
+pub fn basket_rates(
+    baskets: &[Basket],
+    held: &[&Denomination],
+    conversions: &[ExchangeRate],
+    base: &Denomination,
+) -> Result<Vec<ExchangeRate>, WorthyError> {
+    if baskets.is_empty() {
+        return Ok(Vec::new());
+    }
+    for basket in baskets {
+        basket.validate()?;
+    }
+    let prices = common_currency::in_common_currency(conversions, base)?;
+    let timestamp = conversions
+        .iter()
+        .map(|conversion| conversion.timestamp)
+        .min()
+        .unwrap_or_else(Utc::now);
+
+    let mut rates = Vec::new();
+    for denomination in held {
+        let Denomination::Stock { stock, .. } = denomination else {
+            continue;
+        };
+        let Some(basket) = baskets.iter().find(|basket| basket.stock == *stock) else {
+            continue;
+        };
+        match basket.price(&prices) {
+            // A zero rate can't be inverted.
+            Ok(rate) if rate.is_zero() => warn!("basket {} is worth nothing", stock),
+            Ok(rate) => rates.push(ExchangeRate {
+                timestamp,
+                from: (*denomination).clone(),
+                to: base.clone(),
+                rate,
+                spread_bps: Decimal::ZERO,
+            }),
+            Err(unpriced) => warn!(
+                "basket {} component {} is unpriced, so is the basket",
+                stock, unpriced
+            ),
+        }
+    }
+    Ok(rates)
+}
@@ -0,0 +1,97 @@
+use basket::{Basket, basket_rates};
+use chrono::DateTime;
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use test_util::currency;
+use worthy_error::WorthyError;
+
+fn stock(symbol: &str) -> Denomination {
+    Denomination::Stock {
+        stock: symbol.to_string(),
+        isin: None,
+    }
+}
+
+fn rate(from: Denomination, to: Denomination, rate: Decimal) -> ExchangeRate {
+    ExchangeRate {
+        timestamp: DateTime::UNIX_EPOCH,
+        from,
+        to,
+        rate,
+        spread_bps: dec!(0),
+    }
+}
+
+fn basket(yaml: &str) -> Basket {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+fn vwce() -> Basket {
+    basket(
+        "
+        stock: VWCE
+        components:
+          - stock: VT
+            weight: 0.6
+          - currency: EUR
+            weight: 0.4
+        ",
+    )
+}
+
+#[test]
+fn two_component_basket() {
+    let usd = currency("USD");
+    let held = Denomination::Stock {
+        stock: "VWCE".to_string(),
+        isin: Some("IE00BK5BQT80".to_string()),
+    };
+    let conversions = vec![
+        rate(stock("VT"), usd.clone(), dec!(100)),
+        rate(currency("EUR"), usd.clone(), dec!(1.1)),
+    ];
+
+    let rates = basket_rates(&[vwce()], &[&held, &usd], &conversions, &usd).unwrap();
+
+    // 0.6 * 100 + 0.4 * 1.1
+    assert_eq!(rates, vec![rate(held.clone(), usd.clone(), dec!(60.44))]);
+    let all: Vec<ExchangeRate> = conversions.into_iter().chain(rates).collect();
+    let price = common_currency::in_common_currency(&all, &usd).unwrap()[&held];
+    assert!((price - dec!(60.44)).abs() < dec!(0.000001), "{}", price);
+}
+
+#[test]
+fn unpriced_component() {
+    let usd = currency("USD");
+    let conversions = vec![rate(stock("VT"), usd.clone(), dec!(100))];
+
+    let rates = basket_rates(&[vwce()], &[&stock("VWCE")], &conversions, &usd).unwrap();
+
+    assert_eq!(rates, vec![]);
+}
+
+#[test]
+fn weights_must_add_up_to_one() {
+    let lopsided = basket(
+        "
+        stock: VWCE
+        components:
+          - stock: VT
+            weight: 0.6
+          - currency: EUR
+            weight: 0.3
+        ",
+    );
+    assert!(matches!(lopsided.validate(), Err(WorthyError::Config(_))));
+    assert!(matches!(
+        basket_rates(&[lopsided], &[], &[], &currency("USD")),
+        Err(WorthyError::Config(_))
+    ));
+    assert!(matches!(
+        basket("{stock: VWCE, components: []}").validate(),
+        Err(WorthyError::Config(_))
+    ));
+    assert!(vwce().validate().is_ok());
+}
@@ -0,0 +1,66 @@
+//! API calls made by each converter per UTC day, kept in the XDG cache
+//! between runs to warn before hitting quotas.
+
+use chrono::NaiveDate;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use worthy_error::WorthyError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallCount {
+    pub date: NaiveDate,
+    pub converter_id: String,
+    pub count: u32,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CallCounts {
+    counts: Vec<CallCount>,
+}
+
+impl CallCounts {
+    /// Counts saved at `path`, or none if it's missing or unreadable.
+    pub fn load(path: &Path) -> CallCounts {
+        let Ok(json) = std::fs::read_to_string(path) else {
+            return CallCounts::default();
+        };
+        serde_json::from_str(&json).unwrap_or_else(|error| {
+            warn!("ignoring call counts in {}: {}", path.display(), error);
+            CallCounts::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), WorthyError> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?).map_err(|error| {
+            WorthyError::Config(format!("cannot write {}: {}", path.display(), error))
+        })
+    }
+
+    /// Calls `converter_id` made on `date`.
+    pub fn count(&self, converter_id: &str, date: NaiveDate) -> u32 {
+        self.counts
+            .iter()
+            .find(|c| c.converter_id == converter_id && c.date == date)
+            .map_or(0, |c| c.count)
+    }
+
+    /// Counts `calls` more calls of `converter_id` on `date`. Counts of
+    /// earlier days are dropped, so they start over at UTC midnight.
+    pub fn add(&mut self, converter_id: &str, date: NaiveDate, calls: u32) {
+        self.counts.retain(|c| c.date >= date);
+        match self
+            .counts
+            .iter_mut()
+            .find(|c| c.converter_id == converter_id && c.date == date)
+        {
+            Some(c) => c.count += calls,
+            None => self.counts.push(CallCount {
+                date,
+                converter_id: converter_id.to_string(),
+                count: calls,
+            }),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use call_counts::CallCounts;
+use chrono::NaiveDate;
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2021, 3, day).unwrap()
+}
+
+#[test]
+fn add_increments_per_converter() {
+    let mut counts = CallCounts::default();
+    counts.add("alpha_vantage", date(1), 3);
+    counts.add("alpha_vantage", date(1), 2);
+    counts.add("fixer", date(1), 1);
+    assert_eq!(counts.count("alpha_vantage", date(1)), 5);
+    assert_eq!(counts.count("fixer", date(1)), 1);
+    assert_eq!(counts.count("currency_layer", date(1)), 0);
+}
+
+#[test]
+fn counts_start_over_the_next_day() {
+    let mut counts = CallCounts::default();
+    counts.add("alpha_vantage", date(1), 25);
+    assert_eq!(counts.count("alpha_vantage", date(2)), 0);
+    counts.add("alpha_vantage", date(2), 1);
+    assert_eq!(counts.count("alpha_vantage", date(2)), 1);
+    assert_eq!(counts.count("alpha_vantage", date(1)), 0);
+}
+
+#[test]
+fn save_and_load() {
+    let path = std::env::temp_dir().join(format!("call_counts_test_{}.json", std::process::id()));
+    let mut counts = CallCounts::default();
+    counts.add("alpha_vantage", date(1), 7);
+    counts.save(&path).unwrap();
+    assert_eq!(CallCounts::load(&path), counts);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(CallCounts::load(&path), CallCounts::default());
+}
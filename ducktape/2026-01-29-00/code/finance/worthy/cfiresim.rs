@@ -0,0 +1,51 @@
+//! Results of cFIREsim simulations.
+
+use json_output::CfireSimResult;
+use rust_decimal::Decimal;
+use worthy_error::WorthyError;
+
+/// Cycles of a statistics row, e.g. "12.34% - Failed 56 of 78 total cycles."
+/// as (failed, total).
+fn parse_cycles(row: &str) -> Option<(u32, u32)> {
+    let words: Vec<&str> = row.split_whitespace().collect();
+    let failed = words.iter().position(|word| *word == "Failed")?;
+    match words[failed + 1..] {
+        [failed, "of", total, ..] => Some((failed.parse().ok()?, total.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Parses the `stats` HTML fragment of a simulation, going by its first row
+/// with a count of failed cycles. The success rate is computed from the
+/// cycles, in percent.
+pub fn parse_stats(fragment: &str, tracking_uuid: &str) -> Result<CfireSimResult, WorthyError> {
+    let html = scraper::Html::parse_fragment(fragment);
+    let selector = scraper::Selector::parse("table.table > tbody > tr > td[scope=row]").unwrap();
+    let (cycles_failed, cycles_total) = html
+        .select(&selector)
+        .find_map(|element| parse_cycles(&element.text().collect::<String>()))
+        .ok_or_else(|| WorthyError::Parse("cFIREsim stats have no failed cycles".to_string()))?;
+    if cycles_total == 0 || cycles_failed > cycles_total {
+        return Err(WorthyError::Parse(format!(
+            "cFIREsim stats have {} of {} cycles failed",
+            cycles_failed, cycles_total
+        )));
+    }
+    let succeeded = Decimal::from(cycles_total - cycles_failed);
+    Ok(CfireSimResult {
+        success_rate: (succeeded * Decimal::ONE_HUNDRED / Decimal::from(cycles_total)).round_dp(2),
+        cycles_total,
+        cycles_failed,
+        tracking_uuid: tracking_uuid.to_string(),
+    })
+}
+
+/// Parses the JSON response of a simulation.
+pub fn parse_response(response: &serde_json::Value) -> Result<CfireSimResult, WorthyError> {
+    let field = |name: &str| {
+        response[name]
+            .as_str()
+            .ok_or_else(|| WorthyError::Parse(format!("cFIREsim response has no {}", name)))
+    };
+    parse_stats(field("stats")?, field("tracking_uuid")?)
+}
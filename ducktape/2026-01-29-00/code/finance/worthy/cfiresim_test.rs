@@ -0,0 +1,48 @@
+use cfiresim::{parse_response, parse_stats};
+use json_output::CfireSimResult;
+use rust_decimal_macros::*;
+use worthy_error::WorthyError;
+
+const STATS: &str = r#"<table class="table">
+  <tbody>
+    <tr>
+      <td scope="row">
+                12.34% - Failed 56 of 78 total cycles.
+              </td>
+    </tr>
+  </tbody>
+</table>"#;
+
+#[test]
+fn parse_documented_stats() {
+    assert_eq!(
+        parse_stats(STATS, "0123-abcd").unwrap(),
+        CfireSimResult {
+            // 22 of 78 cycles succeeded.
+            success_rate: dec!(28.21),
+            cycles_total: 78,
+            cycles_failed: 56,
+            tracking_uuid: "0123-abcd".to_string(),
+        }
+    );
+}
+
+#[test]
+fn parse_response_fields() {
+    let response = serde_json::json!({"stats": STATS, "tracking_uuid": "0123-abcd"});
+    assert_eq!(parse_response(&response).unwrap().cycles_failed, 56);
+
+    let error = parse_response(&serde_json::json!({"stats": STATS})).unwrap_err();
+    assert!(
+        matches!(&error, WorthyError::Parse(message) if message.contains("tracking_uuid")),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn stats_without_cycles() {
+    assert!(parse_stats("<table class=\"table\"></table>", "0123-abcd").is_err());
+    let no_cycles = STATS.replace("56 of 78", "0 of 0");
+    assert!(parse_stats(&no_cycles, "0123-abcd").is_err());
+}
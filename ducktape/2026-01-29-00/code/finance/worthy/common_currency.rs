@@ -1,92 +1,183 @@
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
-use log::{trace, warn};
+use log::{debug, info, trace, warn};
 use petgraph::{
-    algo::FloatMeasure,
-    // algo::bellman_ford,
+    dot::Dot,
     prelude::*,
     visit::{IntoEdges, IntoNodeIdentifiers, NodeCount, NodeIndexable},
 };
 use rust_decimal::prelude::*;
 use rust_decimal_macros::*;
-use std::collections::{HashMap, HashSet};
-use std::{
-    cmp::{Ord, Ordering, Ordering::*},
-    default::Default,
-    fmt::Debug,
-    ops::Add,
-};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use worthy_error::WorthyError;
+
+/// Cost of a path for `bellman_ford`: how edge weights combine along a path,
+/// and which of two paths is shorter.
+pub trait Measure: Copy + Debug {
+    /// Cost of the empty path.
+    fn identity() -> Self;
+    /// Cost of a path to an unreachable node.
+    fn infinite() -> Self;
+    /// Cost of a path extended by an edge of cost `other`.
+    fn combine(self, other: Self) -> Self;
+    fn less_than(&self, other: &Self) -> bool;
+}
 
-#[derive(Copy, Clone, Debug, PartialEq, Default)]
+/// Plain additive costs, as in the textbook algorithm.
+impl Measure for f64 {
+    fn identity() -> Self {
+        0.0
+    }
+    fn infinite() -> Self {
+        f64::INFINITY
+    }
+    fn combine(self, other: Self) -> Self {
+        self + other
+    }
+    fn less_than(&self, other: &Self) -> bool {
+        self < other
+    }
+}
+
+/// Exchange rates, which multiply along a path of conversions.
+///
+/// `rate` is the mid-market rate and decides which path is taken, as before
+/// spreads were modelled. `net_rate` is what is actually realized after the
+/// spread of every conversion on the path; among paths with the same `rate`,
+/// the one with the best `net_rate` (e.g. the shortest) wins. Round trips
+/// therefore only ever lose value, and don't form negative cycles.
+///
+/// Rates are compared with a little tolerance: a round trip through `1 / rate`
+/// is off by rounding in the last digits, which must not make it look shorter.
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum MultiplyDecimal {
-    Finite(Decimal),
-    #[default]
+    Finite { rate: Decimal, net_rate: Decimal },
     Infinite,
 }
 
 use MultiplyDecimal::*;
 
-impl PartialOrd for MultiplyDecimal {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(match (self, other) {
-            (Infinite, Finite(_)) => Greater,
-            (Finite(_), Infinite) => Less,
-            (Infinite, Infinite) => Equal,
-            (Finite(a), Finite(b)) => a.cmp(b),
-        })
-    }
-}
-
-impl FloatMeasure for MultiplyDecimal {
-    fn zero() -> Self {
-        Finite(dec!(1))
+impl Measure for MultiplyDecimal {
+    fn identity() -> Self {
+        Finite {
+            rate: dec!(1),
+            net_rate: dec!(1),
+        }
     }
     fn infinite() -> Self {
         Infinite
     }
-    fn from_f32(val: f32) -> Self {
-        Decimal::try_from(val).map(Finite).unwrap_or(Infinite)
+    fn combine(self, other: Self) -> Self {
+        match (self, other) {
+            (
+                Finite { rate, net_rate },
+                Finite {
+                    rate: other_rate,
+                    net_rate: other_net_rate,
+                },
+            ) => Finite {
+                rate: rate * other_rate,
+                net_rate: net_rate * other_net_rate,
+            },
+            _ => Infinite,
+        }
     }
-    fn from_f64(val: f64) -> Self {
-        Decimal::try_from(val).map(Finite).unwrap_or(Infinite)
+    fn less_than(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Finite { rate, net_rate },
+                Finite {
+                    rate: other_rate,
+                    net_rate: other_net_rate,
+                },
+            ) => {
+                if same_rate(*rate, *other_rate) {
+                    net_rate > other_net_rate
+                } else {
+                    rate < other_rate
+                }
+            }
+            (Finite { .. }, Infinite) => true,
+            (Infinite, _) => false,
+        }
     }
 }
 
-impl Add for MultiplyDecimal {
-    type Output = Self;
-
-    // Intentionally uses multiplication: this type represents multiplicative
-    // edge weights for shortest-path algorithms that need a monoid.
-    #[allow(clippy::suspicious_arithmetic_impl)]
-    fn add(self, other: Self) -> Self {
-        match (self, other) {
-            (Finite(x), Finite(y)) => Finite(x * y),
-            _ => Infinite,
-        }
+/// Bellman-Ford shortest paths from `source`, adapted from petgraph's to take
+/// any `Measure` of edge weights. Returns the cost of reaching each node, by
+/// node index, or a negative cycle reachable from `source` if there is one.
+pub fn bellman_ford<G>(
+    g: G,
+    source: G::NodeId,
+) -> Result<Vec<G::EdgeWeight>, NegativeCycle<G::NodeId>>
+where
+    G: NodeCount + IntoNodeIdentifiers + IntoEdges + NodeIndexable,
+    G::EdgeWeight: Measure,
+    G::NodeId: Debug,
+{
+    let mut paths = bellman_ford_with_predecessors(g, source);
+    if paths.negative_cycles.is_empty() {
+        Ok(paths.costs)
+    } else {
+        Err(paths.negative_cycles.swap_remove(0))
     }
 }
 
-pub fn bellman_ford<G>(g: G, source: G::NodeId) -> Vec<G::EdgeWeight>
+/// Cycle whose edges cost less than nothing, so that going around it again
+/// and again makes paths ever shorter.
+#[derive(Debug, PartialEq)]
+pub struct NegativeCycle<N> {
+    /// Nodes of the cycle in the order of its edges, the first not repeated
+    /// at the end.
+    pub nodes: Vec<N>,
+}
+
+/// Shortest paths from one node, by `bellman_ford_with_predecessors`.
+#[derive(Debug)]
+pub struct ShortestPaths<W, N> {
+    /// Cost of reaching each node, by node index.
+    pub costs: Vec<W>,
+    /// Predecessor of each node on its shortest path, by node index.
+    pub predecessors: Vec<Option<N>>,
+    /// Negative cycles reachable from the source, each once. Costs of nodes
+    /// reachable from them are only as short as the paths tried.
+    pub negative_cycles: Vec<NegativeCycle<N>>,
+}
+
+/// `bellman_ford`, along with the predecessor of each node on its shortest
+/// path from `source`, and all negative cycles found instead of the first.
+pub fn bellman_ford_with_predecessors<G>(
+    g: G,
+    source: G::NodeId,
+) -> ShortestPaths<G::EdgeWeight, G::NodeId>
 where
     G: NodeCount + IntoNodeIdentifiers + IntoEdges + NodeIndexable,
-    G::EdgeWeight: FloatMeasure,
+    G::EdgeWeight: Measure,
     G::NodeId: Debug,
 {
+    let mut distance = vec![<G::EdgeWeight as Measure>::infinite(); g.node_bound()];
     let mut predecessor = vec![None; g.node_bound()];
-    let mut distance = vec![<_>::infinite(); g.node_bound()];
 
     let ix = |i| g.to_index(i);
 
-    distance[ix(source)] = FloatMeasure::zero();
+    distance[ix(source)] = Measure::identity();
     // scan up to |V| - 1 times.
     for _ in 1..g.node_count() {
         let mut did_update = false;
         for edge in g.edge_references() {
             let i = edge.source();
             let j = edge.target();
+            // Coming back around a negative cycle mustn't make the source
+            // itself cheaper than nothing.
+            if j == source {
+                continue;
+            }
             let w = *edge.weight();
-            if distance[ix(i)] + w < distance[ix(j)] {
-                distance[ix(j)] = distance[ix(i)] + w;
+            let through_i = distance[ix(i)].combine(w);
+            if through_i.less_than(&distance[ix(j)]) {
+                distance[ix(j)] = through_i;
                 predecessor[ix(j)] = Some(i);
                 did_update = true;
             }
@@ -96,136 +187,580 @@ where
         }
     }
 
+    let mut negative_cycles: Vec<NegativeCycle<G::NodeId>> = Vec::new();
     for i in g.node_identifiers() {
         for edge in g.edges(i) {
             let j = edge.target();
             let w = *edge.weight();
-            if distance[ix(i)] + w < distance[ix(j)] {
+            if !distance[ix(i)].combine(w).less_than(&distance[ix(j)]) {
+                continue;
+            }
+            debug!(
+                "neg cycle, detected from {:?} to {:?}, weight={:?}",
+                i, j, w
+            );
+            // Taking the edge, |V| steps back along predecessors are on the
+            // cycle.
+            let mut predecessor = predecessor.clone();
+            predecessor[ix(j)] = Some(i);
+            let mut on_cycle = Some(j);
+            for _ in 0..g.node_count() {
+                on_cycle = on_cycle.and_then(|node| predecessor[ix(node)]);
+            }
+            let Some(start) = on_cycle else {
+                continue;
+            };
+            let mut nodes = vec![start];
+            let mut node = start;
+            while let Some(previous) = predecessor[ix(node)] {
+                if previous == start || nodes.len() > g.node_count() {
+                    break;
+                }
+                nodes.push(previous);
+                node = previous;
+            }
+            nodes.reverse();
+            // The same cycle may be found from each of its edges.
+            let first = (0..nodes.len()).min_by_key(|&k| ix(nodes[k])).unwrap();
+            nodes.rotate_left(first);
+            if !negative_cycles.iter().any(|cycle| cycle.nodes == nodes) {
+                negative_cycles.push(NegativeCycle { nodes });
+            }
+        }
+    }
+
+    ShortestPaths {
+        costs: distance,
+        predecessors: predecessor,
+        negative_cycles,
+    }
+}
+
+fn same_rate(a: Decimal, b: Decimal) -> bool {
+    (a - b).abs() <= a.abs().max(b.abs()) * dec!(0.000000000000000001)
+}
+
+/// Edge weight converting at `rate`, minus a spread of `spread_bps`.
+fn edge(rate: Decimal, spread_bps: Decimal) -> MultiplyDecimal {
+    Finite {
+        rate,
+        net_rate: rate * (Decimal::ONE - spread_bps / dec!(10000)),
+    }
+}
+
+/// Whether `conversion` says a derivative is worth nothing, e.g. a future
+/// whose gains and losses are settled into cash daily. It's no edge of the
+/// conversion graph, as nothing can be priced through it, but the derivative
+/// is priced at zero wherever it's quoted in has a price.
+fn is_worthless_derivative(conversion: &ExchangeRate) -> bool {
+    matches!(conversion.from, Denomination::Derivative { .. }) && conversion.rate.is_zero()
+}
+
+/// Conversions that can be edges of the conversion graph. Conversions of a
+/// denomination into itself (e.g. USD to USD from CurrencyLayer) are dropped,
+/// and so are conversions at rates that aren't positive, with a warning unless
+/// they price a derivative at zero.
+fn usable_conversions(all_conversions: &[ExchangeRate]) -> Vec<&ExchangeRate> {
+    all_conversions
+        .iter()
+        .filter(|conversion| {
+            if conversion.from == conversion.to {
+                debug!("ignoring conversion of {} into itself", conversion.from);
+                return false;
+            }
+            if is_worthless_derivative(conversion) {
+                debug!("{} is worth nothing in {}", conversion.from, conversion.to);
+                return false;
+            }
+            if conversion.rate <= Decimal::ZERO {
                 warn!(
-                    "neg cycle, detected from {:?} to {:?}, weight={:?}",
-                    i, j, w
+                    "ignoring conversion from {} to {} at rate {}",
+                    conversion.from, conversion.to, conversion.rate
                 );
-                //break true;
+                return false;
             }
-        }
+            true
+        })
+        .collect()
+}
+
+/// How to combine the rates several converters quote for the same pair of
+/// denominations.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RatePolicy {
+    /// Keep every rate, so that the pair is priced along the path through the
+    /// lowest. Paths are chosen, and cycles found, at the mean rate.
+    #[default]
+    All,
+    /// The first rate, i.e. of the converter with the highest priority.
+    First,
+    /// The median rate, or the mean of the middle two.
+    Median,
+    /// The mean rate.
+    Mean,
+}
+
+/// `rate` from `from`, inverting it if it converts the other way.
+fn oriented(rate: &ExchangeRate, from: &Denomination) -> ExchangeRate {
+    if rate.from == *from {
+        return rate.clone();
+    }
+    ExchangeRate {
+        from: rate.to.clone(),
+        to: rate.from.clone(),
+        rate: Decimal::ONE / rate.rate,
+        ..rate.clone()
     }
+}
 
-    distance
+/// Rate and spread of `rates` (of one pair, oriented alike) averaged, as old
+/// as the oldest of them.
+fn mean(rates: &[ExchangeRate]) -> ExchangeRate {
+    let count = Decimal::from(rates.len());
+    ExchangeRate {
+        timestamp: rates.iter().map(|rate| rate.timestamp).min().unwrap(),
+        from: rates[0].from.clone(),
+        to: rates[0].to.clone(),
+        rate: rates.iter().map(|rate| rate.rate).sum::<Decimal>() / count,
+        spread_bps: rates.iter().map(|rate| rate.spread_bps).sum::<Decimal>() / count,
+    }
 }
 
-/// From petgraph, modified to use multiplication instead of addition.
-/// https://docs.rs/petgraph/0.4.0/src/petgraph/.cargo/registry/src/github.com-1ecc6299db9ec823/petgraph-0.4.0/src/algo.rs.html#550-592,
-///
-/// TODO(agentydragon): send PR to upstream petgraph for custom binary function
-//pub fn bellman_ford<G>(
-//    g: G,
-//    source: G::NodeId,
-//) -> Result<(Vec<f64>, Vec<Option<G::NodeId>>), petgraph::algo::NegativeCycle>
-//where
-//    G: NodeCount + IntoNodeIdentifiers + IntoEdges + NodeIndexable,
-//    G::EdgeWeight: f64,
-//{
-//    let mut predecessor = vec![None; g.node_bound()];
-//    let mut distance = vec![<_>::infinite(); g.node_bound()];
-//
-//    let ix = |i| g.to_index(i);
-//
-//    distance[ix(source)] = /* zero */;
-//    // scan up to |V| - 1 times.
-//    for _ in 1..g.node_count() {
-//        let mut did_update = false;
-//        for edge in g.edge_references() {
-//            let i = edge.source();
-//            let j = edge.target();
-//            let w = *edge.weight();
-//            if distance[ix(i)] + w < distance[ix(j)] {
-//                distance[ix(j)] = distance[ix(i)] + w;
-//                predecessor[ix(j)] = Some(i);
-//                did_update = true;
-//            }
-//        }
-//        if !did_update {
-//            break;
-//        }
-//    }
-//
-//    // check for negative weight cycle
-//    for i in g.node_identifiers() {
-//        for edge in g.edges(i) {
-//            let j = edge.target();
-//            let w = *edge.weight();
-//            if distance[ix(i)] * w < distance[ix(j)] {
-//                //println!("neg cycle, detected from {} to {}, weight={}", i, j, w);
-//                return Err(NegativeCycle(()));
-//            }
-//        }
-//    }
-//
-//    Ok((distance, predecessor))
-//}
-//
-pub fn in_common_currency(
+/// `all_conversions` with one conversion per pair of denominations, combining
+/// those of the same pair per `policy`. A conversion the other way round is of
+/// the same pair, at the inverse rate. Pairs stay in order of first
+/// appearance; conversions that can't be edges of the conversion graph are
+/// kept as they are, to be dropped with it.
+pub fn dedup_conversions(
     all_conversions: &[ExchangeRate],
-    base: &Denomination,
-) -> HashMap<Denomination, Decimal> {
+    policy: RatePolicy,
+) -> Vec<ExchangeRate> {
+    if policy == RatePolicy::All {
+        return all_conversions.to_vec();
+    }
+    let mut pairs: Vec<Vec<ExchangeRate>> = Vec::new();
+    for conversion in all_conversions {
+        if conversion.from == conversion.to || conversion.rate <= Decimal::ZERO {
+            pairs.push(vec![conversion.clone()]);
+            continue;
+        }
+        let same_pair = pairs.iter_mut().find(|pair| {
+            let first = &pair[0];
+            first.from != first.to
+                && ((first.from == conversion.from && first.to == conversion.to)
+                    || (first.from == conversion.to && first.to == conversion.from))
+        });
+        match same_pair {
+            Some(pair) => {
+                let from = pair[0].from.clone();
+                pair.push(oriented(conversion, &from));
+            }
+            None => pairs.push(vec![conversion.clone()]),
+        }
+    }
+    pairs
+        .into_iter()
+        .map(|mut pair| {
+            if pair.len() > 1 {
+                debug!(
+                    "combining {} rates of {} to {} by {:?}",
+                    pair.len(),
+                    pair[0].from,
+                    pair[0].to,
+                    policy
+                );
+            }
+            match policy {
+                RatePolicy::All | RatePolicy::First => pair.swap_remove(0),
+                RatePolicy::Mean => mean(&pair),
+                RatePolicy::Median => {
+                    pair.sort_by_key(|conversion| conversion.rate);
+                    let middle = pair.len() / 2;
+                    if pair.len() % 2 == 1 {
+                        pair.swap_remove(middle)
+                    } else {
+                        mean(&pair[middle - 1..=middle])
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// Graph with two edges per usable conversion, one each way. An edge from A to B
+/// is weighted with the price of one B in A, so path costs from the base are
+/// prices in the base.
+fn conversion_graph(
+    all_conversions: &[ExchangeRate],
+) -> (
+    Graph<Denomination, MultiplyDecimal>,
+    HashMap<Denomination, NodeIndex>,
+) {
+    let conversions = usable_conversions(all_conversions);
     let mut g = Graph::new();
-    // Exchanges might have created even more denominations.
-    let unique_denominations: HashSet<Denomination> = all_conversions
-        .iter()
-        .cloned()
-        .flat_map(|c| vec![c.from.clone(), c.to])
-        .collect();
-    // TODO: if base not in unique_denominations, fail
-    let denomination_to_node: HashMap<Denomination, petgraph::graph::NodeIndex<_>> =
-        unique_denominations
-            .iter()
-            .cloned()
-            .map(|denomination| {
-                (
-                    denomination.clone(),
-                    g.add_node(/* weight */ Some(denomination)),
-                )
-            })
-            .collect();
-    let conversion_tuples: Vec<_> = all_conversions
+    // Exchanges might have created even more denominations. Nodes are added
+    // in order of first appearance, to keep the graph deterministic.
+    let mut denomination_to_node: HashMap<Denomination, NodeIndex> = HashMap::new();
+    for denomination in conversions.iter().flat_map(|c| [&c.from, &c.to]) {
+        if !denomination_to_node.contains_key(denomination) {
+            let node = g.add_node(denomination.clone());
+            denomination_to_node.insert(denomination.clone(), node);
+        }
+    }
+    let conversion_tuples: Vec<_> = conversions
         .iter()
         .flat_map(|conversion| {
             vec![
                 (
                     denomination_to_node[&conversion.to],
                     denomination_to_node[&conversion.from],
-                    Finite(conversion.rate),
+                    edge(conversion.rate, conversion.spread_bps),
                 ),
                 // Reverse edges, if needed:
                 (
                     denomination_to_node[&conversion.from],
                     denomination_to_node[&conversion.to],
-                    Finite(dec!(1.0) / conversion.rate),
+                    edge(dec!(1.0) / conversion.rate, conversion.spread_bps),
                 ),
             ]
         })
         .collect();
     trace!("{:?}", conversion_tuples);
-    g.extend_with_edges(&conversion_tuples);
+    for (a, b, weight) in conversion_tuples {
+        g.add_edge(a, b, weight);
+    }
+    (g, denomination_to_node)
+}
+
+/// The conversion graph in Graphviz DOT format, for debugging surprising
+/// totals. Nodes are labeled with denominations, and an edge from A to B with the
+/// price of one B in A (and the price net of spreads, if different).
+pub fn conversion_graph_dot(all_conversions: &[ExchangeRate]) -> String {
+    let (g, _) = conversion_graph(all_conversions);
+    let labeled = g.map(
+        |_, denomination| denomination.to_string(),
+        |_, weight| match *weight {
+            Finite { rate, net_rate } if rate != net_rate => {
+                format!("{} (net {})", rate, net_rate)
+            }
+            Finite { rate, .. } => rate.to_string(),
+            Infinite => "unreachable".to_string(),
+        },
+    );
+    format!("{}", Dot::with_config(&labeled, &[]))
+}
+
+/// Round trips gaining less than this are taken to be rates quoted at slightly
+/// different times or rounded differently, not arbitrage. `checkrates` reports
+/// them.
+const ARBITRAGE_TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 2);
 
-    // println!("{:?}", petgraph::dot::Dot::with_config(&g, &[]));
+/// Conversions around a cycle that end with more than they started with,
+/// because converters' rates are inconsistent.
+#[derive(Debug, PartialEq)]
+pub struct ArbitrageCycle {
+    /// Denominations in the order they are converted through, the first not
+    /// repeated at the end.
+    pub denominations: Vec<Denomination>,
+    /// Product of the rates around the cycle: how many units of the first
+    /// denomination one unit of it converts back into.
+    pub rate: Decimal,
+}
 
-    // TODO: from config
-    let start = denomination_to_node[base];
+impl Display for ArbitrageCycle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for denomination in &self.denominations {
+            write!(f, "{} -> ", denomination)?;
+        }
+        write!(
+            f,
+            "{} converts 1 into {}",
+            self.denominations[0],
+            self.rate.round_dp(6)
+        )
+    }
+}
+
+impl From<ArbitrageCycle> for WorthyError {
+    fn from(cycle: ArbitrageCycle) -> Self {
+        WorthyError::Arbitrage {
+            denominations: cycle.denominations,
+            rate: cycle.rate,
+        }
+    }
+}
+
+/// Why denominations can't be priced in the base.
+#[derive(Debug, PartialEq)]
+pub enum CommonCurrencyError {
+    /// Nothing converts to or from `base`, e.g. because the converters
+    /// pricing it failed, so nothing else has a price.
+    BaseNotReachable {
+        base: Denomination,
+    },
+    Arbitrage(ArbitrageCycle),
+}
+
+impl From<ArbitrageCycle> for CommonCurrencyError {
+    fn from(cycle: ArbitrageCycle) -> Self {
+        CommonCurrencyError::Arbitrage(cycle)
+    }
+}
+
+impl From<CommonCurrencyError> for WorthyError {
+    fn from(error: CommonCurrencyError) -> Self {
+        match error {
+            CommonCurrencyError::BaseNotReachable { base } => {
+                WorthyError::BaseNotReachable { base }
+            }
+            CommonCurrencyError::Arbitrage(cycle) => cycle.into(),
+        }
+    }
+}
+
+/// The cycle of `nodes` in `g` with its rate, taking the best of parallel
+/// conversions like the shortest paths do.
+fn arbitrage_cycle(
+    g: &Graph<Denomination, MultiplyDecimal>,
+    nodes: &[NodeIndex],
+) -> Option<ArbitrageCycle> {
+    let mut cost = Decimal::ONE;
+    for (k, &from) in nodes.iter().enumerate() {
+        let to = nodes[(k + 1) % nodes.len()];
+        let rate = g
+            .edges_connecting(from, to)
+            .filter_map(|edge| match *edge.weight() {
+                Finite { rate, .. } => Some(rate),
+                Infinite => None,
+            })
+            .min()?;
+        // Paths through extreme rates may leave the range of `Decimal`.
+        cost = cost.checked_mul(rate)?;
+    }
+    Some(ArbitrageCycle {
+        denominations: nodes.iter().map(|&node| g[node].clone()).collect(),
+        // An edge is weighted with the price of its target, so converting
+        // along it divides by its weight.
+        rate: Decimal::ONE.checked_div(cost)?,
+    })
+}
+
+/// Price of one unit of each reachable denomination in `base`, net of the
+/// spreads of the conversions it takes to get there. Fails if nothing converts
+/// to or from `base`, or with the most profitable cycle of conversions
+/// reachable from `base` that gains more than `ARBITRAGE_TOLERANCE`, as prices
+/// would depend on how often it's gone around. Derivatives quoted at zero in
+/// a priced denomination are priced at zero.
+pub fn in_common_currency(
+    all_conversions: &[ExchangeRate],
+    base: &Denomination,
+) -> Result<HashMap<Denomination, Decimal>, CommonCurrencyError> {
+    Ok(in_common_currency_with_paths(all_conversions, base)?
+        .into_iter()
+        .map(|(denomination, (price, _))| (denomination, price))
+        .collect())
+}
+
+/// `in_common_currency`, along with the denominations each price was found by
+/// converting through, from the priced one back to `base`.
+pub fn in_common_currency_with_paths(
+    all_conversions: &[ExchangeRate],
+    base: &Denomination,
+) -> Result<HashMap<Denomination, (Decimal, Vec<Denomination>)>, CommonCurrencyError> {
+    let (g, denomination_to_node) = conversion_graph(all_conversions);
+    if !denomination_to_node.contains_key(base) {
+        return Err(CommonCurrencyError::BaseNotReachable { base: base.clone() });
+    }
+
+    // Several converters quoting one pair differently make a cycle of their
+    // own, as each way is priced at the lowest quote. That's for `checkrates`
+    // to report, so paths and cycles are found with each pair at its mean
+    // rate, and prices are then taken along the paths at the lowest quotes.
+    let (mean_g, mean_nodes) =
+        conversion_graph(&dedup_conversions(all_conversions, RatePolicy::Mean));
+    let start = mean_nodes[base];
     trace!("Start: {:?}", &start);
-    let costs = bellman_ford(&g, start);
+    let ShortestPaths {
+        costs,
+        predecessors,
+        negative_cycles,
+    } = bellman_ford_with_predecessors(&mean_g, start);
     trace!("costs={:?}", costs);
 
-    // On success, return one vec with path costs, and another one which points
-    // out the predecessor of a node along a shortest path.
-    denomination_to_node
-        .into_iter()
-        .filter_map(|(denomination, node)| {
-            let cost = costs[node.index()];
-            match cost {
+    let mut arbitrage: Option<ArbitrageCycle> = None;
+    for cycle in negative_cycles {
+        let Some(cycle) = arbitrage_cycle(&mean_g, &cycle.nodes) else {
+            continue;
+        };
+        if cycle.rate - Decimal::ONE <= ARBITRAGE_TOLERANCE {
+            info!("ignoring small arbitrage: {}", cycle);
+        } else if arbitrage
+            .as_ref()
+            .is_none_or(|worst| cycle.rate > worst.rate)
+        {
+            arbitrage = Some(cycle);
+        }
+    }
+    if let Some(cycle) = arbitrage {
+        return Err(cycle.into());
+    }
+
+    // Back along the predecessors, to the base.
+    let path_from = |node: NodeIndex| {
+        let mut path = vec![node];
+        let mut node = node;
+        while let Some(predecessor) = predecessors[node.index()] {
+            // A tolerated negative cycle would never end.
+            if node == start || path.len() > mean_g.node_count() {
+                break;
+            }
+            path.push(predecessor);
+            node = predecessor;
+        }
+        path
+    };
+    // Each step from the base outward at the lowest quote for it.
+    let price_along = |path: &[NodeIndex]| {
+        path.windows(2)
+            .map(|step| {
+                let (from, to) = (
+                    denomination_to_node[&mean_g[step[1]]],
+                    denomination_to_node[&mean_g[step[0]]],
+                );
+                g.edges_connecting(from, to)
+                    .map(|edge| *edge.weight())
+                    .reduce(|best, cost| if cost.less_than(&best) { cost } else { best })
+                    .unwrap_or(Infinite)
+            })
+            .fold(MultiplyDecimal::identity(), MultiplyDecimal::combine)
+    };
+    let mut prices: HashMap<Denomination, (Decimal, Vec<Denomination>)> = mean_nodes
+        .iter()
+        .filter_map(|(denomination, &node)| {
+            if let Infinite = costs[node.index()] {
+                return None;
+            }
+            let path = path_from(node);
+            match price_along(&path) {
                 Infinite => None,
-                Finite(x) => Some((denomination, x)),
+                Finite { net_rate, .. } => Some((
+                    denomination.clone(),
+                    (
+                        net_rate,
+                        path.iter().map(|&node| mean_g[node].clone()).collect(),
+                    ),
+                )),
+            }
+        })
+        .collect();
+    // Worthless derivatives are priced at zero without taking the graph.
+    for conversion in all_conversions {
+        if !is_worthless_derivative(conversion) || prices.contains_key(&conversion.from) {
+            continue;
+        }
+        let Some((_, path)) = prices.get(&conversion.to) else {
+            continue;
+        };
+        let path = std::iter::once(conversion.from.clone())
+            .chain(path.iter().cloned())
+            .collect();
+        prices.insert(conversion.from.clone(), (Decimal::ZERO, path));
+    }
+    Ok(prices)
+}
+
+/// Mid-market prices of one `to` in `from` implied by different paths of
+/// conversions between them.
+#[derive(Debug, PartialEq)]
+pub struct RateDisagreement {
+    pub from: Denomination,
+    pub to: Denomination,
+    /// Number of paths between the two.
+    pub paths: usize,
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+impl RateDisagreement {
+    /// How much higher the highest rate is than the lowest, in basis points.
+    /// Spreads too wide for `Decimal` are `Decimal::MAX`.
+    pub fn spread_bps(&self) -> Decimal {
+        (self.max - self.min)
+            .checked_div(self.min)
+            .and_then(|spread| spread.checked_mul(dec!(10000)))
+            .unwrap_or(Decimal::MAX)
+    }
+}
+
+/// For each pair of denominations converted between directly, the range of
+/// the direct rates and of the price along the path the pair would be priced
+/// through without them, if it takes at most `max_hops` conversions. Only
+/// pairs with more than one rate are listed, ordered by pair.
+pub fn rate_disagreements(
+    all_conversions: &[ExchangeRate],
+    max_hops: usize,
+) -> Vec<RateDisagreement> {
+    // Mid-market rates, as spreads aren't what converters disagree on.
+    let conversions: Vec<ExchangeRate> = usable_conversions(all_conversions)
+        .into_iter()
+        .map(|conversion| ExchangeRate {
+            spread_bps: Decimal::ZERO,
+            ..conversion.clone()
+        })
+        .collect();
+    let mut pairs: Vec<(&Denomination, &Denomination)> = conversions
+        .iter()
+        .map(|conversion| {
+            if conversion.from.to_string() <= conversion.to.to_string() {
+                (&conversion.from, &conversion.to)
+            } else {
+                (&conversion.to, &conversion.from)
+            }
+        })
+        .collect();
+    pairs.sort_by_key(|(from, to)| (from.to_string(), to.to_string()));
+    pairs.dedup();
+
+    pairs
+        .into_iter()
+        .filter_map(|(from, to)| {
+            let (direct, others): (Vec<&ExchangeRate>, Vec<&ExchangeRate>) =
+                conversions.iter().partition(|conversion| {
+                    (&conversion.from, &conversion.to) == (from, to)
+                        || (&conversion.from, &conversion.to) == (to, from)
+                });
+            // A conversion's rate is the price of its `from` in its `to`.
+            let mut rates: Vec<Decimal> = direct
+                .iter()
+                .filter_map(|conversion| {
+                    if &conversion.from == to {
+                        Some(conversion.rate)
+                    } else {
+                        Decimal::ONE.checked_div(conversion.rate)
+                    }
+                })
+                .collect();
+            if max_hops > 1 {
+                let others: Vec<ExchangeRate> = others.into_iter().cloned().collect();
+                // Cycles elsewhere are left for the snapshot to fail on.
+                if let Ok(prices) = in_common_currency_with_paths(&others, from)
+                    && let Some((price, path)) = prices.get(to)
+                    && path.len() - 1 <= max_hops
+                {
+                    rates.push(*price);
+                }
             }
+            if rates.len() < 2 {
+                return None;
+            }
+            Some(RateDisagreement {
+                from: from.clone(),
+                to: to.clone(),
+                paths: rates.len(),
+                min: rates.iter().copied().min()?,
+                max: rates.iter().copied().max()?,
+            })
         })
         .collect()
 }
@@ -1,6 +1,34 @@
+use chrono::DateTime;
+use common_currency::{
+    ArbitrageCycle, CommonCurrencyError, NegativeCycle, RatePolicy, bellman_ford,
+};
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
+use petgraph::prelude::*;
+use rust_decimal::prelude::*;
 use rust_decimal_macros::*;
+use std::collections::HashMap;
+use test_util::currency;
+use worthy_error::WorthyError;
+
+fn rate(from: &Denomination, to: &Denomination, rate: Decimal) -> ExchangeRate {
+    rate_with_spread(from, to, rate, dec!(0))
+}
+
+fn rate_with_spread(
+    from: &Denomination,
+    to: &Denomination,
+    rate: Decimal,
+    spread_bps: Decimal,
+) -> ExchangeRate {
+    ExchangeRate {
+        timestamp: DateTime::UNIX_EPOCH,
+        from: from.clone(),
+        to: to.clone(),
+        rate,
+        spread_bps,
+    }
+}
 
 #[test]
 fn one_conversion() {
@@ -14,12 +42,15 @@ fn one_conversion() {
     };
     let result = common_currency::in_common_currency(
         &[ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
             from: usd.clone(),
             to: czk.clone(),
             rate: dec!(30),
+            spread_bps: dec!(0),
         }],
         &czk,
-    );
+    )
+    .unwrap();
     println!("{:?}", result);
     assert!((result[&usd] - dec!(30)).abs() < dec!(0.001));
 }
@@ -41,18 +72,449 @@ fn two_conversions_chain() {
     let result = common_currency::in_common_currency(
         &[
             ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
                 from: usd.clone(),
                 to: czk.clone(),
                 rate: dec!(30),
+                spread_bps: dec!(0),
             },
             ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
                 from: czk,
                 to: plz.clone(),
                 rate: dec!(0.2),
+                spread_bps: dec!(0),
             },
         ],
         &plz,
-    );
+    )
+    .unwrap();
     println!("{:?}", result);
     assert!((result[&usd] - dec!(6.0)).abs() < dec!(0.001));
 }
+
+#[test]
+fn bellman_ford_additive() {
+    // a -1-> b -1-> c is shorter than a -5-> c.
+    let mut g = Graph::<(), f64>::new();
+    let a = g.add_node(());
+    let b = g.add_node(());
+    let c = g.add_node(());
+    let unreachable = g.add_node(());
+    g.extend_with_edges([(a, b, 1.0), (b, c, 1.0), (a, c, 5.0)]);
+    let costs = bellman_ford(&g, a).unwrap();
+    assert_eq!(costs[a.index()], 0.0);
+    assert_eq!(costs[b.index()], 1.0);
+    assert_eq!(costs[c.index()], 2.0);
+    assert_eq!(costs[unreachable.index()], f64::INFINITY);
+}
+
+#[test]
+fn bellman_ford_negative_cycle() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    // Going around b -> c -> b again and again makes paths ever shorter.
+    let mut g = Graph::<(), f64>::new();
+    let a = g.add_node(());
+    let b = g.add_node(());
+    let c = g.add_node(());
+    g.extend_with_edges([(a, b, 1.0), (c, b, 1.0), (b, c, -3.0)]);
+    assert_eq!(
+        bellman_ford(&g, a),
+        Err(NegativeCycle { nodes: vec![b, c] })
+    );
+}
+
+#[test]
+fn bellman_ford_additive_logarithms_match_conversions() {
+    // Adding logarithms of rates is multiplying the rates, so the additive
+    // instance must agree with in_common_currency.
+    // 1 USD is 30 CZK, 1 CZK is 0.2 PLZ.
+    let usd = Denomination::Currency {
+        currency: "USD".to_string(),
+    };
+    let czk = Denomination::Currency {
+        currency: "CZK".to_string(),
+    };
+    let plz = Denomination::Currency {
+        currency: "PLZ".to_string(),
+    };
+    let conversions = [
+        ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
+            from: usd.clone(),
+            to: czk.clone(),
+            rate: dec!(30),
+            spread_bps: dec!(0),
+        },
+        ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
+            from: czk.clone(),
+            to: plz.clone(),
+            rate: dec!(0.2),
+            spread_bps: dec!(0),
+        },
+    ];
+    let expected = common_currency::in_common_currency(&conversions, &plz).unwrap();
+
+    let mut g = Graph::<(), f64>::new();
+    let nodes: HashMap<&Denomination, NodeIndex> = [&usd, &czk, &plz]
+        .into_iter()
+        .map(|d| (d, g.add_node(())))
+        .collect();
+    for conversion in &conversions {
+        let ln_rate = conversion.rate.to_f64().unwrap().ln();
+        g.add_edge(nodes[&conversion.to], nodes[&conversion.from], ln_rate);
+        g.add_edge(nodes[&conversion.from], nodes[&conversion.to], -ln_rate);
+    }
+    let costs = bellman_ford(&g, nodes[&plz]).unwrap();
+
+    for denomination in [&usd, &czk, &plz] {
+        let multiplied = expected[denomination].to_f64().unwrap();
+        let added = costs[nodes[denomination].index()].exp();
+        assert!((multiplied - added).abs() < 1e-9, "{:?}", denomination);
+    }
+}
+
+#[test]
+fn spread_compounds_along_path() {
+    // 1 USD is 30 CZK, 1 CZK is 0.2 PLZ, each conversion at a 50bps spread.
+    // CZK is one hop from PLZ, USD two.
+    let usd = Denomination::Currency {
+        currency: "USD".to_string(),
+    };
+    let czk = Denomination::Currency {
+        currency: "CZK".to_string(),
+    };
+    let plz = Denomination::Currency {
+        currency: "PLZ".to_string(),
+    };
+    let conversions = |spread_bps| {
+        vec![
+            ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: usd.clone(),
+                to: czk.clone(),
+                rate: dec!(30),
+                spread_bps,
+            },
+            ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: czk.clone(),
+                to: plz.clone(),
+                rate: dec!(0.2),
+                spread_bps,
+            },
+        ]
+    };
+    let mid = common_currency::in_common_currency(&conversions(dec!(0)), &plz).unwrap();
+    let net = common_currency::in_common_currency(&conversions(dec!(50)), &plz).unwrap();
+
+    assert_eq!(net[&plz], dec!(1));
+    assert_eq!(net[&czk], dec!(0.2) * dec!(0.995));
+    assert!((net[&usd] - dec!(6) * dec!(0.995) * dec!(0.995)).abs() < dec!(0.000001));
+    let one_hop_loss = dec!(1) - net[&czk] / mid[&czk];
+    let two_hop_loss = dec!(1) - net[&usd] / mid[&usd];
+    assert!(two_hop_loss > one_hop_loss);
+}
+
+#[test]
+fn conversion_graph_dot_labels() {
+    let usd = Denomination::Currency {
+        currency: "USD".to_string(),
+    };
+    let czk = Denomination::Currency {
+        currency: "CZK".to_string(),
+    };
+    let plz = Denomination::Currency {
+        currency: "PLZ".to_string(),
+    };
+    let dot = common_currency::conversion_graph_dot(&[
+        ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
+            from: usd,
+            to: czk.clone(),
+            rate: dec!(30),
+            spread_bps: dec!(0),
+        },
+        ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
+            from: czk,
+            to: plz,
+            rate: dec!(0.2),
+            spread_bps: dec!(50),
+        },
+    ]);
+    // Nodes in order of first appearance: USD, CZK, PLZ.
+    assert!(dot.contains("0 [ label = \"currency:USD\" ]"), "{}", dot);
+    assert!(dot.contains("1 [ label = \"currency:CZK\" ]"), "{}", dot);
+    assert!(dot.contains("2 [ label = \"currency:PLZ\" ]"), "{}", dot);
+    // 1 USD is 30 CZK.
+    assert!(dot.contains("1 -> 0 [ label = \"30\" ]"), "{}", dot);
+    // 1 CZK is 0.2 PLZ, 0.199 net of the spread.
+    assert!(
+        dot.contains("2 -> 1 [ label = \"0.2 (net 0.1990)\" ]"),
+        "{}",
+        dot
+    );
+}
+
+#[test]
+fn paths_and_hops() {
+    let (usd, czk, plz) = (currency("USD"), currency("CZK"), currency("PLZ"));
+    let btc = Denomination::Cryptocurrency {
+        symbol: "BTC".to_string(),
+    };
+    let result = common_currency::in_common_currency_with_paths(
+        &[
+            rate(&usd, &czk, dec!(30)),
+            rate(&czk, &plz, dec!(0.2)),
+            // Not connected to the rest.
+            rate(&btc, &currency("XBT"), dec!(1)),
+        ],
+        &plz,
+    )
+    .unwrap();
+
+    assert_eq!(result[&plz], (dec!(1), vec![plz.clone()]));
+    assert_eq!(
+        result[&usd],
+        (dec!(6.0), vec![usd.clone(), czk.clone(), plz.clone()])
+    );
+    assert!(!result.contains_key(&btc));
+}
+
+#[test]
+fn rate_disagreements_between_converters() {
+    let (usd, eur, czk) = (currency("USD"), currency("EUR"), currency("CZK"));
+    let conversions = [
+        // One converter...
+        rate(&eur, &usd, dec!(1.1)),
+        rate(&usd, &czk, dec!(20)),
+        rate(&eur, &czk, dec!(22)),
+        // ... and another one, disagreeing.
+        rate(&eur, &usd, dec!(1.2)),
+    ];
+
+    let disagreements = common_currency::rate_disagreements(&conversions, 3);
+    assert_eq!(
+        disagreements
+            .iter()
+            .map(|d| (d.from.clone(), d.to.clone(), d.paths))
+            .collect::<Vec<_>>(),
+        vec![
+            (czk.clone(), eur.clone(), 2),
+            (czk.clone(), usd.clone(), 2),
+            (eur.clone(), usd.clone(), 3),
+        ]
+    );
+    // Price of one EUR in CZK: directly, or through USD at the lower rate.
+    assert_eq!(disagreements[0].min, dec!(22));
+    assert_eq!(disagreements[0].max, dec!(22));
+    // Price of one USD in CZK: directly, or through EUR at 1.2 EUR/USD.
+    assert_eq!(disagreements[1].min.round_dp(4), dec!(18.3333));
+    assert_eq!(disagreements[1].max, dec!(20));
+    assert_eq!(disagreements[1].spread_bps().round_dp(2), dec!(909.09));
+
+    // With direct paths only, just the two EUR/USD rates disagree.
+    let direct = common_currency::rate_disagreements(&conversions, 1);
+    assert_eq!(direct.len(), 1);
+    assert_eq!((&direct[0].from, &direct[0].to), (&eur, &usd));
+    assert_eq!(direct[0].spread_bps().round_dp(2), dec!(909.09));
+
+    // Consistent rates agree, but for rounding.
+    let consistent = common_currency::rate_disagreements(&conversions[..3], 3);
+    assert_eq!(consistent.len(), 3);
+    for disagreement in consistent {
+        assert!(
+            disagreement.spread_bps() < dec!(0.000001),
+            "{:?}",
+            disagreement
+        );
+    }
+}
+
+#[test]
+fn dedup_conversions_by_policy() {
+    let (usd, eur, czk) = (currency("USD"), currency("EUR"), currency("CZK"));
+    let conversions = [
+        rate_with_spread(&eur, &usd, dec!(1.1), dec!(10)),
+        rate_with_spread(&usd, &czk, dec!(20), dec!(0)),
+        // The same pair the other way round, at 1.25 EUR/USD.
+        rate_with_spread(&usd, &eur, dec!(0.8), dec!(30)),
+        rate_with_spread(&eur, &usd, dec!(1.3), dec!(20)),
+    ];
+    let combined = |policy| {
+        common_currency::dedup_conversions(&conversions, policy)
+            .into_iter()
+            .map(|conversion| {
+                (
+                    conversion.from,
+                    conversion.to,
+                    conversion.rate.round_dp(6),
+                    conversion.spread_bps.round_dp(6),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        common_currency::dedup_conversions(&conversions, RatePolicy::All),
+        conversions
+    );
+    assert_eq!(
+        combined(RatePolicy::First),
+        vec![
+            (eur.clone(), usd.clone(), dec!(1.1), dec!(10)),
+            (usd.clone(), czk.clone(), dec!(20), dec!(0)),
+        ]
+    );
+    assert_eq!(
+        combined(RatePolicy::Median),
+        vec![
+            (eur.clone(), usd.clone(), dec!(1.25), dec!(30)),
+            (usd.clone(), czk.clone(), dec!(20), dec!(0)),
+        ]
+    );
+    assert_eq!(
+        combined(RatePolicy::Mean),
+        vec![
+            (eur.clone(), usd.clone(), dec!(1.216667), dec!(20)),
+            (usd.clone(), czk.clone(), dec!(20), dec!(0)),
+        ]
+    );
+    // Of an even number of rates, the median is the mean of the middle two.
+    assert_eq!(
+        common_currency::dedup_conversions(&conversions[..3], RatePolicy::Median)[0].rate,
+        dec!(1.175)
+    );
+}
+
+#[test]
+fn self_loop_rate_is_ignored() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let (usd, czk) = (currency("USD"), currency("CZK"));
+    // CurrencyLayer quotes USD in USD, too.
+    let conversions = [rate(&usd, &usd, dec!(1)), rate(&usd, &czk, dec!(30))];
+
+    let result = common_currency::in_common_currency_with_paths(&conversions, &czk).unwrap();
+    assert_eq!(result[&usd], (dec!(30), vec![usd.clone(), czk.clone()]));
+    assert!(common_currency::rate_disagreements(&conversions, 3).is_empty());
+    let dot = common_currency::conversion_graph_dot(&conversions);
+    assert!(!dot.contains("0 -> 0"), "{}", dot);
+}
+
+#[test]
+fn zero_rate_is_ignored() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let (usd, eur, czk) = (currency("USD"), currency("EUR"), currency("CZK"));
+    // Inverting a zero rate would divide by zero.
+    let conversions = [
+        rate(&usd, &czk, dec!(30)),
+        rate(&eur, &czk, dec!(0)),
+        rate(&eur, &usd, dec!(-1.1)),
+    ];
+
+    let result = common_currency::in_common_currency(&conversions, &czk).unwrap();
+    assert_eq!(result.get(&usd), Some(&dec!(30)));
+    assert!(!result.contains_key(&eur));
+    assert!(common_currency::rate_disagreements(&conversions, 3).is_empty());
+}
+
+#[test]
+fn base_without_conversions() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let btc = Denomination::Cryptocurrency {
+        symbol: "BTC".to_string(),
+    };
+    let eth = Denomination::Cryptocurrency {
+        symbol: "ETH".to_string(),
+    };
+    let chf = Denomination::Currency {
+        currency: "CHF".to_string(),
+    };
+    // E.g. only a crypto converter answered.
+    let conversions = [ExchangeRate {
+        timestamp: DateTime::UNIX_EPOCH,
+        from: btc.clone(),
+        to: eth.clone(),
+        rate: dec!(15),
+        spread_bps: dec!(0),
+    }];
+
+    let error = common_currency::in_common_currency(&conversions, &chf).unwrap_err();
+    assert_eq!(
+        error,
+        CommonCurrencyError::BaseNotReachable { base: chf.clone() }
+    );
+    assert_eq!(
+        WorthyError::from(error).to_string(),
+        "no rates convert to or from the common currency currency:CHF"
+    );
+    // Nor any at all.
+    assert_eq!(
+        common_currency::in_common_currency_with_paths(&[], &chf),
+        Err(CommonCurrencyError::BaseNotReachable { base: chf })
+    );
+}
+
+#[test]
+fn arbitrage_is_an_error() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let (usd, eur, czk) = (currency("USD"), currency("EUR"), currency("CZK"));
+    // Through EUR, a USD is worth 36 CZK, but directly only 30.
+    let conversions = |eur_usd| {
+        [
+            rate(&usd, &czk, dec!(30)),
+            rate(&eur, &czk, dec!(36)),
+            rate(&eur, &usd, eur_usd),
+        ]
+    };
+
+    let error = common_currency::in_common_currency(&conversions(dec!(1)), &czk).unwrap_err();
+    let CommonCurrencyError::Arbitrage(ArbitrageCycle {
+        denominations,
+        rate,
+    }) = &error
+    else {
+        panic!("{:?}", error);
+    };
+    // 1 USD buys 1 EUR, which buys 36 CZK, which buy 1.2 USD.
+    assert_eq!(denominations, &vec![usd.clone(), eur.clone(), czk.clone()]);
+    assert!((rate - dec!(1.2)).abs() < dec!(0.000001), "{}", rate);
+    assert_eq!(
+        WorthyError::from(error).to_string(),
+        "inconsistent rates: converting around currency:USD -> currency:EUR -> currency:CZK -> \
+         currency:USD multiplies money by 1.200000"
+    );
+
+    // Rates a little off are only reported by checkrates, and USD is priced
+    // along the cheaper path, through EUR.
+    let result = common_currency::in_common_currency(&conversions(dec!(1.201)), &czk).unwrap();
+    assert_eq!(result[&czk], dec!(1));
+    assert!(
+        (result[&usd] - dec!(36) / dec!(1.201)).abs() < dec!(0.000001),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn converters_disagreeing_on_a_pair_are_not_arbitrage() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let (usd, eur, czk) = (currency("USD"), currency("EUR"), currency("CZK"));
+    // Two converters 10% apart on EUR/USD, as kept by `RatePolicy::All`.
+    let conversions = [
+        rate(&eur, &usd, dec!(1.1)),
+        rate(&eur, &usd, dec!(1.2)),
+        rate(&usd, &czk, dec!(20)),
+    ];
+
+    let result = common_currency::in_common_currency(&conversions, &czk).unwrap();
+    // Priced at the lower quote.
+    assert_eq!(result[&eur], dec!(22));
+    assert_eq!(result[&usd], dec!(20));
+    assert_eq!(
+        common_currency::in_common_currency(&conversions, &usd).unwrap()[&eur],
+        dec!(1.1)
+    );
+}
@@ -1,43 +1,133 @@
-use alphavantage_converter::AlphaVantageConverterConfig;
 use asset::Asset;
-use currencylayer_converter::CurrencyLayerConverterConfig;
-use fixer_converter::FixerConverterConfig;
-use ibflex_source::IBFlexSourceConfig;
+use basket::Basket;
+use common_currency::RatePolicy;
+use denomination::Denomination;
+use log::warn;
 use rust_decimal::prelude::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, de, de::DeserializeOwned};
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
-#[serde(tag = "type")]
-pub enum SourceType {
-    Hardcoded { assets: Vec<Asset> },
-    IBFlex(IBFlexSourceConfig),
+/// The `type` of a source or converter, e.g. "ibflex", and the rest of its
+/// settings, which only the implementation registered for that type parses.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct TypedConfig {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(flatten)]
+    pub settings: serde_yaml::Mapping,
+}
+
+impl TypedConfig {
+    /// `settings` without `type`, as a value to parse the implementation's
+    /// configuration from.
+    pub fn settings_value(&self) -> serde_yaml::Value {
+        serde_yaml::Value::Mapping(self.settings.clone())
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct SourceConfig {
     pub name: String,
     #[serde(flatten)]
-    pub source_type: SourceType,
+    pub typed: TypedConfig,
+}
+
+pub type ConverterConfig = TypedConfig;
+
+/// Monthly spending to model: one amount, or a basket of amounts in different
+/// denominations (e.g. rent in CHF and travel in EUR), each priced on its own.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "MonthlyTargetConfig")]
+pub struct MonthlyTarget {
+    pub components: Vec<Asset>,
+}
+
+impl From<Asset> for MonthlyTarget {
+    fn from(asset: Asset) -> Self {
+        MonthlyTarget {
+            components: vec![asset],
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "snake_case")]
-#[serde(tag = "type")]
-pub enum ConverterConfig {
-    CurrencyLayer(CurrencyLayerConverterConfig),
-    AlphaVantage(AlphaVantageConverterConfig),
-    Fixer(FixerConverterConfig),
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MonthlyTargetConfig {
+    Basket { basket: Vec<Asset> },
+    Single(Asset),
+}
+
+impl TryFrom<MonthlyTargetConfig> for MonthlyTarget {
+    type Error = String;
+
+    fn try_from(config: MonthlyTargetConfig) -> Result<Self, Self::Error> {
+        match config {
+            MonthlyTargetConfig::Basket { basket } if basket.is_empty() => {
+                Err("monthly target basket has no components".to_string())
+            }
+            MonthlyTargetConfig::Basket { basket } => Ok(MonthlyTarget { components: basket }),
+            MonthlyTargetConfig::Single(asset) => Ok(asset.into()),
+        }
+    }
+}
+
+/// Decimal places shown in the model table. Those not set are shown as by
+/// default.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct DisplayPrecision {
+    /// Amounts in ISO currencies. By default, their minor units, e.g. 2 for
+    /// USD.
+    pub currency: Option<u32>,
+    /// Percentages: yields (by default 2) and how far a goal is exceeded (by
+    /// default 0).
+    pub percent: Option<u32>,
+    /// Amounts in cryptocurrencies. By default, all of them.
+    pub crypto: Option<u32>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ModellingConfig {
     pub monthly_saving: Asset,
-    /// Yearly yields. 0.03 = 3%
+    /// Yearly yields. 0.03 = 3%, or with `yields_as_percent`, 3 = 3%.
     pub yearly_yields: Vec<Decimal>,
     /// Monthly spending targets to simulate
-    pub monthly_targets: Vec<Asset>,
+    pub monthly_targets: Vec<MonthlyTarget>,
+    /// Whether `yearly_yields` are written in percent.
+    #[serde(default)]
+    pub yields_as_percent: bool,
+    #[serde(default)]
+    pub display_precision: DisplayPrecision,
+    /// How many more years to model for (i.e., remaining lifetime).
+    #[serde(default = "default_horizon_years")]
+    pub horizon_years: Decimal,
+}
+
+fn default_horizon_years() -> Decimal {
+    Decimal::from(75)
+}
+
+impl ModellingConfig {
+    /// Turns `yearly_yields` into fractions if they are written in percent,
+    /// and warns about yields over 100%, which are most likely percentages
+    /// written as fractions.
+    pub fn normalize_yields(&mut self) {
+        if self.yields_as_percent {
+            for yearly_yield in &mut self.yearly_yields {
+                *yearly_yield /= Decimal::ONE_HUNDRED;
+            }
+            self.yields_as_percent = false;
+        }
+        for yearly_yield in &self.yearly_yields {
+            if *yearly_yield > Decimal::ONE {
+                warn!(
+                    "yearly yield {} is over 100%; if you meant {}%, write {} or set yields_as_percent",
+                    yearly_yield,
+                    yearly_yield,
+                    *yearly_yield / Decimal::ONE_HUNDRED
+                );
+            }
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -76,21 +166,138 @@ pub struct CFireSimConfig {
     pub initial_yearly_spending: u32,
 }
 
+fn denomination_from_str<'de, D>(deserializer: D) -> Result<Denomination, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+fn denominations_from_str<'de, D>(deserializer: D) -> Result<Vec<Denomination>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|denomination| denomination.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+/// Parses each entry of `raw` on its own, so one malformed entry (e.g. with a
+/// renamed field) doesn't keep the rest from loading. Returns the entries that
+/// parse, and the error of each one that doesn't, prefixed with its ID.
+pub fn parse_entries<T: DeserializeOwned>(
+    raw: HashMap<String, serde_yaml::Value>,
+) -> (HashMap<String, T>, Vec<String>) {
+    let mut entries = HashMap::new();
+    let mut errors = Vec::new();
+    for (id, value) in raw {
+        match serde_yaml::from_value(value) {
+            Ok(entry) => {
+                entries.insert(id, entry);
+            }
+            Err(error) => errors.push(format!("{}: {}", id, error)),
+        }
+    }
+    errors.sort();
+    (entries, errors)
+}
+
+fn lenient_entries<'de, D, T>(deserializer: D) -> Result<HashMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let (entries, errors) = parse_entries(HashMap::deserialize(deserializer)?);
+    for error in errors {
+        warn!("skipping {}", error);
+    }
+    Ok(entries)
+}
+
+/// Like `lenient_entries`, but fails naming every entry that doesn't parse.
+fn strict_entries<'de, D, T>(deserializer: D) -> Result<HashMap<String, T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let (entries, errors) = parse_entries(HashMap::deserialize(deserializer)?);
+    if !errors.is_empty() {
+        return Err(de::Error::custom(errors.join("; ")));
+    }
+    Ok(entries)
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    /// Keyed by source ID.
-    #[serde(rename = "sources")]
+    /// Keyed by source ID. A source that doesn't parse fails the whole config,
+    /// as the total would silently lack it.
+    #[serde(rename = "sources", default, deserialize_with = "strict_entries")]
     pub source_config: HashMap<String, SourceConfig>,
 
-    /// Keyed by converter ID.
-    #[serde(rename = "converters")]
+    /// Keyed by converter ID. Converters that don't parse are left out.
+    #[serde(rename = "converters", default, deserialize_with = "lenient_entries")]
     pub converter_config: HashMap<String, ConverterConfig>,
 
-    pub common_currency: String,
+    /// Denomination everything is valued in: a currency code such as "USD",
+    /// or e.g. "crypto:BTC" or "stock:VT".
+    #[serde(deserialize_with = "denomination_from_str")]
+    pub common_currency: Denomination,
+    /// Where snapshots are written. `%s` is replaced by the snapshot's
+    /// timestamp, `%date` by its date (YYYY-MM-DD) and `%label` by its label.
     pub dated_json_output: String,
+    /// strftime-style format of the timestamp `%s` is replaced by in
+    /// `dated_json_output`, e.g. "%Y%m%dT%H%M%S". RFC 3339 if unset.
+    #[serde(default)]
+    pub timestamp_format: Option<String>,
+    /// Optional JSON Lines file every snapshot is also appended to, one per
+    /// line. If set, the CSV export streams it instead of reading each
+    /// snapshot file.
+    #[serde(default)]
+    pub jsonl_history: Option<String>,
+    /// Optional directory each converter's rates are recorded to, one file
+    /// per snapshot. A converter that can't be reached is priced with its
+    /// newest recording, and `--replay-rates` only uses recordings.
+    #[serde(default)]
+    pub rate_recordings: Option<String>,
+    /// How rates several converters quote for the same pair are combined
+    /// before pricing: "all" (the default) keeps each, "first", "median" or
+    /// "mean" combine them into one.
+    #[serde(default)]
+    pub rate_policy: RatePolicy,
+    /// Converter IDs in order of priority for `rate_policy: first`.
+    /// Converters not listed come after, in no particular order.
+    #[serde(default)]
+    pub converter_priority: Vec<String>,
     pub csv_output: String,
+    /// Optional HTML page `-command=html` writes the model of the newest
+    /// snapshot to.
+    #[serde(default)]
+    pub html_output: Option<String>,
     pub modelling: ModellingConfig,
 
     /// cFIREsim configuration.
     pub cfiresim: Option<CFireSimConfig>,
+
+    /// Stocks priced through what they hold, for lack of a direct price.
+    #[serde(default)]
+    pub baskets: Vec<Basket>,
+
+    /// Symbols to rename in everything sources and converters return, from
+    /// the alias to the canonical symbol, e.g. `XBT: BTC` or `VOD.L: VOD`.
+    /// Makes assets and rates that name the same thing differently meet.
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+
+    /// Source IDs left out of the modelled total, e.g. illiquid funds or a
+    /// pension. Snapshots still hold them.
+    #[serde(default)]
+    pub exclude_sources: Vec<String>,
+
+    /// Denominations left out of the modelled total, written like
+    /// `common_currency`.
+    #[serde(default, deserialize_with = "denominations_from_str")]
+    pub exclude_denominations: Vec<Denomination>,
 }
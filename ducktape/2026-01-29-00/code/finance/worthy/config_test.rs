@@ -0,0 +1,195 @@
+use asset::Asset;
+use common_currency::RatePolicy;
+use config::{Config, ConverterConfig, ModellingConfig, MonthlyTarget, parse_entries};
+use denomination::Denomination;
+use rust_decimal_macros::dec;
+
+fn modelling(yaml: &str) -> ModellingConfig {
+    let mut modelling: ModellingConfig = serde_yaml::from_str(&format!(
+        "monthly_saving: {{currency: USD, amount: 100}}\nmonthly_targets: []\n{}",
+        yaml
+    ))
+    .unwrap();
+    modelling.normalize_yields();
+    modelling
+}
+
+#[test]
+fn yields_as_fractions() {
+    let modelling = modelling("yearly_yields: [0.03, 0.06]");
+    assert_eq!(modelling.yearly_yields, vec![dec!(0.03), dec!(0.06)]);
+}
+
+#[test]
+fn yields_as_percent() {
+    let modelling = modelling("yearly_yields: [3, 6.5]\nyields_as_percent: true");
+    assert_eq!(modelling.yearly_yields, vec![dec!(0.03), dec!(0.065)]);
+
+    // Normalizing again leaves them alone.
+    let mut modelling = modelling;
+    modelling.normalize_yields();
+    assert_eq!(modelling.yearly_yields, vec![dec!(0.03), dec!(0.065)]);
+}
+
+#[test]
+fn horizon_years() {
+    assert_eq!(modelling("yearly_yields: []").horizon_years, dec!(75));
+    assert_eq!(
+        modelling("yearly_yields: []\nhorizon_years: 40").horizon_years,
+        dec!(40)
+    );
+}
+
+#[test]
+fn suspicious_yields_are_kept() {
+    // Only warned about: the yields could be meant.
+    let modelling = modelling("yearly_yields: [3]");
+    assert_eq!(modelling.yearly_yields, vec![dec!(3)]);
+}
+
+fn common_currency(common_currency: &str) -> Result<Denomination, serde_yaml::Error> {
+    serde_yaml::from_str::<Config>(&format!(
+        r#"
+sources: {{}}
+converters: {{}}
+common_currency: "{}"
+dated_json_output: /tmp/worthy-%s.json
+csv_output: /tmp/worthy.csv
+modelling:
+  monthly_saving: {{currency: USD, amount: 100}}
+  yearly_yields: []
+  monthly_targets: []
+"#,
+        common_currency
+    ))
+    .map(|config| config.common_currency)
+}
+
+#[test]
+fn common_currency_of_any_denomination() {
+    assert_eq!(
+        common_currency("USD").unwrap(),
+        Denomination::Currency {
+            currency: "USD".to_string()
+        }
+    );
+    assert_eq!(
+        common_currency("crypto:BTC").unwrap(),
+        Denomination::Cryptocurrency {
+            symbol: "BTC".to_string()
+        }
+    );
+    assert_eq!(
+        common_currency("stock:VT").unwrap(),
+        Denomination::Stock {
+            stock: "VT".to_string(),
+            isin: None,
+        }
+    );
+    assert!(common_currency("bond:X").is_err());
+}
+
+fn monthly_targets(yaml: &str) -> Result<Vec<MonthlyTarget>, serde_yaml::Error> {
+    serde_yaml::from_str::<ModellingConfig>(&format!(
+        "monthly_saving: {{currency: USD, amount: 100}}\nyearly_yields: []\nmonthly_targets: {}",
+        yaml
+    ))
+    .map(|modelling| modelling.monthly_targets)
+}
+
+#[test]
+fn monthly_target_baskets() {
+    let asset = |currency: &str, amount| Asset {
+        amount,
+        denomination: Denomination::Currency {
+            currency: currency.to_string(),
+        },
+        cost_basis: None,
+    };
+    let targets = monthly_targets(
+        "[{currency: CHF, amount: 3000}, \
+          {basket: [{currency: CHF, amount: 2000}, {currency: EUR, amount: 500}]}]",
+    )
+    .unwrap();
+    assert_eq!(
+        targets,
+        vec![
+            asset("CHF", dec!(3000)).into(),
+            MonthlyTarget {
+                components: vec![asset("CHF", dec!(2000)), asset("EUR", dec!(500))],
+            },
+        ]
+    );
+
+    assert!(monthly_targets("[{basket: []}]").is_err());
+}
+
+const MALFORMED_CONVERTER: &str = r#"
+sources: {}
+converters:
+  fixer:
+    type: fixer
+    api_key: FIXER_KEY
+  currencylayer:
+    # Missing type.
+    api_key: CURRENCYLAYER_KEY
+common_currency: USD
+dated_json_output: /tmp/worthy-%s.json
+csv_output: /tmp/worthy.csv
+modelling:
+  monthly_saving: {currency: USD, amount: 100}
+  yearly_yields: []
+  monthly_targets: []
+"#;
+
+#[test]
+fn rate_policy() {
+    let config: Config = serde_yaml::from_str(MALFORMED_CONVERTER).unwrap();
+    assert_eq!(config.rate_policy, RatePolicy::All);
+    assert!(config.converter_priority.is_empty());
+
+    let config: Config = serde_yaml::from_str(&format!(
+        "{}rate_policy: first\nconverter_priority: [fixer]\n",
+        MALFORMED_CONVERTER
+    ))
+    .unwrap();
+    assert_eq!(config.rate_policy, RatePolicy::First);
+    assert_eq!(config.converter_priority, vec!["fixer"]);
+    assert!(
+        serde_yaml::from_str::<Config>(&format!("{}rate_policy: best\n", MALFORMED_CONVERTER))
+            .is_err()
+    );
+}
+
+#[test]
+fn malformed_converter_is_skipped() {
+    let config: Config = serde_yaml::from_str(MALFORMED_CONVERTER).unwrap();
+    assert_eq!(config.converter_config.len(), 1);
+    let fixer = &config.converter_config["fixer"];
+    assert_eq!(fixer.type_name, "fixer");
+    // Everything but the type is left for the converter to parse.
+    assert_eq!(
+        fixer.settings_value(),
+        serde_yaml::from_str::<serde_yaml::Value>("api_key: FIXER_KEY").unwrap()
+    );
+}
+
+#[test]
+fn malformed_entries_are_reported() {
+    let config: serde_yaml::Value = serde_yaml::from_str(MALFORMED_CONVERTER).unwrap();
+    let raw = serde_yaml::from_value(config["converters"].clone()).unwrap();
+    let (converters, errors) = parse_entries::<ConverterConfig>(raw);
+    assert_eq!(converters.keys().collect::<Vec<_>>(), vec!["fixer"]);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("currencylayer: "), "{:?}", errors);
+}
+
+#[test]
+fn malformed_source_fails() {
+    let config = MALFORMED_CONVERTER.replace(
+        "sources: {}",
+        "sources:\n  bank:\n    type: hardcoded\n    asets: []",
+    );
+    let error = serde_yaml::from_str::<Config>(&config).unwrap_err();
+    assert!(error.to_string().contains("bank: "), "{}", error);
+}
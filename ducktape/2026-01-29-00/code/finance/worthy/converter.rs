@@ -1,15 +1,161 @@
 use async_trait::async_trait;
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
-use std::error::Error;
+use log::warn;
+use serde::Deserialize;
+use std::marker::PhantomData;
+use worthy_error::WorthyError;
 
 #[async_trait]
 pub trait Converter {
     type Config;
 
+    /// Whether this converter can price the given denomination. Denominations
+    /// it can't handle are not passed to `take_snapshot`.
+    fn can_handle(_denomination: &Denomination) -> bool {
+        true
+    }
+
+    /// Number of API calls `take_snapshot` makes for `denominations` at most,
+    /// counted against the daily call budget.
+    fn api_calls(_config: &Self::Config, _denominations: &[&Denomination]) -> u32 {
+        1
+    }
+
     async fn take_snapshot(
         config: &Self::Config,
         denominations: &'life1 [&Denomination],
         base: &Denomination,
-    ) -> Result<Vec<ExchangeRate>, Box<dyn Error>>;
+    ) -> Result<Vec<ExchangeRate>, WorthyError>;
+}
+
+/// A configured converter, callable without knowing its type, e.g. when
+/// looked up by its `type` in the configuration.
+#[async_trait]
+pub trait DynConverter: Send + Sync {
+    /// The subset of `denominations` the converter can price.
+    fn handled<'a>(&self, denominations: &[&'a Denomination]) -> Vec<&'a Denomination>;
+
+    /// See `Converter::api_calls`.
+    fn api_calls(&self, denominations: &[&Denomination]) -> u32;
+
+    async fn take_snapshot(
+        &self,
+        denominations: &'life1 [&Denomination],
+        base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError>;
+}
+
+/// Converter `C` along with its configuration.
+pub struct Configured<C: Converter> {
+    config: C::Config,
+    converter: PhantomData<fn() -> C>,
+}
+
+impl<C: Converter> Configured<C> {
+    pub fn new(config: C::Config) -> Self {
+        Configured {
+            config,
+            converter: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<C> DynConverter for Configured<C>
+where
+    C: Converter,
+    C::Config: Send + Sync,
+{
+    fn handled<'a>(&self, denominations: &[&'a Denomination]) -> Vec<&'a Denomination> {
+        handled_denominations::<C>(denominations)
+    }
+
+    fn api_calls(&self, denominations: &[&Denomination]) -> u32 {
+        C::api_calls(&self.config, denominations)
+    }
+
+    async fn take_snapshot(
+        &self,
+        denominations: &'life1 [&Denomination],
+        base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        C::take_snapshot(&self.config, denominations, base).await
+    }
+}
+
+/// Which denominations a configured converter is asked about, by symbol.
+#[derive(Debug, Default, Deserialize)]
+pub struct DenominationFilter {
+    /// If not empty, only these symbols are passed to the converter.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Symbols never passed to the converter.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl DenominationFilter {
+    pub fn allows(&self, denomination: &Denomination) -> bool {
+        let symbol = denomination.symbol();
+        (self.include.is_empty() || self.include.iter().any(|s| s == symbol))
+            && !self.exclude.iter().any(|s| s == symbol)
+    }
+
+    /// The subset of `denominations` the filter allows.
+    pub fn apply<'a>(&self, denominations: &[&'a Denomination]) -> Vec<&'a Denomination> {
+        denominations
+            .iter()
+            .copied()
+            .filter(|denomination| self.allows(denomination))
+            .collect()
+    }
+}
+
+/// How many API calls per UTC day a configured converter may make, e.g. to
+/// stay within a free tier.
+#[derive(Debug, Default, Deserialize)]
+pub struct CallBudget {
+    /// Calls per day after which worthy warns. Unlimited if unset.
+    #[serde(default)]
+    pub daily_call_budget: Option<u32>,
+    /// Refuse calls over the budget instead of only warning.
+    #[serde(default)]
+    pub refuse_over_budget: bool,
+}
+
+impl CallBudget {
+    /// Whether `converter_id`, having made `made` calls today, may make
+    /// `calls` more. Going over the budget is only warned about, unless
+    /// `refuse_over_budget` is set.
+    pub fn check(&self, converter_id: &str, made: u32, calls: u32) -> Result<(), WorthyError> {
+        let Some(budget) = self.daily_call_budget else {
+            return Ok(());
+        };
+        if made + calls <= budget {
+            return Ok(());
+        }
+        warn!(
+            "{}: {} more API calls on top of {} today exceed the daily budget of {}",
+            converter_id, calls, made, budget
+        );
+        if self.refuse_over_budget {
+            return Err(WorthyError::ConverterQuota {
+                converter: converter_id.to_string(),
+                message: format!("daily budget of {} API calls used up", budget),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The subset of `denominations` that converter `C` can price.
+pub fn handled_denominations<'a, C: Converter>(
+    denominations: &[&'a Denomination],
+) -> Vec<&'a Denomination> {
+    denominations
+        .iter()
+        .copied()
+        .filter(|denomination| C::can_handle(denomination))
+        .collect()
 }
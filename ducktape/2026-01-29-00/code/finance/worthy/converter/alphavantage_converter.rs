@@ -1,31 +1,428 @@
-use alphavantage::{Client, time_series::IntradayInterval};
 use async_trait::async_trait;
-use converter::Converter;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use converter::{CallBudget, Converter, DenominationFilter};
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
-use log::{error, trace};
+use futures::stream::{self, StreamExt};
+use log::{error, info, trace, warn};
+use rate_limit::RateLimiter;
+use reqwest::StatusCode;
+use retry::{RetryConfig, retry_request};
 use rust_decimal::prelude::*;
 use serde::Deserialize;
-use std::error::Error;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use stock_rate::stock_rate;
+use url::Url;
+use worthy_error::WorthyError;
 
 pub struct AlphaVantageConverter {}
 
 #[derive(Debug, Deserialize)]
 pub struct AlphaVantageConverterConfig {
     api_key: String,
+    /// Spread of the emitted rates, in basis points.
+    #[serde(default)]
+    spread_bps: Decimal,
+    /// Quote US stocks up to `BULK_QUOTES_MAX_SYMBOLS` per call with the
+    /// realtime bulk quotes endpoint, which needs a premium key.
+    #[serde(default)]
+    bulk_quotes: bool,
+    #[serde(default = "default_endpoint")]
+    endpoint: Url,
+    #[serde(default)]
+    retry: RetryConfig,
+    /// How many requests to make at the same time. 1 keeps to the request
+    /// rate of free keys.
+    #[serde(default = "default_parallel_requests")]
+    parallel_requests: usize,
+    /// Most requests to make a minute, waiting for the next one if needed; 0
+    /// for no limit. Free keys take 5.
+    #[serde(default = "default_requests_per_minute")]
+    requests_per_minute: u32,
+    /// Symbols to ask this converter about.
+    #[serde(flatten)]
+    pub filter: DenominationFilter,
+    #[serde(flatten)]
+    pub budget: CallBudget,
+}
+
+fn default_endpoint() -> Url {
+    Url::parse("https://www.alphavantage.co/query").unwrap()
+}
+
+fn default_parallel_requests() -> usize {
+    1
+}
+
+fn default_requests_per_minute() -> u32 {
+    5
+}
+
+/// Most symbols the bulk quotes endpoint takes in one call.
+pub const BULK_QUOTES_MAX_SYMBOLS: usize = 100;
+
+/// How to quote a set of stocks: deduplicated, US listings in batches for the
+/// bulk quotes endpoint if it's enabled, everything else one by one.
+#[derive(Debug, PartialEq)]
+pub struct QuotePlan {
+    pub batches: Vec<Vec<String>>,
+    pub single: Vec<String>,
+}
+
+impl QuotePlan {
+    pub fn new(stocks: &[&str], bulk_quotes: bool) -> QuotePlan {
+        let mut stocks: Vec<&str> = stocks.to_vec();
+        stocks.sort();
+        stocks.dedup();
+        let (us, other): (Vec<&str>, Vec<&str>) = stocks
+            .into_iter()
+            .partition(|stock| bulk_quotes && !stock.contains('.'));
+        QuotePlan {
+            batches: us
+                .chunks(BULK_QUOTES_MAX_SYMBOLS)
+                .map(|batch| batch.iter().map(|stock| stock.to_string()).collect())
+                .collect(),
+            single: other.into_iter().map(str::to_string).collect(),
+        }
+    }
+
+    /// API calls the plan takes at most: one per batch, and per stock quoted
+    /// on its own an intraday and a daily time series, and a symbol search if
+    /// it's listed outside the US. Stocks in a batch are quoted on their own
+    /// too if the bulk quote leaves them out, e.g. without a premium key.
+    pub fn calls(&self) -> u32 {
+        let single: usize = self
+            .batches
+            .iter()
+            .flatten()
+            .chain(&self.single)
+            .map(|stock| if stock.contains('.') { 3 } else { 2 })
+            .sum();
+        (self.batches.len() + single) as u32
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkQuote {
+    pub symbol: String,
+    pub close: Decimal,
+}
+
+/// Response of the bulk quotes endpoint. Without a premium key, there is no
+/// data, only an explanation.
+#[derive(Debug, Deserialize)]
+pub struct BulkQuotesResponse {
+    #[serde(default)]
+    pub data: Vec<BulkQuote>,
+    #[serde(default, alias = "Information", alias = "Error Message")]
+    pub message: Option<String>,
+}
+
+/// Requests to the configured endpoint, spaced out per the rate limit and
+/// counted, retries included.
+struct Api<'a> {
+    config: &'a AlphaVantageConverterConfig,
+    limiter: RateLimiter,
+    calls: AtomicU32,
+}
+
+impl Api<'_> {
+    /// Response of `function` called with `params`.
+    async fn get<T: DeserializeOwned>(
+        &self,
+        function: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T, WorthyError> {
+        let mut url = self.config.endpoint.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query.clear().append_pair("function", function);
+            for (key, value) in params {
+                query.append_pair(key, value);
+            }
+            query.append_pair("apikey", &self.config.api_key);
+        }
+        let url = &url;
+        let response = retry_request(&self.config.retry, || async move {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.limiter.wait().await;
+            reqwest::get(url.clone()).await
+        })
+        .await?;
+        if response.status() != StatusCode::OK {
+            return Err(WorthyError::Http(format!(
+                "alphavantage {} returned {}",
+                function,
+                response.status()
+            )));
+        }
+        Ok(response.json().await?)
+    }
+}
+
+/// Latest closing prices of the US-listed `stocks`, by symbol. Stocks the
+/// response leaves out are missing.
+async fn get_bulk_quotes(
+    api: &Api<'_>,
+    stocks: &[String],
+) -> Result<HashMap<String, Decimal>, WorthyError> {
+    let response: BulkQuotesResponse = api
+        .get("REALTIME_BULK_QUOTES", &[("symbol", &stocks.join(","))])
+        .await?;
+    if let Some(message) = &response.message {
+        info!("alphavantage bulk quotes: {}", message);
+    }
+    Ok(response
+        .data
+        .into_iter()
+        .filter(|quote| stocks.contains(&quote.symbol))
+        .map(|quote| (quote.symbol, quote.close))
+        .collect())
+}
+
+/// Closing price in a time series.
+#[derive(Debug, Deserialize)]
+struct TimeSeriesEntry {
+    #[serde(rename = "4. close")]
+    close: Decimal,
+}
+
+/// Response of the time series endpoints: metadata including the time zone
+/// of the series, and the series (e.g. "Time Series (1min)") by local time.
+/// On errors, there is only a message.
+#[derive(Debug, Deserialize)]
+pub struct TimeSeriesResponse {
+    #[serde(rename = "Meta Data", default)]
+    meta_data: HashMap<String, String>,
+    #[serde(flatten)]
+    rest: HashMap<String, serde_json::Value>,
+}
+
+/// A closing price in a time series.
+#[derive(Debug, PartialEq)]
+pub struct Close {
+    pub at: DateTime<Utc>,
+    pub price: Decimal,
+}
+
+impl TimeSeriesResponse {
+    /// Closing prices of the series, oldest first.
+    pub fn closes(self) -> Result<Vec<Close>, String> {
+        let Some((_, series)) = self
+            .rest
+            .iter()
+            .find(|(key, _)| key.starts_with("Time Series"))
+        else {
+            return Err(match self.rest.values().find_map(|value| value.as_str()) {
+                Some(message) => message.to_string(),
+                None => "no time series".to_string(),
+            });
+        };
+        let time_zone: Tz = self
+            .meta_data
+            .iter()
+            .find(|(key, _)| key.ends_with("Time Zone"))
+            .ok_or("no time zone")?
+            .1
+            .parse()
+            .map_err(|error| format!("time zone: {}", error))?;
+        let series: HashMap<String, TimeSeriesEntry> =
+            serde_json::from_value(series.clone()).map_err(|error| error.to_string())?;
+        let mut closes = series
+            .into_iter()
+            .map(|(at, entry)| {
+                // Daily series are by date.
+                let local = NaiveDateTime::parse_from_str(&at, "%Y-%m-%d %H:%M:%S")
+                    .or_else(|_| {
+                        NaiveDate::parse_from_str(&at, "%Y-%m-%d")
+                            .map(|date| date.and_time(NaiveTime::MIN))
+                    })
+                    .map_err(|error| format!("{}: {}", at, error))?;
+                let at = time_zone
+                    .from_local_datetime(&local)
+                    .earliest()
+                    .ok_or_else(|| format!("{} does not exist in {}", at, time_zone))?;
+                Ok(Close {
+                    at: at.with_timezone(&Utc),
+                    price: entry.close,
+                })
+            })
+            .collect::<Result<Vec<Close>, String>>()?;
+        closes.sort_by_key(|close| close.at);
+        Ok(closes)
+    }
+}
+
+/// Closing prices of `stock` in the time series of `function` (with
+/// `params`), oldest first.
+async fn get_time_series(
+    api: &Api<'_>,
+    function: &str,
+    stock: &str,
+    params: &[(&str, &str)],
+) -> Result<Vec<Close>, WorthyError> {
+    let mut params = params.to_vec();
+    params.insert(0, ("symbol", stock));
+    let response: TimeSeriesResponse = api.get(function, &params).await?;
+    response.closes().map_err(|error| {
+        WorthyError::Parse(format!("alphavantage {} of {}: {}", function, stock, error))
+    })
+}
+
+/// A symbol search result.
+#[derive(Debug, Deserialize)]
+pub struct SymbolMatch {
+    #[serde(rename = "1. symbol")]
+    pub symbol: String,
+    #[serde(rename = "8. currency")]
+    pub currency: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SymbolSearchResponse {
+    #[serde(rename = "bestMatches", default)]
+    best_matches: Vec<SymbolMatch>,
+}
+
+/// Currency AlphaVantage quotes `stock` in, going by the best exact symbol
+/// match among symbol search results.
+pub fn listing_currency(stock: &str, search_results: &[SymbolMatch]) -> Option<String> {
+    search_results
+        .iter()
+        .find(|entry| entry.symbol == stock)
+        .map(|entry| entry.currency.clone())
+}
+
+/// The most recent entry of `intraday`, or if there are no intraday prices
+/// (e.g. for thinly traded symbols), of `daily`. Also returns the name of the
+/// series the entry came from.
+pub fn latest_entry<'a, T>(intraday: &'a [T], daily: &'a [T]) -> Option<(&'a T, &'static str)> {
+    intraday
+        .last()
+        .map(|entry| (entry, "intraday"))
+        .or_else(|| daily.last().map(|entry| (entry, "daily")))
+}
+
+/// Symbols without an exchange suffix (e.g. "GOOG", unlike "VOD.LON") are
+/// US listings, so they are assumed to be in USD without spending a request
+/// on a symbol search.
+async fn get_listing_currency(api: &Api<'_>, stock: &str) -> String {
+    if !stock.contains('.') {
+        return "USD".to_string();
+    }
+    let currency = match api
+        .get::<SymbolSearchResponse>("SYMBOL_SEARCH", &[("keywords", stock)])
+        .await
+    {
+        Ok(results) => listing_currency(stock, &results.best_matches),
+        Err(error) => {
+            error!("symbol search for {}: {:?}", stock, error);
+            None
+        }
+    };
+    currency.unwrap_or_else(|| {
+        warn!("unknown listing currency of {}, assuming USD", stock);
+        "USD".to_string()
+    })
+}
+
+/// Price of a stock, in the currency it's listed in.
+struct Quote {
+    price: Decimal,
+    currency: String,
+    /// When the price was quoted.
+    at: DateTime<Utc>,
+}
+
+/// Latest price of `stock` from its own time series, or `None` if there are
+/// no prices.
+async fn quote_single(api: &Api<'_>, stock: &str) -> Option<Quote> {
+    let intraday =
+        match get_time_series(api, "TIME_SERIES_INTRADAY", stock, &[("interval", "1min")]).await {
+            Ok(closes) => closes,
+            Err(error) => {
+                error!("{} {}", stock, error);
+                return None;
+            }
+        };
+    let daily = if intraday.is_empty() {
+        match get_time_series(api, "TIME_SERIES_DAILY", stock, &[]).await {
+            Ok(closes) => closes,
+            Err(error) => {
+                error!("{} daily {}", stock, error);
+                Vec::new()
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    let Some((close, series)) = latest_entry(&intraday, &daily) else {
+        error!("{}: no intraday or daily prices, skipping", stock);
+        return None;
+    };
+    trace!("{} from {} series: {:?}", stock, series, close);
+    Some(Quote {
+        price: close.price,
+        currency: get_listing_currency(api, stock).await,
+        at: close.at,
+    })
+}
+
+/// Asks for the exchange rate from `from` to `to`, only to log it.
+async fn log_exchange_rate(api: &Api<'_>, from: &str, to: &str) {
+    match api
+        .get::<serde_json::Value>(
+            "CURRENCY_EXCHANGE_RATE",
+            &[("from_currency", from), ("to_currency", to)],
+        )
+        .await
+    {
+        Ok(exchange_rate) => trace!("{}: ok", exchange_rate),
+        Err(error) => error!("for {}:{} -> {}: skip", from, to, error),
+    }
 }
 
 #[async_trait]
 impl Converter for AlphaVantageConverter {
     type Config = AlphaVantageConverterConfig;
 
+    fn can_handle(denomination: &Denomination) -> bool {
+        matches!(
+            denomination,
+            Denomination::Currency { .. } | Denomination::Stock { .. }
+        )
+    }
+
+    /// The calls of the `QuotePlan` of the stocks, and an exchange rate per
+    /// ordered pair of currencies. Retries are not known upfront.
+    fn api_calls(config: &Self::Config, denominations: &[&Denomination]) -> u32 {
+        let mut stocks = Vec::new();
+        let mut currencies: u32 = 0;
+        for denomination in denominations {
+            match denomination {
+                Denomination::Stock { stock, .. } => stocks.push(stock.as_str()),
+                Denomination::Currency { .. } => currencies += 1,
+                _ => {}
+            }
+        }
+        QuotePlan::new(&stocks, config.bulk_quotes).calls()
+            + currencies * currencies.saturating_sub(1)
+    }
+
     async fn take_snapshot(
         config: &Self::Config,
         denominations: &'life1 [&Denomination],
         _base: &Denomination,
-    ) -> Result<Vec<ExchangeRate>, Box<dyn Error>> {
-        let AlphaVantageConverterConfig { api_key } = config;
-        let client = Client::new(api_key);
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        let api = Api {
+            config,
+            limiter: RateLimiter::per_minute(config.requests_per_minute),
+            calls: AtomicU32::new(0),
+        };
 
         //let rates = Vec::new();
         let currencies: Vec<&str> = denominations
@@ -36,53 +433,93 @@ impl Converter for AlphaVantageConverter {
             })
             .collect();
 
-        let mut rates = Vec::new();
-        // TODO(agentydragon): Do this in parallel. But ensure we keep a slow QPS.
-        for denomination in denominations.iter() {
-            if let Denomination::Stock { stock } = denomination {
-                let time_series = client
-                    .get_time_series_intraday(stock, IntradayInterval::OneMinute)
-                    .await;
-                if time_series.is_err() {
-                    error!("{} {:?}", stock, time_series);
-                    continue;
+        let stocks: Vec<&str> = denominations
+            .iter()
+            .filter_map(|d| match d {
+                Denomination::Stock { stock, .. } => Some(stock.as_str()),
+                _ => None,
+            })
+            .collect();
+        let plan = QuotePlan::new(&stocks, config.bulk_quotes);
+
+        // Price and currency of each stock.
+        let mut quotes: HashMap<String, Quote> = HashMap::new();
+        let mut single = plan.single;
+        for batch in &plan.batches {
+            let prices = match get_bulk_quotes(&api, batch).await {
+                Ok(prices) => prices,
+                Err(error) => {
+                    error!("bulk quotes of {}: {}", batch.join(","), error);
+                    HashMap::new()
+                }
+            };
+            // Bulk quotes are timestamped without a time zone.
+            let fetched_at = Utc::now();
+            for stock in batch {
+                match prices.get(stock) {
+                    Some(price) => {
+                        let quote = Quote {
+                            price: *price,
+                            currency: "USD".to_string(),
+                            at: fetched_at,
+                        };
+                        quotes.insert(stock.clone(), quote);
+                    }
+                    None => {
+                        warn!("{}: no bulk quote, quoting on its own", stock);
+                        single.push(stock.clone());
+                    }
                 }
-                let time_series = time_series.unwrap();
+            }
+        }
 
-                let entry = time_series.entries.last().unwrap();
-                trace!("{} {:?}", stock, entry);
+        // Collected first: a stream mapping with a closure trips up the `Send`
+        // check of `async_trait`.
+        let requests: Vec<_> = single
+            .iter()
+            .map(|stock| quote_single(&api, stock))
+            .collect();
+        let quoted: Vec<_> = stream::iter(requests)
+            .buffered(config.parallel_requests.max(1))
+            .collect()
+            .await;
+        for (stock, quote) in single.into_iter().zip(quoted) {
+            if let Some(quote) = quote {
+                quotes.insert(stock, quote);
+            }
+        }
 
+        let mut rates = Vec::new();
+        for denomination in denominations.iter() {
+            if let Denomination::Stock { stock, .. } = denomination
+                && let Some(quote) = quotes.get(stock)
+            {
+                // Price the denomination as asked, including its ISIN.
                 rates.push(ExchangeRate {
-                    //timestamp: entry.date.timestamp,
-                    from: Denomination::Stock {
-                        stock: stock.clone(),
-                    },
-                    to: Denomination::Currency {
-                        currency: "USD".to_string(),
-                    },
-                    // TODO: it's OHLC, maybe another?
-                    rate: Decimal::from_f64(entry.close).unwrap(),
+                    from: (*denomination).clone(),
+                    spread_bps: config.spread_bps,
+                    ..stock_rate(stock, quote.price, &quote.currency, quote.at)
                 });
             }
         }
 
-        // TODO(agentydragon): Do this in parallel. But ensure we keep a slow QPS.
-        for currency_from in currencies.iter() {
-            for currency_to in currencies.iter() {
-                if currency_from == currency_to {
-                    continue;
-                }
-                let exchange_rate = client.get_exchange_rate(currency_from, currency_to).await;
-                if exchange_rate.is_err() {
-                    error!(
-                        "for {}:{} -> {:?}: skip",
-                        currency_from, currency_to, exchange_rate
-                    );
-                } else {
-                    trace!("{:?}: ok", exchange_rate.unwrap());
-                }
-            }
-        }
+        let pairs: Vec<(&str, &str)> = currencies
+            .iter()
+            .flat_map(|from| currencies.iter().map(move |to| (*from, *to)))
+            .filter(|(from, to)| from != to)
+            .collect();
+        let requests: Vec<_> = pairs
+            .iter()
+            .map(|(from, to)| log_exchange_rate(&api, from, to))
+            .collect();
+        stream::iter(requests)
+            .buffer_unordered(config.parallel_requests.max(1))
+            .collect::<Vec<()>>()
+            .await;
+        info!(
+            "alphavantage: {} API calls",
+            api.calls.load(Ordering::Relaxed)
+        );
         Ok(rates)
     }
 }
@@ -0,0 +1,275 @@
+use alphavantage_converter::{
+    AlphaVantageConverter, AlphaVantageConverterConfig, BULK_QUOTES_MAX_SYMBOLS, Close, QuotePlan,
+    SymbolMatch, TimeSeriesResponse, latest_entry, listing_currency,
+};
+use chrono::{DateTime, Utc};
+use converter::Converter;
+use denomination::Denomination;
+use fixture_server::{FixtureServer, Response};
+use rust_decimal_macros::*;
+use stock_rate::stock_rate;
+use test_util::currency;
+
+fn config(bulk_quotes: bool, endpoint: &str) -> AlphaVantageConverterConfig {
+    serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "bulk_quotes": bulk_quotes,
+        "endpoint": endpoint,
+        "requests_per_minute": 0,
+    }))
+    .unwrap()
+}
+
+fn stock(symbol: &str) -> Denomination {
+    Denomination::Stock {
+        stock: symbol.to_string(),
+        isin: None,
+    }
+}
+
+fn entry(symbol: &str, currency: &str) -> SymbolMatch {
+    SymbolMatch {
+        symbol: symbol.to_string(),
+        currency: currency.to_string(),
+    }
+}
+
+fn at(rfc3339: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+const VOD_INTRADAY: &str = r#"{
+  "Meta Data": {
+    "1. Information": "Intraday (1min) open, high, low, close prices and volume",
+    "2. Symbol": "VOD.LON",
+    "3. Last Refreshed": "2024-06-07 16:29:00",
+    "4. Interval": "1min",
+    "5. Output Size": "Compact",
+    "6. Time Zone": "Europe/London"
+  },
+  "Time Series (1min)": {
+    "2024-06-07 16:29:00": {"1. open": "71.2000", "2. high": "71.3000", "3. low": "71.1000", "4. close": "71.2500", "5. volume": "1000"},
+    "2024-06-07 16:28:00": {"1. open": "71.0000", "2. high": "71.2000", "3. low": "71.0000", "4. close": "71.2000", "5. volume": "900"}
+  }
+}"#;
+
+#[test]
+fn listing_currency_exact_match() {
+    let results = vec![entry("VOD", "USD"), entry("VOD.LON", "GBX")];
+    assert_eq!(
+        listing_currency("VOD.LON", &results),
+        Some("GBX".to_string())
+    );
+    assert_eq!(listing_currency("VODPF", &results), None);
+}
+
+#[test]
+fn api_calls_per_denomination() {
+    let config = config(false, "http://localhost/");
+    let (goog, vod, usd, gbp, czk) = (
+        stock("GOOG"),
+        stock("VOD.LON"),
+        currency("USD"),
+        currency("GBP"),
+        currency("CZK"),
+    );
+    // At most intraday and daily time series for GOOG, those and a symbol
+    // search for VOD.LON, and 3 * 2 currency pairs.
+    assert_eq!(
+        AlphaVantageConverter::api_calls(&config, &[&goog, &vod, &usd, &gbp, &czk]),
+        11
+    );
+    assert_eq!(AlphaVantageConverter::api_calls(&config, &[&usd]), 0);
+}
+
+#[test]
+fn quote_plan_batches_us_stocks() {
+    let symbols: Vec<String> = (0..250).map(|i| format!("S{}", i)).collect();
+    let mut stocks: Vec<&str> = symbols.iter().map(String::as_str).collect();
+    stocks.push("S0");
+    stocks.push("VOD.LON");
+
+    let plan = QuotePlan::new(&stocks, true);
+    assert_eq!(
+        plan.batches.iter().map(Vec::len).collect::<Vec<_>>(),
+        vec![BULK_QUOTES_MAX_SYMBOLS, BULK_QUOTES_MAX_SYMBOLS, 50]
+    );
+    // Outside the US, with a symbol search.
+    assert_eq!(plan.single, vec!["VOD.LON".to_string()]);
+    // Batched stocks may be quoted on their own after all.
+    assert_eq!(plan.calls(), 3 + 250 * 2 + 3);
+
+    let plan = QuotePlan::new(&stocks, false);
+    assert!(plan.batches.is_empty());
+    assert_eq!(plan.single.len(), 251);
+    assert_eq!(plan.calls(), 250 * 2 + 3);
+}
+
+#[test]
+fn api_calls_with_bulk_quotes() {
+    let config = config(true, "http://localhost/");
+    let (goog, msft, aapl) = (stock("GOOG"), stock("MSFT"), stock("AAPL"));
+    // One batch, unless the bulk quote leaves them out.
+    assert_eq!(
+        AlphaVantageConverter::api_calls(&config, &[&goog, &msft, &aapl]),
+        1 + 3 * 2
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_with_bulk_quotes() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(
+        r#"{
+          "endpoint": "Realtime Bulk Quotes",
+          "data": [
+            {"symbol": "GOOG", "timestamp": "2024-06-07 16:00:00", "close": "175.9500"},
+            {"symbol": "MSFT", "timestamp": "2024-06-07 16:00:00", "close": "423.8500"}
+          ]
+        }"#,
+    )]);
+    let config = config(true, &format!("{}query", server.url()));
+    let goog_with_isin = Denomination::Stock {
+        stock: "GOOG".to_string(),
+        isin: Some("US02079K1079".to_string()),
+    };
+    let (goog, msft) = (stock("GOOG"), stock("MSFT"));
+
+    let before = Utc::now();
+    let rates =
+        AlphaVantageConverter::take_snapshot(&config, &[&goog, &msft, &goog_with_isin], &goog)
+            .await
+            .unwrap();
+
+    assert_eq!(
+        rates
+            .iter()
+            .map(|rate| (rate.from.clone(), rate.rate))
+            .collect::<Vec<_>>(),
+        vec![
+            (goog, dec!(175.95)),
+            (msft, dec!(423.85)),
+            (goog_with_isin, dec!(175.95)),
+        ]
+    );
+    // Observed when fetched.
+    assert!(rates.iter().all(|rate| rate.timestamp >= before));
+    // Three denominations, one call.
+    assert_eq!(
+        server.requests(),
+        vec!["GET /query?function=REALTIME_BULK_QUOTES&symbol=GOOG%2CMSFT&apikey=KEY HTTP/1.1"]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_quotes_at_endpoint() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![
+        Response::ok(VOD_INTRADAY),
+        Response::ok(
+            r#"{"bestMatches": [
+              {"1. symbol": "VOD.LON", "2. name": "Vodafone Group plc", "8. currency": "GBX"}
+            ]}"#,
+        ),
+    ]);
+    let config = config(false, &format!("{}query", server.url()));
+    let vod = stock("VOD.LON");
+
+    let rates = AlphaVantageConverter::take_snapshot(&config, &[&vod], &vod)
+        .await
+        .unwrap();
+
+    assert_eq!(rates.len(), 1);
+    assert_eq!(rates[0].rate, dec!(0.7125));
+    // 16:29 in London in summer.
+    assert_eq!(rates[0].timestamp, at("2024-06-07T15:29:00Z"));
+    assert_eq!(
+        server.requests(),
+        vec![
+            "GET /query?function=TIME_SERIES_INTRADAY&symbol=VOD.LON&interval=1min&apikey=KEY HTTP/1.1",
+            "GET /query?function=SYMBOL_SEARCH&keywords=VOD.LON&apikey=KEY HTTP/1.1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_without_premium_key() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![
+        Response::ok(
+            r#"{"Information": "This is a premium endpoint. You may subscribe to any of the premium plans."}"#,
+        ),
+        Response::ok(VOD_INTRADAY.replace("VOD.LON", "GOOG")),
+    ]);
+    let config = config(true, &format!("{}query", server.url()));
+    let goog = stock("GOOG");
+
+    let rates = AlphaVantageConverter::take_snapshot(&config, &[&goog], &goog)
+        .await
+        .unwrap();
+
+    // Quoted on its own after all, in USD as a US listing.
+    assert_eq!(rates.len(), 1);
+    assert_eq!(rates[0].rate, dec!(71.25));
+    assert_eq!(server.requests().len(), 2);
+    assert!(
+        (server.requests().len() as u32) <= AlphaVantageConverter::api_calls(&config, &[&goog])
+    );
+}
+
+#[test]
+fn time_series_closes() {
+    let response: TimeSeriesResponse = serde_json::from_str(
+        r#"{
+          "Meta Data": {"2. Symbol": "GOOG", "5. Time Zone": "US/Eastern"},
+          "Time Series (Daily)": {
+            "2024-06-07": {"4. close": "175.9500"},
+            "2024-06-06": {"4. close": "178.3500"}
+          }
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        response.closes().unwrap(),
+        vec![
+            Close {
+                at: at("2024-06-06T04:00:00Z"),
+                price: dec!(178.35),
+            },
+            Close {
+                at: at("2024-06-07T04:00:00Z"),
+                price: dec!(175.95),
+            },
+        ]
+    );
+
+    let response: TimeSeriesResponse =
+        serde_json::from_str(r#"{"Error Message": "Invalid API call."}"#).unwrap();
+    assert_eq!(response.closes().unwrap_err(), "Invalid API call.");
+}
+
+#[test]
+fn latest_entry_prefers_intraday() {
+    let intraday = [dec!(10.5), dec!(10.7)];
+    let daily = [dec!(9), dec!(10)];
+    assert_eq!(
+        latest_entry(&intraday, &daily),
+        Some((&dec!(10.7), "intraday"))
+    );
+}
+
+#[test]
+fn latest_entry_falls_back_to_daily_close() {
+    // Thinly traded symbols can come back with an empty intraday series.
+    let daily = [dec!(9), dec!(10)];
+    let (close, series) = latest_entry(&[], &daily).unwrap();
+    assert_eq!(series, "daily");
+    assert_eq!(
+        stock_rate("VOD.LON", *close, "GBX", Utc::now()).rate,
+        dec!(0.1)
+    );
+
+    assert_eq!(latest_entry::<f64>(&[], &[]), None);
+}
@@ -0,0 +1,259 @@
+//! Converter wrapper recording the rates of another converter to files, and
+//! replaying them, e.g. to work offline or to re-run against the same rates.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use converter::Converter;
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use worthy_error::WorthyError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Ask the wrapped converter and record what it returns. If it can't be
+    /// reached, replay the newest recording instead.
+    Record,
+    /// Only replay the newest recording; never ask the wrapped converter.
+    Replay,
+}
+
+/// Directory of recorded rates, one file per snapshot of a converter, named
+/// `<converter id>-<time>.json`.
+#[derive(Debug, Clone)]
+pub struct RateRecordings {
+    pub dir: PathBuf,
+    pub mode: CacheMode,
+}
+
+pub struct CachingConfig<'a, C: Converter> {
+    pub inner: &'a C::Config,
+    /// Recordings are kept apart by this id, e.g. "fixer".
+    pub converter_id: String,
+    pub recordings: RateRecordings,
+}
+
+/// `C`, with its rates recorded or replayed per `CachingConfig::recordings`.
+pub struct CachingConverter<'a, C>(PhantomData<&'a C>);
+
+/// A rate as recorded, with denominations in their compact form, e.g.
+/// "crypto:BTC".
+#[derive(Serialize, Deserialize)]
+struct RecordedRate {
+    from: String,
+    to: String,
+    rate: Decimal,
+    #[serde(default)]
+    spread_bps: Decimal,
+    /// Missing in recordings from before it was recorded.
+    #[serde(default)]
+    observed_at: Option<DateTime<Utc>>,
+}
+
+/// Rates of one snapshot, along with what they were asked for.
+#[derive(Serialize, Deserialize)]
+struct Recording {
+    denominations: Vec<String>,
+    base: String,
+    rates: Vec<RecordedRate>,
+}
+
+/// What a snapshot was asked for: the denominations, sorted, and the base.
+fn request_key(denominations: &[&Denomination], base: &Denomination) -> (Vec<String>, String) {
+    let mut denominations: Vec<String> = denominations.iter().map(|d| d.to_string()).collect();
+    denominations.sort();
+    denominations.dedup();
+    (denominations, base.to_string())
+}
+
+fn parse_denomination(s: &str) -> Result<Denomination, WorthyError> {
+    Denomination::from_str(s).map_err(WorthyError::Parse)
+}
+
+/// Writes `rates` of `converter_id`, asked for `denominations` in `base`, to a
+/// new file in `dir` named after `at`. Returns the file's path.
+pub fn record(
+    dir: &Path,
+    converter_id: &str,
+    denominations: &[&Denomination],
+    base: &Denomination,
+    rates: &[ExchangeRate],
+    at: DateTime<Utc>,
+) -> Result<PathBuf, WorthyError> {
+    let (denominations, base) = request_key(denominations, base);
+    let recording = Recording {
+        denominations,
+        base,
+        rates: rates
+            .iter()
+            .map(|rate| RecordedRate {
+                from: rate.from.to_string(),
+                to: rate.to.to_string(),
+                rate: rate.rate,
+                spread_bps: rate.spread_bps,
+                observed_at: Some(rate.timestamp),
+            })
+            .collect(),
+    };
+    std::fs::create_dir_all(dir).map_err(|error| {
+        WorthyError::Config(format!("cannot create {}: {}", dir.display(), error))
+    })?;
+    let path = dir.join(format!(
+        "{}-{}.json",
+        converter_id,
+        at.format("%Y%m%dT%H%M%S%.9fZ")
+    ));
+    let json = serde_json::to_string_pretty(&recording).unwrap();
+    std::fs::write(&path, json).map_err(|error| {
+        WorthyError::Config(format!("cannot write {}: {}", path.display(), error))
+    })?;
+    Ok(path)
+}
+
+/// Rates of the newest recording of `converter_id` in `dir` asked for the same
+/// `denominations` in `base`, if there is one. Recordings that don't parse are
+/// skipped with a warning. Rates recorded without the time they were observed
+/// at are taken to be as old as their recording.
+pub fn replay(
+    dir: &Path,
+    converter_id: &str,
+    denominations: &[&Denomination],
+    base: &Denomination,
+) -> Result<Option<Vec<ExchangeRate>>, WorthyError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(WorthyError::Config(format!(
+                "cannot list {}: {}",
+                dir.display(),
+                error
+            )));
+        }
+    };
+    let prefix = format!("{}-", converter_id);
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(&prefix))
+                // Other converters' ids may start with this one's, and a
+                // dash; times have no dashes.
+                .is_some_and(|time| {
+                    time.starts_with(|c: char| c.is_ascii_digit()) && !time.contains('-')
+                })
+        })
+        .collect();
+    paths.sort();
+    let key = request_key(denominations, base);
+    for path in paths.iter().rev() {
+        let recording: Recording = match std::fs::read_to_string(path)
+            .map_err(|error| error.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|error| error.to_string()))
+        {
+            Ok(recording) => recording,
+            Err(error) => {
+                warn!("skipping {}: {}", path.display(), error);
+                continue;
+            }
+        };
+        if (recording.denominations, recording.base) != key {
+            continue;
+        }
+        info!("{}: replaying {}", converter_id, path.display());
+        let recorded_at = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_or_else(|_| Utc::now(), DateTime::<Utc>::from);
+        return recording
+            .rates
+            .iter()
+            .map(|rate| {
+                Ok(ExchangeRate {
+                    timestamp: rate.observed_at.unwrap_or(recorded_at),
+                    from: parse_denomination(&rate.from)?,
+                    to: parse_denomination(&rate.to)?,
+                    rate: rate.rate,
+                    spread_bps: rate.spread_bps,
+                })
+            })
+            .collect::<Result<Vec<_>, WorthyError>>()
+            .map(Some);
+    }
+    Ok(None)
+}
+
+/// Rates of `fetch`, recorded, or replayed per `recordings.mode`. `fetch` is
+/// only awaited when recording.
+pub async fn cached_snapshot(
+    recordings: &RateRecordings,
+    converter_id: &str,
+    denominations: &[&Denomination],
+    base: &Denomination,
+    fetch: impl Future<Output = Result<Vec<ExchangeRate>, WorthyError>>,
+) -> Result<Vec<ExchangeRate>, WorthyError> {
+    let RateRecordings { dir, mode } = recordings;
+    let not_recorded = || {
+        WorthyError::Config(format!(
+            "no recorded rates of {} for these denominations in {}",
+            converter_id,
+            dir.display()
+        ))
+    };
+    match mode {
+        CacheMode::Replay => {
+            replay(dir, converter_id, denominations, base)?.ok_or_else(not_recorded)
+        }
+        CacheMode::Record => match fetch.await {
+            Ok(rates) => {
+                record(dir, converter_id, denominations, base, &rates, Utc::now())?;
+                Ok(rates)
+            }
+            Err(WorthyError::Http(error)) => {
+                warn!("{}: {}; replaying recorded rates", converter_id, error);
+                replay(dir, converter_id, denominations, base)?.ok_or(WorthyError::Http(error))
+            }
+            Err(error) => Err(error),
+        },
+    }
+}
+
+#[async_trait]
+impl<'a, C> Converter for CachingConverter<'a, C>
+where
+    C: Converter + Sync,
+    C::Config: Sync,
+{
+    type Config = CachingConfig<'a, C>;
+
+    fn can_handle(denomination: &Denomination) -> bool {
+        C::can_handle(denomination)
+    }
+
+    fn api_calls(config: &Self::Config, denominations: &[&Denomination]) -> u32 {
+        match config.recordings.mode {
+            CacheMode::Record => C::api_calls(config.inner, denominations),
+            CacheMode::Replay => 0,
+        }
+    }
+
+    async fn take_snapshot(
+        config: &Self::Config,
+        denominations: &'life1 [&Denomination],
+        base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        cached_snapshot(
+            &config.recordings,
+            &config.converter_id,
+            denominations,
+            base,
+            C::take_snapshot(config.inner, denominations, base),
+        )
+        .await
+    }
+}
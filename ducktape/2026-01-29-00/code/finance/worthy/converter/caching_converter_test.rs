@@ -0,0 +1,162 @@
+use async_trait::async_trait;
+use caching_converter::{CacheMode, CachingConfig, CachingConverter, RateRecordings};
+use chrono::DateTime;
+use converter::Converter;
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use test_util::{TempDir, currency};
+use worthy_error::WorthyError;
+
+/// Answers with fixed rates into the base, or fails as if offline.
+struct FixedConverter {}
+
+struct FixedConfig {
+    online: bool,
+}
+
+#[async_trait]
+impl Converter for FixedConverter {
+    type Config = FixedConfig;
+
+    async fn take_snapshot(
+        config: &Self::Config,
+        denominations: &'life1 [&Denomination],
+        base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        if !config.online {
+            return Err(WorthyError::Http("connection refused".to_string()));
+        }
+        Ok(denominations
+            .iter()
+            .map(|denomination| ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: (*denomination).clone(),
+                to: base.clone(),
+                rate: dec!(1.2345),
+                spread_bps: dec!(10),
+            })
+            .collect())
+    }
+}
+
+fn config<'a>(
+    dir: &TempDir,
+    mode: CacheMode,
+    inner: &'a FixedConfig,
+) -> CachingConfig<'a, FixedConverter> {
+    CachingConfig {
+        inner,
+        converter_id: "fixed".to_string(),
+        recordings: RateRecordings {
+            dir: dir.path().to_path_buf(),
+            mode,
+        },
+    }
+}
+
+#[tokio::test]
+async fn record_then_replay() {
+    let dir = TempDir::new("caching_converter_record_then_replay");
+    let online = FixedConfig { online: true };
+    let offline = FixedConfig { online: false };
+    let (eur, gbp, usd) = (currency("EUR"), currency("GBP"), currency("USD"));
+    let btc = Denomination::Cryptocurrency {
+        symbol: "BTC".to_string(),
+    };
+
+    let recorded = CachingConverter::take_snapshot(
+        &config(&dir, CacheMode::Record, &online),
+        &[&eur, &btc],
+        &usd,
+    )
+    .await
+    .unwrap();
+    assert_eq!(recorded.len(), 2);
+
+    // In any order, and without asking the converter.
+    let replayed = CachingConverter::take_snapshot(
+        &config(&dir, CacheMode::Replay, &offline),
+        &[&btc, &eur],
+        &usd,
+    )
+    .await
+    .unwrap();
+    assert_eq!(replayed, recorded);
+    assert_eq!(
+        CachingConverter::<FixedConverter>::api_calls(
+            &config(&dir, CacheMode::Replay, &offline),
+            &[&eur]
+        ),
+        0
+    );
+
+    // Recording that can't reach the converter replays.
+    let fallback = CachingConverter::take_snapshot(
+        &config(&dir, CacheMode::Record, &offline),
+        &[&eur, &btc],
+        &usd,
+    )
+    .await
+    .unwrap();
+    assert_eq!(fallback, recorded);
+
+    // Nothing was recorded for other denominations or bases.
+    for (denominations, base) in [(vec![&eur, &gbp], &usd), (vec![&eur, &btc], &gbp)] {
+        let error = CachingConverter::take_snapshot(
+            &config(&dir, CacheMode::Replay, &offline),
+            &denominations,
+            base,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+    }
+    let error =
+        CachingConverter::take_snapshot(&config(&dir, CacheMode::Record, &offline), &[&gbp], &usd)
+            .await
+            .unwrap_err();
+    assert!(matches!(error, WorthyError::Http(_)), "{:?}", error);
+}
+
+#[test]
+fn newest_recording_wins() {
+    let dir = TempDir::new("caching_converter_newest_recording_wins");
+    let (eur, usd) = (currency("EUR"), currency("USD"));
+    let rate = |rate| ExchangeRate {
+        timestamp: DateTime::UNIX_EPOCH,
+        from: eur.clone(),
+        to: usd.clone(),
+        rate,
+        spread_bps: Decimal::ZERO,
+    };
+    let at = |day| {
+        chrono::DateTime::parse_from_rfc3339(&format!("2021-01-{:02}T00:00:00Z", day))
+            .unwrap()
+            .into()
+    };
+    for (day, r) in [(2, dec!(1.2)), (10, dec!(1.1)), (1, dec!(1.3))] {
+        caching_converter::record(dir.path(), "fixed", &[&eur], &usd, &[rate(r)], at(day)).unwrap();
+    }
+    // Another converter whose id starts with this one's.
+    caching_converter::record(
+        dir.path(),
+        "fixed-2",
+        &[&eur],
+        &usd,
+        &[rate(dec!(9))],
+        at(20),
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("fixed-20210111T000000Z.json"), "{").unwrap();
+
+    assert_eq!(
+        caching_converter::replay(dir.path(), "fixed", &[&eur], &usd).unwrap(),
+        Some(vec![rate(dec!(1.1))])
+    );
+    assert_eq!(
+        caching_converter::replay(dir.path(), "other", &[&eur], &usd).unwrap(),
+        None
+    );
+}
@@ -1,56 +1,143 @@
 use async_trait::async_trait;
-use converter::Converter;
-use currency_layer::Client;
+use chrono::{DateTime, Utc};
+use converter::{CallBudget, Converter, DenominationFilter};
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
-use rusty_money::Money;
+use retry::{RetryConfig, retry_request};
+use rust_decimal::prelude::*;
 use serde::Deserialize;
-use std::error::Error;
+use std::collections::HashMap;
+use url::Url;
+use worthy_error::WorthyError;
 
 pub struct CurrencyLayerConverter {}
 
 #[derive(Debug, Deserialize)]
 pub struct CurrencyLayerConverterConfig {
     api_key: String,
+    #[serde(default)]
+    retry: RetryConfig,
+    #[serde(default = "default_endpoint")]
+    endpoint: Url,
+    /// Spread of the emitted rates, in basis points.
+    #[serde(default)]
+    spread_bps: Decimal,
+    /// Symbols to ask this converter about.
+    #[serde(flatten)]
+    pub filter: DenominationFilter,
+    #[serde(flatten)]
+    pub budget: CallBudget,
+}
+
+fn default_endpoint() -> Url {
+    Url::parse("http://apilayer.net/api/live").unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ErrorBody {
+    pub code: u16,
+    pub info: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum LiveResponse {
+    Success {
+        // 1613851867
+        #[serde(default, with = "chrono::serde::ts_seconds_option")]
+        timestamp: Option<DateTime<Utc>>,
+        // "USD"
+        source: String,
+        // "USDCZK": 21.5 means 1 USD is 21.5 CZK.
+        quotes: HashMap<String, Decimal>,
+    },
+    Error {
+        error: ErrorBody,
+    },
+}
+
+fn api_error(error: ErrorBody) -> WorthyError {
+    let message = format!("currencylayer error {}: {}", error.code, error.info);
+    match error.code {
+        // Missing or invalid access key, inactive account.
+        101 | 102 => WorthyError::Config(message),
+        // Monthly request volume reached.
+        104 => WorthyError::ConverterQuota {
+            converter: "currencylayer".to_string(),
+            message,
+        },
+        _ => WorthyError::Http(message),
+    }
 }
 
 #[async_trait]
 impl Converter for CurrencyLayerConverter {
     type Config = CurrencyLayerConverterConfig;
 
+    fn can_handle(denomination: &Denomination) -> bool {
+        matches!(denomination, Denomination::Currency { .. })
+    }
+
     async fn take_snapshot(
         config: &Self::Config,
         denominations: &'life1 [&Denomination],
         _base: &Denomination,
-    ) -> Result<Vec<ExchangeRate>, Box<dyn Error>> {
-        let CurrencyLayerConverterConfig { api_key } = config;
-        let client = Client::new(api_key);
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        let CurrencyLayerConverterConfig {
+            api_key,
+            retry,
+            endpoint,
+            spread_bps,
+            ..
+        } = config;
 
-        let currencies = denominations
+        let currencies: Vec<&str> = denominations
             .iter()
             .filter_map(|d| match d {
                 Denomination::Currency { currency } => Some(currency.as_str()),
                 _ => None,
             })
             .collect();
+        let mut url = endpoint.clone();
+        url.query_pairs_mut()
+            .clear()
+            .append_pair("currencies", &currencies.join(","))
+            .append_pair("format", "1")
+            .append_pair("access_key", api_key);
+
         // Do this for all currencies.
         // Will return everything relative to USD. Ugh.
-        let res = client.get_live_rates(currencies).await.unwrap();
-
-        Ok(res
-            .quotes
-            .values()
-            .map(|exchange_rate| ExchangeRate {
-                from: Denomination::Currency {
-                    currency: exchange_rate.from.iso_alpha_code.to_string(),
-                },
-                to: Denomination::Currency {
-                    currency: exchange_rate.to.iso_alpha_code.to_string(),
-                },
-                rate: *exchange_rate
-                    .convert(&Money::from_major(1, exchange_rate.from))
-                    .unwrap()
-                    .amount(),
+        let response = retry_request(retry, || reqwest::get(url.clone()))
+            .await?
+            .error_for_status()?;
+        let (timestamp, source, quotes) = match response.json().await? {
+            LiveResponse::Success {
+                timestamp,
+                source,
+                quotes,
+            } => (timestamp.unwrap_or_else(Utc::now), source, quotes),
+            LiveResponse::Error { error } => return Err(api_error(error)),
+        };
+
+        Ok(quotes
+            .into_iter()
+            .filter_map(|(pair, rate)| {
+                let target = pair.strip_prefix(&source)?;
+                // Drop "USDUSD".
+                if target == source {
+                    return None;
+                }
+                Some(ExchangeRate {
+                    timestamp,
+                    from: Denomination::Currency {
+                        currency: source.clone(),
+                    },
+                    to: Denomination::Currency {
+                        currency: target.to_string(),
+                    },
+                    rate,
+                    spread_bps: *spread_bps,
+                })
             })
             .collect())
     }
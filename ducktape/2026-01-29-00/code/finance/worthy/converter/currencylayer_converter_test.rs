@@ -0,0 +1,83 @@
+use converter::Converter;
+use currencylayer_converter::{CurrencyLayerConverter, CurrencyLayerConverterConfig};
+use fixture_server::{FixtureServer, Response};
+use rust_decimal_macros::*;
+use test_util::currency;
+use worthy_error::WorthyError;
+
+#[tokio::test]
+async fn take_snapshot_from_recorded_response() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(include_str!(
+        "../testdata/currencylayer_live.json"
+    ))]);
+    let config: CurrencyLayerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "endpoint": format!("{}api/live", server.url()),
+    }))
+    .unwrap();
+
+    let chf = currency("CHF");
+    let czk = currency("CZK");
+    let mut rates = CurrencyLayerConverter::take_snapshot(&config, &[&chf, &czk], &chf)
+        .await
+        .unwrap();
+    rates.sort_by_key(|rate| format!("{:?}", rate.to));
+
+    // USDUSD is dropped.
+    assert_eq!(rates.len(), 2);
+    assert_eq!(rates[0].from, currency("USD"));
+    assert_eq!(rates[0].to, chf);
+    assert_eq!(rates[0].rate, dec!(0.8958));
+    assert_eq!(rates[1].to, czk);
+    assert_eq!(rates[1].rate, dec!(21.361));
+    // As of the response's timestamp.
+    assert_eq!(rates[0].timestamp.timestamp(), 1613851867);
+    assert_eq!(
+        server.requests(),
+        vec!["GET /api/live?currencies=CHF%2CCZK&format=1&access_key=KEY HTTP/1.1"]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_error_response() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(
+        r#"{"success": false, "error": {"code": 101, "info": "You have not supplied a valid API Access Key."}}"#,
+    )]);
+    let config: CurrencyLayerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "BAD",
+        "endpoint": server.url(),
+    }))
+    .unwrap();
+
+    let chf = currency("CHF");
+    let error = CurrencyLayerConverter::take_snapshot(&config, &[&chf], &chf)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+    assert!(error.to_string().contains("101"), "{}", error);
+}
+
+#[tokio::test]
+async fn take_snapshot_quota_exceeded() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(
+        r#"{"success": false, "error": {"code": 104, "info": "Your monthly usage limit has been reached."}}"#,
+    )]);
+    let config: CurrencyLayerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "endpoint": server.url(),
+    }))
+    .unwrap();
+
+    let chf = currency("CHF");
+    let error = CurrencyLayerConverter::take_snapshot(&config, &[&chf], &chf)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, WorthyError::ConverterQuota { .. }),
+        "{:?}",
+        error
+    );
+}
@@ -1,56 +1,124 @@
 use async_trait::async_trait;
-use converter::Converter;
+use chrono::{DateTime, Utc};
+use converter::{CallBudget, Converter, DenominationFilter};
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
 use reqwest::StatusCode;
+use retry::{RetryConfig, retry_request};
 use rust_decimal::prelude::*;
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::error::Error;
 use url::Url;
+use worthy_error::WorthyError;
 
 pub struct FixerConverter {}
 
 #[derive(Debug, Deserialize)]
 pub struct FixerConverterConfig {
     api_key: String,
+    #[serde(default)]
+    retry: RetryConfig,
+    #[serde(default = "default_endpoint")]
+    endpoint: Url,
+    /// Spread of the emitted rates, in basis points.
+    #[serde(default)]
+    spread_bps: Decimal,
+    /// Symbols to ask this converter about.
+    #[serde(flatten)]
+    pub filter: DenominationFilter,
+    #[serde(flatten)]
+    pub budget: CallBudget,
+}
+
+fn default_endpoint() -> Url {
+    Url::parse("http://data.fixer.io/api/latest").unwrap()
 }
 
 #[derive(Debug, Deserialize)]
 pub struct RatesResponse {
     pub success: bool,
     // "timestamp":1613851867
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub timestamp: Option<DateTime<Utc>>,
     pub base: String,
     // "date":"2021-02-20"
     pub rates: HashMap<String, Decimal>,
     // if base = USD and rates[CZK] = 0, then 1 USD is 20 CZK.
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ErrorBody {
+    pub code: u16,
+    pub info: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum LatestResponse {
+    Success(RatesResponse),
+    // {"success": false, "error": {"code": 104, "info": "..."}}
+    Error { error: ErrorBody },
+}
+
+fn api_error(error: ErrorBody) -> WorthyError {
+    let message = format!("fixer.io error {}: {}", error.code, error.info);
+    match error.code {
+        // Missing or invalid access key, inactive account.
+        101 | 102 => WorthyError::Config(message),
+        // Monthly request volume reached.
+        104 => WorthyError::ConverterQuota {
+            converter: "fixer".to_string(),
+            message,
+        },
+        _ => WorthyError::Http(message),
+    }
+}
+
 #[async_trait]
 impl Converter for FixerConverter {
     type Config = FixerConverterConfig;
 
+    fn can_handle(denomination: &Denomination) -> bool {
+        matches!(denomination, Denomination::Currency { .. })
+    }
+
     async fn take_snapshot(
         config: &Self::Config,
         _denominations: &'life1 [&Denomination],
         _base: &Denomination,
-    ) -> Result<Vec<ExchangeRate>, Box<dyn Error>> {
-        let FixerConverterConfig { api_key } = config;
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        let FixerConverterConfig {
+            api_key,
+            retry,
+            endpoint,
+            spread_bps,
+            ..
+        } = config;
 
-        let mut url = Url::parse("http://data.fixer.io/api/latest")?;
+        let mut url = endpoint.clone();
         url.query_pairs_mut()
             .clear()
             .append_pair("access_key", api_key);
 
-        let response = reqwest::get(url).await?;
-        assert_eq!(response.status(), StatusCode::OK);
+        let response = retry_request(retry, || reqwest::get(url.clone())).await?;
+        if response.status() != StatusCode::OK {
+            return Err(WorthyError::Http(format!(
+                "fixer.io returned {}",
+                response.status()
+            )));
+        }
 
-        let r: RatesResponse = response.json().await?;
+        let r = match response.json().await? {
+            LatestResponse::Success(r) => r,
+            LatestResponse::Error { error } => return Err(api_error(error)),
+        };
 
         let base = r.base;
+        let timestamp = r.timestamp.unwrap_or_else(Utc::now);
         Ok(r.rates
             .into_iter()
             .map(|(to_symbol, rate)| ExchangeRate {
+                timestamp,
                 from: Denomination::Currency {
                     currency: base.clone(),
                 },
@@ -58,6 +126,7 @@ impl Converter for FixerConverter {
                     currency: to_symbol,
                 },
                 rate,
+                spread_bps: *spread_bps,
             })
             .collect())
     }
@@ -0,0 +1,92 @@
+use converter::Converter;
+use fixer_converter::{FixerConverter, FixerConverterConfig};
+use fixture_server::{FixtureServer, Response};
+use rust_decimal_macros::*;
+use test_util::currency;
+use worthy_error::WorthyError;
+
+#[tokio::test]
+async fn take_snapshot_from_recorded_response() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(include_str!(
+        "../testdata/fixer_latest.json"
+    ))]);
+    let config: FixerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "endpoint": format!("{}api/latest", server.url()),
+    }))
+    .unwrap();
+
+    let czk = currency("CZK");
+    let mut rates = FixerConverter::take_snapshot(&config, &[&czk], &czk)
+        .await
+        .unwrap();
+    rates.sort_by_key(|rate| format!("{:?}", rate.to));
+
+    assert_eq!(rates.len(), 2);
+    assert_eq!(rates[0].from, currency("EUR"));
+    assert_eq!(rates[0].to, currency("CZK"));
+    assert_eq!(rates[0].rate, dec!(25.9));
+    assert_eq!(rates[1].to, currency("USD"));
+    assert_eq!(rates[1].rate, dec!(1.2125));
+    // As of the response's timestamp.
+    assert_eq!(rates[0].timestamp.timestamp(), 1613851867);
+    assert_eq!(
+        server.requests(),
+        vec!["GET /api/latest?access_key=KEY HTTP/1.1"]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_quota_exceeded() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(
+        r#"{"success": false, "error": {"code": 104, "type": "usage_limit_reached", "info": "Your monthly API request volume has been reached."}}"#,
+    )]);
+    let config: FixerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "endpoint": server.url(),
+    }))
+    .unwrap();
+
+    let czk = currency("CZK");
+    let error = FixerConverter::take_snapshot(&config, &[&czk], &czk)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, WorthyError::ConverterQuota { .. }),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_garbage_response() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok("<html>not json</html>")]);
+    let config: FixerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "endpoint": server.url(),
+    }))
+    .unwrap();
+
+    let czk = currency("CZK");
+    let error = FixerConverter::take_snapshot(&config, &[&czk], &czk)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, WorthyError::Parse(_)), "{:?}", error);
+}
+
+#[test]
+fn config_denomination_filter() {
+    let config: FixerConverterConfig = serde_json::from_value(serde_json::json!({
+        "api_key": "KEY",
+        "spread_bps": 10,
+        "exclude": ["CZK"],
+    }))
+    .unwrap();
+
+    let usd = currency("USD");
+    let czk = currency("CZK");
+    assert_eq!(config.filter.apply(&[&usd, &czk]), vec![&usd]);
+}
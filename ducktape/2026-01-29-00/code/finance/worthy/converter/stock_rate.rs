@@ -0,0 +1,37 @@
+//! Stock prices as exchange rates, for the converters quoting stocks.
+
+use chrono::{DateTime, Utc};
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use rust_decimal::prelude::*;
+
+/// Price of one share of `stock`, quoted at `price` in `currency` at
+/// `timestamp`.
+///
+/// Some exchanges quote in minor units (e.g. London in pence, "GBp" or
+/// "GBX"), which are converted to the major currency.
+pub fn stock_rate(
+    stock: &str,
+    price: Decimal,
+    currency: &str,
+    timestamp: DateTime<Utc>,
+) -> ExchangeRate {
+    let (currency, rate) = match currency {
+        "GBp" | "GBX" => ("GBP", price / Decimal::ONE_HUNDRED),
+        "ZAc" | "ZAC" => ("ZAR", price / Decimal::ONE_HUNDRED),
+        "ILA" => ("ILS", price / Decimal::ONE_HUNDRED),
+        _ => (currency, price),
+    };
+    ExchangeRate {
+        timestamp,
+        from: Denomination::Stock {
+            stock: stock.to_string(),
+            isin: None,
+        },
+        to: Denomination::Currency {
+            currency: currency.to_string(),
+        },
+        rate,
+        spread_bps: Decimal::ZERO,
+    }
+}
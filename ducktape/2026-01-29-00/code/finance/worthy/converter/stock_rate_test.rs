@@ -0,0 +1,53 @@
+use chrono::{DateTime, Utc};
+use denomination::Denomination;
+use rust_decimal_macros::*;
+use stock_rate::stock_rate;
+use test_util::currency;
+
+#[test]
+fn gbp_listed_stock() {
+    let at = DateTime::parse_from_rfc3339("2024-06-07T16:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let rate = stock_rate("VOD.LON", dec!(7125), "GBX", at);
+    assert_eq!(
+        rate.from,
+        Denomination::Stock {
+            stock: "VOD.LON".to_string(),
+            isin: None,
+        }
+    );
+    assert_eq!(
+        rate.to,
+        Denomination::Currency {
+            currency: "GBP".to_string()
+        }
+    );
+    assert_eq!(rate.rate, dec!(71.25));
+    assert_eq!(rate.timestamp, at);
+}
+
+#[test]
+fn chf_listed_stock() {
+    let rate = stock_rate("NESN.SWX", dec!(99.5), "CHF", Utc::now());
+    assert_eq!(
+        rate.to,
+        Denomination::Currency {
+            currency: "CHF".to_string()
+        }
+    );
+    assert_eq!(rate.rate, dec!(99.5));
+}
+
+#[test]
+fn minor_units() {
+    for (minor, major) in [("GBp", "GBP"), ("ZAc", "ZAR"), ("ILA", "ILS")] {
+        let rate = stock_rate("X", dec!(150), minor, Utc::now());
+        assert_eq!(
+            (rate.to, rate.rate),
+            (currency(major), dec!(1.5)),
+            "{}",
+            minor
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use converter::{CallBudget, Converter, DenominationFilter};
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use log::{info, warn};
+use reqwest::StatusCode;
+use retry::{RetryConfig, retry_request};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use stock_rate::stock_rate;
+use url::Url;
+use worthy_error::WorthyError;
+
+/// Prices stocks with Yahoo Finance quotes, in the currency each is quoted
+/// in. Needs no API key.
+pub struct YahooFinanceConverter {}
+
+#[derive(Debug, Deserialize)]
+pub struct YahooFinanceConverterConfig {
+    #[serde(default)]
+    retry: RetryConfig,
+    #[serde(default = "default_endpoint")]
+    endpoint: Url,
+    /// Spread of the emitted rates, in basis points.
+    #[serde(default)]
+    spread_bps: Decimal,
+    /// Symbols to ask this converter about.
+    #[serde(flatten)]
+    pub filter: DenominationFilter,
+    #[serde(flatten)]
+    pub budget: CallBudget,
+}
+
+fn default_endpoint() -> Url {
+    Url::parse("https://query1.finance.yahoo.com/v8/finance/chart").unwrap()
+}
+
+/// Yahoo turns away requests without a browser-like user agent.
+const USER_AGENT: &str = "Mozilla/5.0 (compatible; worthy)";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartMeta {
+    pub symbol: String,
+    /// Missing for symbols Yahoo knows but has no price of.
+    #[serde(default)]
+    pub regular_market_price: Option<Decimal>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    // "regularMarketTime":1717790400
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub regular_market_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChartResult {
+    pub meta: ChartMeta,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Chart {
+    /// Null for symbols Yahoo doesn't know.
+    #[serde(default)]
+    pub result: Option<Vec<ChartResult>>,
+}
+
+// {"chart": {"result": [{"meta": {...}, "timestamp": [...], ...}], "error": null}}
+#[derive(Debug, Deserialize)]
+pub struct ChartResponse {
+    pub chart: Chart,
+}
+
+/// Deduplicated symbols of the stocks among `denominations`.
+fn stock_symbols(denominations: &[&Denomination]) -> Vec<String> {
+    let mut stocks: Vec<String> = denominations
+        .iter()
+        .filter_map(|denomination| match denomination {
+            Denomination::Stock { stock, .. } => Some(stock.clone()),
+            _ => None,
+        })
+        .collect();
+    stocks.sort();
+    stocks.dedup();
+    stocks
+}
+
+/// The latest quote of `stock`, or none if Yahoo doesn't know it. Unlike the
+/// quote endpoint, the chart endpoint takes no cookie and crumb, but only one
+/// symbol a call.
+async fn get_quote(
+    config: &YahooFinanceConverterConfig,
+    client: &reqwest::Client,
+    stock: &str,
+) -> Result<Option<ChartMeta>, WorthyError> {
+    let mut url = config.endpoint.clone();
+    url.path_segments_mut()
+        .map_err(|()| {
+            WorthyError::Config(format!(
+                "yahoo finance endpoint {} has no path",
+                config.endpoint
+            ))
+        })?
+        .pop_if_empty()
+        .push(stock);
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("range", "1d")
+        .append_pair("interval", "1d");
+    let response = retry_request(&config.retry, || client.get(url.clone()).send()).await?;
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND => return Ok(None),
+        status => {
+            return Err(WorthyError::Http(format!(
+                "yahoo finance returned {}",
+                status
+            )));
+        }
+    }
+    let response: ChartResponse = response.json().await?;
+    Ok(response
+        .chart
+        .result
+        .and_then(|results| results.into_iter().next())
+        .map(|result| result.meta))
+}
+
+#[async_trait]
+impl Converter for YahooFinanceConverter {
+    type Config = YahooFinanceConverterConfig;
+
+    fn can_handle(denomination: &Denomination) -> bool {
+        matches!(denomination, Denomination::Stock { .. })
+    }
+
+    /// One call per stock.
+    fn api_calls(_config: &Self::Config, denominations: &[&Denomination]) -> u32 {
+        stock_symbols(denominations).len() as u32
+    }
+
+    async fn take_snapshot(
+        config: &Self::Config,
+        denominations: &'life1 [&Denomination],
+        _base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        let client = reqwest::Client::builder().user_agent(USER_AGENT).build()?;
+        let stocks = stock_symbols(denominations);
+
+        let mut quotes: HashMap<String, ChartMeta> = HashMap::new();
+        for stock in &stocks {
+            if let Some(quote) = get_quote(config, &client, stock).await? {
+                quotes.insert(stock.clone(), quote);
+            }
+        }
+        let fetched_at = Utc::now();
+
+        let mut rates = Vec::new();
+        for denomination in denominations.iter() {
+            let Denomination::Stock { stock, .. } = denomination else {
+                continue;
+            };
+            let Some(quote) = quotes.get(stock) else {
+                warn!("yahoo finance: no quote of {}", stock);
+                continue;
+            };
+            let (Some(price), Some(currency)) = (quote.regular_market_price, &quote.currency)
+            else {
+                warn!("yahoo finance: no price of {}", stock);
+                continue;
+            };
+            // Price the denomination as asked, including its ISIN.
+            rates.push(ExchangeRate {
+                from: (*denomination).clone(),
+                spread_bps: config.spread_bps,
+                ..stock_rate(
+                    stock,
+                    price,
+                    currency,
+                    quote.regular_market_time.unwrap_or(fetched_at),
+                )
+            });
+        }
+        info!(
+            "yahoo finance: {} of {} stocks quoted",
+            quotes.len(),
+            stocks.len()
+        );
+        Ok(rates)
+    }
+}
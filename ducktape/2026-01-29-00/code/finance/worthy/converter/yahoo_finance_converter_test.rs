@@ -0,0 +1,103 @@
+use chrono::DateTime;
+use converter::Converter;
+use denomination::Denomination;
+use fixture_server::{FixtureServer, Response};
+use rust_decimal_macros::*;
+use test_util::currency;
+use worthy_error::WorthyError;
+use yahoo_finance_converter::{YahooFinanceConverter, YahooFinanceConverterConfig};
+
+fn config(endpoint: &str) -> YahooFinanceConverterConfig {
+    serde_json::from_value(serde_json::json!({ "endpoint": endpoint })).unwrap()
+}
+
+fn stock(symbol: &str) -> Denomination {
+    Denomination::Stock {
+        stock: symbol.to_string(),
+        isin: None,
+    }
+}
+
+#[tokio::test]
+async fn take_snapshot_from_recorded_response() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![
+        Response::ok(include_str!("../testdata/yahoo_finance_chart_GOOG.json")),
+        Response::ok(include_str!("../testdata/yahoo_finance_chart_NESN.SW.json")),
+        Response::status("404 Not Found"),
+        Response::ok(include_str!("../testdata/yahoo_finance_chart_VOD.L.json")),
+    ]);
+    let config = config(&format!("{}v8/finance/chart", server.url()));
+    let goog_with_isin = Denomination::Stock {
+        stock: "GOOG".to_string(),
+        isin: Some("US02079K1079".to_string()),
+    };
+    let (goog, vod, nesn, unknown) = (
+        stock("GOOG"),
+        stock("VOD.L"),
+        stock("NESN.SW"),
+        stock("NOPE"),
+    );
+
+    let rates = YahooFinanceConverter::take_snapshot(
+        &config,
+        &[&goog, &vod, &nesn, &unknown, &goog_with_isin],
+        &currency("USD"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        rates
+            .iter()
+            .map(|rate| (rate.from.clone(), rate.to.clone(), rate.rate))
+            .collect::<Vec<_>>(),
+        vec![
+            (goog.clone(), currency("USD"), dec!(175.95)),
+            // Quoted in pence.
+            (vod.clone(), currency("GBP"), dec!(0.7125)),
+            // Not assumed to be in USD.
+            (nesn.clone(), currency("CHF"), dec!(94.6)),
+            (goog_with_isin.clone(), currency("USD"), dec!(175.95)),
+        ]
+    );
+    // As of the quote's time.
+    assert_eq!(
+        rates[0].timestamp,
+        DateTime::from_timestamp(1717790400, 0).unwrap()
+    );
+    // One call per symbol, each asked about once.
+    assert_eq!(
+        server.requests(),
+        vec![
+            "GET /v8/finance/chart/GOOG?range=1d&interval=1d HTTP/1.1",
+            "GET /v8/finance/chart/NESN.SW?range=1d&interval=1d HTTP/1.1",
+            "GET /v8/finance/chart/NOPE?range=1d&interval=1d HTTP/1.1",
+            "GET /v8/finance/chart/VOD.L?range=1d&interval=1d HTTP/1.1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_error_status() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::status("401 Unauthorized")]);
+    let config = config(server.url());
+
+    let vt = stock("VT");
+    let error = YahooFinanceConverter::take_snapshot(&config, &[&vt], &vt)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, WorthyError::Http(_)), "{:?}", error);
+}
+
+#[test]
+fn api_calls_per_stock() {
+    let config = config("http://localhost/");
+    let (goog, vod, usd) = (stock("GOOG"), stock("VOD.L"), currency("USD"));
+    assert_eq!(
+        YahooFinanceConverter::api_calls(&config, &[&goog, &vod, &usd, &goog]),
+        2
+    );
+    assert_eq!(YahooFinanceConverter::api_calls(&config, &[&usd]), 0);
+}
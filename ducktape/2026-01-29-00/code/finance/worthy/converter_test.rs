@@ -0,0 +1,140 @@
+use async_trait::async_trait;
+use converter::{CallBudget, Converter, DenominationFilter, handled_denominations};
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use test_util::currency;
+use worthy_error::WorthyError;
+
+struct CryptoOnlyConverter {}
+
+#[async_trait]
+impl Converter for CryptoOnlyConverter {
+    type Config = ();
+
+    fn can_handle(denomination: &Denomination) -> bool {
+        matches!(denomination, Denomination::Cryptocurrency { .. })
+    }
+
+    async fn take_snapshot(
+        _config: &Self::Config,
+        _denominations: &'life1 [&Denomination],
+        _base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        Ok(vec![])
+    }
+}
+
+struct AnythingConverter {}
+
+#[async_trait]
+impl Converter for AnythingConverter {
+    type Config = ();
+
+    async fn take_snapshot(
+        _config: &Self::Config,
+        _denominations: &'life1 [&Denomination],
+        _base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        Ok(vec![])
+    }
+}
+
+#[test]
+fn crypto_only_converter_skipped_for_currencies() {
+    let usd = currency("USD");
+    let czk = currency("CZK");
+    assert!(handled_denominations::<CryptoOnlyConverter>(&[&usd, &czk]).is_empty());
+}
+
+#[test]
+fn crypto_only_converter_gets_only_crypto() {
+    let usd = currency("USD");
+    let btc = Denomination::Cryptocurrency {
+        symbol: "BTC".to_string(),
+    };
+    assert_eq!(
+        handled_denominations::<CryptoOnlyConverter>(&[&usd, &btc]),
+        vec![&btc]
+    );
+}
+
+#[test]
+fn converters_handle_everything_by_default() {
+    let usd = currency("USD");
+    let goog = Denomination::Stock {
+        stock: "GOOG".to_string(),
+        isin: None,
+    };
+    assert_eq!(
+        handled_denominations::<AnythingConverter>(&[&usd, &goog]),
+        vec![&usd, &goog]
+    );
+}
+
+#[test]
+fn filter_allows_everything_by_default() {
+    let usd = currency("USD");
+    let goog = Denomination::Stock {
+        stock: "GOOG".to_string(),
+        isin: None,
+    };
+    assert_eq!(
+        DenominationFilter::default().apply(&[&usd, &goog]),
+        vec![&usd, &goog]
+    );
+}
+
+#[test]
+fn filter_include_and_exclude() {
+    let usd = currency("USD");
+    let czk = currency("CZK");
+    let goog = Denomination::Stock {
+        stock: "GOOG".to_string(),
+        isin: None,
+    };
+    let include = DenominationFilter {
+        include: vec!["GOOG".to_string(), "CZK".to_string()],
+        exclude: vec![],
+    };
+    assert_eq!(include.apply(&[&usd, &czk, &goog]), vec![&czk, &goog]);
+
+    let exclude = DenominationFilter {
+        include: vec![],
+        exclude: vec!["GOOG".to_string()],
+    };
+    assert_eq!(exclude.apply(&[&usd, &czk, &goog]), vec![&usd, &czk]);
+
+    // Exclusion wins over inclusion.
+    let both = DenominationFilter {
+        include: vec!["GOOG".to_string()],
+        exclude: vec!["GOOG".to_string()],
+    };
+    assert!(both.apply(&[&usd, &goog]).is_empty());
+}
+
+#[test]
+fn call_budget_unlimited_by_default() {
+    assert!(CallBudget::default().check("fixer", 1000, 1).is_ok());
+}
+
+#[test]
+fn call_budget_exceeded() {
+    let warn_only = CallBudget {
+        daily_call_budget: Some(25),
+        refuse_over_budget: false,
+    };
+    assert!(warn_only.check("alpha_vantage", 20, 5).is_ok());
+    assert!(warn_only.check("alpha_vantage", 20, 6).is_ok());
+
+    let refusing = CallBudget {
+        daily_call_budget: Some(25),
+        refuse_over_budget: true,
+    };
+    assert!(refusing.check("alpha_vantage", 20, 5).is_ok());
+    let error = refusing.check("alpha_vantage", 20, 6).unwrap_err();
+    assert!(
+        matches!(&error, WorthyError::ConverterQuota { converter, .. } if converter == "alpha_vantage"),
+        "{:?}",
+        error
+    );
+}
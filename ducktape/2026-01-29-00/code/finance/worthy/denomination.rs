@@ -1,4 +1,7 @@
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Deserialize, PartialEq, Eq, Hash, Debug, Clone)]
 #[serde(untagged)]
@@ -14,6 +17,102 @@ pub enum Denomination {
         symbol: String,
     },
     Stock {
+        /// Ticker, for display. Tickers collide across exchanges.
         stock: String,
+        /// Identifies the security uniquely, if known, so that different
+        /// securities with the same ticker are kept apart.
+        #[serde(default)]
+        isin: Option<String>,
     },
+    /// An option or future, by its contract symbol. Kept apart from stocks so
+    /// that no converter is asked to quote it; the source holding it prices it.
+    Derivative {
+        derivative: String,
+    },
+}
+
+impl Denomination {
+    /// Currency code, cryptocurrency symbol, stock ticker or contract symbol.
+    pub fn symbol(&self) -> &str {
+        match self {
+            Denomination::Currency { currency } => currency,
+            Denomination::Cryptocurrency { symbol } => symbol,
+            Denomination::Stock { stock, .. } => stock,
+            Denomination::Derivative { derivative } => derivative,
+        }
+    }
+
+    /// This denomination with its symbol replaced by what `aliases` maps it
+    /// to, if anything. E.g. with `XBT: BTC`, "crypto:XBT" is "crypto:BTC".
+    pub fn canonical(&self, aliases: &HashMap<String, String>) -> Denomination {
+        let Some(symbol) = aliases.get(self.symbol()) else {
+            return self.clone();
+        };
+        match self {
+            Denomination::Currency { .. } => Denomination::Currency {
+                currency: symbol.clone(),
+            },
+            Denomination::Cryptocurrency { .. } => Denomination::Cryptocurrency {
+                symbol: symbol.clone(),
+            },
+            Denomination::Stock { isin, .. } => Denomination::Stock {
+                stock: symbol.clone(),
+                isin: isin.clone(),
+            },
+            Denomination::Derivative { .. } => Denomination::Derivative {
+                derivative: symbol.clone(),
+            },
+        }
+    }
+}
+
+/// Compact form, e.g. "currency:USD", "crypto:BTC", "stock:GOOG", or
+/// "stock:GOOG:US02079K1079" with an ISIN, or "derivative:ESM4".
+impl fmt::Display for Denomination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Denomination::Currency { currency } => write!(f, "currency:{}", currency),
+            Denomination::Cryptocurrency { symbol } => write!(f, "crypto:{}", symbol),
+            Denomination::Stock { stock, isin: None } => write!(f, "stock:{}", stock),
+            Denomination::Stock {
+                stock,
+                isin: Some(isin),
+            } => write!(f, "stock:{}:{}", stock, isin),
+            Denomination::Derivative { derivative } => write!(f, "derivative:{}", derivative),
+        }
+    }
+}
+
+/// Parses the `Display` form. A bare code, e.g. "USD", is a currency.
+impl FromStr for Denomination {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(format!("invalid denomination {:?}", s));
+        }
+        match parts[..] {
+            [currency] | ["currency", currency] => Ok(Denomination::Currency {
+                currency: currency.to_string(),
+            }),
+            ["crypto", symbol] => Ok(Denomination::Cryptocurrency {
+                symbol: symbol.to_string(),
+            }),
+            ["stock", stock] => Ok(Denomination::Stock {
+                stock: stock.to_string(),
+                isin: None,
+            }),
+            ["stock", stock, isin] => Ok(Denomination::Stock {
+                stock: stock.to_string(),
+                isin: Some(isin.to_string()),
+            }),
+            ["derivative", derivative] => Ok(Denomination::Derivative {
+                derivative: derivative.to_string(),
+            }),
+            _ => Err(format!(
+                "invalid denomination {:?}: expected e.g. USD, currency:USD, crypto:BTC or stock:GOOG",
+                s
+            )),
+        }
+    }
 }
@@ -0,0 +1,87 @@
+use denomination::Denomination;
+use std::collections::HashMap;
+
+fn round_trip(s: &str, denomination: Denomination) {
+    assert_eq!(s.parse::<Denomination>(), Ok(denomination.clone()));
+    assert_eq!(denomination.to_string(), s);
+}
+
+#[test]
+fn currency() {
+    round_trip(
+        "currency:USD",
+        Denomination::Currency {
+            currency: "USD".to_string(),
+        },
+    );
+}
+
+#[test]
+fn crypto() {
+    round_trip(
+        "crypto:BTC",
+        Denomination::Cryptocurrency {
+            symbol: "BTC".to_string(),
+        },
+    );
+}
+
+#[test]
+fn stock() {
+    round_trip(
+        "stock:GOOG",
+        Denomination::Stock {
+            stock: "GOOG".to_string(),
+            isin: None,
+        },
+    );
+    round_trip(
+        "stock:GOOG:US02079K1079",
+        Denomination::Stock {
+            stock: "GOOG".to_string(),
+            isin: Some("US02079K1079".to_string()),
+        },
+    );
+}
+
+#[test]
+fn derivative() {
+    round_trip(
+        "derivative:ESM4",
+        Denomination::Derivative {
+            derivative: "ESM4".to_string(),
+        },
+    );
+}
+
+#[test]
+fn bare_code_is_currency() {
+    assert_eq!(
+        "CZK".parse::<Denomination>(),
+        Ok(Denomination::Currency {
+            currency: "CZK".to_string(),
+        })
+    );
+}
+
+#[test]
+fn invalid() {
+    for s in ["", "bond:X", "stock:", "crypto:BTC:X", "currency:USD:X"] {
+        assert!(s.parse::<Denomination>().is_err(), "{:?}", s);
+    }
+}
+
+#[test]
+fn canonical() {
+    let aliases = HashMap::from([
+        ("XBT".to_string(), "BTC".to_string()),
+        ("VOD.L".to_string(), "VOD".to_string()),
+    ]);
+    let canonical = |s: &str| s.parse::<Denomination>().unwrap().canonical(&aliases);
+    assert_eq!(canonical("crypto:XBT"), "crypto:BTC".parse().unwrap());
+    assert_eq!(
+        canonical("stock:VOD.L:GB00BH4HKS39"),
+        "stock:VOD:GB00BH4HKS39".parse().unwrap()
+    );
+    assert_eq!(canonical("USD"), "USD".parse().unwrap());
+}
@@ -1,13 +1,16 @@
-//use chrono::prelude::*;
-//use chrono_tz::Tz;
+use chrono::{DateTime, Utc};
 use denomination::Denomination;
 use rust_decimal::prelude::Decimal;
-//use std::time::Instant;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ExchangeRate {
-    //timestamp: DateTime<Tz>,
+    /// When the rate was observed: the time its API quotes it at if it says,
+    /// or else when it was fetched.
+    pub timestamp: DateTime<Utc>,
     pub from: Denomination,
     pub to: Denomination,
     pub rate: Decimal,
+    /// Cost of converting in either direction, in basis points of the
+    /// converted amount. Zero for mid-market rates.
+    pub spread_bps: Decimal,
 }
@@ -0,0 +1,93 @@
+//! Minimal HTTP server replaying recorded responses, for tests that exercise
+//! real HTTP code paths without hitting live APIs.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub struct Response {
+    /// Status line, e.g. "200 OK".
+    pub status: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<String>) -> Response {
+        Response {
+            status: "200 OK",
+            body: body.into(),
+        }
+    }
+
+    pub fn status(status: &'static str) -> Response {
+        Response {
+            status,
+            body: String::new(),
+        }
+    }
+}
+
+pub struct FixtureServer {
+    listener: Option<TcpListener>,
+    url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl FixtureServer {
+    /// Binds to a free local port. Call `serve` to start answering requests.
+    pub fn bind() -> FixtureServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        FixtureServer {
+            listener: Some(listener),
+            url,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Base URL of the server, with a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Answers one connection with each of `responses`, in order.
+    pub fn serve(&mut self, responses: Vec<Response>) {
+        let listener = self.listener.take().expect("already serving");
+        let requests = self.requests.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                // Skip headers; fixtures don't look at request bodies.
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                }
+                requests
+                    .lock()
+                    .unwrap()
+                    .push(request_line.trim().to_string());
+                let mut stream = reader.into_inner();
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.status,
+                    response.body.len(),
+                    response.body
+                )
+                .unwrap();
+            }
+        });
+    }
+
+    /// Request lines received so far, e.g. "GET /api/latest?x=y HTTP/1.1".
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -1,3 +1,9 @@
+use chrono::Duration;
+use chrono::prelude::*;
+use denomination::Denomination;
+use log::LevelFilter;
+use rust_decimal::Decimal;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use structopt::StructOpt;
@@ -10,8 +16,13 @@ pub enum Command {
     Csv,
     // TODO: implement
     ModelLastSnapshot,
-    // TODO: implement
+    ModelAll,
     Server,
+    List,
+    Check,
+    CheckRates,
+    SelfTest,
+    Html,
 }
 
 impl FromStr for Command {
@@ -21,12 +32,97 @@ impl FromStr for Command {
             "snapshot" => Ok(Command::Snapshot),
             "csv" => Ok(Command::Csv),
             "modellastsnapshot" => Ok(Command::ModelLastSnapshot),
+            "modelall" => Ok(Command::ModelAll),
             "server" => Ok(Command::Server),
+            "list" => Ok(Command::List),
+            "check" => Ok(Command::Check),
+            "checkrates" => Ok(Command::CheckRates),
+            "selftest" => Ok(Command::SelfTest),
+            "html" => Ok(Command::Html),
             _ => Err("unknown command"),
         }
     }
 }
 
+/// How `snapshot` and `modellastsnapshot` print the model.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err("unknown format"),
+        }
+    }
+}
+
+/// Parses a date given either as RFC3339 or as a bare `YYYY-MM-DD`, which is
+/// taken as midnight UTC.
+pub fn parse_date(s: &str) -> Result<DateTime<FixedOffset>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(s) {
+        return Ok(timestamp);
+    }
+    match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_time(NaiveTime::MIN).and_utc().fixed_offset()),
+        Err(_) => Err(format!(
+            "cannot parse date {:?}: expected RFC3339 or YYYY-MM-DD",
+            s
+        )),
+    }
+}
+
+/// Parses a duration such as `7d` or `12h`: a whole number followed by one of
+/// the units `w`, `d`, `h`, `m` or `s`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let error = || {
+        format!(
+            "cannot parse duration {:?}: expected e.g. 7d, 12h or 30m",
+            s
+        )
+    };
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let (number, unit) = s.split_at(split);
+    let number: i64 = number.parse().map_err(|_| error())?;
+    let duration = match unit {
+        "w" => Duration::try_weeks(number),
+        "d" => Duration::try_days(number),
+        "h" => Duration::try_hours(number),
+        "m" => Duration::try_minutes(number),
+        "s" => Duration::try_seconds(number),
+        _ => None,
+    };
+    duration.ok_or_else(error)
+}
+
+/// `duration` in days, hours, minutes and seconds, leaving out zeros, e.g.
+/// `7d 12h`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds();
+    let parts: Vec<String> = [
+        (seconds / 86400, "d"),
+        (seconds % 86400 / 3600, "h"),
+        (seconds % 3600 / 60, "m"),
+        (seconds % 60, "s"),
+    ]
+    .into_iter()
+    .filter(|(amount, _)| *amount != 0)
+    .map(|(amount, unit)| format!("{}{}", amount, unit))
+    .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
 #[derive(Debug, StructOpt, PartialEq)]
 pub struct Opt {
     // TODO: implement
@@ -38,8 +134,199 @@ pub struct Opt {
 
     #[structopt(
         long,
-        help = "command; one of snapshot, csv, modellastsnapshot, server",
+        help = "configuration file to use instead of ~/.config/worthy/config.yaml"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "command; one of snapshot, csv, modellastsnapshot, modelall, server, list, check, checkrates, selftest, html",
         default_value = "snapshot"
     )]
     pub command: Command,
+
+    #[structopt(
+        long,
+        help = "model output format; one of table, json, csv",
+        default_value = "table"
+    )]
+    pub format: Format,
+
+    #[structopt(
+        long,
+        help = "snapshot, modellastsnapshot, html, check, checkrates: use the account base currency of this source (e.g. an ibflex source) as the common currency"
+    )]
+    pub base_from_source: Option<String>,
+
+    #[structopt(
+        long,
+        help = "print tables in plain ASCII, without emoji, arrows or box drawing"
+    )]
+    pub ascii: bool,
+
+    #[structopt(
+        long,
+        help = "snapshot, modellastsnapshot: write the conversion graph to this DOT file"
+    )]
+    pub dump_graph: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "snapshot, modellastsnapshot: print how each denomination is priced in the common currency"
+    )]
+    pub reachability: bool,
+
+    #[structopt(
+        long,
+        help = "snapshot, modellastsnapshot: print the inputs and intermediate values of each modelled cell to stderr"
+    )]
+    pub explain: bool,
+
+    #[structopt(
+        long,
+        help = "snapshot: store how each monthly goal comes out under each yearly yield in the snapshot"
+    )]
+    pub emit_modelling: bool,
+
+    #[structopt(
+        long,
+        help = "snapshot, modellastsnapshot: end the output with a line like \"TOTAL 12345.67 USD\" for scripts"
+    )]
+    pub machine: bool,
+
+    #[structopt(
+        long,
+        help = "server: address to serve /metrics on",
+        default_value = "127.0.0.1:9747"
+    )]
+    pub listen: SocketAddr,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "snapshot: only refresh these sources (comma-separated ids), carrying the rest over from the newest snapshot"
+    )]
+    pub only: Vec<String>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "snapshot: only refresh these converters (comma-separated ids), carrying the rest over from the newest snapshot"
+    )]
+    pub only_converters: Vec<String>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "snapshot, modellastsnapshot: leave these sources (comma-separated ids) out of the modelled total, besides exclude_sources"
+    )]
+    pub exclude_sources: Vec<String>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "snapshot, modellastsnapshot: leave these denominations (comma-separated, e.g. USD,stock:VT) out of the modelled total, besides exclude_denominations"
+    )]
+    pub exclude_denominations: Vec<Denomination>,
+
+    #[structopt(
+        long,
+        default_value = "4",
+        help = "snapshot: how many sources to fetch at the same time"
+    )]
+    pub parallel_sources: usize,
+
+    #[structopt(
+        long,
+        default_value = "4",
+        help = "snapshot, checkrates: how many converters to fetch at the same time"
+    )]
+    pub parallel_converters: usize,
+
+    #[structopt(
+        long,
+        help = "log level; one of off, error, warn, info, debug, trace (RUST_LOG overrides it)"
+    )]
+    pub log_level: Option<LevelFilter>,
+
+    #[structopt(long, help = "append logs to this file instead of stderr")]
+    pub log_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "check: total the snapshot should re-value to, in the common currency"
+    )]
+    pub expected_total: Option<Decimal>,
+
+    #[structopt(
+        long,
+        help = "check: how far the total may be from --expected-total",
+        default_value = "0"
+    )]
+    pub tolerance: Decimal,
+
+    #[structopt(
+        long,
+        help = "checkrates: how far apart rates of the same pair may be, in basis points",
+        default_value = "100"
+    )]
+    pub max_rate_spread_bps: Decimal,
+
+    #[structopt(
+        long,
+        help = "years to model for, instead of modelling.horizon_years from the configuration"
+    )]
+    pub horizon: Option<Decimal>,
+
+    #[structopt(long, help = "check: snapshot file to check instead of the newest one")]
+    pub snapshot_file: Option<PathBuf>,
+
+    #[structopt(long, help = "snapshot: label to store with the snapshot")]
+    pub label: Option<String>,
+
+    #[structopt(
+        long,
+        help = "snapshot: replace the snapshot taken earlier today, if any, instead of adding another"
+    )]
+    pub overwrite_today: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_date),
+        help = "csv: only include snapshots taken at or after this date (RFC3339 or YYYY-MM-DD)"
+    )]
+    pub since: Option<DateTime<FixedOffset>>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_date),
+        help = "csv: only include snapshots taken at or before this date (RFC3339 or YYYY-MM-DD)"
+    )]
+    pub until: Option<DateTime<FixedOffset>>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_date),
+        help = "model as of this time instead of now, and time-stamp snapshots with it (RFC3339 or YYYY-MM-DD)"
+    )]
+    pub as_of: Option<DateTime<FixedOffset>>,
+
+    #[structopt(
+        long,
+        help = "snapshot, checkrates: price with the newest recorded rates (see rate_recordings) instead of asking converters"
+    )]
+    pub replay_rates: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_duration),
+        help = "modellastsnapshot: refuse to model a snapshot older than this, e.g. 7d or 12h"
+    )]
+    pub max_age: Option<Duration>,
+
+    #[structopt(
+        long,
+        help = "modellastsnapshot: with --max-age, only warn about an older snapshot"
+    )]
+    pub max_age_warn: bool,
 }
@@ -1,4 +1,9 @@
-use flags::{Command, Opt};
+use chrono::Duration;
+use chrono::prelude::*;
+use denomination::Denomination;
+use flags::{Command, Format, Opt, format_duration, parse_date, parse_duration};
+use log::LevelFilter;
+use rust_decimal::Decimal;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
@@ -12,7 +17,271 @@ fn test_flag_parsing() {
         ]),
         Opt {
             json_output_path: Some(PathBuf::from("/home/test.json")),
+            config: None,
             command: Command::Csv,
+            format: Format::Table,
+            base_from_source: None,
+            ascii: false,
+            dump_graph: None,
+            reachability: false,
+            explain: false,
+            emit_modelling: false,
+            machine: false,
+            listen: "127.0.0.1:9747".parse().unwrap(),
+            only: vec![],
+            only_converters: vec![],
+            exclude_sources: vec![],
+            exclude_denominations: vec![],
+            parallel_sources: 4,
+            parallel_converters: 4,
+            log_level: None,
+            log_file: None,
+            expected_total: None,
+            tolerance: Decimal::ZERO,
+            max_rate_spread_bps: Decimal::ONE_HUNDRED,
+            horizon: None,
+            snapshot_file: None,
+            label: None,
+            overwrite_today: false,
+            since: None,
+            until: None,
+            as_of: None,
+            replay_rates: false,
+            max_age: None,
+            max_age_warn: false,
         }
     );
 }
+
+#[test]
+fn test_format_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--format=json"]);
+    assert_eq!(opt.format, Format::Json);
+    assert!(Opt::from_iter_safe(&["worthy", "--format=xml"]).is_err());
+}
+
+#[test]
+fn test_list_and_label_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--label=year-end"]);
+    assert_eq!(opt.command, Command::Snapshot);
+    assert_eq!(opt.label, Some("year-end".to_string()));
+    assert!(!opt.overwrite_today);
+    assert!(Opt::from_iter(&["worthy", "--overwrite-today"]).overwrite_today);
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--command=list"]).command,
+        Command::List
+    );
+}
+
+#[test]
+fn test_horizon_parsing() {
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--horizon=40"]).horizon,
+        Some(Decimal::from(40))
+    );
+    assert!(Opt::from_iter_safe(&["worthy", "--horizon=forever"]).is_err());
+}
+
+#[test]
+fn test_dump_graph_parsing() {
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--dump-graph=/tmp/graph.dot"]).dump_graph,
+        Some(PathBuf::from("/tmp/graph.dot"))
+    );
+}
+
+#[test]
+fn test_explain_parsing() {
+    assert!(!Opt::from_iter(&["worthy"]).explain);
+    assert!(Opt::from_iter(&["worthy", "--explain"]).explain);
+}
+
+#[test]
+fn test_only_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--only=ib,bank", "--only-converters=fixer"]);
+    assert_eq!(opt.only, vec!["ib".to_string(), "bank".to_string()]);
+    assert_eq!(opt.only_converters, vec!["fixer".to_string()]);
+}
+
+#[test]
+fn test_exclude_parsing() {
+    let opt = Opt::from_iter(&[
+        "worthy",
+        "--exclude-sources=fund,pension",
+        "--exclude-denominations=CZK,stock:VT",
+    ]);
+    assert_eq!(
+        opt.exclude_sources,
+        vec!["fund".to_string(), "pension".to_string()]
+    );
+    assert_eq!(
+        opt.exclude_denominations,
+        vec![
+            Denomination::Currency {
+                currency: "CZK".to_string()
+            },
+            Denomination::Stock {
+                stock: "VT".to_string(),
+                isin: None
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_parallel_sources_parsing() {
+    assert_eq!(Opt::from_iter(&["worthy"]).parallel_sources, 4);
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--parallel-sources=1"]).parallel_sources,
+        1
+    );
+    assert_eq!(Opt::from_iter(&["worthy"]).parallel_converters, 4);
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--parallel-converters=2"]).parallel_converters,
+        2
+    );
+}
+
+#[test]
+fn test_log_parsing() {
+    let opt = Opt::from_iter(&[
+        "worthy",
+        "--log-level=debug",
+        "--log-file=/var/log/worthy.log",
+    ]);
+    assert_eq!(opt.log_level, Some(LevelFilter::Debug));
+    assert_eq!(opt.log_file, Some(PathBuf::from("/var/log/worthy.log")));
+    assert!(Opt::from_iter_safe(&["worthy", "--log-level=loud"]).is_err());
+}
+
+#[test]
+fn test_model_all_parsing() {
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--command=modelall"]).command,
+        Command::ModelAll
+    );
+}
+
+#[test]
+fn test_base_from_source_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--base-from-source=interactive_brokers"]);
+    assert_eq!(
+        opt.base_from_source,
+        Some("interactive_brokers".to_string())
+    );
+}
+
+#[test]
+fn test_check_parsing() {
+    let opt = Opt::from_iter(&[
+        "worthy",
+        "--command=check",
+        "--expected-total=12345.6",
+        "--tolerance=0.5",
+        "--snapshot-file=/tmp/golden.json",
+    ]);
+    assert_eq!(opt.command, Command::Check);
+    assert_eq!(opt.expected_total, Some(Decimal::new(123456, 1)));
+    assert_eq!(opt.tolerance, Decimal::new(5, 1));
+    assert_eq!(opt.snapshot_file, Some(PathBuf::from("/tmp/golden.json")));
+}
+
+#[test]
+fn test_since_until_parsing() {
+    let opt = Opt::from_iter(&[
+        "worthy",
+        "--command=csv",
+        "--since=2021-02-03",
+        "--until=2021-03-04T05:06:07+01:00",
+    ]);
+    assert_eq!(
+        opt.since,
+        Some(DateTime::parse_from_rfc3339("2021-02-03T00:00:00Z").unwrap())
+    );
+    assert_eq!(
+        opt.until,
+        Some(DateTime::parse_from_rfc3339("2021-03-04T05:06:07+01:00").unwrap())
+    );
+}
+
+#[test]
+fn test_as_of_parsing() {
+    assert_eq!(
+        Opt::from_iter(&["worthy", "--as-of=2021-02-03"]).as_of,
+        Some(DateTime::parse_from_rfc3339("2021-02-03T00:00:00Z").unwrap())
+    );
+}
+
+#[test]
+fn test_check_rates_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--command=checkrates", "--max-rate-spread-bps=25"]);
+    assert_eq!(opt.command, Command::CheckRates);
+    assert_eq!(opt.max_rate_spread_bps, Decimal::new(25, 0));
+}
+
+#[test]
+fn test_self_test_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--command=selftest"]);
+    assert_eq!(opt.command, Command::SelfTest);
+}
+
+#[test]
+fn test_emit_modelling_parsing() {
+    assert!(Opt::from_iter(&["worthy", "--emit-modelling"]).emit_modelling);
+    assert!(!Opt::from_iter(&["worthy"]).emit_modelling);
+}
+
+#[test]
+fn test_config_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--config=~/worthy/family.yaml"]);
+    assert_eq!(opt.config, Some(PathBuf::from("~/worthy/family.yaml")));
+}
+
+#[test]
+fn test_html_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--command=html"]);
+    assert_eq!(opt.command, Command::Html);
+}
+
+#[test]
+fn test_max_age_parsing() {
+    let opt = Opt::from_iter(&["worthy", "--max-age=7d", "--max-age-warn"]);
+    assert_eq!(opt.max_age, Some(Duration::days(7)));
+    assert!(opt.max_age_warn);
+    assert!(Opt::from_iter_safe(&["worthy", "--max-age=7"]).is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("12h"), Ok(Duration::hours(12)));
+    assert_eq!(parse_duration("2w"), Ok(Duration::days(14)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::seconds(90)));
+    for invalid in [
+        "",
+        "d",
+        "7",
+        "7y",
+        "-7d",
+        "1.5d",
+        "7d12h",
+        "99999999999999999999d",
+    ] {
+        assert!(parse_duration(invalid).is_err(), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(Duration::days(7)), "7d");
+    assert_eq!(
+        format_duration(Duration::days(1) + Duration::hours(2) + Duration::seconds(3)),
+        "1d 2h 3s"
+    );
+    assert_eq!(format_duration(Duration::zero()), "0s");
+}
+
+#[test]
+fn test_parse_date_invalid() {
+    assert!(parse_date("2021-02-30").is_err());
+    assert!(parse_date("yesterday").is_err());
+}
@@ -1,15 +1,17 @@
+use chrono::NaiveDate;
 use log::{error, trace};
-use reqwest::StatusCode;
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, de};
 use std::{
     collections::HashSet,
     error::Error,
     fmt,
     fmt::{Display, Formatter},
+    str::FromStr,
 };
 use tokio::time::{Duration, sleep};
 use url::Url;
+use worthy_error::WorthyError;
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub enum Status {
@@ -44,6 +46,10 @@ pub struct FlexStatementResponse {
 pub enum AssetCategory {
     #[serde(rename = "STK")]
     Stock,
+    #[serde(rename = "OPT")]
+    Option,
+    #[serde(rename = "FUT")]
+    Future,
 }
 /*
 if openPosition.Multiplier != "1" {
@@ -79,6 +85,8 @@ self.logger.Println(openPosition.Symbol, openPosition.Description,
 #[derive(Debug, Deserialize, PartialEq)]
 pub enum Side {
     Long,
+    /// Sold short. IB reports the position as negative.
+    Short,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -93,7 +101,10 @@ pub struct OpenPosition {
     pub asset_category: AssetCategory, /*STK*/
     pub symbol: String,      /* TSLA*/
     pub description: String, /* TSLA*/
-    pub multiplier: Decimal, /* 1*/
+    /// Units of the underlying per contract: 1 for stocks, e.g. 100 for
+    /// options on stocks. Empty for some instruments.
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub multiplier: Option<Decimal>,
     #[serde(rename = "fxRateToBase")]
     pub fx_rate_to_base: Decimal,
     //Conid             string `xml:"conid,attr"`
@@ -113,12 +124,37 @@ pub struct OpenPosition {
     //OpenPrice         string `xml:"openPrice,attr"`
     //PercentOfNAV      string `xml:"percentOfNAV,attr"`
     //CostBasisPrice    string `xml:"costBasisPrice,attr"`
-    //CostBasisMoney    string `xml:"costBasisMoney,attr"`
-    //FifoPnlUnrealized string `xml:"fifoPnlUnrealized,attr"`
+    /// Total cost of the position, in `currency`. Only present if the Flex
+    /// query includes it.
+    #[serde(rename = "costBasisMoney", default, deserialize_with = "empty_as_none")]
+    pub cost_basis_money: Option<Decimal>,
+    /// Unrealized gain of the position going by FIFO, in `currency`.
+    #[serde(
+        rename = "fifoPnlUnrealized",
+        default,
+        deserialize_with = "empty_as_none"
+    )]
+    pub fifo_pnl_unrealized: Option<Decimal>,
     pub issuer: String,
-    pub expiry: String,
-    #[serde(rename = "putCall")]
-    pub put_call: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub expiry: Option<String>,
+    #[serde(rename = "putCall", default, deserialize_with = "empty_as_none")]
+    pub put_call: Option<String>,
+}
+
+/// Deserializes an attribute IB leaves empty when it doesn't apply as `None`.
+fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    s.parse().map(Some).map_err(de::Error::custom)
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
@@ -129,12 +165,64 @@ pub struct OpenPositions {
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub enum LevelOfDetail {
+    /// The whole position.
     #[serde(rename = "SUMMARY")]
     Summary,
+    /// One tax lot of a position, if the Flex query includes lots.
+    #[serde(rename = "LOT")]
+    Lot,
+}
+
+/// Row of the Cash Report section: the cash of one account in one currency,
+/// or in all of them converted to the base currency.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct CashReportCurrency {
+    #[serde(rename = "accountId")]
+    pub account_id: String,
+    #[serde(rename = "acctAlias")]
+    pub acct_alias: String,
+    /// "BASE_SUMMARY" for the row in the base currency.
+    pub currency: String,
+    #[serde(rename = "levelOfDetail")]
+    pub level_of_detail: CashLevelOfDetail,
+    /// Cash at the end of the statement period. Only present if the Flex
+    /// query includes it.
+    #[serde(rename = "endingCash", default, deserialize_with = "empty_as_none")]
+    pub ending_cash: Option<Decimal>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub enum CashLevelOfDetail {
+    /// Cash in all currencies, converted to the base currency.
+    BaseCurrency,
+    /// Cash in one currency.
+    Currency,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct CashReport {
+    #[serde(rename = "CashReportCurrency")]
+    pub cash_report_currency: Option<Vec<CashReportCurrency>>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct AccountInformation {
+    #[serde(rename = "accountId")]
+    pub account_id: String,
+    /// Base currency of the account.
+    #[serde(rename = "currency")]
+    pub currency: String,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct FlexStatement {
+    /// Only present if the Flex query includes the Account Information
+    /// section.
+    #[serde(rename = "AccountInformation", default)]
+    pub account_information: Option<AccountInformation>,
+    /// Only present if the Flex query includes the Cash Report section.
+    #[serde(rename = "CashReport", default)]
+    pub cash_report: Option<CashReport>,
     #[serde(rename = "OpenPositions")]
     pub open_positions: OpenPositions,
     #[serde(rename = "accountId")]
@@ -159,13 +247,27 @@ pub struct FlexStatements {
 #[derive(Debug, Deserialize, PartialEq)]
 pub enum Period {
     LastBusinessDay,
+    LastWeek,
+    LastMonth,
+    LastQuarter,
+    LastYear,
+    Last365CalendarDays,
+    WeekToDate,
+    MonthToDate,
+    QuarterToDate,
+    YearToDate,
+    /// Custom date ranges and periods not listed above.
+    #[serde(other)]
+    Other,
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct FlexQueryResponseXml {
-    // present on error
+    // present on error; IB sometimes sends them empty along with a success
+    #[serde(default, deserialize_with = "empty_as_none")]
     pub error_code: Option<i32>,
+    #[serde(default, deserialize_with = "empty_as_none")]
     pub error_message: Option<String>,
 
     #[serde(rename = "type")]
@@ -186,8 +288,8 @@ pub enum FlexQueryResponse {
     Success(FlexQuerySuccess),
 }
 
-const FLEX_API_VERSION: i32 = 3;
-const ENDPOINT: &str =
+pub const DEFAULT_FLEX_API_VERSION: i32 = 3;
+pub const DEFAULT_ENDPOINT: &str =
     "https://gdcdyn.interactivebrokers.com/Universal/servlet/FlexStatementService.SendRequest";
 
 pub fn parse_flex_statement_response(
@@ -196,27 +298,19 @@ pub fn parse_flex_statement_response(
     serde_xml_rs::from_str(text)
 }
 
-#[derive(Debug, PartialEq)]
-enum IBFlexError {
-    ParseError,
-    HttpError { status: StatusCode },
-}
-
-impl Error for IBFlexError {}
-
-impl Display for IBFlexError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Parse error")
-    }
-}
-
-pub fn parse_flex_query_response(text: &str) -> Result<FlexQueryResponse, Box<dyn Error>> {
+pub fn parse_flex_query_response(text: &str) -> Result<FlexQueryResponse, WorthyError> {
     let FlexQueryResponseXml {
         error_code,
         error_message,
         response_type,
         flex_statements,
     } = serde_xml_rs::from_str(text)?;
+    let fields = [
+        ("ErrorCode", error_code.is_some()),
+        ("ErrorMessage", error_message.is_some()),
+        ("type", response_type.is_some()),
+        ("FlexStatements", flex_statements.is_some()),
+    ];
     match (error_code, error_message, response_type, flex_statements) {
         (Some(code), Some(message), None, None) => {
             Ok(FlexQueryResponse::Error(FlexError { code, message }))
@@ -227,50 +321,91 @@ pub fn parse_flex_query_response(text: &str) -> Result<FlexQueryResponse, Box<dy
                 flex_statements,
             }))
         }
-        _ => Err(IBFlexError::ParseError.into()),
+        _ => {
+            let listed = |set: bool| {
+                let names: Vec<&str> = fields
+                    .iter()
+                    .filter(|(_, is_set)| *is_set == set)
+                    .map(|(name, _)| *name)
+                    .collect();
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            };
+            Err(WorthyError::Parse(format!(
+                "FlexQueryResponse is neither an error (ErrorCode and ErrorMessage) nor a \
+                 statement (type and FlexStatements): set: {}; unset: {}",
+                listed(true),
+                listed(false)
+            )))
+        }
     }
 }
 
-fn check_http_ok(r: &reqwest::Response) -> Result<(), IBFlexError> {
-    match r.status() {
-        StatusCode::OK => Ok(()),
-        status => Err(IBFlexError::HttpError { status }),
+/// URL of a Flex web service request: `query` is the query ID when sending a
+/// request, or the reference code when fetching its result.
+pub fn flex_request_url(endpoint: &Url, token: &str, query: &str, version: i32) -> Url {
+    let mut url = endpoint.clone();
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("t", token)
+        .append_pair("q", query)
+        .append_pair("v", &version.to_string());
+    url
+}
+
+/// Report dates (inclusive) requested instead of the period saved in the Flex
+/// query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportDates {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+/// URL of the request that starts generating the statement for `query`.
+pub fn send_request_url(
+    endpoint: &Url,
+    token: &str,
+    query: &str,
+    version: i32,
+    dates: Option<ReportDates>,
+) -> Url {
+    let mut url = flex_request_url(endpoint, token, query, version);
+    if let Some(ReportDates { from, to }) = dates {
+        url.query_pairs_mut()
+            .append_pair("fd", &from.format("%Y%m%d").to_string())
+            .append_pair("td", &to.format("%Y%m%d").to_string());
     }
+    url
 }
 
 async fn run_flex_query2(
+    endpoint: &Url,
+    version: i32,
     token: &str,
     query_id: &str,
-) -> Result<FlexStatementResponse, Box<dyn Error>> {
-    let mut url = Url::parse(ENDPOINT)?;
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("t", token)
-        .append_pair("q", query_id)
-        .append_pair("v", &FLEX_API_VERSION.to_string());
+    dates: Option<ReportDates>,
+) -> Result<FlexStatementResponse, WorthyError> {
+    let url = send_request_url(endpoint, token, query_id, version, dates);
 
-    let response = reqwest::get(url).await?;
-    check_http_ok(&response)?;
+    let response = reqwest::get(url).await?.error_for_status()?;
     trace!("{:?}", response);
     let text = response.text().await?;
     trace!("{:?}", text);
-    Ok(serde_xml_rs::from_str(&text).unwrap())
+    Ok(serde_xml_rs::from_str(&text)?)
 }
 
 async fn fetch_flex_query_result(
+    version: i32,
     token: &str,
     reference_code: &str,
     url: &Url,
-) -> Result<FlexQueryResponse, Box<dyn Error>> {
-    let mut url = url.clone();
-    url.query_pairs_mut()
-        .clear()
-        .append_pair("t", token)
-        .append_pair("q", reference_code)
-        .append_pair("v", &FLEX_API_VERSION.to_string());
+) -> Result<FlexQueryResponse, WorthyError> {
+    let url = flex_request_url(url, token, reference_code, version);
 
-    let response = reqwest::get(url).await?;
-    check_http_ok(&response)?;
+    let response = reqwest::get(url).await?.error_for_status()?;
     let text = response.text().await?;
     trace!("{:?}", text);
     parse_flex_query_response(&text)
@@ -284,56 +419,183 @@ pub struct FlexError {
 
 impl Display for FlexError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Flex error: {} {}", self.code, self.message)
+        write!(f, "Flex error: {} {}", self.code, self.message)?;
+        if let Some(explanation) = self.explanation() {
+            write!(f, " ({})", explanation)?;
+        }
+        Ok(())
     }
 }
 impl Error for FlexError {}
 
+impl From<FlexError> for WorthyError {
+    fn from(error: FlexError) -> Self {
+        let message = match error.explanation() {
+            Some(explanation) => format!("{} ({})", error.message, explanation),
+            None => error.message,
+        };
+        WorthyError::IbFlex {
+            code: Some(error.code),
+            message,
+        }
+    }
+}
+
+/// Statement is incomplete at this time. Please try again shortly.
+const STATEMENT_INCOMPLETE: i32 = 1004;
+/// Statement is not available.
+const STATEMENT_NOT_AVAILABLE: i32 = 1003;
+/// Query is invalid.
+const QUERY_INVALID: i32 = 1014;
+
 impl FlexError {
-    fn is_retriable(&self) -> bool {
-        let retriable_codes: HashSet<i32> = [1009, 1019, 1004].iter().cloned().collect();
-        retriable_codes.contains(&self.code) && self.message.contains("Please try again shortly")
+    pub fn new(code: i32, message: &str) -> FlexError {
+        FlexError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// What to do about the documented errors IB's messages don't explain.
+    fn explanation(&self) -> Option<&'static str> {
+        match self.code {
+            STATEMENT_INCOMPLETE => Some("IB is still generating the statement"),
+            STATEMENT_NOT_AVAILABLE => {
+                Some("no statement for the requested dates, e.g. before the account was opened")
+            }
+            QUERY_INVALID => Some("check the query ID and that the Flex query still exists"),
+            _ => None,
+        }
+    }
+}
+
+/// Which Flex errors are worth asking again about later.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct FlexRetry {
+    /// Error codes that may go away on a later attempt.
+    #[serde(default = "default_retriable_codes")]
+    pub retriable_codes: HashSet<i32>,
+    /// Only retry if IB's message also says to "try again shortly", which it
+    /// does in English only.
+    #[serde(default)]
+    pub require_try_again_message: bool,
+}
+
+/// Statement is incomplete, the server is busy, and statement generation is in
+/// progress.
+pub fn default_retriable_codes() -> HashSet<i32> {
+    [STATEMENT_INCOMPLETE, 1009, 1019].into_iter().collect()
+}
+
+impl Default for FlexRetry {
+    fn default() -> Self {
+        FlexRetry {
+            retriable_codes: default_retriable_codes(),
+            require_try_again_message: false,
+        }
+    }
+}
+
+/// Whether asking again later may get past `error`.
+pub fn is_retriable(error: &FlexError, retry: &FlexRetry) -> bool {
+    retry.retriable_codes.contains(&error.code)
+        && (!retry.require_try_again_message || error.message.contains("Please try again shortly"))
+}
+
+/// Where to fetch the statement a send request started generating.
+#[derive(Debug, PartialEq)]
+pub struct StatementLocation {
+    pub url: Url,
+    pub reference_code: String,
+}
+
+/// Why a send request didn't start generating a statement.
+#[derive(Debug, PartialEq)]
+pub enum SendRequestError {
+    Flex(FlexError),
+    /// The response lacks what its status requires, e.g. a URL on success.
+    UnhandledResponse(String),
+}
+
+impl From<SendRequestError> for WorthyError {
+    fn from(error: SendRequestError) -> Self {
+        match error {
+            SendRequestError::Flex(error) => error.into(),
+            SendRequestError::UnhandledResponse(message) => WorthyError::IbFlex {
+                code: None,
+                message,
+            },
+        }
     }
 }
 
+impl FlexStatementResponse {
+    /// Where to fetch the statement, or the error IB reported instead.
+    pub fn location(self) -> Result<StatementLocation, SendRequestError> {
+        let missing = |field: &str| {
+            SendRequestError::UnhandledResponse(format!(
+                "FlexStatementResponse with status {:?} has no {} ({})",
+                self.status, field, self.timestamp
+            ))
+        };
+        match self.status {
+            Status::Success => {
+                if let Some(code) = self.error_code {
+                    return Err(SendRequestError::UnhandledResponse(format!(
+                        "FlexStatementResponse with status Success has error code {}: {}",
+                        code,
+                        self.error_message.as_deref().unwrap_or_default()
+                    )));
+                }
+                Ok(StatementLocation {
+                    url: self.url.clone().ok_or_else(|| missing("Url"))?,
+                    reference_code: self
+                        .reference_code
+                        .clone()
+                        .ok_or_else(|| missing("ReferenceCode"))?,
+                })
+            }
+            Status::Fail => match (self.error_code, &self.error_message) {
+                (Some(code), Some(message)) => {
+                    Err(SendRequestError::Flex(FlexError::new(code, message)))
+                }
+                (None, _) => Err(missing("ErrorCode")),
+                (Some(_), None) => Err(missing("ErrorMessage")),
+            },
+        }
+    }
+}
+
+/// Sends the query to `endpoint` (usually `DEFAULT_ENDPOINT`) and fetches its
+/// result, using Flex web service API `version` (usually
+/// `DEFAULT_FLEX_API_VERSION`). Without `dates`, the statement covers the
+/// period saved in the query. Fetching is retried on errors `retry` allows.
 pub async fn run_flex_query(
+    endpoint: &Url,
+    version: i32,
     token: &str,
     query_id: &str,
-) -> Result<FlexQuerySuccess, Box<dyn Error>> {
-    let response = run_flex_query2(token, query_id).await?;
+    dates: Option<ReportDates>,
+    retry: &FlexRetry,
+) -> Result<FlexQuerySuccess, WorthyError> {
+    let response = run_flex_query2(endpoint, version, token, query_id, dates).await?;
     trace!("Response: {:?}", response);
-    // TODO: Error response: code=1004 message=Statement is incomplete at this time. Please try again shortly.
-    if response.status != Status::Success {
-        error!(
-            "Error response: code={} message={}",
-            response.error_code.as_ref().unwrap(),
-            response.error_message.as_ref().unwrap(),
-        );
-        return Err(Box::new(FlexError {
-            code: response.error_code.unwrap(),
-            message: response.error_message.unwrap(),
-        }));
-    }
-    // TODO: error if: if response.error_code.is_some() {
-    // TODO: error if: }
-    // "<FlexStatementResponse timestamp=\'13 March, 2021 02:07 PM EST\'>
-    // <Status>Fail</Status>
-    // <ErrorCode>1014</ErrorCode>
-    // <ErrorMessage>Query is invalid.</ErrorMessage>
-    // </FlexStatementResponse>\n"
-    // TODO: raise FlexError otherwise
-    // FlexStatementResponse { status: Fail, timestamp: "08 September, 2021 06:17 PM EDT", reference_code: None, url: None, error_code: Some(1003), error_message: Some("Statement is not available.") }
-    let url = response.url.unwrap();
-    let reference_code = response.reference_code.unwrap();
+    let StatementLocation {
+        url,
+        reference_code,
+    } = response.location().map_err(|error| {
+        error!("Error response: {:?}", error);
+        WorthyError::from(error)
+    })?;
 
     let mut retries = 0;
     'attempt: loop {
-        let r = fetch_flex_query_result(token, &reference_code, &url).await?;
+        let r = fetch_flex_query_result(version, token, &reference_code, &url).await?;
 
         match r {
             FlexQueryResponse::Error(error) => {
-                if !error.is_retriable() {
-                    error!("unretriable error");
+                if !is_retriable(&error, retry) {
+                    error!("unretriable error: {}", error);
                     return Err(error.into());
                 }
                 if retries >= 5 {
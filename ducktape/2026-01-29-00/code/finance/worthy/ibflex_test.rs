@@ -1,15 +1,21 @@
+use chrono::NaiveDate;
 use ibflex::{
+    AccountInformation,
     AssetCategory::Stock,
-    FlexQueryResponse, FlexQuerySuccess, FlexStatement, FlexStatementResponse, FlexStatements,
+    CashLevelOfDetail, CashReport, CashReportCurrency, FlexError, FlexQueryResponse,
+    FlexQuerySuccess, FlexRetry, FlexStatement, FlexStatementResponse, FlexStatements,
     LevelOfDetail::Summary,
     OpenPosition, OpenPositions,
-    Period::LastBusinessDay,
-    Side::Long,
+    Period::{self, LastBusinessDay},
+    ReportDates, SendRequestError,
+    Side::{Long, Short},
+    StatementLocation,
     Status::{Fail, Success},
-    parse_flex_statement_response,
+    flex_request_url, is_retriable, parse_flex_statement_response, send_request_url,
 };
 use rust_decimal::Decimal;
 use url::Url;
+use worthy_error::WorthyError;
 
 #[test]
 fn flex_statement_response_success() {
@@ -51,28 +57,127 @@ fn flex_statement_response_error_invalid() {
     );
 }
 
+#[test]
+fn flex_statement_response_location() {
+    let xml = "<FlexStatementResponse timestamp='16 February, 2021 04:50 PM EST'>
+<Status>Success</Status>
+<ReferenceCode>4672968268</ReferenceCode>
+<Url>https://example.com/GetStatement</Url>
+</FlexStatementResponse>";
+    assert_eq!(
+        parse_flex_statement_response(xml)
+            .unwrap()
+            .location()
+            .unwrap(),
+        StatementLocation {
+            url: Url::parse("https://example.com/GetStatement").unwrap(),
+            reference_code: "4672968268".to_string(),
+        }
+    );
+}
+
+#[test]
+fn flex_statement_response_success_without_url() {
+    let xml = "<FlexStatementResponse timestamp='16 February, 2021 04:50 PM EST'>
+<Status>Success</Status>
+<ReferenceCode>4672968268</ReferenceCode>
+</FlexStatementResponse>";
+    let error = parse_flex_statement_response(xml)
+        .unwrap()
+        .location()
+        .unwrap_err();
+    assert!(
+        matches!(&error, SendRequestError::UnhandledResponse(message) if message.contains("no Url")),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn flex_statement_response_query_invalid() {
+    let xml = "<FlexStatementResponse timestamp='13 March, 2021 02:07 PM EST'>
+<Status>Fail</Status>
+<ErrorCode>1014</ErrorCode>
+<ErrorMessage>Query is invalid.</ErrorMessage>
+</FlexStatementResponse>";
+    let error = parse_flex_statement_response(xml)
+        .unwrap()
+        .location()
+        .unwrap_err();
+    let SendRequestError::Flex(error) = error else {
+        panic!("{:?}", error);
+    };
+    assert_eq!(error, FlexError::new(1014, "Query is invalid."));
+    assert!(!is_retriable(&error, &FlexRetry::default()));
+    assert!(
+        error.to_string().contains("check the query ID"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn flex_error_retriable_by_default() {
+    let retry = FlexRetry::default();
+    let incomplete = FlexError::new(
+        1004,
+        "Statement is incomplete at this time. Please try again shortly.",
+    );
+    assert!(is_retriable(&incomplete, &retry));
+    // Whatever language the message is in.
+    assert!(is_retriable(
+        &FlexError::new(1019, "Generování výpisu probíhá."),
+        &retry
+    ));
+    assert!(!is_retriable(
+        &FlexError::new(1003, "Statement is not available."),
+        &retry
+    ));
+    assert!(!is_retriable(
+        &FlexError::new(1020, "Invalid request or unable to validate request."),
+        &retry
+    ));
+}
+
+#[test]
+fn flex_error_retriable_configured() {
+    let retry = FlexRetry {
+        retriable_codes: [1003].into_iter().collect(),
+        require_try_again_message: false,
+    };
+    assert!(is_retriable(
+        &FlexError::new(1003, "Statement is not available."),
+        &retry
+    ));
+    assert!(!is_retriable(
+        &FlexError::new(
+            1019,
+            "Statement generation in progress. Please try again shortly."
+        ),
+        &retry
+    ));
+
+    let retry = FlexRetry {
+        require_try_again_message: true,
+        ..FlexRetry::default()
+    };
+    assert!(is_retriable(
+        &FlexError::new(
+            1019,
+            "Statement generation in progress. Please try again shortly."
+        ),
+        &retry
+    ));
+    assert!(!is_retriable(
+        &FlexError::new(1019, "Generování výpisu probíhá."),
+        &retry
+    ));
+}
+
 /// Test with highly pruned actual response.
 #[test]
 fn flex_query_response_valid() {
-    let xml = r#"<FlexQueryResponse queryName="TestFlexQuery" type="AF">
-<FlexStatements count="1">
-<FlexStatement accountId="U99999" fromDate="20210215" toDate="20210215" period="LastBusinessDay" whenGenerated="20210216;175211">
-<AccountInformation accountId="U99999" currency="CHF" acctAlias="" name="John Doe" accountType="Individual" customerType="Individual" accountCapabilities="Cash" tradingPermissions="Stocks,Warrants,Forex" masterName="" />
-<CashReport>
-<CashReportCurrency accountId="U99999" acctAlias="" currency="BASE_SUMMARY" levelOfDetail="BaseCurrency" fromDate="20210215" toDate="20210215" />
-<CashReportCurrency accountId="U99999" acctAlias="" currency="CHF" levelOfDetail="Currency" fromDate="20210215" toDate="20210215" />
-<CashReportCurrency accountId="U99999" acctAlias="" currency="USD" levelOfDetail="Currency" fromDate="20210215" toDate="20210215" />
-</CashReport>
-<StmtFunds>
-</StmtFunds>
-<OpenPositions>
-<OpenPosition accountId="U99999" acctAlias="" currency="USD" fxRateToBase="0.8903" assetCategory="STK" symbol="ABCD" description="Abcd Stock" conid="11111" securityID="US12345" securityIDType="ISIN" cusip="AA111" isin="US12345" listingExchange="NASDAQ" issuer="" multiplier="1" strike="" expiry="" putCall="" principalAdjustFactor="" reportDate="20210215" position="1111" markPrice="11.11" positionValue="123" openPrice="1.1" costBasisPrice="11.1" costBasisMoney="9999" percentOfNAV="80.5" fifoPnlUnrealized="111" side="Long" levelOfDetail="SUMMARY" />
-<OpenPosition accountId="U99999" acctAlias="" currency="USD" fxRateToBase="0.8903" assetCategory="STK" symbol="EFGH" description="Efgh Stock" conid="22222" securityID="US12346" securityIDType="ISIN" cusip="BB222" isin="US12346" listingExchange="ARCA" issuer="" multiplier="1" strike="" expiry="" putCall="" principalAdjustFactor="" reportDate="20210215" position="1112" markPrice="22.22" positionValue="456" openPrice="1.2" costBasisPrice="11.1" costBasisMoney="1111" percentOfNAV="19.5" fifoPnlUnrealized="222" side="Long" levelOfDetail="SUMMARY" />
-</OpenPositions><NetStockPositionSummary>
-</NetStockPositionSummary>
-</FlexStatement>
-</FlexStatements>
-</FlexQueryResponse>"#;
+    let xml = include_str!("testdata/flex_query_response.xml");
     assert_eq!(
         ibflex::parse_flex_query_response(xml).unwrap(),
         FlexQueryResponse::Success(FlexQuerySuccess {
@@ -80,6 +185,28 @@ fn flex_query_response_valid() {
             flex_statements: FlexStatements {
                 count: 1,
                 flex_statements: vec![FlexStatement {
+                    account_information: Some(AccountInformation {
+                        account_id: "U99999".to_string(),
+                        currency: "CHF".to_string(),
+                    }),
+                    cash_report: Some(CashReport {
+                        cash_report_currency: Some(
+                            [
+                                ("BASE_SUMMARY", CashLevelOfDetail::BaseCurrency),
+                                ("CHF", CashLevelOfDetail::Currency),
+                                ("USD", CashLevelOfDetail::Currency),
+                            ]
+                            .into_iter()
+                            .map(|(currency, level_of_detail)| CashReportCurrency {
+                                account_id: "U99999".to_string(),
+                                acct_alias: "".to_string(),
+                                currency: currency.to_string(),
+                                level_of_detail,
+                                ending_cash: None,
+                            })
+                            .collect()
+                        ),
+                    }),
                     open_positions: OpenPositions {
                         open_position: Some(vec![
                             OpenPosition {
@@ -89,15 +216,17 @@ fn flex_query_response_valid() {
                                 asset_category: Stock,
                                 symbol: "ABCD".to_string(),
                                 description: "Abcd Stock".to_string(),
-                                multiplier: Decimal::new(1, 0),
+                                multiplier: Some(Decimal::new(1, 0)),
                                 fx_rate_to_base: Decimal::new(8903, 4),
                                 mark_price: Decimal::new(1111, 2),
+                                cost_basis_money: Some(Decimal::new(9999, 0)),
+                                fifo_pnl_unrealized: Some(Decimal::new(111, 0)),
                                 position: Decimal::new(1111, 0),
                                 side: Long,
                                 level_of_detail: Summary,
                                 issuer: "".to_string(),
-                                expiry: "".to_string(),
-                                put_call: "".to_string(),
+                                expiry: None,
+                                put_call: None,
                                 isin: "US12345".to_string(),
                             },
                             OpenPosition {
@@ -107,15 +236,17 @@ fn flex_query_response_valid() {
                                 asset_category: Stock,
                                 symbol: "EFGH".to_string(),
                                 description: "Efgh Stock".to_string(),
-                                multiplier: Decimal::new(1, 0),
+                                multiplier: Some(Decimal::new(1, 0)),
                                 fx_rate_to_base: Decimal::new(8903, 4),
                                 mark_price: Decimal::new(2222, 2),
+                                cost_basis_money: Some(Decimal::new(1111, 0)),
+                                fifo_pnl_unrealized: Some(Decimal::new(222, 0)),
                                 position: Decimal::new(1112, 0),
                                 side: Long,
                                 level_of_detail: Summary,
                                 issuer: "".to_string(),
-                                expiry: "".to_string(),
-                                put_call: "".to_string(),
+                                expiry: None,
+                                put_call: None,
                                 isin: "US12346".to_string(),
                             }
                         ])
@@ -130,3 +261,188 @@ fn flex_query_response_valid() {
         })
     );
 }
+
+/// Test with a response of a Flex query covering two linked accounts.
+#[test]
+fn flex_query_response_two_statements() {
+    let xml = include_str!("testdata/flex_query_response_two_accounts.xml");
+    let FlexQueryResponse::Success(success) = ibflex::parse_flex_query_response(xml).unwrap()
+    else {
+        panic!("expected a statement");
+    };
+    assert_eq!(success.flex_statements.count, 2);
+    let statements: Vec<_> = success
+        .flex_statements
+        .flex_statements
+        .iter()
+        .map(|statement| {
+            (
+                statement.account_id.as_str(),
+                statement.open_positions.open_position.as_ref().unwrap()[0]
+                    .symbol
+                    .as_str(),
+                statement
+                    .cash_report
+                    .as_ref()
+                    .unwrap()
+                    .cash_report_currency
+                    .as_ref()
+                    .unwrap()
+                    .len(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        statements,
+        vec![("U99999", "ABCD", 2), ("U88888", "EFGH", 2)]
+    );
+}
+
+#[test]
+fn flex_request_url_custom_endpoint() {
+    let endpoint = Url::parse("https://example.com/Custom/SendRequest").unwrap();
+    assert_eq!(
+        flex_request_url(&endpoint, "TOKEN", "123", 4).as_str(),
+        "https://example.com/Custom/SendRequest?t=TOKEN&q=123&v=4"
+    );
+}
+
+#[test]
+fn send_request_url_with_report_dates() {
+    let endpoint = Url::parse("https://example.com/SendRequest").unwrap();
+    assert_eq!(
+        send_request_url(&endpoint, "TOKEN", "123", 3, None).as_str(),
+        "https://example.com/SendRequest?t=TOKEN&q=123&v=3"
+    );
+    let dates = ReportDates {
+        from: NaiveDate::from_ymd_opt(2021, 2, 1).unwrap(),
+        to: NaiveDate::from_ymd_opt(2021, 2, 15).unwrap(),
+    };
+    assert_eq!(
+        send_request_url(&endpoint, "TOKEN", "123", 3, Some(dates)).as_str(),
+        "https://example.com/SendRequest?t=TOKEN&q=123&v=3&fd=20210201&td=20210215"
+    );
+}
+
+/// The statement of the recorded response, with `from` replaced by `to`.
+fn patched_statement(from: &str, to: &str) -> FlexStatement {
+    let xml = include_str!("testdata/flex_query_response.xml").replace(from, to);
+    match ibflex::parse_flex_query_response(&xml).unwrap() {
+        FlexQueryResponse::Success(success) => success
+            .flex_statements
+            .flex_statements
+            .into_iter()
+            .next()
+            .unwrap(),
+        response => panic!("unexpected response {:?}", response),
+    }
+}
+
+fn statement_period(period: &str) -> Period {
+    patched_statement(
+        "period=\"LastBusinessDay\"",
+        &format!("period=\"{}\"", period),
+    )
+    .period
+}
+
+#[test]
+fn period_variants() {
+    assert_eq!(statement_period("MonthToDate"), Period::MonthToDate);
+    assert_eq!(statement_period("YearToDate"), Period::YearToDate);
+    assert_eq!(
+        statement_period("Last365CalendarDays"),
+        Period::Last365CalendarDays
+    );
+    assert_eq!(statement_period("CustomDateRange"), Period::Other);
+}
+
+fn first_position_multiplier(multiplier: &str) -> Option<Decimal> {
+    let statement = patched_statement(
+        "multiplier=\"1\"",
+        &format!("multiplier=\"{}\"", multiplier),
+    );
+    statement.open_positions.open_position.unwrap()[0].multiplier
+}
+
+#[test]
+fn empty_multiplier() {
+    assert_eq!(first_position_multiplier(""), None);
+}
+
+#[test]
+fn decimal_multiplier() {
+    assert_eq!(first_position_multiplier("1.0"), Some(Decimal::ONE));
+}
+
+#[test]
+fn put_call() {
+    let statement = patched_statement("putCall=\"\"", "putCall=\"C\"");
+    let position = &statement.open_positions.open_position.unwrap()[0];
+    assert_eq!(position.put_call, Some("C".to_string()));
+    assert_eq!(position.expiry, None);
+}
+
+#[test]
+fn short_position() {
+    let statement = patched_statement(
+        "fifoPnlUnrealized=\"111\" side=\"Long\"",
+        "fifoPnlUnrealized=\"111\" side=\"Short\"",
+    );
+    let positions = statement.open_positions.open_position.unwrap();
+    assert_eq!(positions[0].side, Short);
+    assert_eq!(positions[1].side, Long);
+}
+
+#[test]
+fn account_information_currency() {
+    let statement = patched_statement("currency=\"CHF\" acctAlias", "currency=\"EUR\" acctAlias");
+    assert_eq!(statement.account_information.unwrap().currency, "EUR");
+
+    let statement = patched_statement("<AccountInformation ", "<Unrelated ");
+    assert_eq!(statement.account_information, None);
+}
+
+#[test]
+fn cash_report_ending_cash() {
+    let statement = patched_statement(
+        "currency=\"USD\" levelOfDetail=\"Currency\"",
+        "currency=\"USD\" levelOfDetail=\"Currency\" endingCash=\"998.88\"",
+    );
+    let rows = statement.cash_report.unwrap().cash_report_currency.unwrap();
+    assert_eq!(rows[2].ending_cash, Some(Decimal::new(99888, 2)));
+    assert_eq!(rows[1].ending_cash, None);
+
+    let statement = patched_statement("CashReport>", "Unrelated>");
+    assert_eq!(statement.cash_report, None);
+}
+
+#[test]
+fn flex_query_response_success_with_empty_error_message() {
+    let xml = include_str!("testdata/flex_query_response.xml").replace(
+        "<FlexStatements count=\"1\">",
+        "<ErrorMessage></ErrorMessage>\n<FlexStatements count=\"1\">",
+    );
+    assert_eq!(
+        ibflex::parse_flex_query_response(&xml).unwrap(),
+        ibflex::parse_flex_query_response(include_str!("testdata/flex_query_response.xml"))
+            .unwrap()
+    );
+}
+
+#[test]
+fn flex_query_response_neither_error_nor_success() {
+    let xml = r#"<FlexQueryResponse type="AF">
+<ErrorCode>1020</ErrorCode>
+</FlexQueryResponse>"#;
+    let error = ibflex::parse_flex_query_response(xml).unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            WorthyError::Parse(message)
+                if message.ends_with("set: ErrorCode, type; unset: ErrorMessage, FlexStatements")
+        ),
+        "{:?}",
+        error
+    );
+}
@@ -1,8 +1,13 @@
 //! Module parsing JSON output of worthy2.
 
 use chrono::prelude::*;
+use log::warn;
 use rust_decimal::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, Write};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "PascalCase")]
@@ -11,14 +16,102 @@ pub struct Snapshot {
     pub source_snapshot: Vec<SourceSnapshot>,
     pub converter_snapshots: Vec<ConverterSnapshot>,
     pub total: Asset,
+    /// Amounts left out of `total` because they couldn't be converted to the
+    /// common currency.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unpriced: Vec<Asset>,
+    /// Percentage of `total` in each type of denomination, e.g. "stock".
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub allocation: BTreeMap<String, Decimal>,
+    /// Free-form note given when taking the snapshot, e.g. "year-end".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Outcome of the cFIREsim simulation, if one was run.
+    #[serde(default, rename = "CfireSim", skip_serializing_if = "Option::is_none")]
+    pub cfiresim: Option<CfireSimResult>,
+    /// How each monthly target comes out under each yearly yield, if taken
+    /// with `--emit-modelling`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modelling: Option<Vec<ModellingResult>>,
 }
 
+/// A monthly target modelled under a yearly yield.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ModellingResult {
+    /// 0.03 is 3% a year.
+    pub yearly_yield: Decimal,
+    /// Components of the monthly target, added up.
+    pub monthly_target: Vec<Asset>,
+    pub state: ModellingState,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "Type")]
+pub enum ModellingState {
+    //  "Type": "reached",
+    //  "OverreachPercentage": 120.5,
+    #[serde(rename = "reached")]
+    Reached {
+        #[serde(rename = "OverreachPercentage")]
+        overreach_percentage: Decimal,
+    },
+    #[serde(rename = "not_reached")]
+    NotReached {
+        #[serde(rename = "ProjectedUntilSaved")]
+        projected_until_saved: DateTime<Utc>,
+        #[serde(rename = "LastsUntil")]
+        lasts_until: DateTime<Utc>,
+    },
+    /// Savings never grow to the target under this yield.
+    #[serde(rename = "never_reached")]
+    NeverReached {
+        #[serde(rename = "LastsUntil")]
+        lasts_until: DateTime<Utc>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct CfireSimResult {
+    /// Percentage of simulated cycles that didn't run out of money.
+    pub success_rate: Decimal,
+    pub cycles_total: u32,
+    pub cycles_failed: u32,
+    /// Identifies the simulation at https://www.cfiresim.com/<uuid>.
+    pub tracking_uuid: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
 pub enum SourceType {
-    #[serde(rename = "hardcoded")]
     Hardcoded,
-    #[serde(rename = "ibflex")]
     IBFlex,
+    Csv,
+    /// A source registered under this type by something other than worthy.
+    Other(String),
+}
+
+impl From<String> for SourceType {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "hardcoded" => SourceType::Hardcoded,
+            "ibflex" => SourceType::IBFlex,
+            "csv" => SourceType::Csv,
+            _ => SourceType::Other(name),
+        }
+    }
+}
+
+impl From<SourceType> for String {
+    fn from(source_type: SourceType) -> Self {
+        match source_type {
+            SourceType::Hardcoded => "hardcoded".to_string(),
+            SourceType::IBFlex => "ibflex".to_string(),
+            SourceType::Csv => "csv".to_string(),
+            SourceType::Other(name) => name,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -29,6 +122,22 @@ pub struct SourceSnapshot {
     #[serde(rename = "Type")]
     pub source_type: SourceType,
     pub snapshot: Vec<Asset>,
+    /// Base currency of the source's account, if it reports one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_currency: Option<String>,
+    /// `snapshot` split by account, for sources with several accounts, such
+    /// as an IB Flex query covering sub-accounts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<AccountSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct AccountSnapshot {
+    pub account_id: String,
+    /// The account's alias, or its ID if it has none.
+    pub name: String,
+    pub snapshot: Vec<Asset>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -50,6 +159,13 @@ pub enum Denomination {
     Stock {
         #[serde(rename = "Symbol")]
         symbol: String,
+        #[serde(rename = "Isin", default, skip_serializing_if = "Option::is_none")]
+        isin: Option<String>,
+    },
+    #[serde(rename = "derivative")]
+    Derivative {
+        #[serde(rename = "Symbol")]
+        symbol: String,
     },
 }
 
@@ -59,16 +175,59 @@ pub struct Asset {
     pub denomination: Denomination,
     #[serde(rename = "Amount")]
     pub amount: Decimal,
+    #[serde(rename = "CostBasis", default, skip_serializing_if = "Option::is_none")]
+    pub cost_basis: Option<CostBasis>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CostBasis {
+    pub currency: String,
+    pub money: Decimal,
+    pub unrealized_pnl: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(from = "String", into = "String")]
 pub enum ConverterType {
-    #[serde(rename = "currencylayer")]
     CurrencyLayer,
-    #[serde(rename = "alphavantage")]
     AlphaVantage,
-    #[serde(rename = "fixer")]
     Fixer,
+    YahooFinance,
+    /// Rates of baskets, derived from the other converters' rates.
+    Basket,
+    /// Prices reported by a source, e.g. unit prices entered by hand.
+    Source,
+    /// A converter registered under this type by something other than worthy.
+    Other(String),
+}
+
+impl From<String> for ConverterType {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "currencylayer" => ConverterType::CurrencyLayer,
+            "alphavantage" => ConverterType::AlphaVantage,
+            "fixer" => ConverterType::Fixer,
+            "yahoofinance" => ConverterType::YahooFinance,
+            "basket" => ConverterType::Basket,
+            "source" => ConverterType::Source,
+            _ => ConverterType::Other(name),
+        }
+    }
+}
+
+impl From<ConverterType> for String {
+    fn from(converter_type: ConverterType) -> Self {
+        match converter_type {
+            ConverterType::CurrencyLayer => "currencylayer".to_string(),
+            ConverterType::AlphaVantage => "alphavantage".to_string(),
+            ConverterType::Fixer => "fixer".to_string(),
+            ConverterType::YahooFinance => "yahoofinance".to_string(),
+            ConverterType::Basket => "basket".to_string(),
+            ConverterType::Source => "source".to_string(),
+            ConverterType::Other(name) => name,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -86,4 +245,114 @@ pub struct Conversion {
     pub source: Denomination,
     pub target: Denomination,
     pub target_per_source: Decimal,
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub spread_bps: Decimal,
+    /// When the rate was observed. Missing in snapshots from before it was
+    /// recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observed_at: Option<DateTime<Utc>>,
+}
+
+/// Reads one JSON snapshot file.
+pub fn read_snapshot(path: &str) -> Result<Snapshot, Box<dyn Error>> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+/// Writes `snapshot` as one line of a JSON Lines history.
+pub fn write_jsonl(writer: &mut impl Write, snapshot: &Snapshot) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer(&mut *writer, snapshot)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Snapshots in a JSON Lines history, one per line, read one at a time as the
+/// iterator is advanced. Blank lines are ignored.
+pub fn read_jsonl(reader: impl BufRead) -> impl Iterator<Item = Result<Snapshot, Box<dyn Error>>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map_err(Into::into)),
+        Err(error) => Some(Err(error.into())),
+    })
+}
+
+/// Copies a JSON Lines history from `reader` to `writer`, with the line of the
+/// snapshot taken at `replaced` replaced by `snapshot`, or `snapshot` appended
+/// if there's none. Other lines are copied as they are, even if they don't
+/// parse.
+pub fn replace_jsonl(
+    reader: impl BufRead,
+    writer: &mut impl Write,
+    replaced: DateTime<FixedOffset>,
+    snapshot: &Snapshot,
+) -> Result<(), Box<dyn Error>> {
+    let mut found = false;
+    for line in reader.lines() {
+        let line = line?;
+        let taken = serde_json::from_str::<Snapshot>(&line).map(|old| old.timestamp);
+        if !found && taken.is_ok_and(|taken| taken == replaced) {
+            write_jsonl(writer, snapshot)?;
+            found = true;
+        } else {
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    if !found {
+        write_jsonl(writer, snapshot)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ReadSnapshots {
+    /// Successfully parsed snapshots, keyed by path, in the order of the input paths.
+    pub snapshots: Vec<(String, Snapshot)>,
+    /// Number of files that could not be read or parsed.
+    pub skipped: usize,
+}
+
+/// Reads all given snapshot files, skipping (with a warning) those that cannot
+/// be parsed, e.g. half-written files from interrupted runs.
+pub fn read_snapshots(paths: &[String]) -> ReadSnapshots {
+    let mut snapshots = Vec::new();
+    let mut skipped = 0;
+    for path in paths {
+        match read_snapshot(path) {
+            Ok(snapshot) => snapshots.push((path.clone(), snapshot)),
+            Err(error) => {
+                warn!("skipping {}: {}", path, error);
+                skipped += 1;
+            }
+        }
+    }
+    ReadSnapshots { snapshots, skipped }
+}
+
+/// Sorts snapshots by their timestamp, oldest first. File names don't
+/// necessarily sort the same way, e.g. across time zone offsets.
+pub fn sort_by_timestamp(snapshots: &mut [(String, Snapshot)]) {
+    snapshots.sort_by_key(|(_, snapshot)| snapshot.timestamp);
+}
+
+/// Fails if `since` is after `until`.
+pub fn check_date_range(
+    since: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+) -> Result<(), Box<dyn Error>> {
+    if let (Some(since), Some(until)) = (since, until)
+        && since > until
+    {
+        return Err(format!("--since {} is after --until {}", since, until).into());
+    }
+    Ok(())
+}
+
+/// Whether `snapshot` was taken between `since` and `until`, both inclusive.
+pub fn taken_between(
+    snapshot: &Snapshot,
+    since: Option<DateTime<FixedOffset>>,
+    until: Option<DateTime<FixedOffset>>,
+) -> bool {
+    since.is_none_or(|since| snapshot.timestamp >= since)
+        && until.is_none_or(|until| snapshot.timestamp <= until)
 }
@@ -1,7 +1,9 @@
 use chrono::prelude::*;
 use json_output::{
-    Asset, Conversion, ConverterSnapshot, ConverterType::*, Denomination, Denomination::*,
-    Snapshot, SourceSnapshot, SourceType, SourceType::*,
+    Asset, Conversion, ConverterSnapshot, ConverterType, ConverterType::*, Denomination,
+    Denomination::*, ModellingResult, ModellingState, ReadSnapshots, Snapshot, SourceSnapshot,
+    SourceType, SourceType::*, check_date_range, read_jsonl, read_snapshots, replace_jsonl,
+    sort_by_timestamp, taken_between, write_jsonl,
 };
 use rust_decimal_macros::*;
 
@@ -16,6 +18,7 @@ fn parse_asset() {
             symbol: "A".to_string(),
         },
         amount: dec!(1.23),
+        cost_basis: None,
     };
 
     assert_eq!(expected, parsed);
@@ -58,7 +61,8 @@ fn parse_snapshot() {
                 {
                   "Source": {"Type": "currency", "Symbol": "EUR"},
                   "Target": {"Type": "currency", "Symbol": "USD"},
-                  "TargetPerSource": 2.2
+                  "TargetPerSource": 2.2,
+                  "ObservedAt": "2001-01-02T11:00:00Z"
                 }
               ]
             },
@@ -101,7 +105,10 @@ fn parse_snapshot() {
                         symbol: "A".to_string(),
                     },
                     amount: dec!(1.23),
+                    cost_basis: None,
                 }],
+                base_currency: None,
+                accounts: Vec::new(),
             },
             SourceSnapshot {
                 id: "b".to_string(),
@@ -113,20 +120,25 @@ fn parse_snapshot() {
                             symbol: "AAA".to_string(),
                         },
                         amount: dec!(1),
+                        cost_basis: None,
                     },
                     Asset {
                         denomination: Cryptocurrency {
                             symbol: "BBB".to_string(),
                         },
                         amount: dec!(2),
+                        cost_basis: None,
                     },
                     Asset {
                         denomination: Cryptocurrency {
                             symbol: "CCC".to_string(),
                         },
                         amount: dec!(3),
+                        cost_basis: None,
                     },
                 ],
+                base_currency: None,
+                accounts: Vec::new(),
             },
         ],
         converter_snapshots: vec![
@@ -142,6 +154,8 @@ fn parse_snapshot() {
                             symbol: "USD".to_string(),
                         },
                         target_per_source: dec!(1.1),
+                        spread_bps: dec!(0),
+                        observed_at: None,
                     },
                     Conversion {
                         source: Currency {
@@ -151,6 +165,8 @@ fn parse_snapshot() {
                             symbol: "USD".to_string(),
                         },
                         target_per_source: dec!(2.2),
+                        spread_bps: dec!(0),
+                        observed_at: Some(Utc.with_ymd_and_hms(2001, 1, 2, 11, 0, 0).unwrap()),
                     },
                 ],
             },
@@ -163,8 +179,11 @@ fn parse_snapshot() {
                     },
                     target: Stock {
                         symbol: "GOOG".to_string(),
+                        isin: None,
                     },
                     target_per_source: dec!(0.0004),
+                    spread_bps: dec!(0),
+                    observed_at: None,
                 }],
             },
         ],
@@ -173,8 +192,341 @@ fn parse_snapshot() {
                 symbol: "CHF".to_string(),
             },
             amount: dec!(1234),
+            cost_basis: None,
         },
+        unpriced: vec![],
+        allocation: Default::default(),
+        label: None,
+        cfiresim: None,
+        modelling: None,
     };
 
     assert_eq!(expected, parsed);
 }
+
+#[test]
+fn read_snapshots_skips_garbage() {
+    let dir = std::env::temp_dir().join(format!("json_output_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let valid = dir.join("2001-01-02T12:34:56+01:00.json");
+    std::fs::write(
+        &valid,
+        r#"{
+          "Timestamp": "2001-01-02T12:34:56+01:00",
+          "SourceSnapshot": [],
+          "ConverterSnapshots": [],
+          "Total": {"Type": "currency", "Symbol": "CHF", "Amount": 1234}
+        }"#,
+    )
+    .unwrap();
+    let garbage = dir.join("2001-01-03T12:34:56+01:00.json");
+    std::fs::write(&garbage, r#"{"Timestamp": "2001-01-03T1"#).unwrap();
+
+    let paths = vec![
+        valid.to_str().unwrap().to_string(),
+        garbage.to_str().unwrap().to_string(),
+    ];
+    let ReadSnapshots { snapshots, skipped } = read_snapshots(&paths);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(skipped, 1);
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].0, paths[0]);
+    assert_eq!(snapshots[0].1.total.amount, dec!(1234));
+}
+
+fn snapshot_at(timestamp: &str) -> (String, Snapshot) {
+    (
+        format!("{}.json", timestamp),
+        Snapshot {
+            timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+            source_snapshot: vec![],
+            converter_snapshots: vec![],
+            total: Asset {
+                denomination: Currency {
+                    symbol: "CHF".to_string(),
+                },
+                amount: dec!(1),
+                cost_basis: None,
+            },
+            unpriced: vec![],
+            allocation: Default::default(),
+            label: None,
+            cfiresim: None,
+            modelling: None,
+        },
+    )
+}
+
+fn snapshots_at(timestamps: &[&str]) -> Vec<(String, Snapshot)> {
+    timestamps.iter().map(|t| snapshot_at(t)).collect()
+}
+
+#[test]
+fn taken_between_inclusive() {
+    let snapshots = snapshots_at(&[
+        "2021-01-01T00:00:00Z",
+        "2021-02-01T00:00:00Z",
+        "2021-03-01T00:00:00Z",
+        "2021-04-01T00:00:00Z",
+    ]);
+    let since = Some(DateTime::parse_from_rfc3339("2021-02-01T00:00:00Z").unwrap());
+    let until = Some(DateTime::parse_from_rfc3339("2021-03-01T00:00:00Z").unwrap());
+    let paths: Vec<&str> = snapshots
+        .iter()
+        .filter(|(_, snapshot)| taken_between(snapshot, since, until))
+        .map(|(path, _)| path.as_str())
+        .collect();
+    assert_eq!(
+        paths,
+        vec!["2021-02-01T00:00:00Z.json", "2021-03-01T00:00:00Z.json"]
+    );
+}
+
+#[test]
+fn taken_between_open_ended() {
+    let (_, snapshot) = snapshot_at("2021-01-01T00:00:00Z");
+    let at = |timestamp| Some(DateTime::parse_from_rfc3339(timestamp).unwrap());
+    assert!(taken_between(&snapshot, None, None));
+    assert!(taken_between(&snapshot, None, at("2021-01-01T00:00:00Z")));
+    assert!(!taken_between(&snapshot, at("2021-02-01T00:00:00Z"), None));
+    assert!(!taken_between(&snapshot, None, at("2020-12-31T00:00:00Z")));
+}
+
+#[test]
+fn check_date_range_since_after_until() {
+    let at = |timestamp| Some(DateTime::parse_from_rfc3339(timestamp).unwrap());
+    let error =
+        check_date_range(at("2021-03-01T00:00:00Z"), at("2021-02-01T00:00:00Z")).unwrap_err();
+    assert!(error.to_string().contains("is after --until"), "{}", error);
+    assert!(check_date_range(at("2021-02-01T00:00:00Z"), at("2021-02-01T00:00:00Z")).is_ok());
+    assert!(check_date_range(None, at("2021-02-01T00:00:00Z")).is_ok());
+}
+
+#[test]
+fn label_round_trip() {
+    let (_, mut snapshot) = snapshot_at("2021-01-01T00:00:00Z");
+    snapshot.label = Some("before buying a house".to_string());
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert!(
+        json.contains(r#""Label":"before buying a house""#),
+        "{}",
+        json
+    );
+    let parsed: Snapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, snapshot);
+}
+
+#[test]
+fn no_label_not_written() {
+    let (_, snapshot) = snapshot_at("2021-01-01T00:00:00Z");
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert!(!json.contains("Label"), "{}", json);
+}
+
+#[test]
+fn modelling_round_trip() {
+    let (_, mut snapshot) = snapshot_at("2021-01-01T00:00:00Z");
+    let chf = |amount| Asset {
+        denomination: Currency {
+            symbol: "CHF".to_string(),
+        },
+        amount,
+        cost_basis: None,
+    };
+    snapshot.modelling = Some(vec![
+        ModellingResult {
+            yearly_yield: dec!(0.03),
+            monthly_target: vec![chf(dec!(100))],
+            state: ModellingState::Reached {
+                overreach_percentage: dec!(120.5),
+            },
+        },
+        ModellingResult {
+            yearly_yield: dec!(0.03),
+            monthly_target: vec![chf(dec!(5000))],
+            state: ModellingState::NotReached {
+                projected_until_saved: "2040-01-01T00:00:00Z".parse().unwrap(),
+                lasts_until: "2021-06-01T00:00:00Z".parse().unwrap(),
+            },
+        },
+    ]);
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert!(
+        json.contains(r#""State":{"Type":"reached","OverreachPercentage":"120.5"}"#),
+        "{}",
+        json
+    );
+    assert!(
+        json.contains(r#""LastsUntil":"2021-06-01T00:00:00Z""#),
+        "{}",
+        json
+    );
+    let parsed: Snapshot = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, snapshot);
+}
+
+#[test]
+fn no_modelling_not_written() {
+    let (_, snapshot) = snapshot_at("2021-01-01T00:00:00Z");
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert!(!json.contains("Modelling"), "{}", json);
+}
+
+#[test]
+fn sort_by_timestamp_not_file_name() {
+    // By file name, 12:00Z would come first, though 13:30+02:00 is earlier.
+    let mut snapshots = snapshots_at(&[
+        "2021-01-01T12:00:00Z",
+        "2021-01-01T13:30:00+02:00",
+        "2020-12-31T00:00:00Z",
+    ]);
+    sort_by_timestamp(&mut snapshots);
+    let paths: Vec<&str> = snapshots.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "2020-12-31T00:00:00Z.json",
+            "2021-01-01T13:30:00+02:00.json",
+            "2021-01-01T12:00:00Z.json",
+        ]
+    );
+}
+
+#[test]
+fn registered_types_round_trip() {
+    for (source_type, json) in [
+        (SourceType::IBFlex, r#""ibflex""#),
+        (SourceType::Csv, r#""csv""#),
+        (SourceType::Other("plaid".to_string()), r#""plaid""#),
+    ] {
+        assert_eq!(serde_json::to_string(&source_type).unwrap(), json);
+        assert_eq!(
+            serde_json::from_str::<SourceType>(json).unwrap(),
+            source_type
+        );
+    }
+    for (converter_type, json) in [
+        (Fixer, r#""fixer""#),
+        (ConverterType::Source, r#""source""#),
+        (
+            ConverterType::Other("coingecko".to_string()),
+            r#""coingecko""#,
+        ),
+    ] {
+        assert_eq!(serde_json::to_string(&converter_type).unwrap(), json);
+        assert_eq!(
+            serde_json::from_str::<ConverterType>(json).unwrap(),
+            converter_type
+        );
+    }
+}
+
+#[test]
+fn parse_conversion_spread() {
+    let json = r#"{
+      "Source": {"Type": "currency", "Symbol": "CHF"},
+      "Target": {"Type": "currency", "Symbol": "USD"},
+      "TargetPerSource": 1.1,
+      "SpreadBps": 50
+    }"#;
+    let parsed: Conversion = serde_json::from_str(json).unwrap();
+    assert_eq!(parsed.spread_bps, dec!(50));
+}
+
+#[test]
+fn stock_isin_round_trip() {
+    let stock: Denomination =
+        serde_json::from_str(r#"{"Type": "stock", "Symbol": "ABC", "Isin": "GB0001234567"}"#)
+            .unwrap();
+    assert_eq!(
+        stock,
+        Stock {
+            symbol: "ABC".to_string(),
+            isin: Some("GB0001234567".to_string()),
+        }
+    );
+    assert_eq!(
+        serde_json::to_value(&stock).unwrap(),
+        serde_json::json!({"Type": "stock", "Symbol": "ABC", "Isin": "GB0001234567"})
+    );
+
+    // Older snapshots have no ISINs.
+    let stock: Denomination =
+        serde_json::from_str(r#"{"Type": "stock", "Symbol": "ABC"}"#).unwrap();
+    assert_eq!(
+        stock,
+        Stock {
+            symbol: "ABC".to_string(),
+            isin: None,
+        }
+    );
+}
+
+#[test]
+fn jsonl_round_trip() {
+    let timestamps = [
+        "2021-01-01T00:00:00Z",
+        "2021-02-01T00:00:00+01:00",
+        "2021-03-01T00:00:00Z",
+    ];
+    let mut jsonl = Vec::new();
+    for (_, snapshot) in snapshots_at(&timestamps) {
+        write_jsonl(&mut jsonl, &snapshot).unwrap();
+    }
+    // Trailing blank lines, e.g. from editing the file by hand, are fine.
+    jsonl.extend_from_slice(b"\n");
+    assert_eq!(jsonl.iter().filter(|&&byte| byte == b'\n').count(), 4);
+
+    let read: Vec<Snapshot> = read_jsonl(jsonl.as_slice())
+        .map(|snapshot| snapshot.unwrap())
+        .collect();
+    let expected: Vec<Snapshot> = snapshots_at(&timestamps)
+        .into_iter()
+        .map(|(_, snapshot)| snapshot)
+        .collect();
+    assert_eq!(read, expected);
+
+    let garbage = read_jsonl(&b"{\"Timestamp\": \"2021-01-0\n"[..]).collect::<Vec<_>>();
+    assert_eq!(garbage.len(), 1);
+    assert!(garbage[0].is_err());
+}
+
+#[test]
+fn jsonl_replace() {
+    let timestamps = ["2021-01-01T00:00:00Z", "2021-02-01T00:00:00Z"];
+    let mut jsonl = Vec::new();
+    for (_, snapshot) in snapshots_at(&timestamps) {
+        write_jsonl(&mut jsonl, &snapshot).unwrap();
+    }
+    jsonl.extend_from_slice(b"not a snapshot\n");
+    let (_, retaken) = snapshots_at(&["2021-01-01T12:00:00Z"]).pop().unwrap();
+    let taken = |timestamp: &str| DateTime::parse_from_rfc3339(timestamp).unwrap();
+
+    let mut replaced = Vec::new();
+    replace_jsonl(
+        jsonl.as_slice(),
+        &mut replaced,
+        taken(timestamps[0]),
+        &retaken,
+    )
+    .unwrap();
+    let read: Vec<_> = read_jsonl(replaced.as_slice()).collect();
+    assert_eq!(read.len(), 3);
+    assert_eq!(read[0].as_ref().unwrap(), &retaken);
+    assert_eq!(read[1].as_ref().unwrap().timestamp, taken(timestamps[1]));
+    assert!(read[2].is_err());
+
+    // With no line to replace, it's appended.
+    let mut appended = Vec::new();
+    replace_jsonl(
+        jsonl.as_slice(),
+        &mut appended,
+        taken("2020-01-01T00:00:00Z"),
+        &retaken,
+    )
+    .unwrap();
+    let read: Vec<_> = read_jsonl(appended.as_slice()).collect();
+    assert_eq!(read.len(), 4);
+    assert_eq!(read[3].as_ref().unwrap(), &retaken);
+}
@@ -0,0 +1,57 @@
+//! Lock file keeping overlapping runs (e.g. from cron) from taking snapshots at
+//! the same time.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use worthy_error::WorthyError;
+
+/// Held lock, with the PID of this process written in its file. Released when
+/// dropped, or by the operating system when the process exits, even if it
+/// crashes.
+#[derive(Debug)]
+pub struct Lock {
+    _file: File,
+}
+
+#[derive(Debug)]
+pub enum Acquired {
+    Acquired(Lock),
+    /// Another process holds the lock. Its PID, unless it hasn't written it
+    /// yet.
+    HeldBy(Option<u32>),
+}
+
+fn lock_error(path: &Path, error: std::io::Error) -> WorthyError {
+    WorthyError::Config(format!("cannot lock {}: {}", path.display(), error))
+}
+
+impl Lock {
+    /// Takes the lock at `path`, an advisory lock on the file, so taking it is
+    /// atomic and a file left behind by a run that has exited doesn't hold it.
+    pub fn acquire(path: &Path) -> Result<Acquired, WorthyError> {
+        paths::create_parent_dir(path)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|error| lock_error(path, error))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)
+                    .map_err(|error| lock_error(path, error))?;
+                return Ok(Acquired::HeldBy(holder.trim().parse().ok()));
+            }
+            Err(TryLockError::Error(error)) => return Err(lock_error(path, error)),
+        }
+        file.set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| write!(file, "{}", std::process::id()))
+            .map_err(|error| lock_error(path, error))?;
+        Ok(Acquired::Acquired(Lock { _file: file }))
+    }
+}
@@ -0,0 +1,50 @@
+use lock::{Acquired, Lock};
+use std::path::PathBuf;
+use test_util::TempDir;
+
+#[test]
+fn acquire_and_release() {
+    let dir = TempDir::new("lock_acquire");
+    let path = PathBuf::from(dir.file("snapshot.lock"));
+    let lock = match Lock::acquire(&path).unwrap() {
+        Acquired::Acquired(lock) => lock,
+        Acquired::HeldBy(pid) => panic!("held by {:?}", pid),
+    };
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        std::process::id().to_string()
+    );
+
+    // The lock is held, so a second run has to wait.
+    assert!(matches!(
+        Lock::acquire(&path).unwrap(),
+        Acquired::HeldBy(Some(pid)) if pid == std::process::id()
+    ));
+
+    drop(lock);
+    assert!(matches!(
+        Lock::acquire(&path).unwrap(),
+        Acquired::Acquired(_)
+    ));
+}
+
+#[test]
+fn left_behind_lock_is_taken_over() {
+    // PID no process has, being over the Linux maximum of 2^22.
+    for left_behind in ["4000000000", "garbage"] {
+        let dir = TempDir::new("lock_left_behind");
+        let path = PathBuf::from(dir.write("snapshot.lock", left_behind));
+
+        let lock = Lock::acquire(&path).unwrap();
+        assert!(
+            matches!(lock, Acquired::Acquired(_)),
+            "{}: {:?}",
+            left_behind,
+            lock
+        );
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+}
@@ -1,56 +1,121 @@
 // RUST_LOG=rust_main=trace bazel run :rust_main
+// bazel run :rust_main -- --log-level=trace --log-file=/tmp/worthy.log
 
-use alphavantage_converter::AlphaVantageConverter;
-use asset::Asset;
+use allocation::DenominationType;
+use asset::{Asset, CostBasis, sum_by_denomination};
+use caching_converter::{CacheMode, RateRecordings};
+use call_counts::CallCounts;
 use chrono::prelude::*;
-use config::{Config, ConverterConfig, SourceConfig};
-use converter::Converter;
-use currencylayer_converter::CurrencyLayerConverter;
+use common_currency::CommonCurrencyError;
+use config::{Config, DisplayPrecision, MonthlyTarget};
 use denomination::Denomination;
 use exchange_rate::ExchangeRate;
-use fixer_converter::FixerConverter;
-use flags::Opt;
+use flags::{Format, Opt};
 use futures::prelude::*;
-use glob::glob;
-use ibflex_source::IBFlexSource;
-use log::{info, trace, warn};
+use hardcoded_source::HardcodedSourceConfig;
+use json_output::{ConverterType, SourceType};
+use lock::{Acquired, Lock};
+use log::{error, info, trace, warn};
+use registry::{RegisteredConverter, RegisteredSource, Registry};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::*;
 use rusty_money::{Money, iso};
-use source::Source;
+use snapshot_store::SnapshotStore;
+use source::AccountAssets;
 use std::collections::{HashMap, HashSet};
-use std::error::Error;
-use std::ffi::OsStr;
 use std::fs::File;
-use std::io::prelude::*;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use structopt::StructOpt;
 use term_table::{Table, TableStyle, row::Row, table_cell::Alignment, table_cell::TableCell};
+use warp::Filter;
+use worthy_error::WorthyError;
 
 // TODO: cache conversions
 // TODO: save cached in xdg cache dir?
 
-async fn process_source(source: &SourceConfig) -> Result<Vec<Asset>, Box<dyn Error>> {
-    use config::SourceType::*;
-    match &source.source_type {
-        // TODO: static dispatch
-        IBFlex(config) => IBFlexSource::take_snapshot(config).await,
-        Hardcoded { assets } => Ok(assets.to_vec()),
-    }
-}
-
-fn asset_to_money(x: &Asset) -> Money<iso::Currency> {
+/// None if the asset is not in a known ISO currency.
+fn asset_to_money(x: &Asset) -> Option<Money<'_, iso::Currency>> {
     match &x.denomination {
         Denomination::Currency { currency } => {
-            Money::from_decimal(x.amount, iso::find(currency).unwrap())
+            Some(Money::from_decimal(x.amount, iso::find(currency)?))
         }
-        _ => panic!("arg"),
+        _ => None,
+    }
+}
+
+/// Formats ISO currencies as money, rounded to their minor units. Anything
+/// else, including unknown currency codes, as "<amount> <symbol>".
+fn format_asset(x: &Asset) -> String {
+    match asset_to_money(x) {
+        Some(money) => money.to_string(),
+        None => format!("{} {}", x.amount, x.denomination.symbol()),
+    }
+}
+
+/// Like `format_asset`, but with ISO codes instead of currency signs such as
+/// "€" that aren't ASCII.
+fn format_asset_ascii(x: &Asset) -> String {
+    match asset_to_money(x) {
+        Some(money) => format!(
+            "{:.*} {}",
+            money.currency().exponent as usize,
+            x.amount.round_dp(money.currency().exponent),
+            money.currency().iso_alpha_code
+        ),
+        None => format_asset(x),
+    }
+}
+
+/// Like `format_asset`, or `format_asset_ascii` with `ascii`, but with the
+/// decimal places set in `precision`.
+fn format_asset_with_precision(x: &Asset, precision: &DisplayPrecision, ascii: bool) -> String {
+    let format = if ascii {
+        format_asset_ascii
+    } else {
+        format_asset
+    };
+    match (asset_to_money(x), &x.denomination) {
+        (Some(money), _) => match precision.currency {
+            Some(places) => {
+                let currency = iso::Currency {
+                    exponent: places,
+                    ..*money.currency()
+                };
+                if ascii {
+                    format!(
+                        "{:.*} {}",
+                        places as usize,
+                        x.amount.round_dp(places),
+                        currency.iso_alpha_code
+                    )
+                } else {
+                    Money::from_decimal(x.amount, &currency).to_string()
+                }
+            }
+            None => format(x),
+        },
+        (None, Denomination::Cryptocurrency { symbol }) => match precision.crypto {
+            Some(places) => format!(
+                "{:.*} {}",
+                places as usize,
+                x.amount.round_dp(places),
+                symbol
+            ),
+            None => format(x),
+        },
+        (None, _) => format(x),
     }
 }
 
-enum SourceType {
-    Hardcoded,
-    IBFlex,
+/// Box-drawing table style, or plain ASCII with `ascii`.
+fn table_style(ascii: bool) -> TableStyle {
+    if ascii {
+        TableStyle::simple()
+    } else {
+        TableStyle::extended()
+    }
 }
 
 struct SourceSnapshot {
@@ -58,724 +123,5802 @@ struct SourceSnapshot {
     name: String,
     source_type: SourceType,
     snapshot: Vec<Asset>,
+    base_currency: Option<String>,
+    /// `snapshot` by account, if the source has accounts.
+    accounts: Vec<AccountAssets>,
+}
+
+/// Assets of all `snapshots`, added up per denomination, so that e.g. USD
+/// held at two banks is one asset.
+fn aggregate_assets(snapshots: &[SourceSnapshot]) -> Result<Vec<Asset>, WorthyError> {
+    sum_by_denomination(snapshots.iter().flat_map(|ss| ss.snapshot.iter()))
+        .ok_or_else(|| WorthyError::Overflow("sum of the assets of all sources".to_string()))
+}
+
+/// Denominations to ask converters about: those of `assets`, then those the
+/// modelling amounts are set in, which needn't be held.
+fn denominations_to_price<'a>(
+    assets: &'a [Asset],
+    modelling: &'a config::ModellingConfig,
+) -> Vec<&'a Denomination> {
+    let mut denominations: Vec<&Denomination> = Vec::new();
+    let goals = modelling
+        .monthly_targets
+        .iter()
+        .flat_map(|goal| goal.components.iter())
+        .chain(std::iter::once(&modelling.monthly_saving));
+    for asset in assets.iter().chain(goals) {
+        if !denominations.contains(&&asset.denomination) {
+            denominations.push(&asset.denomination);
+        }
+    }
+    denominations
+}
+
+/// Runs `fetch` on each of `items`, up to `parallelism` at a time. Results
+/// are in the order of `items` however long each takes, so that snapshots come
+/// out the same from run to run. Fails with the first error.
+async fn fetch_concurrently<T, R, F, Fut>(
+    items: Vec<T>,
+    parallelism: usize,
+    fetch: F,
+) -> Result<Vec<R>, WorthyError>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R, WorthyError>>,
+{
+    let mut results: Vec<(usize, R)> = stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| fetch(item).map_ok(move |result| (index, result)))
+        .buffer_unordered(parallelism.max(1))
+        .try_collect()
+        .await?;
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
 }
 
+/// Snapshots of `sources`, and a converter snapshot of the prices each one
+/// reports, if any.
 async fn get_source_snapshots(
-    source_configs: &HashMap<String, config::SourceConfig>,
-) -> Vec<SourceSnapshot> {
-    stream::iter(source_configs)
-        .flat_map(|(source_id, source_config)| {
-            process_source(source_config)
-                .map(move |result| {
-                    let assets = result.unwrap_or_else(|_| {
-                        panic!("getting result from source {source_id} failed")
-                    });
-                    info!("{} {} {:?}", source_id, source_config.name, assets);
-                    use config::SourceType::*;
-                    SourceSnapshot {
-                        id: source_id.clone(),
-                        name: source_config.name.clone(),
-                        source_type: match source_config.source_type {
-                            IBFlex(_) => SourceType::IBFlex,
-                            Hardcoded { .. } => SourceType::Hardcoded,
-                        },
-                        snapshot: assets,
-                    }
-                })
-                .into_stream()
+    sources: Vec<(&String, &RegisteredSource)>,
+    parallelism: usize,
+) -> Result<(Vec<SourceSnapshot>, Vec<ConverterSnapshot>), WorthyError> {
+    let reports = fetch_concurrently(sources, parallelism, |(source_id, source)| {
+        source.source.report().map_ok(move |report| {
+            info!("{} {} {:?}", source_id, source.name, report.assets);
+            let prices = (!report.rates.is_empty()).then(|| ConverterSnapshot {
+                id: source_prices_id(source_id),
+                converter_type: ConverterType::Source,
+                snapshot: report.rates,
+            });
+            let snapshot = SourceSnapshot {
+                id: source_id.clone(),
+                name: source.name.clone(),
+                source_type: source.source_type.clone(),
+                snapshot: report.assets,
+                base_currency: report.base_currency,
+                accounts: report.accounts,
+            };
+            (snapshot, prices)
         })
-        .collect()
-        .await
+    })
+    .await?;
+    let (snapshots, prices): (Vec<_>, Vec<_>) = reports.into_iter().unzip();
+    Ok((snapshots, prices.into_iter().flatten().collect()))
 }
 
-enum ConverterType {
-    CurrencyLayer,
-    AlphaVantage,
-    Fixer,
+/// ID of the converter snapshot holding the prices source `source_id`
+/// reports, kept apart from converters' IDs.
+fn source_prices_id(source_id: &str) -> String {
+    format!("source:{}", source_id)
 }
 
+/// ID of the converter snapshot holding rates derived from baskets.
+const BASKET_CONVERTER_ID: &str = "baskets";
+
 struct ConverterSnapshot {
     id: String,
     converter_type: ConverterType,
     snapshot: Vec<ExchangeRate>,
 }
 
+async fn take_converter_snapshot(
+    converter_name: &str,
+    converter: &RegisteredConverter,
+    denominations: &[&Denomination],
+    base: &Denomination,
+    call_counts: &Mutex<CallCounts>,
+    recordings: Option<&RateRecordings>,
+) -> Result<Option<ConverterSnapshot>, WorthyError> {
+    let allowed = converter.filter.apply(denominations);
+    let handled = converter.converter.handled(&allowed);
+    if handled.is_empty() {
+        info!("{}: no denominations to price, skipping", converter_name);
+        return Ok(None);
+    }
+    info!("{}", converter_name);
+    // Replaying recorded rates makes no calls.
+    let calls = match recordings {
+        Some(RateRecordings {
+            mode: CacheMode::Replay,
+            ..
+        }) => 0,
+        _ => converter.converter.api_calls(&handled),
+    };
+    {
+        let today = Utc::now().date_naive();
+        let mut call_counts = call_counts.lock().unwrap();
+        converter.budget.check(
+            converter_name,
+            call_counts.count(converter_name, today),
+            calls,
+        )?;
+        call_counts.add(converter_name, today, calls);
+    }
+    // TODO: Err(ParsingError("missing metadata"))
+    // Err(ParsingError("missing exchange rate data"))
+    // this seems to happen with AlphaVantage on probably too many requests in
+    // too short a time.
+    let fetch = converter.converter.take_snapshot(&handled, base);
+    let conversions = match recordings {
+        Some(recordings) => {
+            caching_converter::cached_snapshot(recordings, converter_name, &handled, base, fetch)
+                .await
+        }
+        None => fetch.await,
+    }?;
+    Ok(Some(ConverterSnapshot {
+        id: converter_name.to_string(),
+        converter_type: converter.converter_type.clone(),
+        snapshot: conversions,
+    }))
+}
+
+/// Snapshots of `converters`, up to `parallelism` at a time, ordered by
+/// converter ID.
 async fn get_converter_snapshots(
     denominations: &[&Denomination],
-    converter_configs: &HashMap<String, ConverterConfig>,
+    converters: Vec<(&String, &RegisteredConverter)>,
     base: &Denomination,
-) -> Vec<ConverterSnapshot> {
-    use ConverterConfig::*;
-    stream::iter(converter_configs)
-        .flat_map(|(converter_name, converter_config)| {
-            info!("{}", converter_name);
-            match converter_config {
-                AlphaVantage(config) => {
-                    // TODO: Err(ParsingError("missing metadata"))
-                    // Err(ParsingError("missing exchange rate data"))
-                    // this seems to happen on probably too many requests in too
-                    // short a time.
-                    AlphaVantageConverter::take_snapshot(config, denominations, base)
-                }
-                Fixer(config) => FixerConverter::take_snapshot(config, denominations, base),
-                CurrencyLayer(config) => {
-                    CurrencyLayerConverter::take_snapshot(config, denominations, base)
-                }
-            } // TODO
-            .map(move |conversions| {
-                let conversions = conversions.unwrap();
-                ConverterSnapshot {
-                    id: converter_name.clone(),
-                    converter_type: match converter_config {
-                        AlphaVantage(_) => ConverterType::AlphaVantage,
-                        Fixer(_) => ConverterType::Fixer,
-                        CurrencyLayer(_) => ConverterType::CurrencyLayer,
-                    },
-                    snapshot: conversions,
-                }
-            })
-            .into_stream()
+    call_counts: &Mutex<CallCounts>,
+    recordings: Option<&RateRecordings>,
+    parallelism: usize,
+) -> Result<Vec<ConverterSnapshot>, WorthyError> {
+    let mut snapshots: Vec<ConverterSnapshot> =
+        fetch_concurrently(converters, parallelism, |(converter_name, converter)| {
+            take_converter_snapshot(
+                converter_name,
+                converter,
+                denominations,
+                base,
+                call_counts,
+                recordings,
+            )
         })
-        .collect()
-        .await
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(snapshots)
 }
 
-fn load_config(xdg_dirs: &xdg::BaseDirectories) -> Result<Config, Box<dyn Error>> {
-    let config_path = xdg_dirs.place_config_file("config.yaml")?;
-    // TODO: file must exist
-    let f = File::open(config_path)?;
-    Ok(serde_yaml::from_reader(f).expect("cannot parse config file"))
+/// Fails if the snapshot taken at `taken` is more than `max_age` old at `now`.
+fn check_snapshot_age(
+    taken: DateTime<FixedOffset>,
+    now: DateTime<FixedOffset>,
+    max_age: chrono::Duration,
+) -> Result<(), WorthyError> {
+    let age = now - taken;
+    if age <= max_age {
+        return Ok(());
+    }
+    Err(WorthyError::StaleSnapshot {
+        taken: taken.to_rfc3339(),
+        age: flags::format_duration(age),
+        max_age: flags::format_duration(max_age),
+    })
 }
 
-fn get_snapshot_paths(config: &Config) -> Vec<String> {
-    let pattern = Path::new(OsStr::new(
-        &shellexpand::tilde(&config.dated_json_output).into_owned(),
-    ))
-    .parent()
-    .unwrap()
-    .join("*.json");
-    let mut paths: Vec<String> = Vec::new();
-    for entry in glob(pattern.as_path().to_str().unwrap()).unwrap() {
-        match entry {
-            Ok(path) => paths.push(path.to_str().unwrap().to_string()),
-            Err(e) => panic!("{:?}", e),
+/// Where converter rates are recorded, and whether to only replay them. Replaying
+/// needs somewhere to replay from.
+fn rate_recordings(config: &Config, replay: bool) -> Result<Option<RateRecordings>, WorthyError> {
+    let mode = if replay {
+        CacheMode::Replay
+    } else {
+        CacheMode::Record
+    };
+    match &config.rate_recordings {
+        Some(dir) => Ok(Some(RateRecordings {
+            dir: paths::expand(dir)?,
+            mode,
+        })),
+        None if replay => Err(WorthyError::Config(
+            "--replay-rates needs rate_recordings configured".to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Renames the denominations of assets to their canonical symbols, per the
+/// configured aliases.
+fn canonicalize_assets(source_snapshots: &mut [SourceSnapshot], aliases: &HashMap<String, String>) {
+    for asset in source_snapshots.iter_mut().flat_map(|ss| {
+        ss.snapshot.iter_mut().chain(
+            ss.accounts
+                .iter_mut()
+                .flat_map(|account| account.assets.iter_mut()),
+        )
+    }) {
+        asset.denomination = asset.denomination.canonical(aliases);
+    }
+}
+
+/// Renames the denominations of rates to their canonical symbols, per the
+/// configured aliases. Rates between aliases of the same thing are dropped.
+fn canonicalize_rates(
+    converter_snapshots: &mut [ConverterSnapshot],
+    aliases: &HashMap<String, String>,
+) {
+    for converter_snapshot in converter_snapshots.iter_mut() {
+        for rate in converter_snapshot.snapshot.iter_mut() {
+            rate.from = rate.from.canonical(aliases);
+            rate.to = rate.to.canonical(aliases);
         }
+        converter_snapshot
+            .snapshot
+            .retain(|rate| rate.from != rate.to);
     }
-    paths
 }
 
-async fn model_and_show(
+/// The configured common currency, or with `base_from_source`, the base
+/// currency of that source's account. Falls back to the configured one if the
+/// source doesn't report a base currency.
+fn common_currency(
     config: &Config,
-    converter_snapshots: &[ConverterSnapshot],
+    base_from_source: Option<&str>,
     source_snapshots: &[SourceSnapshot],
-) -> Asset {
-    let base = Denomination::Currency {
-        currency: config.common_currency.clone(),
+) -> Result<Denomination, WorthyError> {
+    let configured = config.common_currency.clone();
+    let Some(source_id) = base_from_source else {
+        return Ok(configured);
     };
-    let all_conversions: Vec<_> = converter_snapshots
+    if !config.source_config.contains_key(source_id) {
+        return Err(WorthyError::Config(format!("unknown source {}", source_id)));
+    }
+    let base_currency = source_snapshots
         .iter()
-        .flat_map(|snapshot| snapshot.snapshot.clone())
-        .collect();
-    info!("All conversions: {:?}", all_conversions);
-
-    // TODO: deduplicate
-    let mut all_assets = HashMap::new();
-    for ss in source_snapshots.iter() {
-        for asset in ss.snapshot.iter() {
-            all_assets
-                .entry(asset.denomination.clone())
-                .or_insert(Decimal::ZERO);
-            *all_assets.get_mut(&asset.denomination).unwrap() += asset.amount;
+        .find(|snapshot| snapshot.id == source_id)
+        .and_then(|snapshot| snapshot.base_currency.clone());
+    match base_currency {
+        Some(currency) => {
+            info!("common currency {} from source {}", currency, source_id);
+            Ok(Denomination::Currency { currency })
+        }
+        None => {
+            warn!(
+                "source {} has no base currency, using {}",
+                source_id, config.common_currency
+            );
+            Ok(configured)
         }
     }
-    info!("All assets: {:?}", all_assets);
-
-    let in_common_currency = common_currency::in_common_currency(&all_conversions, &base);
-    info!("In common currency: {:?}", in_common_currency);
+}
 
-    let mut total_amount = Decimal::ZERO;
-    for ss in source_snapshots.iter() {
-        info!("{} {}", ss.id, ss.name);
-        for asset in ss.snapshot.iter() {
-            if let Some(conversion_rate) = in_common_currency.get(&asset.denomination) {
-                let amount = asset.amount * conversion_rate;
-                info!("{:?}: {:?} in common currency", asset, amount);
-                total_amount += amount;
-            } else {
-                warn!("{:?} not connected to common currency", asset.denomination);
-            }
-        }
+/// Rejects `--base-from-source` on subcommands that don't value a single
+/// snapshot, so it isn't silently ignored.
+fn check_base_from_source(opt: &Opt) -> Result<(), WorthyError> {
+    use flags::Command::*;
+    if opt.base_from_source.is_none()
+        || matches!(
+            opt.command,
+            Snapshot | ModelLastSnapshot | Html | Check | CheckRates
+        )
+    {
+        return Ok(());
     }
+    Err(WorthyError::Config(
+        "--base-from-source only works with snapshot, modellastsnapshot, html, check and \
+         checkrates"
+            .to_string(),
+    ))
+}
 
-    let total = Asset {
-        amount: total_amount,
-        denomination: base.clone(),
+/// The configured `kind`s (sources or converters) with the given ids, or all
+/// of them if `ids` is `None`.
+fn select_by_id<'a, T>(
+    configs: &'a HashMap<String, T>,
+    ids: Option<&[String]>,
+    kind: &str,
+) -> Result<Vec<(&'a String, &'a T)>, WorthyError> {
+    let Some(ids) = ids else {
+        return Ok(configs.iter().collect());
     };
-    info!("Total in common currency: {:?}", total);
-
-    if config.cfiresim.is_some() {
-        let c = config.cfiresim.as_ref().unwrap();
-        // Post to cFIREsim.
+    ids.iter()
+        .map(|id| {
+            configs
+                .get_key_value(id)
+                .ok_or_else(|| WorthyError::Config(format!("unknown {} {}", kind, id)))
+        })
+        .collect()
+}
 
-        // Add up all sources that are in the portfolio.
-        let snapshot_by_id: HashMap<String, &SourceSnapshot> = source_snapshots
-            .iter()
-            .map(|snapshot| (snapshot.id.clone(), snapshot))
-            .collect();
+/// `base` with its entries replaced by the entries of `fresh` with the same
+/// id. Entries only in `base` are kept as they are, entries only in `fresh`
+/// are added.
+fn merge_by_id<T>(base: Vec<T>, fresh: Vec<T>, id: impl Fn(&T) -> &str) -> Vec<T> {
+    let fresh_ids: HashSet<String> = fresh.iter().map(|entry| id(entry).to_string()).collect();
+    base.into_iter()
+        .filter(|entry| !fresh_ids.contains(id(entry)))
+        .chain(fresh)
+        .collect()
+}
 
-        let add_up_amounts = |account_names: &Vec<String>| -> Decimal {
-            let mut total = Decimal::zero();
-            for source in account_names {
-                info!("source: {}", source);
-                let snapshot = &snapshot_by_id[source];
-                for asset in snapshot.snapshot.iter() {
-                    let val = in_common_currency[&asset.denomination] * asset.amount;
-                    info!("{:?}: {:?} in common currency", asset, val);
-                    total += val;
-                }
+/// Loads the configuration from `path` (`~` and environment variables
+/// expanded), or without one from `config.yaml` in the XDG config directory.
+fn load_config(
+    xdg_dirs: &xdg::BaseDirectories,
+    path: Option<&Path>,
+) -> Result<Config, WorthyError> {
+    let config_path = match path {
+        Some(path) => {
+            let path = paths::expand(&path.to_string_lossy())?;
+            if !path.exists() {
+                return Err(WorthyError::Config(format!(
+                    "configuration file {} does not exist",
+                    path.display()
+                )));
             }
-            total.floor()
-        };
-
-        let portfolio_total = add_up_amounts(&c.portfolio);
-        info!("portfolio total: {}", portfolio_total);
+            path
+        }
+        None => xdg_dirs
+            .place_config_file("config.yaml")
+            .map_err(|error| WorthyError::Config(error.to_string()))?,
+    };
+    let f = File::open(&config_path).map_err(|error| {
+        WorthyError::Config(format!("cannot open {}: {}", config_path.display(), error))
+    })?;
+    secret::warn_if_world_readable(&config_path);
+    let mut config: Config = serde_yaml::from_reader(f)?;
+    config.modelling.normalize_yields();
+    for settings in config
+        .source_config
+        .values_mut()
+        .map(|source| &mut source.typed.settings)
+        .chain(
+            config
+                .converter_config
+                .values_mut()
+                .map(|converter| &mut converter.settings),
+        )
+    {
+        secret::resolve_secrets(settings)?;
+    }
+    let unknown = unknown_currencies(&config);
+    if !unknown.is_empty() {
+        return Err(WorthyError::Config(format!(
+            "unknown ISO 4217 currency codes in {}: {}",
+            config_path.display(),
+            unknown.join(", ")
+        )));
+    }
+    Ok(config)
+}
 
-        let csrf_middleware_token: &str =
-            "eFBajFh8XEERVEK6yuI00J4R1qWjonS4xv417X4toibJYzGc220Y36dEcFGcvFZr";
-        let mut params: HashMap<String, String> = HashMap::new();
-        for (k, v) in &[
-            ("csrfmiddlewaretoken", csrf_middleware_token),
-            ("data_method", "historical_all"),
-            ("single_simulation_year", "1966"),
-            ("historical_data_start_year", "1900"),
-            ("historical_data_end_year", "1980"),
-            ("constant_market_growth", "7.50"),
-            ("spending_plan", "inflation_adjusted"),
-            ("inflation_type", "cpi"),
-            ("inflation_flat_rate", "3.10"),
-            ("guyton_exceeds", "20"),
-            ("guyton_cut", "10"),
-            ("guyton_fall", "20"),
-            ("guyton_raise", "10"),
-            ("yearly_spending_percent_of_portfolio", "4"),
-            ("z_value", "0.50"),
-            ("vpw_rate_of_return", "4.30"),
-            ("vpw_future_value", "0"),
-            ("hebeler_age_at_retirement", "0"),
-            ("hebeler_weighted_rmd", "50"),
-            ("hebeler_weighted_cpi", "50"),
-            ("cape_yield_multiplier", "0.50"),
-            ("cape_constant_adjustment", "1.00"),
-            ("spending_floor_type", "none"),
-            ("spending_floor_value", "0"),
-            ("spending_ceiling_type", "none"),
-            ("spending_ceiling_value", "0"),
-            ("investigate_initial_yearly_spending_threshold", "95"),
-            // TODO: split between cash and equities
-            ("equities", "100"),
-            ("bonds", "0"),
-            ("fees", "0.18"),
-            ("rebalance_annually", "on"),
-            ("gold", "0"),
-            ("cash", "0"),
-            ("growth_of_cash", "0.25"),
-            ("keep_allocation_constant", "on"),
-            ("change_allocation_start_year", "2031"),
-            ("target_equities", "50"),
-            ("target_bonds", "50"),
-            ("change_allocation_end_year", "2041"),
-            ("target_gold", "0"),
-            ("target_cash", "0"),
-            ("ss_frequency_toggle", "monthly"),
-            ("ss_end_year", "2100"),
-            ("ss_spouse_frequency_toggle", "annual"),
-            ("ss_spouse_annual_value", "0"),
-            ("ss_spouse_start_year", "2036"),
-            ("ss_spouse_end_year", "2100"),
-            ("form-TOTAL_FORMS", "10"),
-            ("form-INITIAL_FORMS", "0"),
-            ("form-MIN_NUM_FORMS", "0"),
-            ("form-MAX_NUM_FORMS", "1000"),
-        ] {
-            params.insert(k.to_string(), v.to_string());
+/// Currency codes `rusty_money` doesn't know, each with where in `config` it
+/// is, e.g. "EURO in source bank": in the common currency, the modelling
+/// configuration and the assets of hardcoded sources. Sorted, each listed
+/// once.
+fn unknown_currencies(config: &Config) -> Vec<String> {
+    let mut denominations = vec![
+        (
+            "common_currency".to_string(),
+            config.common_currency.clone(),
+        ),
+        (
+            "modelling.monthly_saving".to_string(),
+            config.modelling.monthly_saving.denomination.clone(),
+        ),
+    ];
+    for goal in &config.modelling.monthly_targets {
+        for component in &goal.components {
+            denominations.push((
+                "modelling.monthly_targets".to_string(),
+                component.denomination.clone(),
+            ));
         }
-        params.insert("retirement_year".to_string(), c.retirement_year.to_string());
-        params.insert(
-            "retirement_end_year".to_string(),
-            c.retirement_end_year.to_string(),
-        );
-        params.insert(
-            "initial_yearly_spending".to_string(),
-            c.initial_yearly_spending.to_string(),
-        );
-        params.insert(
-            "ss_start_year".to_string(),
-            c.social_security.start_year.to_string(),
-        );
-        params.insert(
-            "ss_annual_value".to_string(),
-            c.social_security.monthly_amount.to_string(),
-        );
-        params.insert("portfolio_value".to_string(), portfolio_total.to_string());
-
-        if !c.adjustment.is_empty() {
-            // First adjustment.
-            params.insert("form-0-label".to_string(), c.adjustment[0].name.clone());
-            params.insert("form-0-adjustment_type".to_string(), "pension".to_string());
-            params.insert("form-0-inflation_adjusted".to_string(), "on".to_string());
-            params.insert("form-0-inflation_type".to_string(), "cpi".to_string());
-            params.insert(
-                "form-0-start_year".to_string(),
-                c.adjustment[0].year.to_string(),
-            );
-            let adjustment_total = add_up_amounts(&c.adjustment[0].source);
-            info!("adjustment total: {}", adjustment_total);
-            params.insert(
-                "form-0-amount_per_year".to_string(),
-                adjustment_total.to_string(),
-            );
-        } else {
-            params.insert("form-0-label".to_string(), "".to_string());
-            params.insert("form-0-adjustment_type".to_string(), "income".to_string());
-            params.insert("form-0-inflation_adjusted".to_string(), "on".to_string());
-            params.insert("form-0-inflation_type".to_string(), "cpi".to_string());
-            params.insert("form-0-start_year".to_string(), "2022".to_string());
-            params.insert("form-0-end_year".to_string(), "2100".to_string());
-            params.insert("form-0-recurring".to_string(), "on".to_string());
-        }
-
-        // Remaining adjustments.
-        for i in 1..=10 {
-            for (k, v) in &[
-                ("label", "".to_string()),
-                ("amount_per_year", "".to_string()),
-                ("adjustment_type", "income".to_string()),
-                ("recurring", "on".to_string()),
-                ("inflation_adjusted", "on".to_string()),
-                ("start_year", "2021".to_string()),
-                ("end_year", "2100".to_string()),
-                ("inflation_type", "cpi".to_string()),
-            ] {
-                params.insert(format!("form-{}-{}", i, k), v.clone());
+    }
+    let mut ids: Vec<&String> = config.source_config.keys().collect();
+    ids.sort();
+    for id in ids {
+        let typed = &config.source_config[id].typed;
+        if typed.type_name != "hardcoded" {
+            continue;
+        }
+        // Sources that don't parse are reported when they're registered.
+        let Ok(hardcoded) = serde_yaml::from_value::<HardcodedSourceConfig>(typed.settings_value())
+        else {
+            continue;
+        };
+        for asset in hardcoded.assets {
+            denominations.push((format!("source {}", id), asset.asset.denomination));
+            if let Some(unit_price) = asset.unit_price {
+                denominations.push((format!("source {}", id), unit_price.to));
+            }
+        }
+    }
+    let mut unknown: Vec<String> = denominations
+        .into_iter()
+        .filter_map(|(place, denomination)| match denomination {
+            Denomination::Currency { currency } if iso::find(&currency).is_none() => {
+                Some(format!("{} in {}", currency, place))
             }
+            _ => None,
+        })
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+    unknown
+}
+
+/// Records `snapshot` in the JSON Lines history at `path`. A new history
+/// starts with every snapshot in `store`, which already holds `snapshot`, so
+/// it's as complete as the snapshot files. The line of the snapshot taken at
+/// `replaced`, e.g. overwritten with `--overwrite-today`, is replaced.
+fn record_in_jsonl(
+    path: &Path,
+    store: &SnapshotStore,
+    snapshot: &json_output::Snapshot,
+    replaced: Option<DateTime<FixedOffset>>,
+) -> Result<(), WorthyError> {
+    let cannot_write = |error: &dyn std::fmt::Display| {
+        WorthyError::Config(format!("cannot write {}: {}", path.display(), error))
+    };
+    if !path.exists() {
+        let json_output::ReadSnapshots { mut snapshots, .. } = store.read_all()?;
+        json_output::sort_by_timestamp(&mut snapshots);
+        paths::create_parent_dir(path)?;
+        let mut file = File::create(path).map_err(|error| cannot_write(&error))?;
+        for (_, snapshot) in &snapshots {
+            json_output::write_jsonl(&mut file, snapshot).map_err(|error| cannot_write(&error))?;
         }
+        return Ok(());
+    }
+    let Some(replaced) = replaced else {
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|error| cannot_write(&error))?;
+        return json_output::write_jsonl(&mut file, snapshot).map_err(|error| cannot_write(&error));
+    };
+    // Rewritten next to the history, then moved over it.
+    let rewritten = path.with_extension("jsonl.tmp");
+    let history = File::open(path).map_err(|error| {
+        WorthyError::Config(format!("cannot open {}: {}", path.display(), error))
+    })?;
+    let mut file = File::create(&rewritten).map_err(|error| cannot_write(&error))?;
+    json_output::replace_jsonl(
+        std::io::BufReader::new(history),
+        &mut file,
+        replaced,
+        snapshot,
+    )
+    .map_err(|error| cannot_write(&error))?;
+    std::fs::rename(&rewritten, path).map_err(|error| cannot_write(&error))
+}
 
-        let client = reqwest::Client::new();
-        let response = client
-            .post("https://www.cfiresim.com/calculator/get_simulation")
-            .form(&params)
-            .send()
-            .await
-            .unwrap();
-        if !response.status().is_success() {
-            println!("{:#?}", response);
-            println!("{:#?}", response.text().await);
-            panic!("error response");
-        }
-        let v: serde_json::Value = response.json().await.unwrap();
-        let v = v.as_object().unwrap();
-        // stats
-        let fragment = v["stats"].as_str().unwrap();
-        let frag = scraper::Html::parse_fragment(fragment);
-        let selector =
-            scraper::Selector::parse("table.table > tbody > tr > td[scope=row]").unwrap();
-        for element in frag.select(&selector) {
-            let txt = element.text().collect::<Vec<_>>().join("");
-            //"\n                12.34% - Failed 56 of 78 total cycles.\n              "
-            println!("{:#?}", txt.trim());
-        }
-
-        println!(
-            "https://www.cfiresim.com/{}",
-            v["tracking_uuid"].as_str().unwrap()
-        );
-    }
-
-    // TODO(agentydragon): Make configurable
-    // How many more years to model for (i.e., remaining lifetime)
-    let deadline = dec!(75.0);
-    render_table(
-        deadline,
-        &total,
-        &config.modelling,
-        &base,
-        &in_common_currency,
-    );
-    total
+fn snapshot_store(config: &Config) -> Result<SnapshotStore, WorthyError> {
+    let store = SnapshotStore::new(&paths::expand(&config.dated_json_output)?);
+    match &config.timestamp_format {
+        Some(format) => store.with_timestamp_format(format),
+        None => Ok(store),
+    }
 }
 
-#[tokio::main]
-async fn main() {
-    env_logger::init();
-    let opt = Opt::from_args();
-    trace!("Options: {:?}", opt);
+/// The newest snapshot in `store` that parses, failing if there is none.
+fn newest_snapshot(store: &SnapshotStore) -> Result<json_output::Snapshot, WorthyError> {
+    store.existing_paths()?;
+    store
+        .latest()?
+        .ok_or_else(|| WorthyError::Parse("no parseable snapshot found".to_string()))
+}
 
-    let xdg_dirs = xdg::BaseDirectories::with_prefix("worthy");
-    let config = load_config(&xdg_dirs).unwrap();
-    trace!("Config: {:?}", config);
+/// How many totals the trend under the model shows, including the current one.
+const TREND_SNAPSHOTS: usize = 12;
 
-    let now = Utc::now().into();
+/// Totals in `base` of the snapshots taken before `before`, oldest first, at
+/// most enough to fill the trend with the current total.
+async fn earlier_totals(
+    config: &Config,
+    base: &Denomination,
+    before: DateTime<FixedOffset>,
+) -> Result<Vec<Decimal>, WorthyError> {
+    let json_output::ReadSnapshots { mut snapshots, .. } =
+        load_snapshots(&snapshot_store(config)?.paths()?).await;
+    json_output::sort_by_timestamp(&mut snapshots);
+    Ok(comparable_totals(
+        &snapshots,
+        base,
+        before,
+        TREND_SNAPSHOTS - 1,
+    ))
+}
 
-    use flags::Command::*;
-    match opt.command {
-        Snapshot => {
-            // Collect all assets from all sources.
-            // TODO(agentydragon): would be quite nice to do this via futures...
-            let source_snapshots = get_source_snapshots(&config.source_config).await;
-
-            // TODO: deduplicate
-            let mut all_assets = HashMap::new();
-            for ss in source_snapshots.iter() {
-                for asset in ss.snapshot.iter() {
-                    all_assets
-                        .entry(asset.denomination.clone())
-                        .or_insert(Decimal::ZERO);
-                    *all_assets.get_mut(&asset.denomination).unwrap() += asset.amount;
-                }
+/// The last `count` totals in `base` of `snapshots` (sorted by timestamp)
+/// taken before `before`. Snapshots totalled in another denomination, e.g.
+/// before the common currency changed, can't be compared, so are skipped.
+fn comparable_totals(
+    snapshots: &[(String, json_output::Snapshot)],
+    base: &Denomination,
+    before: DateTime<FixedOffset>,
+    count: usize,
+) -> Vec<Decimal> {
+    let totals: Vec<Decimal> = snapshots
+        .iter()
+        .filter(|(_, snapshot)| snapshot.timestamp < before)
+        .filter_map(|(path, snapshot)| {
+            let total = asset_from_json(&snapshot.total);
+            if total.denomination != *base {
+                info!(
+                    "{}: total in {}, not {}, leaving it out of the trend",
+                    path, total.denomination, base
+                );
+                return None;
             }
-            info!("All assets: {:?}", all_assets);
+            Some(total.amount)
+        })
+        .collect();
+    totals[totals.len().saturating_sub(count)..].to_vec()
+}
 
-            // TODO: check it exists
-            let base = Denomination::Currency {
-                currency: config.common_currency.clone(),
+/// One block per value, from lowest to highest. With `ascii`, characters
+/// from " " up to "#" stand in for the blocks.
+fn sparkline(values: &[Decimal], ascii: bool) -> String {
+    let levels: Vec<char> = if ascii {
+        " .:-=+*#".chars().collect()
+    } else {
+        "\u{2581}\u{2582}\u{2583}\u{2584}\u{2585}\u{2586}\u{2587}\u{2588}"
+            .chars()
+            .collect()
+    };
+    let (Some(min), Some(max)) = (values.iter().min(), values.iter().max()) else {
+        return String::new();
+    };
+    let range = max - min;
+    values
+        .iter()
+        .map(|value| {
+            let level = if range.is_zero() {
+                // A flat line, halfway up.
+                levels.len() / 2
+            } else {
+                ((value - min) / range * Decimal::from(levels.len() - 1))
+                    .round()
+                    .to_usize()
+                    .unwrap_or(0)
             };
+            levels[level.min(levels.len() - 1)]
+        })
+        .collect()
+}
 
-            let converter_snapshots = get_converter_snapshots(
-                &all_assets.keys().collect::<Vec<_>>(),
-                &config.converter_config,
-                &base,
-            )
-            .await;
-
-            let total = model_and_show(&config, &converter_snapshots, &source_snapshots).await;
-
-            // Save JSON snapshot.
-            let json_snapshot = json_output::Snapshot {
-                // TODO(agentydragon): should be shared
-                timestamp: now,
-                source_snapshot: source_snapshots
-                    .iter()
-                    .map(source_snapshot_to_json)
-                    .collect(),
-                converter_snapshots: converter_snapshots
-                    .iter()
-                    .map(converter_snapshot_to_json)
-                    .collect(),
-                total: asset_to_json(&total),
-            };
-            let s = serde_json::to_string_pretty(&json_snapshot).unwrap();
+/// Change of the total since the last earlier one, and a sparkline of them
+/// all, or nothing if there are no earlier totals.
+fn render_trend(earlier_totals: &[Decimal], total: &Asset, ascii: bool) -> String {
+    let Some(last) = earlier_totals.last() else {
+        return String::new();
+    };
+    let format_asset = if ascii {
+        format_asset_ascii
+    } else {
+        format_asset
+    };
+    let change = Asset {
+        amount: total.amount - last,
+        denomination: total.denomination.clone(),
+        cost_basis: None,
+    };
+    let mut totals = earlier_totals.to_vec();
+    totals.push(total.amount);
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![
+        TableCell::new(format!(
+            "{}{} since the last snapshot",
+            if change.amount.is_sign_negative() {
+                ""
+            } else {
+                "+"
+            },
+            format_asset(&change)
+        )),
+        TableCell::new(sparkline(&totals, ascii)),
+    ]));
+    table.render()
+}
 
-            let output_path =
-                shellexpand::tilde(&config.dated_json_output).replace("%s", &now.to_rfc3339());
+/// Reads all given snapshot files concurrently, skipping (with a warning)
+/// those that cannot be parsed. Snapshots are in the order of `paths`.
+async fn load_snapshots(paths: &[String]) -> json_output::ReadSnapshots {
+    let reads = future::join_all(
+        paths
+            .iter()
+            .cloned()
+            .map(|path| tokio::task::spawn_blocking(move || json_output::read_snapshots(&[path]))),
+    )
+    .await;
+    let mut loaded = json_output::ReadSnapshots {
+        snapshots: Vec::new(),
+        skipped: 0,
+    };
+    for read in reads {
+        let read = read.expect("snapshot reading task panicked");
+        loaded.snapshots.extend(read.snapshots);
+        loaded.skipped += read.skipped;
+    }
+    loaded
+}
 
-            {
-                let mut file = File::create(&output_path).unwrap();
-                file.write_all(s.bytes().collect::<Vec<_>>().as_slice())
-                    .unwrap();
-            }
+/// Values of all assets in the given sources, in the common currency.
+fn values_in_common_currency(
+    account_names: &[String],
+    snapshot_by_id: &HashMap<String, &SourceSnapshot>,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<Vec<(Denomination, Decimal)>, WorthyError> {
+    let mut values = Vec::new();
+    for source in account_names {
+        info!("source: {}", source);
+        let snapshot = snapshot_by_id
+            .get(source)
+            .ok_or_else(|| WorthyError::Config(format!("unknown source {}", source)))?;
+        for asset in snapshot.snapshot.iter() {
+            let rate = in_common_currency
+                .get(&asset.denomination)
+                .ok_or_else(|| WorthyError::UnpricedDenomination(asset.denomination.clone()))?;
+            let val = rate * asset.amount;
+            info!("{:?}: {:?} in common currency", asset, val);
+            values.push((asset.denomination.clone(), val));
         }
-        ModelLastSnapshot => {
-            let paths = get_snapshot_paths(&config);
-            let loaded_path = paths.iter().max().unwrap();
-            let file = File::open(loaded_path).unwrap();
-            let snapshot: json_output::Snapshot = serde_json::from_reader(file).unwrap();
+    }
+    Ok(values)
+}
 
-            let converter_snapshots: Vec<ConverterSnapshot> = snapshot
-                .converter_snapshots
-                .iter()
-                .map(converter_snapshot_from_json)
-                .collect();
-            let source_snapshots: Vec<SourceSnapshot> = snapshot
-                .source_snapshot
-                .iter()
-                .map(source_snapshot_from_json)
-                .collect();
-            let _total = model_and_show(&config, &converter_snapshots, &source_snapshots).await;
+/// Floored total of the given sources in the common currency.
+fn add_up_amounts(
+    account_names: &[String],
+    snapshot_by_id: &HashMap<String, &SourceSnapshot>,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<Decimal, WorthyError> {
+    let values = values_in_common_currency(account_names, snapshot_by_id, in_common_currency)?;
+    Ok(values
+        .iter()
+        .map(|(_, value)| value)
+        .sum::<Decimal>()
+        .floor())
+}
+
+/// Warns about hand-entered balances that haven't been updated in a while.
+fn warn_about_stale_sources(sources: &HashMap<String, RegisteredSource>, now: DateTime<Utc>) {
+    let mut ids: Vec<&String> = sources.keys().collect();
+    ids.sort();
+    for id in ids {
+        let source = &sources[id];
+        if let Some(staleness) = source.source.staleness(now) {
+            warn!(
+                "balances of {} ({}) are {} days old{}",
+                source.name,
+                id,
+                staleness.age.num_days(),
+                if staleness.excluded {
+                    ", not counting them".to_string()
+                } else if staleness.weight != Decimal::ONE {
+                    format!(", counting them at {}", staleness.weight)
+                } else {
+                    String::new()
+                }
+            );
         }
-        Csv => {
-            let paths = get_snapshot_paths(&config);
+    }
+}
 
-            let csv_path = shellexpand::tilde(&config.csv_output)
-                .into_owned()
-                .replace("%s", &now.to_rfc3339());
-            let mut wtr = csv::Writer::from_writer(File::create(&csv_path).unwrap());
-            wtr.write_record(["Timestamp", "Total"]).unwrap();
-            for path in paths {
-                let file = File::open(&path).unwrap();
-                let snapshot: json_output::Snapshot =
-                    serde_json::from_reader(file).unwrap_or_else(|error: serde_json::Error| {
-                        panic!("error parsing {}: {}", path, error)
-                    });
+fn all_conversions(converter_snapshots: &[ConverterSnapshot]) -> Vec<ExchangeRate> {
+    converter_snapshots
+        .iter()
+        .flat_map(|snapshot| snapshot.snapshot.clone())
+        .collect()
+}
 
-                wtr.write_record(&[
-                    snapshot.timestamp.to_rfc3339(),
-                    snapshot.total.amount.to_string(),
-                ])
-                .unwrap();
-            }
+/// How conversions several converters quote for the same pair are combined
+/// before pricing.
+#[derive(Debug, Default)]
+struct Pricing {
+    policy: common_currency::RatePolicy,
+    /// Converter IDs, highest priority first.
+    converter_priority: Vec<String>,
+}
 
-            println!("Written: {}", csv_path);
+impl Pricing {
+    fn of(config: &Config) -> Self {
+        Pricing {
+            policy: config.rate_policy,
+            converter_priority: config.converter_priority.clone(),
         }
-        Server => panic!("TODO"),
     }
-}
 
-fn converter_snapshot_to_json(
-    converter_snapshot: &ConverterSnapshot,
-) -> json_output::ConverterSnapshot {
-    json_output::ConverterSnapshot {
-        id: converter_snapshot.id.clone(),
-        converter_type: match converter_snapshot.converter_type {
-            ConverterType::CurrencyLayer => json_output::ConverterType::CurrencyLayer,
-            ConverterType::AlphaVantage => json_output::ConverterType::AlphaVantage,
-            ConverterType::Fixer => json_output::ConverterType::Fixer,
-        },
-        snapshot: converter_snapshot
-            .snapshot
-            .iter()
-            .map(exchange_rate_to_json)
-            .collect(),
+    /// Conversions of all converters, those of higher priority first, combined
+    /// per pair by the policy.
+    fn conversions(&self, converter_snapshots: &[ConverterSnapshot]) -> Vec<ExchangeRate> {
+        let mut by_priority: Vec<&ConverterSnapshot> = converter_snapshots.iter().collect();
+        by_priority.sort_by_key(|snapshot| {
+            self.converter_priority
+                .iter()
+                .position(|id| *id == snapshot.id)
+                .unwrap_or(self.converter_priority.len())
+        });
+        let all_conversions: Vec<ExchangeRate> = by_priority
+            .into_iter()
+            .flat_map(|snapshot| snapshot.snapshot.clone())
+            .collect();
+        common_currency::dedup_conversions(&all_conversions, self.policy)
     }
 }
 
-fn converter_snapshot_from_json(
-    converter_snapshot: &json_output::ConverterSnapshot,
-) -> ConverterSnapshot {
-    ConverterSnapshot {
-        id: converter_snapshot.id.clone(),
-        converter_type: match converter_snapshot.converter_type {
-            json_output::ConverterType::CurrencyLayer => ConverterType::CurrencyLayer,
-            json_output::ConverterType::AlphaVantage => ConverterType::AlphaVantage,
-            json_output::ConverterType::Fixer => ConverterType::Fixer,
-        },
-        snapshot: converter_snapshot
-            .snapshot
-            .iter()
-            .map(exchange_rate_from_json)
-            .collect(),
-    }
+/// When modelling starts, and how many years the money has to last from then.
+#[derive(Clone, Copy, Debug)]
+struct Horizon {
+    now: DateTime<Utc>,
+    deadline: Decimal,
 }
 
-fn exchange_rate_from_json(c: &json_output::Conversion) -> ExchangeRate {
-    let json_output::Conversion {
-        source,
-        target,
-        target_per_source,
-    } = c;
-    ExchangeRate {
-        from: denomination_from_json(source),
-        to: denomination_from_json(target),
-        rate: *target_per_source,
-    }
+/// An asset with a negative amount, e.g. a loan or margin debt, which is
+/// subtracted from the total.
+#[derive(Debug, PartialEq)]
+struct Liability {
+    /// Name of the source holding it.
+    source: String,
+    asset: Asset,
+    /// What it's worth in the common currency (a negative amount).
+    value: Asset,
 }
 
-fn exchange_rate_to_json(exchange_rate: &ExchangeRate) -> json_output::Conversion {
-    let ExchangeRate { from, to, rate } = exchange_rate;
-    json_output::Conversion {
-        source: denomination_to_json(from),
-        target: denomination_to_json(to),
-        target_per_source: *rate,
-    }
+/// Source snapshots valued in the common currency.
+struct Valuation {
+    total: Asset,
+    /// Price of one unit of each reachable denomination in the common
+    /// currency.
+    in_common_currency: HashMap<Denomination, Decimal>,
+    /// Amounts left out of `total` because they can't be converted to the
+    /// common currency, ordered by denomination.
+    unpriced: Vec<Asset>,
+    /// Unrealized gain of all assets with a known cost basis, in the common
+    /// currency. `None` if no asset has one.
+    unrealized_pnl: Option<Asset>,
+    /// Percentage of the assets held (before subtracting liabilities) in each
+    /// type of denomination.
+    allocation: Vec<(DenominationType, Decimal)>,
+    /// Priced assets with negative amounts, in the order of the sources.
+    liabilities: Vec<Liability>,
+    /// What was left out of `total` on purpose, if anything.
+    excluded: Option<Excluded>,
+    /// How much of what's held `total` covers.
+    coverage: Coverage,
 }
 
-fn source_snapshot_to_json(source_snapshot: &SourceSnapshot) -> json_output::SourceSnapshot {
-    json_output::SourceSnapshot {
-        id: source_snapshot.id.clone(),
-        name: source_snapshot.name.clone(),
-        source_type: match source_snapshot.source_type {
-            SourceType::Hardcoded => json_output::SourceType::Hardcoded,
-            SourceType::IBFlex => json_output::SourceType::IBFlex,
-        },
-        snapshot: source_snapshot.snapshot.iter().map(asset_to_json).collect(),
-    }
+/// How much of what's held is priced in the common currency, e.g. when a
+/// converter failed and part of the conversion graph is cut off from it.
+#[derive(Debug, Default, PartialEq)]
+struct Coverage {
+    /// Denominations held.
+    held: usize,
+    /// Denominations held that are priced.
+    priced: usize,
+    /// Value of the unpriced assets, going by what their sources report
+    /// alongside their cost basis (cost plus unrealized P/L), if it's in a
+    /// priced currency.
+    unpriced_value: Decimal,
+    /// Unpriced denominations of which nothing is known to value them by.
+    unvalued: usize,
 }
 
-fn source_snapshot_from_json(json_snapshot: &json_output::SourceSnapshot) -> SourceSnapshot {
-    SourceSnapshot {
-        id: json_snapshot.id.clone(),
-        name: json_snapshot.name.clone(),
-        source_type: match json_snapshot.source_type {
-            json_output::SourceType::Hardcoded => SourceType::Hardcoded,
-            json_output::SourceType::IBFlex => SourceType::IBFlex,
-        },
-        snapshot: json_snapshot.snapshot.iter().map(asset_from_json).collect(),
+impl Coverage {
+    /// Coverage of `assets`, summed by denomination, of which `unpriced` have
+    /// no price in `in_common_currency`.
+    fn of(
+        assets: &[Asset],
+        unpriced: &[Asset],
+        in_common_currency: &HashMap<Denomination, Decimal>,
+    ) -> Coverage {
+        let mut unpriced_value = Decimal::ZERO;
+        let mut unvalued = 0;
+        for asset in unpriced {
+            let reported = asset.cost_basis.as_ref().and_then(|cost_basis| {
+                let currency = Denomination::Currency {
+                    currency: cost_basis.currency.clone(),
+                };
+                in_common_currency
+                    .get(&currency)
+                    .map(|rate| (cost_basis.money + cost_basis.unrealized_pnl) * rate)
+            });
+            match reported {
+                Some(value) => unpriced_value += value,
+                None => unvalued += 1,
+            }
+        }
+        Coverage {
+            held: assets.len(),
+            priced: assets.len() - unpriced.len(),
+            unpriced_value,
+            unvalued,
+        }
     }
-}
 
-fn denomination_to_json(denomination: &Denomination) -> json_output::Denomination {
-    match denomination {
-        Denomination::Currency { currency } => json_output::Denomination::Currency {
-            symbol: currency.clone(),
-        },
-        Denomination::Cryptocurrency { symbol } => json_output::Denomination::Cryptocurrency {
-            symbol: symbol.clone(),
-        },
-        Denomination::Stock { stock } => json_output::Denomination::Stock {
-            symbol: stock.clone(),
-        },
+    /// E.g. "priced 3 of 4 denominations, 12.5% of value unpriced", or `None`
+    /// if everything held is priced. `total` is the value of what's priced.
+    fn annotation(&self, total: Decimal, percent_places: usize) -> Option<String> {
+        if self.priced == self.held {
+            return None;
+        }
+        let priced = format!("priced {} of {} denominations", self.priced, self.held);
+        let everything = total + self.unpriced_value;
+        let unpriced = if self.unvalued == self.held - self.priced || everything <= Decimal::ZERO {
+            "unpriced value unknown".to_string()
+        } else {
+            format!(
+                "{}{:.*}% of value unpriced",
+                if self.unvalued > 0 { "at least " } else { "" },
+                percent_places,
+                self.unpriced_value / everything * dec!(100)
+            )
+        };
+        Some(format!("{}, {}", priced, unpriced))
     }
 }
 
-fn denomination_from_json(denomination: &json_output::Denomination) -> Denomination {
-    match denomination {
-        json_output::Denomination::Currency { symbol } => Denomination::Currency {
-            currency: symbol.clone(),
-        },
-        json_output::Denomination::Cryptocurrency { symbol } => Denomination::Cryptocurrency {
-            symbol: symbol.clone(),
-        },
-        json_output::Denomination::Stock { symbol } => Denomination::Stock {
-            stock: symbol.clone(),
-        },
-    }
+/// Sources and denominations left out of a total, e.g. to see what's liquid.
+#[derive(Debug, PartialEq)]
+struct Excluded {
+    sources: Vec<String>,
+    denominations: Vec<Denomination>,
+    /// The total with them.
+    unfiltered_total: Asset,
 }
 
-fn asset_to_json(asset: &Asset) -> json_output::Asset {
-    json_output::Asset {
-        denomination: denomination_to_json(&asset.denomination),
-        amount: asset.amount,
+/// Sum of the unrealized gains of all assets with a known cost basis, in
+/// the common currency. Gains in currencies that can't be converted to it are
+/// left out with a warning.
+fn unrealized_pnl(
+    source_snapshots: &[SourceSnapshot],
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Option<Decimal> {
+    let mut total = None;
+    for asset in source_snapshots.iter().flat_map(|ss| ss.snapshot.iter()) {
+        let Some(cost_basis) = &asset.cost_basis else {
+            continue;
+        };
+        let currency = Denomination::Currency {
+            currency: cost_basis.currency.clone(),
+        };
+        match in_common_currency.get(&currency) {
+            Some(rate) => {
+                *total.get_or_insert(Decimal::ZERO) += cost_basis.unrealized_pnl * rate;
+            }
+            None => warn!(
+                "unrealized gain of {} in {} not connected to common currency",
+                asset.denomination, currency
+            ),
+        }
     }
+    total
 }
 
-fn asset_from_json(asset: &json_output::Asset) -> Asset {
-    Asset {
-        denomination: denomination_from_json(&asset.denomination),
-        amount: asset.amount,
+/// Total of all source snapshots in `base`, along with the rates of all
+/// denominations into `base`. Assets that can't be converted to `base` are
+/// left out with a warning, and added up separately. Fails if nothing converts
+/// to or from `base` while something else is held, or if the rates allow
+/// arbitrage.
+fn value_snapshot(
+    base: &Denomination,
+    pricing: &Pricing,
+    converter_snapshots: &[ConverterSnapshot],
+    source_snapshots: &[SourceSnapshot],
+) -> Result<Valuation, WorthyError> {
+    let all_conversions = pricing.conversions(converter_snapshots);
+    info!("All conversions: {:?}", all_conversions);
+
+    let all_assets = aggregate_assets(source_snapshots)?;
+    info!("All assets: {:?}", all_assets);
+
+    let in_common_currency = match common_currency::in_common_currency(&all_conversions, base) {
+        // Everything is held in the base, so nothing needs converting.
+        Err(CommonCurrencyError::BaseNotReachable { .. })
+            if all_assets.iter().all(|asset| asset.denomination == *base) =>
+        {
+            HashMap::from([(base.clone(), Decimal::ONE)])
+        }
+        result => result?,
+    };
+    info!("In common currency: {:?}", in_common_currency);
+
+    let mut total_amount = Decimal::ZERO;
+    let mut unpriced = Vec::new();
+    let mut values = Vec::new();
+    let mut liabilities = Vec::new();
+    for ss in source_snapshots.iter() {
+        info!("{} {}", ss.id, ss.name);
+        for asset in ss.snapshot.iter() {
+            if let Some(value) = asset.in_base(base, &in_common_currency) {
+                info!("{:?}: {:?} in common currency", asset, value.amount);
+                total_amount = total_amount.checked_add(value.amount).ok_or_else(|| {
+                    WorthyError::Overflow(format!("total in {} of all sources", base))
+                })?;
+                if value.amount < Decimal::ZERO {
+                    liabilities.push(Liability {
+                        source: ss.name.clone(),
+                        asset: asset.clone(),
+                        value,
+                    });
+                } else {
+                    values.push((asset.denomination.clone(), value.amount));
+                }
+            } else {
+                warn!("{:?} not connected to common currency", asset.denomination);
+                unpriced.push(asset);
+            }
+        }
     }
+
+    let total = Asset {
+        amount: total_amount,
+        denomination: base.clone(),
+        cost_basis: None,
+    };
+    info!("Total in common currency: {:?}", total);
+    let unrealized_pnl =
+        unrealized_pnl(source_snapshots, &in_common_currency).map(|amount| Asset {
+            amount,
+            denomination: base.clone(),
+            cost_basis: None,
+        });
+    let unpriced = sum_by_denomination(unpriced)
+        .ok_or_else(|| WorthyError::Overflow("sum of the unpriced assets".to_string()))?;
+    let coverage = Coverage::of(&all_assets, &unpriced, &in_common_currency);
+    Ok(Valuation {
+        total,
+        in_common_currency,
+        unpriced,
+        unrealized_pnl,
+        allocation: allocation::total_by_type(&values),
+        liabilities,
+        excluded: None,
+        coverage,
+    })
 }
 
-fn render_table(
-    deadline: Decimal,
-    total: &Asset,
-    modelling: &config::ModellingConfig,
+/// Like `value_snapshot`, but without the sources with IDs in `sources`, and
+/// without assets in `denominations` in the rest.
+fn value_excluding(
     base: &Denomination,
+    pricing: &Pricing,
+    converter_snapshots: &[ConverterSnapshot],
+    source_snapshots: &[SourceSnapshot],
+    sources: &[String],
+    denominations: &[Denomination],
+) -> Result<Valuation, WorthyError> {
+    for id in sources {
+        if !source_snapshots.iter().any(|ss| &ss.id == id) {
+            warn!("excluded source {} is not in the snapshot", id);
+        }
+    }
+    let kept: Vec<SourceSnapshot> = source_snapshots
+        .iter()
+        .filter(|ss| !sources.contains(&ss.id))
+        .map(|ss| SourceSnapshot {
+            id: ss.id.clone(),
+            name: ss.name.clone(),
+            source_type: ss.source_type.clone(),
+            snapshot: ss
+                .snapshot
+                .iter()
+                .filter(|asset| !denominations.contains(&asset.denomination))
+                .cloned()
+                .collect(),
+            base_currency: ss.base_currency.clone(),
+            accounts: ss.accounts.clone(),
+        })
+        .collect();
+    let mut valuation = value_snapshot(base, pricing, converter_snapshots, &kept)?;
+    valuation.excluded = Some(Excluded {
+        sources: sources.to_vec(),
+        denominations: denominations.to_vec(),
+        unfiltered_total: value_snapshot(base, pricing, converter_snapshots, source_snapshots)?
+            .total,
+    });
+    Ok(valuation)
+}
+
+/// Posts the portfolio to cFIREsim and returns the outcome of its simulation.
+async fn simulate_cfiresim(
+    c: &config::CFireSimConfig,
+    source_snapshots: &[SourceSnapshot],
     in_common_currency: &HashMap<Denomination, Decimal>,
-) {
-    let mut table = Table::new();
+) -> Result<json_output::CfireSimResult, WorthyError> {
+    // Post to cFIREsim.
 
-    table.max_column_width = 40;
-    table.style = TableStyle::extended();
+    // Add up all sources that are in the portfolio.
+    let snapshot_by_id: HashMap<String, &SourceSnapshot> = source_snapshots
+        .iter()
+        .map(|snapshot| (snapshot.id.clone(), snapshot))
+        .collect();
 
-    table.add_row(Row::new(vec![
-        TableCell::builder(format!(
-            "\u{2211} {}\nHorizon: {} years",
-            asset_to_money(total),
-            deadline
-        ))
-        .col_span(1 + modelling.yearly_yields.len())
-        .alignment(Alignment::Center)
-        .build(),
-    ]));
+    let portfolio_total = add_up_amounts(&c.portfolio, &snapshot_by_id, in_common_currency)?;
+    info!("portfolio total: {}", portfolio_total);
+    let portfolio_allocation = allocation::allocation(&values_in_common_currency(
+        &c.portfolio,
+        &snapshot_by_id,
+        in_common_currency,
+    )?);
+    info!("portfolio allocation: {:?}", portfolio_allocation);
 
-    let mut header = vec![TableCell::new(
-        "Yearly yield \u{2192}\nMonthly goal \u{2193}".to_string(),
-    )];
-    // \u2211 = N-ary summation
-    for yld in &modelling.yearly_yields {
-        header.push(TableCell::new(format!("{:.2}%", yld * dec!(100),)));
+    let csrf_middleware_token: &str =
+        "eFBajFh8XEERVEK6yuI00J4R1qWjonS4xv417X4toibJYzGc220Y36dEcFGcvFZr";
+    let mut params: HashMap<String, String> = HashMap::new();
+    for (k, v) in &[
+        ("csrfmiddlewaretoken", csrf_middleware_token),
+        ("data_method", "historical_all"),
+        ("single_simulation_year", "1966"),
+        ("historical_data_start_year", "1900"),
+        ("historical_data_end_year", "1980"),
+        ("constant_market_growth", "7.50"),
+        ("spending_plan", "inflation_adjusted"),
+        ("inflation_type", "cpi"),
+        ("inflation_flat_rate", "3.10"),
+        ("guyton_exceeds", "20"),
+        ("guyton_cut", "10"),
+        ("guyton_fall", "20"),
+        ("guyton_raise", "10"),
+        ("yearly_spending_percent_of_portfolio", "4"),
+        ("z_value", "0.50"),
+        ("vpw_rate_of_return", "4.30"),
+        ("vpw_future_value", "0"),
+        ("hebeler_age_at_retirement", "0"),
+        ("hebeler_weighted_rmd", "50"),
+        ("hebeler_weighted_cpi", "50"),
+        ("cape_yield_multiplier", "0.50"),
+        ("cape_constant_adjustment", "1.00"),
+        ("spending_floor_type", "none"),
+        ("spending_floor_value", "0"),
+        ("spending_ceiling_type", "none"),
+        ("spending_ceiling_value", "0"),
+        ("investigate_initial_yearly_spending_threshold", "95"),
+        ("fees", "0.18"),
+        ("rebalance_annually", "on"),
+        ("growth_of_cash", "0.25"),
+        ("keep_allocation_constant", "on"),
+        ("change_allocation_start_year", "2031"),
+        ("target_equities", "50"),
+        ("target_bonds", "50"),
+        ("change_allocation_end_year", "2041"),
+        ("target_gold", "0"),
+        ("target_cash", "0"),
+        ("ss_frequency_toggle", "monthly"),
+        ("ss_end_year", "2100"),
+        ("ss_spouse_frequency_toggle", "annual"),
+        ("ss_spouse_annual_value", "0"),
+        ("ss_spouse_start_year", "2036"),
+        ("ss_spouse_end_year", "2100"),
+        ("form-TOTAL_FORMS", "10"),
+        ("form-INITIAL_FORMS", "0"),
+        ("form-MIN_NUM_FORMS", "0"),
+        ("form-MAX_NUM_FORMS", "1000"),
+    ] {
+        params.insert(k.to_string(), v.to_string());
     }
-    table.add_row(Row::new(header));
+    params.insert("retirement_year".to_string(), c.retirement_year.to_string());
+    params.insert(
+        "retirement_end_year".to_string(),
+        c.retirement_end_year.to_string(),
+    );
+    params.insert(
+        "initial_yearly_spending".to_string(),
+        c.initial_yearly_spending.to_string(),
+    );
+    params.insert(
+        "ss_start_year".to_string(),
+        c.social_security.start_year.to_string(),
+    );
+    params.insert(
+        "ss_annual_value".to_string(),
+        c.social_security.monthly_amount.to_string(),
+    );
+    params.insert("portfolio_value".to_string(), portfolio_total.to_string());
+    let allocation::Allocation {
+        equities,
+        bonds,
+        cash,
+        gold,
+    } = portfolio_allocation;
+    params.insert("equities".to_string(), equities.to_string());
+    params.insert("bonds".to_string(), bonds.to_string());
+    params.insert("cash".to_string(), cash.to_string());
+    params.insert("gold".to_string(), gold.to_string());
 
-    let mut perpetuals = vec![TableCell::new("Perpetuals".to_string())];
-    let denominations: HashSet<Denomination> = modelling
-        .monthly_targets
+    if !c.adjustment.is_empty() {
+        // First adjustment.
+        params.insert("form-0-label".to_string(), c.adjustment[0].name.clone());
+        params.insert("form-0-adjustment_type".to_string(), "pension".to_string());
+        params.insert("form-0-inflation_adjusted".to_string(), "on".to_string());
+        params.insert("form-0-inflation_type".to_string(), "cpi".to_string());
+        params.insert(
+            "form-0-start_year".to_string(),
+            c.adjustment[0].year.to_string(),
+        );
+        let adjustment_total =
+            add_up_amounts(&c.adjustment[0].source, &snapshot_by_id, in_common_currency)?;
+        info!("adjustment total: {}", adjustment_total);
+        params.insert(
+            "form-0-amount_per_year".to_string(),
+            adjustment_total.to_string(),
+        );
+    } else {
+        params.insert("form-0-label".to_string(), "".to_string());
+        params.insert("form-0-adjustment_type".to_string(), "income".to_string());
+        params.insert("form-0-inflation_adjusted".to_string(), "on".to_string());
+        params.insert("form-0-inflation_type".to_string(), "cpi".to_string());
+        params.insert("form-0-start_year".to_string(), "2022".to_string());
+        params.insert("form-0-end_year".to_string(), "2100".to_string());
+        params.insert("form-0-recurring".to_string(), "on".to_string());
+    }
+
+    // Remaining adjustments.
+    for i in 1..=10 {
+        for (k, v) in &[
+            ("label", "".to_string()),
+            ("amount_per_year", "".to_string()),
+            ("adjustment_type", "income".to_string()),
+            ("recurring", "on".to_string()),
+            ("inflation_adjusted", "on".to_string()),
+            ("start_year", "2021".to_string()),
+            ("end_year", "2100".to_string()),
+            ("inflation_type", "cpi".to_string()),
+        ] {
+            params.insert(format!("form-{}-{}", i, k), v.clone());
+        }
+    }
+
+    let client = reqwest::Client::new();
+    let response = client
+        .post("https://www.cfiresim.com/calculator/get_simulation")
+        .form(&params)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        println!("{:#?}", response.text().await);
+        return Err(WorthyError::Http(format!("cFIREsim returned {}", status)));
+    }
+    let v: serde_json::Value = response.json().await?;
+    cfiresim::parse_response(&v)
+}
+
+/// Values everything in the snapshots, and, if any sources or denominations
+/// are excluded in the configuration or with flags, what's left without them.
+fn value_modelled(
+    config: &Config,
+    opt: &Opt,
+    base: &Denomination,
+    converter_snapshots: &[ConverterSnapshot],
+    source_snapshots: &[SourceSnapshot],
+) -> Result<(Valuation, Option<Valuation>), WorthyError> {
+    let pricing = Pricing::of(config);
+    let everything = value_snapshot(base, &pricing, converter_snapshots, source_snapshots)?;
+    let exclude_sources: Vec<String> = config
+        .exclude_sources
         .iter()
-        .map(|asset| asset.denomination.clone())
+        .chain(&opt.exclude_sources)
+        .cloned()
         .collect();
-    for yearly_yield in &modelling.yearly_yields {
-        let mut perps = Vec::new();
-        for denomination in denominations.iter() {
-            let perpetual = get_perpetual(total, *yearly_yield, in_common_currency, denomination);
-            perps.push(format!("{}", asset_to_money(&perpetual)));
+    let exclude_denominations: Vec<Denomination> = config
+        .exclude_denominations
+        .iter()
+        .chain(&opt.exclude_denominations)
+        .cloned()
+        .collect();
+    let excluding = (!exclude_sources.is_empty() || !exclude_denominations.is_empty())
+        .then(|| {
+            value_excluding(
+                base,
+                &pricing,
+                converter_snapshots,
+                source_snapshots,
+                &exclude_sources,
+                &exclude_denominations,
+            )
+        })
+        .transpose()?;
+    Ok((everything, excluding))
+}
+
+/// Values and models the snapshots and prints the result. Tables end with the
+/// trend of the totals of snapshots taken before `trend_before`.
+async fn model_and_show(
+    config: &Config,
+    base: &Denomination,
+    converter_snapshots: &[ConverterSnapshot],
+    source_snapshots: &[SourceSnapshot],
+    opt: &Opt,
+    now: DateTime<Utc>,
+    trend_before: DateTime<FixedOffset>,
+) -> Result<
+    (
+        Valuation,
+        Option<json_output::CfireSimResult>,
+        Option<Vec<json_output::ModellingResult>>,
+    ),
+    WorthyError,
+> {
+    if let Some(path) = &opt.dump_graph {
+        let path = paths::expand(&path.to_string_lossy())?;
+        paths::create_parent_dir(&path)?;
+        let dot = common_currency::conversion_graph_dot(&all_conversions(converter_snapshots));
+        std::fs::write(&path, dot).map_err(|error| {
+            WorthyError::Config(format!("cannot write {}: {}", path.display(), error))
+        })?;
+    }
+    let pricing = Pricing::of(config);
+    let (everything, excluding) =
+        value_modelled(config, opt, base, converter_snapshots, source_snapshots)?;
+    // What's modelled and shown. Snapshots keep everything.
+    let valuation = excluding.as_ref().unwrap_or(&everything);
+    let in_common_currency = &valuation.in_common_currency;
+
+    let cfiresim = match &config.cfiresim {
+        Some(c) => {
+            let result = simulate_cfiresim(c, source_snapshots, in_common_currency).await?;
+            println!(
+                "cFIREsim: {}% of cycles succeeded (failed {} of {})",
+                result.success_rate, result.cycles_failed, result.cycles_total
+            );
+            println!("https://www.cfiresim.com/{}", result.tracking_uuid);
+            Some(result)
         }
-        perpetuals.push(TableCell::new(perps.join("\n")));
+        None => None,
+    };
+
+    print!(
+        "{}",
+        render(
+            opt.format,
+            opt.ascii,
+            Horizon {
+                now,
+                deadline: config.modelling.horizon_years,
+            },
+            valuation,
+            &config.modelling,
+            base,
+        )?
+    );
+    if opt.format == Format::Table {
+        print!(
+            "{}",
+            render_trend(
+                &earlier_totals(config, base, trend_before).await?,
+                &everything.total,
+                opt.ascii
+            )
+        );
     }
+    if opt.explain {
+        for (goal, _, fi_info) in fi_grid(
+            Horizon {
+                now,
+                deadline: config.modelling.horizon_years,
+            },
+            &valuation.total,
+            &config.modelling,
+            &valuation.in_common_currency,
+        )? {
+            eprint!("{}", explain_fi_info(goal, &fi_info, base));
+        }
+    }
+    let report = reachability(base, &pricing, converter_snapshots, source_snapshots)?;
+    if opt.reachability {
+        print!("{}", render_reachability(&report, base, opt.ascii));
+    } else {
+        for (denomination, price) in &report {
+            match price {
+                Some((price, path)) => info!(
+                    "{} is priced at {} {} over {} conversions",
+                    denomination,
+                    price,
+                    base,
+                    path.len() - 1
+                ),
+                None => info!("{} is not connected to {}", denomination, base),
+            }
+        }
+    }
+    if opt.machine {
+        println!("{}", machine_total_line(valuation));
+    }
+    let modelling = opt
+        .emit_modelling
+        .then(|| {
+            modelling_results(
+                Horizon {
+                    now,
+                    deadline: config.modelling.horizon_years,
+                },
+                &valuation.total,
+                &config.modelling,
+                &valuation.in_common_currency,
+            )
+        })
+        .transpose()?;
+    Ok((everything, cfiresim, modelling))
+}
 
-    table.add_row(Row::new(perpetuals));
+/// Decimal places of the amount in the `--machine` total line.
+const MACHINE_TOTAL_PLACES: u32 = 2;
 
-    for goal in &modelling.monthly_targets {
-        let mut results = Vec::new();
-        results.push(TableCell::new(format!("{}", asset_to_money(goal))));
+/// The total as one line for scripts, e.g. "TOTAL 12345.60 USD", always with
+/// `MACHINE_TOTAL_PLACES` places and without separators. Ends with "PARTIAL"
+/// if some assets can't be priced and are left out of it.
+fn machine_total_line(valuation: &Valuation) -> String {
+    let mut amount = valuation.total.amount.round_dp(MACHINE_TOTAL_PLACES);
+    amount.rescale(MACHINE_TOTAL_PLACES);
+    let mut line = format!("TOTAL {} {}", amount, valuation.total.denomination.symbol());
+    if !valuation.unpriced.is_empty() {
+        line.push_str(" PARTIAL");
+    }
+    line
+}
 
-        for yearly_yield in &modelling.yearly_yields {
-            let result = model_fi_info(
-                total,
-                in_common_currency,
-                *yearly_yield,
-                goal.clone(),
-                &modelling.monthly_saving,
-                deadline,
-            );
-            use model_rs::State::*;
-            results.push(TableCell::new(match result.model_fi_info.state {
-                NotReached { .. } => {
-                    // 2693 = unicode anchor
-                    // 1F4B0 = bag with money
-                    format!(
-                        "💰 ≥{}\n{}",
-                        asset_to_money(&Asset {
-                            amount: result.model_fi_info.need_to_last_until_deadline,
-                            denomination: base.clone()
-                        }),
-                        result.model_fi_info.lasts_until_short_string()
-                    )
-                }
-                Reached { .. } => result.model_fi_info.lasts_until_short_string(),
-            }));
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+    let result = async move {
+        init_logging(&opt)?;
+        trace!("Options: {:?}", opt);
+        run(opt).await
+    }
+    .await;
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        match &error {
+            WorthyError::Config(_) => {
+                eprintln!(
+                    "Check the configuration (~/.config/worthy/config.yaml, or --config) \
+                     and the API keys in it."
+                )
+            }
+            WorthyError::ConverterQuota { converter, .. } => eprintln!(
+                "Try again later, or disable converter {} for now.",
+                converter
+            ),
+            WorthyError::UnpricedDenomination(_) => {
+                eprintln!("Configure a converter that can price it.")
+            }
+            WorthyError::StaleSnapshot { .. } => {
+                eprintln!("Take a fresh snapshot, or pass --max-age-warn to model it anyway.")
+            }
+            WorthyError::SelfTest { .. } => {
+                eprintln!("The model's formulas have regressed; see the failed scenarios above.")
+            }
+            WorthyError::BaseNotReachable { .. } => eprintln!(
+                "The converters pricing the common currency may have failed; check the log, \
+                 or configure one that prices it."
+            ),
+            WorthyError::Arbitrage { .. } => eprintln!(
+                "A converter is quoting a wrong rate; run `worthy checkrates` to find it, \
+                 and disable it for now."
+            ),
+            WorthyError::Http(_)
+            | WorthyError::Parse(_)
+            | WorthyError::IbFlex { .. }
+            | WorthyError::Overflow(_)
+            | WorthyError::TotalMismatch { .. }
+            | WorthyError::NoSnapshots(_)
+            | WorthyError::RatesDisagree { .. } => {}
         }
-        table.add_row(Row::new(results));
+        std::process::exit(error.exit_code());
     }
-    print!("{}", table.render());
 }
 
-struct FiInfo {
-    model_fi_info: model_rs::FiInfo,
+/// Logs at `--log-level`, unless `RUST_LOG` is set, to stderr or appending to
+/// `--log-file`.
+fn init_logging(opt: &Opt) -> Result<(), WorthyError> {
+    let mut builder = env_logger::Builder::new();
+    if let Some(level) = opt.log_level {
+        builder.filter_level(level);
+    }
+    builder.parse_default_env();
+    if let Some(path) = &opt.log_file {
+        let path = paths::expand(&path.to_string_lossy())?;
+        paths::create_parent_dir(&path)?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|error| {
+                WorthyError::Config(format!(
+                    "cannot open log file {}: {}",
+                    path.display(),
+                    error
+                ))
+            })?;
+        builder.target(env_logger::Target::Pipe(Box::new(file)));
+    }
+    builder.init();
+    Ok(())
 }
 
-fn get_perpetual(
-    total: &Asset,
-    yearly_yield: Decimal,
-    common_prices: &HashMap<Denomination, Decimal>,
-    denomination: &Denomination,
-) -> Asset {
-    let amount = (total.amount * yearly_yield / dec!(12)) / common_prices[denomination];
-    Asset {
-        amount,
-        denomination: denomination.clone(),
+/// Runs the model's hand-computed scenarios, printing how each comes out.
+fn self_test() -> Result<(), WorthyError> {
+    let outcomes: Vec<scenarios::Outcome> = scenarios::scenarios()
+        .iter()
+        .map(|scenario| scenario.run())
+        .collect();
+    for outcome in &outcomes {
+        println!("{}", format_outcome(outcome));
     }
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed).count();
+    if failed > 0 {
+        return Err(WorthyError::SelfTest {
+            failed,
+            total: outcomes.len(),
+        });
+    }
+    println!("All {} scenarios passed.", outcomes.len());
+    Ok(())
 }
 
-// Yearly yield: 0.03 means assumed yearly yield of 3%.
-fn model_fi_info(
-    total: &Asset,
-    common_prices: &HashMap<Denomination, Decimal>,
-    yearly_yield: Decimal,
-    monthly_goal: Asset,
-    monthly_saving: &Asset,
-    deadline: Decimal,
-) -> FiInfo {
-    // TODO(agentydragon): make the monthly spend limited to the deadline, not
-    // perpetual
-    //
-    // does not seem to work so well - sometimes is smaller than perpetual,
-    // which it should not be:
-    //
-    //i_prime := math.Log(1 + yearly_yield)
-    //f := math.Pow(1+yearly_yield, deadline)
-    //projectedInCommon := (total.Amount * i_prime * f / (f - 1)) / 12
-    //projectedMonthlySpend :=
-    //	makeCurrency(monthly_goal.Denomination.Symbol, projectedInCommon/common_prices[monthly_goal.Denomination])
-    //fmt.Printf("yearly yield %.2g%%, monthly goal %s, projected monthly spend %s, perpetual %s\n", yearly_yield*100.0, monthly_goal,
-    //	projectedMonthlySpend)
-
-    let to_common = |x: &Asset| -> Decimal { common_prices[&x.denomination] * x.amount };
-    FiInfo {
-        model_fi_info: model_rs::model_fi_info(
-            to_common(total),
-            yearly_yield,
-            to_common(&monthly_goal),
-            to_common(monthly_saving),
-            deadline,
-        ),
+/// E.g. "ok   saving up at 0% yield: (100000 - 10000) / (12 * 1000) = 7.5".
+fn format_outcome(outcome: &scenarios::Outcome) -> String {
+    let format = |value: Option<Decimal>| match value {
+        Some(value) => value.round_dp(9).normalize().to_string(),
+        None => "none".to_string(),
+    };
+    let mut line = format!(
+        "{} {}: {} = {}",
+        if outcome.passed { "ok  " } else { "FAIL" },
+        outcome.name,
+        outcome.formula,
+        format(outcome.expected)
+    );
+    if !outcome.passed {
+        line += &format!(", got {}", format(outcome.actual));
+    }
+    line
+}
+
+async fn run(opt: Opt) -> Result<(), WorthyError> {
+    // Needs neither the configuration nor the network.
+    if opt.command == flags::Command::SelfTest {
+        return self_test();
+    }
+    check_base_from_source(&opt)?;
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("worthy");
+    let mut config = load_config(&xdg_dirs, opt.config.as_deref())?;
+    if let Some(horizon_years) = opt.horizon {
+        config.modelling.horizon_years = horizon_years;
+    }
+    if config.modelling.horizon_years <= Decimal::ZERO {
+        return Err(WorthyError::Config(format!(
+            "horizon of {} years is not positive",
+            config.modelling.horizon_years
+        )));
+    }
+    trace!("Config: {:?}", config);
+    let registry = Registry::builtin();
+    let (sources, source_warnings) = registry.sources(&config.source_config);
+    let (converters, converter_warnings) = registry.converters(&config.converter_config);
+    for warning in source_warnings.iter().chain(&converter_warnings) {
+        warn!("{}", warning);
+    }
+
+    // The time of the snapshot and of the model, unless backfilling.
+    let now: DateTime<FixedOffset> = opt.as_of.unwrap_or_else(|| Utc::now().into());
+
+    use flags::Command::*;
+    match opt.command {
+        Snapshot => {
+            // Runs from cron may overlap if one is slow. Let the first finish.
+            let lock_path = xdg_dirs
+                .place_runtime_file("snapshot.lock")
+                .or_else(|_| xdg_dirs.place_cache_file("snapshot.lock"))
+                .map_err(|error| WorthyError::Config(error.to_string()))?;
+            let _lock = match Lock::acquire(&lock_path)? {
+                Acquired::Acquired(lock) => lock,
+                Acquired::HeldBy(pid) => {
+                    eprintln!(
+                        "Another snapshot is being taken{}, not taking one.",
+                        pid.map_or_else(String::new, |pid| format!(" (PID {})", pid))
+                    );
+                    return Ok(());
+                }
+            };
+
+            // With --only or --only-converters, refresh just those on top of
+            // the newest snapshot.
+            let incremental = !opt.only.is_empty() || !opt.only_converters.is_empty();
+            let selected_sources = select_by_id(
+                &sources,
+                incremental.then_some(opt.only.as_slice()),
+                "source",
+            )?;
+            let selected_converters = select_by_id(
+                &converters,
+                incremental.then_some(opt.only_converters.as_slice()),
+                "converter",
+            )?;
+            let (base_converter_snapshots, base_source_snapshots) = if incremental {
+                snapshots_from_json(&newest_snapshot(&snapshot_store(&config)?)?)
+            } else {
+                (Vec::new(), Vec::new())
+            };
+
+            // Collect all assets from all sources.
+            let (mut fresh_source_snapshots, source_prices) =
+                get_source_snapshots(selected_sources, opt.parallel_sources).await?;
+            canonicalize_assets(&mut fresh_source_snapshots, &config.aliases);
+            let source_snapshots =
+                merge_by_id(base_source_snapshots, fresh_source_snapshots, |snapshot| {
+                    &snapshot.id
+                });
+
+            let all_assets = aggregate_assets(&source_snapshots)?;
+            info!("All assets: {:?}", all_assets);
+            let held = denominations_to_price(&all_assets, &config.modelling);
+
+            // TODO: check it exists
+            let base =
+                common_currency(&config, opt.base_from_source.as_deref(), &source_snapshots)?;
+
+            // Count API calls across runs, so daily quotas aren't blown.
+            let call_counts_path = xdg_dirs
+                .place_cache_file("call_counts.json")
+                .map_err(|error| WorthyError::Config(error.to_string()))?;
+            let call_counts = Mutex::new(CallCounts::load(&call_counts_path));
+            let recordings = rate_recordings(&config, opt.replay_rates)?;
+            let fresh_converter_snapshots = get_converter_snapshots(
+                &held,
+                selected_converters,
+                &base,
+                &call_counts,
+                recordings.as_ref(),
+                opt.parallel_converters,
+            )
+            .await;
+            call_counts.into_inner().unwrap().save(&call_counts_path)?;
+            let mut fresh_converter_snapshots = fresh_converter_snapshots?;
+            fresh_converter_snapshots.extend(source_prices);
+            canonicalize_rates(&mut fresh_converter_snapshots, &config.aliases);
+            let mut converter_snapshots: Vec<ConverterSnapshot> = merge_by_id(
+                base_converter_snapshots,
+                fresh_converter_snapshots,
+                |snapshot| &snapshot.id,
+            )
+            .into_iter()
+            .filter(|snapshot| !matches!(snapshot.converter_type, ConverterType::Basket))
+            .collect();
+            // Price baskets through their components, as of the rates above.
+            let basket_rates = basket::basket_rates(
+                &config.baskets,
+                &held,
+                &Pricing::of(&config).conversions(&converter_snapshots),
+                &base,
+            )?;
+            if !basket_rates.is_empty() {
+                converter_snapshots.push(ConverterSnapshot {
+                    id: BASKET_CONVERTER_ID.to_string(),
+                    converter_type: ConverterType::Basket,
+                    snapshot: basket_rates,
+                });
+            }
+
+            warn_about_stale_sources(&sources, now.into());
+            let (valuation, cfiresim, modelling) = model_and_show(
+                &config,
+                &base,
+                &converter_snapshots,
+                &source_snapshots,
+                &opt,
+                now.into(),
+                now,
+            )
+            .await?;
+
+            // Save JSON snapshot.
+            let json_snapshot = json_output::Snapshot {
+                timestamp: now,
+                source_snapshot: source_snapshots
+                    .iter()
+                    .map(source_snapshot_to_json)
+                    .collect(),
+                converter_snapshots: converter_snapshots
+                    .iter()
+                    .map(converter_snapshot_to_json)
+                    .collect(),
+                total: asset_to_json(&valuation.total),
+                unpriced: valuation.unpriced.iter().map(asset_to_json).collect(),
+                allocation: valuation
+                    .allocation
+                    .iter()
+                    .map(|(denomination_type, percent)| {
+                        (denomination_type.name().to_string(), percent.round_dp(2))
+                    })
+                    .collect(),
+                label: opt.label.clone(),
+                cfiresim,
+                modelling,
+            };
+            let store = snapshot_store(&config)?;
+            let same_day = if opt.overwrite_today {
+                store.same_day(now)?
+            } else {
+                None
+            };
+            let (path, replaced) = match same_day {
+                Some(earlier) => {
+                    store.overwrite(&earlier.path, &json_snapshot)?;
+                    (earlier.path, Some(earlier.timestamp))
+                }
+                None => (store.write(&json_snapshot)?, None),
+            };
+            if let Some(modelling) = &json_snapshot.modelling {
+                store.write_modelling(&path, modelling)?;
+            }
+            if let Some(jsonl_history) = &config.jsonl_history {
+                record_in_jsonl(
+                    &paths::expand(jsonl_history)?,
+                    &store,
+                    &json_snapshot,
+                    replaced,
+                )?;
+            }
+        }
+        ModelLastSnapshot => {
+            let snapshot = newest_snapshot(&snapshot_store(&config)?)?;
+            if let Some(max_age) = opt.max_age {
+                match check_snapshot_age(snapshot.timestamp, now, max_age) {
+                    Err(error) if opt.max_age_warn => eprintln!("Warning: {}", error),
+                    result => result?,
+                }
+            }
+            let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+            let base =
+                common_currency(&config, opt.base_from_source.as_deref(), &source_snapshots)?;
+            warn_about_stale_sources(&sources, now.into());
+            let _ = model_and_show(
+                &config,
+                &base,
+                &converter_snapshots,
+                &source_snapshots,
+                &opt,
+                now.into(),
+                snapshot.timestamp,
+            )
+            .await?;
+        }
+        Csv => {
+            json_output::check_date_range(opt.since, opt.until)
+                .map_err(|error| WorthyError::Config(error.to_string()))?;
+            let csv_path = paths::expand(&config.csv_output.replace("%s", &now.to_rfc3339()))?;
+            paths::create_parent_dir(&csv_path)?;
+            let mut wtr = csv::Writer::from_writer(File::create(&csv_path).unwrap());
+            wtr.write_record(["Timestamp", "Total"]).unwrap();
+            let mut write_row = |snapshot: &json_output::Snapshot| {
+                if json_output::taken_between(snapshot, opt.since, opt.until) {
+                    wtr.write_record(&[
+                        snapshot.timestamp.to_rfc3339(),
+                        snapshot.total.amount.to_string(),
+                    ])
+                    .unwrap();
+                }
+            };
+
+            let skipped = match &config.jsonl_history {
+                // Stream the history, so it needn't fit in memory.
+                Some(jsonl_history) => {
+                    let jsonl_path = paths::expand(jsonl_history)?;
+                    let file = File::open(&jsonl_path).map_err(|error| {
+                        WorthyError::Config(format!(
+                            "cannot open {}: {}",
+                            jsonl_path.display(),
+                            error
+                        ))
+                    })?;
+                    let mut skipped = 0;
+                    for snapshot in json_output::read_jsonl(std::io::BufReader::new(file)) {
+                        match snapshot {
+                            Ok(snapshot) => write_row(&snapshot),
+                            Err(error) => {
+                                warn!("skipping line of {}: {}", jsonl_path.display(), error);
+                                skipped += 1;
+                            }
+                        }
+                    }
+                    skipped
+                }
+                None => {
+                    let json_output::ReadSnapshots { snapshots, skipped } =
+                        snapshot_store(&config)?.read_all()?;
+                    for (_, snapshot) in &snapshots {
+                        write_row(snapshot);
+                    }
+                    skipped
+                }
+            };
+
+            println!("Written: {}", csv_path.display());
+            if skipped > 0 {
+                println!("Skipped {} unparseable snapshots", skipped);
+            }
+        }
+        ModelAll => {
+            let paths = snapshot_store(&config)?.existing_paths()?;
+            let json_output::ReadSnapshots {
+                mut snapshots,
+                skipped,
+            } = load_snapshots(&paths).await;
+            json_output::sort_by_timestamp(&mut snapshots);
+            let base = config.common_currency.clone();
+            print!(
+                "{}",
+                render_model_history(
+                    &base,
+                    &Pricing::of(&config),
+                    &config.modelling,
+                    &snapshots,
+                    now.into()
+                )?
+            );
+            if skipped > 0 {
+                eprintln!("Skipped {} unparseable snapshot files", skipped);
+            }
+        }
+        List => {
+            let json_output::ReadSnapshots {
+                mut snapshots,
+                skipped,
+            } = snapshot_store(&config)?.read_all()?;
+            json_output::sort_by_timestamp(&mut snapshots);
+            print!("{}", render_snapshot_list(&snapshots, opt.ascii));
+            if skipped > 0 {
+                println!("Skipped {} unparseable snapshot files", skipped);
+            }
+        }
+        Server => serve_metrics(config, opt.listen).await,
+        SelfTest => unreachable!("selftest runs before loading the configuration"),
+        Html => {
+            let html_output = config.html_output.as_ref().ok_or_else(|| {
+                WorthyError::Config("html needs html_output in the configuration".to_string())
+            })?;
+            let snapshot = newest_snapshot(&snapshot_store(&config)?)?;
+            let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+            let base =
+                common_currency(&config, opt.base_from_source.as_deref(), &source_snapshots)?;
+            let (everything, excluding) = value_modelled(
+                &config,
+                &opt,
+                &base,
+                &converter_snapshots,
+                &source_snapshots,
+            )?;
+            let valuation = excluding.as_ref().unwrap_or(&everything);
+            let html = render_html(
+                Horizon {
+                    now: now.into(),
+                    deadline: config.modelling.horizon_years,
+                },
+                &valuation.total,
+                &config.modelling,
+                &valuation.in_common_currency,
+            )?;
+            let html_path = paths::expand(html_output)?;
+            paths::create_parent_dir(&html_path)?;
+            std::fs::write(&html_path, html).map_err(|error| {
+                WorthyError::Config(format!("cannot write {}: {}", html_path.display(), error))
+            })?;
+            println!("Written: {}", html_path.display());
+        }
+        Check => {
+            let expected = opt
+                .expected_total
+                .ok_or_else(|| WorthyError::Config("check needs --expected-total".to_string()))?;
+            let store = snapshot_store(&config)?;
+            let snapshot = match &opt.snapshot_file {
+                Some(path) => {
+                    store.load(&paths::expand(&path.to_string_lossy())?.to_string_lossy())?
+                }
+                None => newest_snapshot(&store)?,
+            };
+            let (_, source_snapshots) = snapshots_from_json(&snapshot);
+            let base =
+                common_currency(&config, opt.base_from_source.as_deref(), &source_snapshots)?;
+            let total = check_snapshot(
+                &base,
+                &Pricing::of(&config),
+                &snapshot,
+                expected,
+                opt.tolerance,
+            )?;
+            println!(
+                "Total {} is within {} of {}",
+                format_asset(&total),
+                opt.tolerance,
+                expected
+            );
+        }
+        CheckRates => {
+            // Price what the newest snapshot holds, with fresh rates.
+            let snapshot = newest_snapshot(&snapshot_store(&config)?)?;
+            let (_, source_snapshots) = snapshots_from_json(&snapshot);
+            let base =
+                common_currency(&config, opt.base_from_source.as_deref(), &source_snapshots)?;
+            let mut held: Vec<&Denomination> = source_snapshots
+                .iter()
+                .flat_map(|ss| ss.snapshot.iter().map(|asset| &asset.denomination))
+                .chain(std::iter::once(&base))
+                .collect();
+            held.sort_by_key(|denomination| denomination.to_string());
+            held.dedup();
+
+            let call_counts_path = xdg_dirs
+                .place_cache_file("call_counts.json")
+                .map_err(|error| WorthyError::Config(error.to_string()))?;
+            let call_counts = Mutex::new(CallCounts::load(&call_counts_path));
+            let recordings = rate_recordings(&config, opt.replay_rates)?;
+            let converter_snapshots = get_converter_snapshots(
+                &held,
+                converters.iter().collect(),
+                &base,
+                &call_counts,
+                recordings.as_ref(),
+                opt.parallel_converters,
+            )
+            .await;
+            call_counts.into_inner().unwrap().save(&call_counts_path)?;
+
+            let disagreements = common_currency::rate_disagreements(
+                &all_conversions(&converter_snapshots?),
+                MAX_RATE_PATH_HOPS,
+            );
+            print!(
+                "{}",
+                render_rate_disagreements(&disagreements, opt.max_rate_spread_bps, opt.ascii)
+            );
+            check_rate_spreads(&disagreements, opt.max_rate_spread_bps)?;
+        }
+    }
+    Ok(())
+}
+
+/// Longest paths of conversions `checkrates` compares rates along.
+const MAX_RATE_PATH_HOPS: usize = 3;
+
+/// Fails if any pair's rates are more than `max_spread_bps` apart.
+fn check_rate_spreads(
+    disagreements: &[common_currency::RateDisagreement],
+    max_spread_bps: Decimal,
+) -> Result<(), WorthyError> {
+    let pairs = disagreements
+        .iter()
+        .filter(|disagreement| disagreement.spread_bps() > max_spread_bps)
+        .count();
+    if pairs > 0 {
+        return Err(WorthyError::RatesDisagree {
+            pairs,
+            max_spread_bps,
+        });
+    }
+    Ok(())
+}
+
+/// Re-values `snapshot` in `base` with its recorded rates, and fails unless
+/// the total is within `tolerance` of `expected`.
+fn check_snapshot(
+    base: &Denomination,
+    pricing: &Pricing,
+    snapshot: &json_output::Snapshot,
+    expected: Decimal,
+    tolerance: Decimal,
+) -> Result<Asset, WorthyError> {
+    let (converter_snapshots, source_snapshots) = snapshots_from_json(snapshot);
+    let total = value_snapshot(base, pricing, &converter_snapshots, &source_snapshots)?.total;
+    if (total.amount - expected).abs() > tolerance {
+        return Err(WorthyError::TotalMismatch {
+            expected,
+            actual: total.amount,
+            tolerance,
+        });
+    }
+    Ok(total)
+}
+
+/// Converter and source snapshots stored in a JSON snapshot. Rates without a
+/// time they were observed at are taken to be as old as the snapshot.
+fn snapshots_from_json(
+    snapshot: &json_output::Snapshot,
+) -> (Vec<ConverterSnapshot>, Vec<SourceSnapshot>) {
+    let taken = snapshot.timestamp.with_timezone(&Utc);
+    (
+        snapshot
+            .converter_snapshots
+            .iter()
+            .map(|converter_snapshot| converter_snapshot_from_json(converter_snapshot, taken))
+            .collect(),
+        snapshot
+            .source_snapshot
+            .iter()
+            .map(source_snapshot_from_json)
+            .collect(),
+    )
+}
+
+fn converter_snapshot_to_json(
+    converter_snapshot: &ConverterSnapshot,
+) -> json_output::ConverterSnapshot {
+    json_output::ConverterSnapshot {
+        id: converter_snapshot.id.clone(),
+        converter_type: converter_snapshot.converter_type.clone(),
+        snapshot: converter_snapshot
+            .snapshot
+            .iter()
+            .map(exchange_rate_to_json)
+            .collect(),
+    }
+}
+
+fn converter_snapshot_from_json(
+    converter_snapshot: &json_output::ConverterSnapshot,
+    taken: DateTime<Utc>,
+) -> ConverterSnapshot {
+    ConverterSnapshot {
+        id: converter_snapshot.id.clone(),
+        converter_type: converter_snapshot.converter_type.clone(),
+        snapshot: converter_snapshot
+            .snapshot
+            .iter()
+            .map(|c| exchange_rate_from_json(c, taken))
+            .collect(),
+    }
+}
+
+/// `c`, observed at `taken` if it doesn't say when.
+fn exchange_rate_from_json(c: &json_output::Conversion, taken: DateTime<Utc>) -> ExchangeRate {
+    let json_output::Conversion {
+        source,
+        target,
+        target_per_source,
+        spread_bps,
+        observed_at,
+    } = c;
+    ExchangeRate {
+        timestamp: observed_at.unwrap_or(taken),
+        from: denomination_from_json(source),
+        to: denomination_from_json(target),
+        rate: *target_per_source,
+        spread_bps: *spread_bps,
+    }
+}
+
+fn exchange_rate_to_json(exchange_rate: &ExchangeRate) -> json_output::Conversion {
+    let ExchangeRate {
+        timestamp,
+        from,
+        to,
+        rate,
+        spread_bps,
+    } = exchange_rate;
+    json_output::Conversion {
+        source: denomination_to_json(from),
+        target: denomination_to_json(to),
+        target_per_source: *rate,
+        spread_bps: *spread_bps,
+        observed_at: Some(*timestamp),
+    }
+}
+
+fn source_snapshot_to_json(source_snapshot: &SourceSnapshot) -> json_output::SourceSnapshot {
+    json_output::SourceSnapshot {
+        id: source_snapshot.id.clone(),
+        name: source_snapshot.name.clone(),
+        source_type: source_snapshot.source_type.clone(),
+        snapshot: source_snapshot.snapshot.iter().map(asset_to_json).collect(),
+        base_currency: source_snapshot.base_currency.clone(),
+        accounts: source_snapshot
+            .accounts
+            .iter()
+            .map(|account| json_output::AccountSnapshot {
+                account_id: account.account_id.clone(),
+                name: account.name.clone(),
+                snapshot: account.assets.iter().map(asset_to_json).collect(),
+            })
+            .collect(),
+    }
+}
+
+fn source_snapshot_from_json(json_snapshot: &json_output::SourceSnapshot) -> SourceSnapshot {
+    SourceSnapshot {
+        id: json_snapshot.id.clone(),
+        name: json_snapshot.name.clone(),
+        source_type: json_snapshot.source_type.clone(),
+        snapshot: json_snapshot.snapshot.iter().map(asset_from_json).collect(),
+        base_currency: json_snapshot.base_currency.clone(),
+        accounts: json_snapshot
+            .accounts
+            .iter()
+            .map(|account| AccountAssets {
+                account_id: account.account_id.clone(),
+                name: account.name.clone(),
+                assets: account.snapshot.iter().map(asset_from_json).collect(),
+            })
+            .collect(),
+    }
+}
+
+fn denomination_to_json(denomination: &Denomination) -> json_output::Denomination {
+    match denomination {
+        Denomination::Currency { currency } => json_output::Denomination::Currency {
+            symbol: currency.clone(),
+        },
+        Denomination::Cryptocurrency { symbol } => json_output::Denomination::Cryptocurrency {
+            symbol: symbol.clone(),
+        },
+        Denomination::Stock { stock, isin } => json_output::Denomination::Stock {
+            symbol: stock.clone(),
+            isin: isin.clone(),
+        },
+        Denomination::Derivative { derivative } => json_output::Denomination::Derivative {
+            symbol: derivative.clone(),
+        },
+    }
+}
+
+fn denomination_from_json(denomination: &json_output::Denomination) -> Denomination {
+    match denomination {
+        json_output::Denomination::Currency { symbol } => Denomination::Currency {
+            currency: symbol.clone(),
+        },
+        json_output::Denomination::Cryptocurrency { symbol } => Denomination::Cryptocurrency {
+            symbol: symbol.clone(),
+        },
+        json_output::Denomination::Stock { symbol, isin } => Denomination::Stock {
+            stock: symbol.clone(),
+            isin: isin.clone(),
+        },
+        json_output::Denomination::Derivative { symbol } => Denomination::Derivative {
+            derivative: symbol.clone(),
+        },
+    }
+}
+
+fn asset_to_json(asset: &Asset) -> json_output::Asset {
+    json_output::Asset {
+        denomination: denomination_to_json(&asset.denomination),
+        amount: asset.amount,
+        cost_basis: asset
+            .cost_basis
+            .as_ref()
+            .map(|cost_basis| json_output::CostBasis {
+                currency: cost_basis.currency.clone(),
+                money: cost_basis.money,
+                unrealized_pnl: cost_basis.unrealized_pnl,
+            }),
+    }
+}
+
+fn asset_from_json(asset: &json_output::Asset) -> Asset {
+    Asset {
+        denomination: denomination_from_json(&asset.denomination),
+        amount: asset.amount,
+        cost_basis: asset.cost_basis.as_ref().map(|cost_basis| CostBasis {
+            currency: cost_basis.currency.clone(),
+            money: cost_basis.money,
+            unrealized_pnl: cost_basis.unrealized_pnl,
+        }),
+    }
+}
+
+fn render(
+    format: Format,
+    ascii: bool,
+    horizon: Horizon,
+    valuation: &Valuation,
+    modelling: &config::ModellingConfig,
+    base: &Denomination,
+) -> Result<String, WorthyError> {
+    let Valuation {
+        total,
+        in_common_currency,
+        unpriced,
+        unrealized_pnl,
+        allocation,
+        liabilities,
+        excluded,
+        coverage,
+    } = valuation;
+    match format {
+        Format::Table => Ok(render_table(
+            horizon,
+            total,
+            unrealized_pnl.as_ref(),
+            coverage,
+            modelling,
+            in_common_currency,
+            ascii,
+        )? + &render_excluded(excluded.as_ref(), total, ascii)
+            + &render_fi_dates(horizon, total, modelling, in_common_currency, ascii)?
+            + &render_liabilities(liabilities, base, ascii)
+            + &render_allocation(allocation, ascii)
+            + &render_unpriced_table(unpriced, ascii)),
+        Format::Json => render_json(
+            horizon,
+            total,
+            unrealized_pnl.as_ref(),
+            modelling,
+            in_common_currency,
+            unpriced,
+            excluded.as_ref(),
+        ),
+        Format::Csv => render_csv(horizon, total, modelling, in_common_currency),
+    }
+}
+
+/// Models every monthly goal under every yearly yield, goal by goal.
+fn fi_grid<'a>(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &'a config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<Vec<(&'a MonthlyTarget, Decimal, model_rs::FiInfo)>, WorthyError> {
+    let mut grid = Vec::new();
+    for goal in &modelling.monthly_targets {
+        for yearly_yield in &modelling.yearly_yields {
+            let result = model_fi_info(
+                total,
+                in_common_currency,
+                *yearly_yield,
+                goal,
+                &modelling.monthly_saving,
+                horizon,
+            )?;
+            grid.push((goal, *yearly_yield, result.model_fi_info));
+        }
+    }
+    Ok(grid)
+}
+
+/// What `render_table` and `render_html` show, modelled once: by yearly
+/// yield, the perpetuals and drawdowns in each denomination the monthly goals
+/// are set in, and each goal under each yearly yield.
+struct ModelGrid<'a> {
+    perpetuals: Vec<Vec<Asset>>,
+    /// None where the total can't be spent down.
+    drawdowns: Vec<Vec<Option<Asset>>>,
+    goals: Vec<GoalRow<'a>>,
+}
+
+/// A monthly goal, its sum in the common currency if it has several
+/// components, and its model under each yearly yield.
+struct GoalRow<'a> {
+    goal: &'a MonthlyTarget,
+    sum: Option<Asset>,
+    results: Vec<model_rs::FiInfo>,
+}
+
+fn model_grid<'a>(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &'a config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<ModelGrid<'a>, WorthyError> {
+    let denominations: HashSet<Denomination> = modelling
+        .monthly_targets
+        .iter()
+        .flat_map(|goal| goal.components.iter())
+        .map(|asset| asset.denomination.clone())
+        .collect();
+    let mut perpetuals = Vec::new();
+    let mut drawdowns = Vec::new();
+    for yearly_yield in &modelling.yearly_yields {
+        perpetuals.push(
+            denominations
+                .iter()
+                .map(|denomination| {
+                    get_perpetual(total, *yearly_yield, in_common_currency, denomination)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+        drawdowns.push(
+            denominations
+                .iter()
+                .map(|denomination| {
+                    get_drawdown(
+                        total,
+                        *yearly_yield,
+                        horizon,
+                        in_common_currency,
+                        denomination,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+    }
+    // `fi_grid` is goal by goal, one cell per yearly yield each.
+    let mut cells = fi_grid(horizon, total, modelling, in_common_currency)?.into_iter();
+    let mut goals = Vec::new();
+    for goal in &modelling.monthly_targets {
+        let sum = if goal.components.len() > 1 {
+            Some(Asset {
+                amount: monthly_target_in_common(goal, in_common_currency)?,
+                denomination: total.denomination.clone(),
+                cost_basis: None,
+            })
+        } else {
+            None
+        };
+        let results = cells
+            .by_ref()
+            .take(modelling.yearly_yields.len())
+            .map(|(_, _, fi_info)| fi_info)
+            .collect();
+        goals.push(GoalRow { goal, sum, results });
+    }
+    Ok(ModelGrid {
+        perpetuals,
+        drawdowns,
+        goals,
+    })
+}
+
+/// State name and the projected dates of a modelled FI state.
+fn state_summary(
+    state: &model_rs::State,
+) -> (&'static str, Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    use model_rs::State::*;
+    match *state {
+        Reached { .. } => ("reached", None, None),
+        NotReached {
+            projected_until_saved,
+            lasts_until,
+            ..
+        } => (
+            "not_reached",
+            Some(projected_until_saved),
+            Some(lasts_until),
+        ),
+        NeverReached { lasts_until, .. } => ("never_reached", None, Some(lasts_until)),
+    }
+}
+
+fn render_json(
+    horizon: Horizon,
+    total: &Asset,
+    unrealized_pnl: Option<&Asset>,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+    unpriced: &[Asset],
+    excluded: Option<&Excluded>,
+) -> Result<String, WorthyError> {
+    let grid: Vec<serde_json::Value> = fi_grid(horizon, total, modelling, in_common_currency)?
+        .into_iter()
+        .map(|(goal, yearly_yield, fi_info)| {
+            let (state, projected_until_saved, lasts_until) = state_summary(&fi_info.state);
+            let overreach_percentage = match fi_info.state {
+                model_rs::State::Reached {
+                    overreach_percentage,
+                } => Some(overreach_percentage),
+                _ => None,
+            };
+            serde_json::json!({
+                // One per denomination, even if there's just one.
+                "monthly_goal": goal.components.iter().map(asset_to_json).collect::<Vec<_>>(),
+                "yearly_yield": yearly_yield,
+                "state": state,
+                "fi_date": fi_info.fi_date.short_string(),
+                "overreach_percentage": overreach_percentage,
+                "projected_until_saved": projected_until_saved.map(|d| d.format("%Y-%m-%d").to_string()),
+                "lasts_until": lasts_until.map(|d| d.format("%Y-%m-%d").to_string()),
+                "need_to_last_until_deadline": fi_info.need_to_last_until_deadline,
+            })
+        })
+        .collect();
+    let result = serde_json::json!({
+        "total": asset_to_json(total),
+        "unpriced": unpriced.iter().map(asset_to_json).collect::<Vec<_>>(),
+        "unrealized_pnl": unrealized_pnl.map(asset_to_json),
+        "excluded": excluded.map(|excluded| serde_json::json!({
+            "sources": excluded.sources,
+            "denominations": excluded
+                .denominations
+                .iter()
+                .map(|denomination| denomination.to_string())
+                .collect::<Vec<_>>(),
+            "unfiltered_total": asset_to_json(&excluded.unfiltered_total),
+        })),
+        "horizon_years": horizon.deadline,
+        "grid": grid,
+    });
+    Ok(format!("{}\n", result))
+}
+
+fn state_to_json(state: &model_rs::State) -> json_output::ModellingState {
+    use model_rs::State::*;
+    match *state {
+        Reached {
+            overreach_percentage,
+        } => json_output::ModellingState::Reached {
+            overreach_percentage,
+        },
+        NotReached {
+            projected_until_saved,
+            lasts_until,
+            ..
+        } => json_output::ModellingState::NotReached {
+            projected_until_saved,
+            lasts_until,
+        },
+        NeverReached { lasts_until, .. } => {
+            json_output::ModellingState::NeverReached { lasts_until }
+        }
+    }
+}
+
+/// `fi_grid` to store in a snapshot.
+fn modelling_results(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<Vec<json_output::ModellingResult>, WorthyError> {
+    Ok(fi_grid(horizon, total, modelling, in_common_currency)?
+        .into_iter()
+        .map(
+            |(goal, yearly_yield, fi_info)| json_output::ModellingResult {
+                yearly_yield,
+                monthly_target: goal.components.iter().map(asset_to_json).collect(),
+                state: state_to_json(&fi_info.state),
+            },
+        )
+        .collect())
+}
+
+const FI_GRID_CSV_HEADER: [&str; 6] = [
+    "Monthly goal",
+    "Yearly yield",
+    "State",
+    "Projected until saved",
+    "Lasts until",
+    "Need to last until deadline",
+];
+
+/// `fi_grid` as CSV records, in the order of `FI_GRID_CSV_HEADER`.
+fn fi_grid_csv_records(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<Vec<Vec<String>>, WorthyError> {
+    let date = |d: Option<DateTime<Utc>>| {
+        d.map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default()
+    };
+    Ok(fi_grid(horizon, total, modelling, in_common_currency)?
+        .into_iter()
+        .map(|(goal, yearly_yield, fi_info)| {
+            let (state, projected_until_saved, lasts_until) = state_summary(&fi_info.state);
+            vec![
+                format_monthly_target(goal, format_asset, " + "),
+                yearly_yield.to_string(),
+                state.to_string(),
+                date(projected_until_saved),
+                date(lasts_until),
+                fi_info
+                    .need_to_last_until_deadline
+                    .map(|amount| amount.to_string())
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect())
+}
+
+fn render_csv(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<String, WorthyError> {
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    wtr.write_record(FI_GRID_CSV_HEADER).unwrap();
+    for record in fi_grid_csv_records(horizon, total, modelling, in_common_currency)? {
+        wtr.write_record(&record).unwrap();
+    }
+    Ok(String::from_utf8(wtr.into_inner().unwrap()).unwrap())
+}
+
+/// Re-values and re-models every snapshot with the current modelling
+/// configuration, as one CSV with a row per snapshot, goal and yield.
+fn render_model_history(
+    base: &Denomination,
+    pricing: &Pricing,
+    modelling: &config::ModellingConfig,
+    snapshots: &[(String, json_output::Snapshot)],
+    now: DateTime<Utc>,
+) -> Result<String, WorthyError> {
+    let horizon = Horizon {
+        now,
+        deadline: modelling.horizon_years,
+    };
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    wtr.write_record(
+        ["Timestamp", "Total"]
+            .iter()
+            .chain(FI_GRID_CSV_HEADER.iter()),
+    )
+    .unwrap();
+    for (_, snapshot) in snapshots {
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(snapshot);
+        let Valuation {
+            total,
+            in_common_currency,
+            ..
+        } = value_snapshot(base, pricing, &converter_snapshots, &source_snapshots)?;
+        for record in fi_grid_csv_records(horizon, &total, modelling, &in_common_currency)? {
+            let mut row = vec![snapshot.timestamp.to_rfc3339(), total.amount.to_string()];
+            row.extend(record);
+            wtr.write_record(&row).unwrap();
+        }
+    }
+    Ok(String::from_utf8(wtr.into_inner().unwrap()).unwrap())
+}
+
+/// Prometheus metrics of `snapshot`, re-valued in `base`.
+fn render_metrics(
+    base: &Denomination,
+    pricing: &Pricing,
+    snapshot: &json_output::Snapshot,
+    now: DateTime<Utc>,
+) -> Result<String, WorthyError> {
+    let (converter_snapshots, source_snapshots) = snapshots_from_json(snapshot);
+    let Valuation {
+        total, unpriced, ..
+    } = value_snapshot(base, pricing, &converter_snapshots, &source_snapshots)?;
+
+    let mut amounts = aggregate_assets(&source_snapshots)?;
+    amounts.sort_by_key(|asset| format!("{:?}", asset.denomination));
+
+    let mut exposition = metrics::Exposition::default();
+    exposition.gauge(
+        "worthy_total",
+        "Value of all assets in the common currency.",
+        &[(
+            vec![("currency", base.symbol().to_string())],
+            total.amount.to_f64().unwrap_or(f64::NAN),
+        )],
+    );
+    exposition.gauge(
+        "worthy_asset_amount",
+        "Amount held of each denomination.",
+        &amounts
+            .iter()
+            .map(
+                |Asset {
+                     denomination,
+                     amount,
+                     ..
+                 }| {
+                    let mut labels = vec![("denomination", denomination.symbol().to_string())];
+                    if let Denomination::Stock {
+                        isin: Some(isin), ..
+                    } = denomination
+                    {
+                        labels.push(("isin", isin.clone()));
+                    }
+                    (labels, amount.to_f64().unwrap_or(f64::NAN))
+                },
+            )
+            .collect::<Vec<_>>(),
+    );
+    exposition.gauge(
+        "worthy_unpriced_denominations",
+        "Number of denominations that can't be converted to the common currency.",
+        &[(vec![], unpriced.len() as f64)],
+    );
+    exposition.gauge(
+        "worthy_snapshot_age_seconds",
+        "Age of the newest snapshot.",
+        &[(
+            vec![],
+            now.signed_duration_since(snapshot.timestamp).num_seconds() as f64,
+        )],
+    );
+    Ok(exposition.into_text())
+}
+
+/// Serves `GET /metrics` on `listen`, from the newest snapshot at the time of
+/// each scrape.
+async fn serve_metrics(config: Config, listen: SocketAddr) {
+    let config = Arc::new(config);
+    let metrics = warp::path("metrics")
+        .and(warp::path::end())
+        .and(warp::get())
+        .map(move || {
+            let base = config.common_currency.clone();
+            let (body, status) = match snapshot_store(&config)
+                .and_then(|store| newest_snapshot(&store))
+                .and_then(|snapshot| {
+                    render_metrics(&base, &Pricing::of(&config), &snapshot, Utc::now())
+                }) {
+                Ok(body) => (body, warp::http::StatusCode::OK),
+                Err(error) => (
+                    format!("{}\n", error),
+                    warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                ),
+            };
+            warp::reply::with_header(
+                warp::reply::with_status(body, status),
+                "Content-Type",
+                "text/plain; version=0.0.4",
+            )
+        });
+    info!("Serving metrics on http://{}/metrics", listen);
+    warp::serve(metrics).run(listen).await;
+}
+
+fn render_snapshot_list(snapshots: &[(String, json_output::Snapshot)], ascii: bool) -> String {
+    let format_asset = if ascii {
+        format_asset_ascii
+    } else {
+        format_asset
+    };
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![
+        TableCell::new("File"),
+        TableCell::new("Timestamp"),
+        TableCell::new("Total"),
+        TableCell::new("Label"),
+    ]));
+    for (path, snapshot) in snapshots {
+        let file_name = Path::new(path)
+            .file_name()
+            .map_or(path.clone(), |name| name.to_string_lossy().into_owned());
+        table.add_row(Row::new(vec![
+            TableCell::new(file_name),
+            TableCell::new(snapshot.timestamp.to_rfc3339()),
+            TableCell::new(format_asset(&asset_from_json(&snapshot.total))),
+            TableCell::new(snapshot.label.clone().unwrap_or_default()),
+        ]));
+    }
+    table.render()
+}
+
+/// Price of a denomination and the denominations it's priced through, if it
+/// has one.
+type PricedThrough = Option<(Decimal, Vec<Denomination>)>;
+
+/// Every held or converted denomination, with its price in `base` and the
+/// denominations it's priced through (from it back to `base`), or `None` if
+/// it's not connected to `base`. Ordered by denomination.
+fn reachability(
+    base: &Denomination,
+    pricing: &Pricing,
+    converter_snapshots: &[ConverterSnapshot],
+    source_snapshots: &[SourceSnapshot],
+) -> Result<Vec<(Denomination, PricedThrough)>, WorthyError> {
+    let all_conversions = pricing.conversions(converter_snapshots);
+    let mut prices = match common_currency::in_common_currency_with_paths(&all_conversions, base) {
+        Err(CommonCurrencyError::BaseNotReachable { .. }) => {
+            HashMap::from([(base.clone(), (Decimal::ONE, vec![base.clone()]))])
+        }
+        result => result?,
+    };
+    let mut denominations: Vec<Denomination> = source_snapshots
+        .iter()
+        .flat_map(|ss| ss.snapshot.iter().map(|asset| asset.denomination.clone()))
+        .chain(
+            all_conversions
+                .iter()
+                .flat_map(|conversion| [conversion.from.clone(), conversion.to.clone()]),
+        )
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    denominations.sort_by_key(|denomination| denomination.to_string());
+    Ok(denominations
+        .into_iter()
+        .map(|denomination| {
+            let price = prices.remove(&denomination);
+            (denomination, price)
+        })
+        .collect())
+}
+
+fn render_reachability(
+    report: &[(Denomination, PricedThrough)],
+    base: &Denomination,
+    ascii: bool,
+) -> String {
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![
+        TableCell::new("Denomination"),
+        TableCell::new("Priced"),
+        TableCell::new(format!("Rate in {}", base.symbol())),
+        TableCell::new("Hops"),
+        TableCell::new("Path"),
+    ]));
+    for (denomination, price) in report {
+        let cells = match price {
+            Some((price, path)) => vec![
+                "yes".to_string(),
+                price.to_string(),
+                (path.len() - 1).to_string(),
+                path.iter()
+                    .map(|step| step.to_string())
+                    .collect::<Vec<_>>()
+                    .join(if ascii { " -> " } else { " \u{2192} " }),
+            ],
+            None => vec![
+                "no".to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ],
+        };
+        let mut row = vec![TableCell::new(denomination.to_string())];
+        row.extend(cells.into_iter().map(TableCell::new));
+        table.add_row(Row::new(row));
+    }
+    table.render()
+}
+
+/// Pairs with rates along more than one path, the ones further apart than
+/// `max_spread_bps` marked with "!".
+fn render_rate_disagreements(
+    disagreements: &[common_currency::RateDisagreement],
+    max_spread_bps: Decimal,
+    ascii: bool,
+) -> String {
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![
+        TableCell::new("From"),
+        TableCell::new("To"),
+        TableCell::new("Paths"),
+        TableCell::new("Min"),
+        TableCell::new("Max"),
+        TableCell::new("Spread (bps)"),
+        TableCell::new(""),
+    ]));
+    for disagreement in disagreements {
+        let spread_bps = disagreement.spread_bps();
+        table.add_row(Row::new(vec![
+            TableCell::new(disagreement.from.to_string()),
+            TableCell::new(disagreement.to.to_string()),
+            TableCell::new(disagreement.paths),
+            TableCell::new(disagreement.min.round_dp(6)),
+            TableCell::new(disagreement.max.round_dp(6)),
+            TableCell::new(spread_bps.round_dp(2)),
+            TableCell::new(if spread_bps > max_spread_bps { "!" } else { "" }),
+        ]));
+    }
+    table.render()
+}
+
+/// Percentage of the total in each type of denomination, as one row, or
+/// nothing if nothing is priced.
+fn render_allocation(allocation: &[(DenominationType, Decimal)], ascii: bool) -> String {
+    if allocation.is_empty() {
+        return String::new();
+    }
+    let mut row = vec![TableCell::new("Allocation")];
+    row.extend(allocation.iter().map(|(denomination_type, percent)| {
+        TableCell::new(format!("{} {:.1}%", denomination_type.name(), percent))
+    }));
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(row));
+    table.render()
+}
+
+/// What was excluded from `total`, the total with it and the difference, or
+/// nothing if nothing was.
+fn render_excluded(excluded: Option<&Excluded>, total: &Asset, ascii: bool) -> String {
+    let Some(excluded) = excluded else {
+        return String::new();
+    };
+    let format_asset = if ascii {
+        format_asset_ascii
+    } else {
+        format_asset
+    };
+    let mut left_out = Vec::new();
+    if !excluded.sources.is_empty() {
+        left_out.push(format!("Sources: {}", excluded.sources.join(", ")));
+    }
+    if !excluded.denominations.is_empty() {
+        let denominations: Vec<String> = excluded
+            .denominations
+            .iter()
+            .map(|denomination| denomination.to_string())
+            .collect();
+        left_out.push(format!("Denominations: {}", denominations.join(", ")));
+    }
+    let difference = Asset {
+        amount: excluded.unfiltered_total.amount - total.amount,
+        ..total.clone()
+    };
+    let mut table = Table::new();
+    table.max_column_width = 60;
+    table.style = table_style(ascii);
+    for (label, value) in [
+        ("Excluded from the total", left_out.join("\n")),
+        ("Total with them", format_asset(&excluded.unfiltered_total)),
+        ("Excluded", format_asset(&difference)),
+    ] {
+        table.add_row(Row::new(vec![TableCell::new(label), TableCell::new(value)]));
+    }
+    table.render()
+}
+
+/// Liabilities with what they're worth in `base`, and their sum, or nothing if
+/// there are none.
+fn render_liabilities(liabilities: &[Liability], base: &Denomination, ascii: bool) -> String {
+    if liabilities.is_empty() {
+        return String::new();
+    }
+    let format_asset = if ascii {
+        format_asset_ascii
+    } else {
+        format_asset
+    };
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![
+        TableCell::builder("Liabilities (subtracted from the total)")
+            .col_span(3)
+            .build(),
+    ]));
+    for liability in liabilities {
+        table.add_row(Row::new(vec![
+            TableCell::new(&liability.source),
+            TableCell::new(format_asset(&liability.asset)),
+            TableCell::new(format_asset(&liability.value)),
+        ]));
+    }
+    let sum = Asset {
+        amount: liabilities
+            .iter()
+            .map(|liability| liability.value.amount)
+            .sum(),
+        denomination: base.clone(),
+        cost_basis: None,
+    };
+    table.add_row(Row::new(vec![
+        TableCell::builder("Sum").col_span(2).build(),
+        TableCell::new(format_asset(&sum)),
+    ]));
+    table.render()
+}
+
+/// Assets left out of the total, or nothing if there are none.
+fn render_unpriced_table(unpriced: &[Asset], ascii: bool) -> String {
+    if unpriced.is_empty() {
+        return String::new();
+    }
+    let format_asset = if ascii {
+        format_asset_ascii
+    } else {
+        format_asset
+    };
+    let mut table = Table::new();
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![TableCell::new(
+        "Unpriced (not in the total)".to_string(),
+    )]));
+    for asset in unpriced {
+        table.add_row(Row::new(vec![TableCell::new(format_asset(asset))]));
+    }
+    table.render()
+}
+
+fn render_table(
+    horizon: Horizon,
+    total: &Asset,
+    unrealized_pnl: Option<&Asset>,
+    coverage: &Coverage,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+    ascii: bool,
+) -> Result<String, WorthyError> {
+    let base = &total.denomination;
+    let precision = &modelling.display_precision;
+    let format_asset = |x: &Asset| format_asset_with_precision(x, precision, ascii);
+    let mut table = Table::new();
+
+    table.max_column_width = 40;
+    table.style = table_style(ascii);
+
+    let mut headline = format!(
+        "{} {}",
+        if ascii { "Total" } else { "\u{2211}" },
+        format_asset(total)
+    );
+    if let Some(unrealized_pnl) = unrealized_pnl {
+        headline += &format!("\nUnrealized P/L: {}", format_asset(unrealized_pnl));
+    }
+    if let Some(annotation) =
+        coverage.annotation(total.amount, precision.percent.unwrap_or(2) as usize)
+    {
+        headline += &format!("\n{}", annotation);
+    }
+    table.add_row(Row::new(vec![
+        TableCell::builder(format!("{}\nHorizon: {} years", headline, horizon.deadline))
+            .col_span(1 + modelling.yearly_yields.len())
+            .alignment(Alignment::Center)
+            .build(),
+    ]));
+
+    let mut header = vec![TableCell::new(if ascii {
+        "Yearly yield ->\nMonthly goal v".to_string()
+    } else {
+        "Yearly yield \u{2192}\nMonthly goal \u{2193}".to_string()
+    })];
+    // \u2211 = N-ary summation
+    for yld in &modelling.yearly_yields {
+        header.push(TableCell::new(format!(
+            "{:.*}%",
+            precision.percent.unwrap_or(2) as usize,
+            yld * dec!(100),
+        )));
+    }
+    table.add_row(Row::new(header));
+
+    let grid = model_grid(horizon, total, modelling, in_common_currency)?;
+    let mut perpetuals = vec![TableCell::new("Perpetuals".to_string())];
+    for perps in &grid.perpetuals {
+        let perps: Vec<String> = perps.iter().map(format_asset).collect();
+        perpetuals.push(TableCell::new(perps.join("\n")));
+    }
+    table.add_row(Row::new(perpetuals));
+
+    let mut drawdowns = vec![TableCell::new(format!(
+        "Spent in {} years",
+        horizon.deadline
+    ))];
+    for spends in &grid.drawdowns {
+        let spends: Vec<String> = spends
+            .iter()
+            .map(|spend| match spend {
+                Some(spend) => format_asset(spend),
+                None => "-".to_string(),
+            })
+            .collect();
+        drawdowns.push(TableCell::new(spends.join("\n")));
+    }
+    table.add_row(Row::new(drawdowns));
+
+    for GoalRow {
+        goal,
+        sum,
+        results: fi_infos,
+    } in &grid.goals
+    {
+        let mut results = Vec::new();
+        // One component per line.
+        let mut label = format_monthly_target(goal, format_asset, "\n+ ");
+        if let Some(sum) = sum {
+            label += &format!("\n= {}", format_asset(sum));
+        }
+        results.push(TableCell::new(label));
+
+        for fi_info in fi_infos {
+            use model_rs::State::*;
+            results.push(TableCell::new(match fi_info.state {
+                NotReached { .. } | NeverReached { .. } => {
+                    // 2693 = unicode anchor
+                    // 1F4B0 = bag with money
+                    let need = match fi_info.need_to_last_until_deadline {
+                        Some(amount) => format!(
+                            "{}{}",
+                            if ascii { ">=" } else { "≥" },
+                            format_asset(&Asset {
+                                amount,
+                                denomination: base.clone(),
+                                cost_basis: None,
+                            })
+                        ),
+                        None if ascii => "inf".to_string(),
+                        // 221E = infinity
+                        None => "\u{221E}".to_string(),
+                    };
+                    format!(
+                        "{} {}\n{}",
+                        if ascii { "need" } else { "💰" },
+                        need,
+                        fi_info.lasts_until_short_string(ascii)
+                    )
+                }
+                Reached { .. } => fi_info.lasts_until_short_string_with_places(
+                    ascii,
+                    precision.percent.unwrap_or(0) as usize,
+                ),
+            }));
+        }
+        table.add_row(Row::new(results));
+    }
+    Ok(table.render())
+}
+
+/// Escapes `text` for HTML element content and attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The grid of `render_table` as a standalone HTML page, e.g. to look at on a
+/// phone: perpetuals, drawdowns and each monthly goal under each yearly
+/// yield, with money right-aligned.
+fn render_html(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+) -> Result<String, WorthyError> {
+    let base = &total.denomination;
+    let precision = &modelling.display_precision;
+    let format_asset = |x: &Asset| format_asset_with_precision(x, precision, false);
+    // Lines of a cell, escaped.
+    let lines = |lines: &[String]| {
+        lines
+            .iter()
+            .map(|line| escape_html(line))
+            .collect::<Vec<_>>()
+            .join("<br>")
+    };
+    let money_cell = |text: String| format!("<td class=\"money\">{}</td>", text);
+    let grid = model_grid(horizon, total, modelling, in_common_currency)?;
+
+    let mut rows = Vec::new();
+    let mut header = vec!["<th>Yearly yield \u{2192}<br>Monthly goal \u{2193}</th>".to_string()];
+    for yearly_yield in &modelling.yearly_yields {
+        header.push(format!(
+            "<th>{:.*}%</th>",
+            precision.percent.unwrap_or(2) as usize,
+            yearly_yield * dec!(100),
+        ));
+    }
+    rows.push(header);
+
+    let mut perpetuals = vec!["<th>Perpetuals</th>".to_string()];
+    for perps in &grid.perpetuals {
+        let perps: Vec<String> = perps.iter().map(format_asset).collect();
+        perpetuals.push(money_cell(lines(&perps)));
+    }
+    rows.push(perpetuals);
+
+    let mut drawdowns = vec![format!("<th>Spent in {} years</th>", horizon.deadline)];
+    for spends in &grid.drawdowns {
+        let spends: Vec<String> = spends
+            .iter()
+            .map(|spend| match spend {
+                Some(spend) => format_asset(spend),
+                None => "-".to_string(),
+            })
+            .collect();
+        drawdowns.push(money_cell(lines(&spends)));
+    }
+    rows.push(drawdowns);
+
+    for GoalRow {
+        goal,
+        sum,
+        results: fi_infos,
+    } in &grid.goals
+    {
+        let mut label = vec![format_monthly_target(goal, format_asset, " + ")];
+        if let Some(sum) = sum {
+            label.push(format!("= {}", format_asset(sum)));
+        }
+        let mut results = vec![format!("<th>{}</th>", lines(&label))];
+        for fi_info in fi_infos {
+            use model_rs::State::*;
+            let cell: Vec<String> = match fi_info.state {
+                NotReached { .. } | NeverReached { .. } => {
+                    let need = match fi_info.need_to_last_until_deadline {
+                        Some(amount) => format!(
+                            "need ≥{}",
+                            format_asset(&Asset {
+                                amount,
+                                denomination: base.clone(),
+                                cost_basis: None,
+                            })
+                        ),
+                        None => "need \u{221E}".to_string(),
+                    };
+                    std::iter::once(need)
+                        .chain(
+                            fi_info
+                                .lasts_until_short_string(true)
+                                .lines()
+                                .map(str::to_string),
+                        )
+                        .collect()
+                }
+                Reached { .. } => vec![fi_info.lasts_until_short_string_with_places(
+                    false,
+                    precision.percent.unwrap_or(0) as usize,
+                )],
+            };
+            results.push(money_cell(lines(&cell)));
+        }
+        rows.push(results);
+    }
+
+    let title = format!("Total {}", format_asset(total));
+    let mut html = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n\
+         <style>\n\
+         table {{ border-collapse: collapse; }}\n\
+         th, td {{ border: 1px solid #ccc; padding: 0.3em 0.5em; }}\n\
+         td.money {{ text-align: right; white-space: nowrap; }}\n\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         <h1>{title}</h1>\n\
+         <p>Horizon: {} years, as of {}</p>\n\
+         <table>\n",
+        horizon.deadline,
+        horizon.now.format("%Y-%m-%d"),
+        title = escape_html(&title),
+    );
+    for row in rows {
+        html += &format!("<tr>{}</tr>\n", row.concat());
+    }
+    html += "</table>\n</body>\n</html>\n";
+    Ok(html)
+}
+
+/// When each monthly goal is reached under each yearly yield, saving the
+/// monthly saving, or nothing if there is nothing to model.
+fn render_fi_dates(
+    horizon: Horizon,
+    total: &Asset,
+    modelling: &config::ModellingConfig,
+    in_common_currency: &HashMap<Denomination, Decimal>,
+    ascii: bool,
+) -> Result<String, WorthyError> {
+    if modelling.yearly_yields.is_empty() || modelling.monthly_targets.is_empty() {
+        return Ok(String::new());
+    }
+    let precision = &modelling.display_precision;
+    let format_asset = |x: &Asset| format_asset_with_precision(x, precision, ascii);
+    let mut table = Table::new();
+    table.max_column_width = 40;
+    table.style = table_style(ascii);
+    table.add_row(Row::new(vec![
+        TableCell::builder(format!(
+            "FI date, saving {} a month",
+            format_asset(&modelling.monthly_saving)
+        ))
+        .col_span(1 + modelling.yearly_yields.len())
+        .alignment(Alignment::Center)
+        .build(),
+    ]));
+    let mut header = vec![TableCell::new("Monthly goal")];
+    for yearly_yield in &modelling.yearly_yields {
+        header.push(TableCell::new(format!(
+            "{:.*}%",
+            precision.percent.unwrap_or(2) as usize,
+            yearly_yield * dec!(100),
+        )));
+    }
+    table.add_row(Row::new(header));
+    let grid = fi_grid(horizon, total, modelling, in_common_currency)?;
+    // Goal by goal, a yield per cell.
+    for cells in grid.chunks(modelling.yearly_yields.len()) {
+        let mut row = vec![TableCell::new(format_monthly_target(
+            cells[0].0,
+            format_asset,
+            "\n+ ",
+        ))];
+        row.extend(
+            cells
+                .iter()
+                .map(|(_, _, fi_info)| TableCell::new(fi_info.fi_date.short_string())),
+        );
+        table.add_row(Row::new(row));
+    }
+    Ok(table.render())
+}
+
+struct FiInfo {
+    model_fi_info: model_rs::FiInfo,
+}
+
+/// Inputs of `fi_info`, in `base`, and the values computed from them, each
+/// with how it was computed.
+fn explain_fi_info(
+    goal: &MonthlyTarget,
+    fi_info: &model_rs::FiInfo,
+    base: &Denomination,
+) -> String {
+    let model_rs::FiInfo {
+        deadline,
+        yearly_yield,
+        monthly_goal,
+        need_to_last_until_deadline,
+        total,
+        monthly_saving,
+        state,
+        fi_date,
+        intermediates,
+    } = fi_info;
+    let base = base.symbol();
+    let mut lines = vec![
+        format!(
+            "Monthly goal {} at yearly yield {:.2}%:",
+            format_monthly_target(goal, format_asset, " + "),
+            yearly_yield * dec!(100)
+        ),
+        format!("total = {} {}", total.round_dp(2), base),
+        format!("monthly goal = {} {}", monthly_goal.round_dp(2), base),
+        format!("monthly saving = {} {}", monthly_saving.round_dp(2), base),
+        format!("deadline = {} years", deadline),
+        format!(
+            "yearly spending = monthly goal * 12 = {} {}",
+            intermediates.yearly_spending.round_dp(2),
+            base
+        ),
+    ];
+    if let Some(continuous_yield) = intermediates.continuous_yield {
+        lines.push(format!(
+            "continuous yield = ln(1 + yearly yield) = {}",
+            continuous_yield.round_dp(6)
+        ));
+    }
+    if let Some(deadline_share) = intermediates.deadline_share {
+        lines.push(format!(
+            "deadline share = 1 - (1 + yearly yield)^-deadline = {}",
+            deadline_share.round_dp(6)
+        ));
+    }
+    lines.push(
+        match (need_to_last_until_deadline, intermediates.continuous_yield) {
+            (None, _) => "target = none, everything is lost right away".to_string(),
+            (Some(target), None) => format!(
+                "target = yearly spending * deadline = {} {}",
+                target.round_dp(2),
+                base
+            ),
+            (Some(target), Some(_)) => format!(
+                "target = yearly spending / continuous yield * deadline share = {} {}",
+                target.round_dp(2),
+                base
+            ),
+        },
+    );
+    if let Some(durability_years) = intermediates.durability_years {
+        lines.push(format!(
+            "total lasts {} years",
+            durability_years.round_dp(2)
+        ));
+    }
+    if let Some(years_until_saved) = intermediates.years_until_saved {
+        lines.push(format!(
+            "saved up to target in {} years",
+            years_until_saved.round_dp(2)
+        ));
+    }
+    let (state, _, _) = state_summary(state);
+    lines.push(format!(
+        "state = {}, {}",
+        state,
+        fi_info.lasts_until_short_string(true).replace('\n', ", ")
+    ));
+    lines.push(format!("FI date = {}", fi_date.short_string()));
+    let mut explanation = lines.join("\n  ");
+    explanation.push('\n');
+    explanation
+}
+
+fn get_perpetual(
+    total: &Asset,
+    yearly_yield: Decimal,
+    common_prices: &HashMap<Denomination, Decimal>,
+    denomination: &Denomination,
+) -> Result<Asset, WorthyError> {
+    let price = common_prices
+        .get(denomination)
+        .ok_or_else(|| WorthyError::UnpricedDenomination(denomination.clone()))?;
+    // Divides by the price last, so a tiny price (e.g. 1e-8 for a token with
+    // a huge supply) scales up an already rounded monthly amount instead of
+    // overflowing halfway through. Liabilities exceeding the assets leave
+    // nothing to live off.
+    let amount = total
+        .amount
+        .max(Decimal::ZERO)
+        .checked_mul(yearly_yield)
+        .and_then(|yearly| yearly.checked_div(dec!(12)))
+        .and_then(|monthly| monthly.checked_div(*price))
+        .ok_or_else(|| {
+            WorthyError::Overflow(format!(
+                "perpetual of {} at {} yearly yield in {} (price {})",
+                format_asset(total),
+                yearly_yield,
+                denomination,
+                price
+            ))
+        })?;
+    Ok(Asset {
+        amount,
+        denomination: denomination.clone(),
+        cost_basis: None,
+    })
+}
+
+/// Monthly spending `total` pays for until the horizon in `denomination`,
+/// spending it down, or `None` if nothing lasts. Liabilities exceeding the
+/// assets leave nothing to spend.
+fn get_drawdown(
+    total: &Asset,
+    yearly_yield: Decimal,
+    horizon: Horizon,
+    common_prices: &HashMap<Denomination, Decimal>,
+    denomination: &Denomination,
+) -> Result<Option<Asset>, WorthyError> {
+    let price = common_prices
+        .get(denomination)
+        .ok_or_else(|| WorthyError::UnpricedDenomination(denomination.clone()))?;
+    let Some(monthly) = model_rs::projected_monthly_spend(
+        total.amount.max(Decimal::ZERO),
+        yearly_yield,
+        horizon.deadline,
+    ) else {
+        return Ok(None);
+    };
+    let amount = monthly.checked_div(*price).ok_or_else(|| {
+        WorthyError::Overflow(format!(
+            "monthly spend of {} over {} years at {} yearly yield in {} (price {})",
+            format_asset(total),
+            horizon.deadline,
+            yearly_yield,
+            denomination,
+            price
+        ))
+    })?;
+    Ok(Some(Asset {
+        amount,
+        denomination: denomination.clone(),
+        cost_basis: None,
+    }))
+}
+
+/// Value of `x` in the common currency. The modelling configuration may be in
+/// denominations no converter priced this run, so says which of its amounts
+/// (`what`) is the culprit.
+fn to_common(
+    x: &Asset,
+    common_prices: &HashMap<Denomination, Decimal>,
+    what: &str,
+) -> Result<Decimal, WorthyError> {
+    let price = common_prices.get(&x.denomination).ok_or_else(|| {
+        error!(
+            "{} denomination {} is unpriced this run",
+            what, x.denomination
+        );
+        WorthyError::UnpricedDenomination(x.denomination.clone())
+    })?;
+    price.checked_mul(x.amount).ok_or_else(|| {
+        WorthyError::Overflow(format!(
+            "{} at {} in the common currency",
+            format_asset(x),
+            price
+        ))
+    })
+}
+
+/// Sum of the components of `goal` in the common currency. Each has to be
+/// priced.
+fn monthly_target_in_common(
+    goal: &MonthlyTarget,
+    common_prices: &HashMap<Denomination, Decimal>,
+) -> Result<Decimal, WorthyError> {
+    let mut sum = Decimal::ZERO;
+    for component in &goal.components {
+        sum = sum
+            .checked_add(to_common(component, common_prices, "monthly goal")?)
+            .ok_or_else(|| {
+                WorthyError::Overflow(format!(
+                    "monthly goal {} in the common currency",
+                    format_monthly_target(goal, format_asset, " + ")
+                ))
+            })?;
+    }
+    Ok(sum)
+}
+
+/// Components of `goal`, added up with `plus` (e.g. " + ").
+fn format_monthly_target(
+    goal: &MonthlyTarget,
+    format_asset: impl Fn(&Asset) -> String,
+    plus: &str,
+) -> String {
+    goal.components
+        .iter()
+        .map(format_asset)
+        .collect::<Vec<_>>()
+        .join(plus)
+}
+
+// Yearly yield: 0.03 means assumed yearly yield of 3%.
+fn model_fi_info(
+    total: &Asset,
+    common_prices: &HashMap<Denomination, Decimal>,
+    yearly_yield: Decimal,
+    monthly_goal: &MonthlyTarget,
+    monthly_saving: &Asset,
+    horizon: Horizon,
+) -> Result<FiInfo, WorthyError> {
+    Ok(FiInfo {
+        model_fi_info: model_rs::model_fi_info(
+            to_common(total, common_prices, "total")?,
+            yearly_yield,
+            monthly_target_in_common(monthly_goal, common_prices)?,
+            to_common(monthly_saving, common_prices, "monthly saving")?,
+            horizon.deadline,
+            horizon.now,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn currency(code: &str) -> Denomination {
+        Denomination::Currency {
+            currency: code.to_string(),
+        }
+    }
+
+    fn horizon() -> Horizon {
+        Horizon {
+            now: Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap(),
+            deadline: dec!(75),
+        }
+    }
+
+    #[test]
+    fn format_asset_non_currency() {
+        let asset = Asset {
+            amount: dec!(1.5),
+            denomination: Denomination::Stock {
+                stock: "GOOG".to_string(),
+                isin: None,
+            },
+            cost_basis: None,
+        };
+        assert_eq!(format_asset(&asset), "1.5 GOOG");
+    }
+
+    #[test]
+    fn format_asset_minor_units() {
+        let usd = Asset {
+            amount: dec!(1234.567),
+            denomination: currency("USD"),
+            cost_basis: None,
+        };
+        let jpy = Asset {
+            amount: dec!(1234.567),
+            denomination: currency("JPY"),
+            cost_basis: None,
+        };
+        assert_eq!(format_asset(&usd), "$1,234.57");
+        assert_eq!(format_asset(&jpy), "¥1,235");
+    }
+
+    #[test]
+    fn format_asset_unknown_currency_code() {
+        let asset = Asset {
+            amount: dec!(0.5),
+            denomination: currency("BOGUS"),
+            cost_basis: None,
+        };
+        assert_eq!(format_asset(&asset), "0.5 BOGUS");
+    }
+
+    #[test]
+    fn render_table_unknown_base_currency() {
+        // E.g. a cryptocurrency symbol accidentally set as the common currency.
+        let btc = currency("BTC");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(0.01),
+                denomination: btc.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(0.1),
+                    denomination: btc.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let in_common_currency = HashMap::from([(btc.clone(), dec!(1))]);
+        let table = render_table(
+            horizon(),
+            &Asset {
+                amount: dec!(2),
+                denomination: btc.clone(),
+                cost_basis: None,
+            },
+            None,
+            &Coverage::default(),
+            &modelling,
+            &in_common_currency,
+            false,
+        )
+        .unwrap();
+        assert!(table.contains("2 BTC"), "{}", table);
+    }
+
+    #[test]
+    fn render_table_stock_monthly_target() {
+        let usd = currency("USD");
+        let goog = Denomination::Stock {
+            stock: "GOOG".to_string(),
+            isin: None,
+        };
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(2),
+                    denomination: goog.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let in_common_currency = HashMap::from([(usd.clone(), dec!(1)), (goog, dec!(100))]);
+        let table = render_table(
+            horizon(),
+            &Asset {
+                amount: dec!(10000),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            None,
+            &Coverage::default(),
+            &modelling,
+            &in_common_currency,
+            false,
+        )
+        .unwrap();
+        assert!(table.contains("2 GOOG"), "{}", table);
+        // Spending 10000 USD down over 75 years at 3% pays 28.06 USD a month.
+        assert!(table.contains("Spent in 75 years"), "{}", table);
+        assert_eq!(
+            get_drawdown(
+                &Asset {
+                    amount: dec!(10000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                },
+                dec!(0.03),
+                horizon(),
+                &in_common_currency,
+                &usd,
+            )
+            .unwrap()
+            .map(|spend| spend.amount.round_dp(2)),
+            Some(dec!(28.06))
+        );
+    }
+
+    #[test]
+    fn render_html_grid() {
+        let usd = currency("USD");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03), dec!(0.05)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(1000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+                Asset {
+                    amount: dec!(10),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let in_common_currency = HashMap::from([(usd.clone(), dec!(1))]);
+        let html = render_html(
+            horizon(),
+            &Asset {
+                amount: dec!(10000),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            &modelling,
+            &in_common_currency,
+        )
+        .unwrap();
+        assert!(html.contains("<h1>Total $10,000</h1>"), "{}", html);
+        assert!(
+            html.contains("Horizon: 75 years, as of 2021-01-01"),
+            "{}",
+            html
+        );
+        assert!(html.contains("<th>3.00%</th><th>5.00%</th>"), "{}", html);
+        // 10000 USD at 3% is 25 USD a month forever, or 28.06 spent down.
+        assert!(
+            html.contains("<th>Perpetuals</th><td class=\"money\">$25.00</td>"),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains("<th>Spent in 75 years</th><td class=\"money\">$28.06</td>"),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains("<th>$1,000</th><td class=\"money\">need ≥$"),
+            "{}",
+            html
+        );
+        assert!(
+            html.contains("<th>$10</th><td class=\"money\">"),
+            "{}",
+            html
+        );
+        assert_eq!(html.matches("<tr>").count(), 5, "{}", html);
+    }
+
+    #[test]
+    fn modelling_results_per_goal_and_yield() {
+        let usd = currency("USD");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(1000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+                Asset {
+                    amount: dec!(10),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let results = modelling_results(
+            horizon(),
+            &Asset {
+                amount: dec!(10000),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            &modelling,
+            &HashMap::from([(usd.clone(), dec!(1))]),
+        )
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].yearly_yield, dec!(0.03));
+        assert_eq!(results[0].monthly_target[0].amount, dec!(1000));
+        assert!(
+            matches!(
+                results[0].state,
+                json_output::ModellingState::NotReached { .. }
+            ),
+            "{:?}",
+            results[0]
+        );
+        assert!(
+            matches!(
+                results[1].state,
+                json_output::ModellingState::Reached { .. }
+            ),
+            "{:?}",
+            results[1]
+        );
+    }
+
+    #[test]
+    fn escape_html_special_characters() {
+        assert_eq!(
+            escape_html("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn render_table_display_precision() {
+        let usd = currency("USD");
+        let btc = Denomination::Cryptocurrency {
+            symbol: "BTC".to_string(),
+        };
+        let mut modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(0.012345),
+                    denomination: btc.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let in_common_currency = HashMap::from([(usd.clone(), dec!(1)), (btc, dec!(10000))]);
+        let table = |modelling: &config::ModellingConfig, ascii| {
+            render_table(
+                horizon(),
+                &Asset {
+                    amount: dec!(12345.67),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                },
+                None,
+                &Coverage::default(),
+                modelling,
+                &in_common_currency,
+                ascii,
+            )
+            .unwrap()
+        };
+
+        let default = table(&modelling, false);
+        assert!(default.contains("$12,345.67"), "{}", default);
+        assert!(default.contains("3.00%"), "{}", default);
+        assert!(default.contains("0.012345 BTC"), "{}", default);
+
+        modelling.display_precision = DisplayPrecision {
+            currency: Some(0),
+            percent: Some(1),
+            crypto: Some(3),
+        };
+        let rounded = table(&modelling, false);
+        assert!(rounded.contains("$12,346"), "{}", rounded);
+        assert!(!rounded.contains("$12,345.67"), "{}", rounded);
+        assert!(rounded.contains("3.0%"), "{}", rounded);
+        assert!(rounded.contains("0.012 BTC"), "{}", rounded);
+        let ascii = table(&modelling, true);
+        assert!(ascii.contains("12346 USD"), "{}", ascii);
+    }
+
+    #[test]
+    fn render_table_ascii() {
+        let eur = currency("EUR");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: eur.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(-1), dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(1),
+                    denomination: eur.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+                Asset {
+                    amount: dec!(1000),
+                    denomination: eur.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let valuation = Valuation {
+            total: Asset {
+                amount: dec!(10000),
+                denomination: eur.clone(),
+                cost_basis: None,
+            },
+            in_common_currency: HashMap::from([(eur.clone(), dec!(1))]),
+            unpriced: vec![Asset {
+                amount: dec!(2),
+                denomination: currency("XAU"),
+                cost_basis: None,
+            }],
+            unrealized_pnl: None,
+            allocation: vec![],
+            liabilities: vec![],
+            excluded: None,
+            coverage: Default::default(),
+        };
+
+        let ascii = render(Format::Table, true, horizon(), &valuation, &modelling, &eur).unwrap();
+        assert!(ascii.is_ascii(), "{}", ascii);
+        assert!(ascii.contains("10000.00 EUR"), "{}", ascii);
+        assert!(ascii.contains("need inf"), "{}", ascii);
+
+        let pretty = render(
+            Format::Table,
+            false,
+            horizon(),
+            &valuation,
+            &modelling,
+            &eur,
+        )
+        .unwrap();
+        assert!(!pretty.is_ascii(), "{}", pretty);
+    }
+
+    #[test]
+    fn unpriced_savings_denomination() {
+        let usd = currency("USD");
+        let chf = currency("CHF");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: chf.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(1000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let valuation = Valuation {
+            total: Asset {
+                amount: dec!(10000),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            in_common_currency: HashMap::from([(usd.clone(), dec!(1))]),
+            unpriced: vec![],
+            unrealized_pnl: None,
+            allocation: vec![],
+            liabilities: vec![],
+            excluded: None,
+            coverage: Default::default(),
+        };
+        for format in [Format::Table, Format::Json, Format::Csv] {
+            let error = render(format, false, horizon(), &valuation, &modelling, &usd).unwrap_err();
+            assert!(
+                matches!(&error, WorthyError::UnpricedDenomination(d) if *d == chf),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn perpetual_in_tiny_price_denomination() {
+        let usd = currency("USD");
+        let shib = Denomination::Cryptocurrency {
+            symbol: "SHIB".to_string(),
+        };
+        let prices = HashMap::from([(usd.clone(), dec!(1)), (shib.clone(), dec!(0.00000001))]);
+        let total = |amount| Asset {
+            amount,
+            denomination: usd.clone(),
+            cost_basis: None,
+        };
+
+        let perpetual =
+            get_perpetual(&total(dec!(1234567.89)), dec!(0.05), &prices, &shib).unwrap();
+        assert_eq!(perpetual.amount, dec!(514403287500));
+
+        let error = get_perpetual(&total(dec!(1e27)), dec!(0.12), &prices, &shib).unwrap_err();
+        assert!(matches!(error, WorthyError::Overflow(_)), "{:?}", error);
+
+        let error = model_fi_info(
+            &Asset {
+                amount: Decimal::MAX,
+                denomination: shib.clone(),
+                cost_basis: None,
+            },
+            &HashMap::from([(shib.clone(), dec!(2))]),
+            dec!(0.03),
+            &total(dec!(1000)).into(),
+            &total(dec!(100)),
+            horizon(),
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, WorthyError::Overflow(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn two_currency_spending_basket() {
+        let chf = currency("CHF");
+        let eur = currency("EUR");
+        let asset = |amount, denomination: &Denomination| Asset {
+            amount,
+            denomination: denomination.clone(),
+            cost_basis: None,
+        };
+        let basket = MonthlyTarget {
+            components: vec![asset(dec!(2000), &chf), asset(dec!(500), &eur)],
+        };
+        let prices = HashMap::from([(chf.clone(), dec!(1)), (eur.clone(), dec!(0.9))]);
+        assert_eq!(
+            monthly_target_in_common(&basket, &prices).unwrap(),
+            dec!(2450)
+        );
+        // Modelled like a single goal of the same value.
+        let total = asset(dec!(1000000), &chf);
+        let monthly_saving = asset(dec!(0), &chf);
+        let model = |goal: &MonthlyTarget| {
+            let fi_info = model_fi_info(
+                &total,
+                &prices,
+                dec!(0.03),
+                goal,
+                &monthly_saving,
+                horizon(),
+            )
+            .unwrap()
+            .model_fi_info;
+            (
+                fi_info.need_to_last_until_deadline,
+                fi_info.lasts_until_short_string(true),
+            )
+        };
+        assert_eq!(model(&basket), model(&asset(dec!(2450), &chf).into()));
+
+        // Every component has to be priced.
+        let error = monthly_target_in_common(&basket, &HashMap::from([(chf.clone(), dec!(1))]))
+            .unwrap_err();
+        assert!(
+            matches!(&error, WorthyError::UnpricedDenomination(d) if *d == eur),
+            "{:?}",
+            error
+        );
+
+        let modelling = config::ModellingConfig {
+            monthly_saving,
+            yearly_yields: vec![dec!(0.03)],
+            monthly_targets: vec![basket],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let table = render_table(
+            horizon(),
+            &total,
+            None,
+            &Coverage::default(),
+            &modelling,
+            &prices,
+            true,
+        )
+        .unwrap();
+        assert!(table.contains("2000.00 CHF"), "{}", table);
+        assert!(table.contains("+ 500.00 EUR"), "{}", table);
+        assert!(table.contains("= 2450.00 CHF"), "{}", table);
+        let csv = render_csv(horizon(), &total, &modelling, &prices).unwrap();
+        assert!(csv.contains("\"Fr2,000 + €500\""), "{}", csv);
+    }
+
+    #[test]
+    fn explain_known_model() {
+        let usd = currency("USD");
+        let goal: MonthlyTarget = Asset {
+            amount: dec!(10),
+            denomination: usd.clone(),
+            cost_basis: None,
+        }
+        .into();
+        let fi_info = model_rs::model_fi_info(
+            dec!(1000),
+            dec!(0),
+            dec!(10),
+            dec!(10),
+            dec!(10),
+            horizon().now,
+        );
+        let explanation = explain_fi_info(&goal, &fi_info, &usd);
+        assert!(
+            explanation.starts_with("Monthly goal $10 at yearly yield 0.00%:\n"),
+            "{}",
+            explanation
+        );
+        for line in [
+            "total = 1000 USD",
+            "yearly spending = monthly goal * 12 = 120 USD",
+            "target = yearly spending * deadline = 1200 USD",
+            "total lasts 8.33 years",
+            "saved up to target in 1.67 years",
+            "state = not_reached, saved 2022-09-01, lasts 2029-05-02",
+            "FI date = 2022-09-01",
+        ] {
+            assert!(explanation.contains(line), "{}: {}", line, explanation);
+        }
+
+        let fi_info = model_rs::model_fi_info(
+            dec!(1000000),
+            dec!(0.03),
+            dec!(2450),
+            dec!(0),
+            dec!(75),
+            horizon().now,
+        );
+        let explanation = explain_fi_info(&goal, &fi_info, &usd);
+        for line in [
+            "continuous yield = ln(1 + yearly yield) = 0.029559",
+            "target = yearly spending / continuous yield * deadline share = 886267.67 USD",
+            "state = reached",
+            "FI date = already reached",
+        ] {
+            assert!(explanation.contains(line), "{}: {}", line, explanation);
+        }
+    }
+
+    #[test]
+    fn render_all_formats() {
+        let usd = currency("USD");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03), dec!(0.06)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(1000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let valuation = Valuation {
+            total: Asset {
+                amount: dec!(10000),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            in_common_currency: HashMap::from([(usd.clone(), dec!(1))]),
+            unpriced: vec![],
+            unrealized_pnl: None,
+            allocation: vec![],
+            liabilities: vec![],
+            excluded: None,
+            coverage: Default::default(),
+        };
+        for format in [Format::Table, Format::Json, Format::Csv] {
+            let output = render(format, false, horizon(), &valuation, &modelling, &usd).unwrap();
+            assert!(!output.trim().is_empty(), "{:?}", format);
+        }
+
+        let json: serde_json::Value = serde_json::from_str(
+            &render(Format::Json, false, horizon(), &valuation, &modelling, &usd).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json["grid"].as_array().unwrap().len(), 2);
+        assert_eq!(json["grid"][0]["fi_date"], "2091-02-22");
+        assert_eq!(json["grid"][0]["monthly_goal"].as_array().unwrap().len(), 1);
+
+        let csv = render(Format::Csv, false, horizon(), &valuation, &modelling, &usd).unwrap();
+        // Header and one row per yield.
+        assert_eq!(csv.lines().count(), 3, "{}", csv);
+    }
+
+    #[test]
+    fn render_fi_dates_table() {
+        let usd = currency("USD");
+        let asset = |amount| Asset {
+            amount,
+            denomination: usd.clone(),
+            cost_basis: None,
+        };
+        let modelling = config::ModellingConfig {
+            monthly_saving: asset(dec!(100)),
+            yearly_yields: vec![dec!(0)],
+            monthly_targets: vec![asset(dec!(10)).into(), asset(dec!(20)).into()],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let in_common_currency = HashMap::from([(usd.clone(), dec!(1))]);
+        let table = render_fi_dates(
+            horizon(),
+            &asset(dec!(10000)),
+            &modelling,
+            &in_common_currency,
+            true,
+        )
+        .unwrap();
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .filter(|line| line.starts_with('|'))
+            .map(|line| {
+                line.trim_matches('|')
+                    .split('|')
+                    .map(|cell| cell.trim())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["FI date, saving 100.00 USD a month"],
+                vec!["Monthly goal", "0.00%"],
+                // 120 a year for 75 years is less than the total.
+                vec!["10.00 USD", "already reached"],
+                // (18000 - 10000) / 1200 a year
+                vec!["20.00 USD", "2027-09-01"],
+            ],
+            "{}",
+            table
+        );
+
+        let nothing = config::ModellingConfig {
+            monthly_targets: vec![],
+            ..modelling
+        };
+        assert_eq!(
+            render_fi_dates(
+                horizon(),
+                &asset(dec!(10000)),
+                &nothing,
+                &in_common_currency,
+                true
+            )
+            .unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn model_projects_from_injected_time() {
+        let usd = currency("USD");
+        let asset = |amount| Asset {
+            amount,
+            denomination: usd.clone(),
+            cost_basis: None,
+        };
+        let modelling = config::ModellingConfig {
+            monthly_saving: asset(dec!(100)),
+            yearly_yields: vec![dec!(0)],
+            monthly_targets: vec![asset(dec!(1000)).into()],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let valuation = Valuation {
+            total: asset(dec!(10000)),
+            in_common_currency: HashMap::from([(usd.clone(), dec!(1))]),
+            unpriced: vec![],
+            unrealized_pnl: None,
+            allocation: vec![],
+            liabilities: vec![],
+            excluded: None,
+            coverage: Default::default(),
+        };
+        let lasts_until = |horizon| {
+            let json: serde_json::Value = serde_json::from_str(
+                &render(Format::Json, false, horizon, &valuation, &modelling, &usd).unwrap(),
+            )
+            .unwrap();
+            json["grid"][0]["lasts_until"].as_str().unwrap().to_string()
+        };
+
+        // 10000 lasts 10 months at 1000 a month.
+        assert_eq!(lasts_until(horizon()), "2021-11-01");
+        assert_eq!(
+            lasts_until(Horizon {
+                now: Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap(),
+                ..horizon()
+            }),
+            "2030-11-01"
+        );
+    }
+
+    #[test]
+    fn jsonl_history_backfills_and_replaces() {
+        let dir = std::env::temp_dir().join(format!("worthy_jsonl_{}", std::process::id()));
+        let store = SnapshotStore::new(&dir.join("worthy-%s.json"));
+        let history = dir.join("history.jsonl");
+        let snapshot = |timestamp: &str, amount| json_output::Snapshot {
+            timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+            source_snapshot: vec![],
+            converter_snapshots: vec![],
+            total: asset_to_json(&Asset {
+                amount,
+                denomination: currency("USD"),
+                cost_basis: None,
+            }),
+            unpriced: vec![],
+            allocation: BTreeMap::new(),
+            label: None,
+            cfiresim: None,
+            modelling: None,
+        };
+        let totals = || -> Vec<Decimal> {
+            let file = File::open(&history).unwrap();
+            json_output::read_jsonl(std::io::BufReader::new(file))
+                .map(|snapshot| snapshot.unwrap().total.amount)
+                .collect()
+        };
+
+        // Snapshots from before the history was set up are backfilled.
+        store
+            .write(&snapshot("2021-01-01T00:00:00Z", dec!(1)))
+            .unwrap();
+        let second = snapshot("2021-01-02T00:00:00Z", dec!(2));
+        store.write(&second).unwrap();
+        record_in_jsonl(&history, &store, &second, None).unwrap();
+        assert_eq!(totals(), vec![dec!(1), dec!(2)]);
+
+        let third = snapshot("2021-01-03T00:00:00Z", dec!(3));
+        record_in_jsonl(&history, &store, &third, None).unwrap();
+        assert_eq!(totals(), vec![dec!(1), dec!(2), dec!(3)]);
+
+        // Overwriting today's snapshot replaces its line.
+        let retaken = snapshot("2021-01-03T12:00:00Z", dec!(4));
+        record_in_jsonl(&history, &store, &retaken, Some(third.timestamp)).unwrap();
+        let replaced = totals();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(replaced, vec![dec!(1), dec!(2), dec!(4)]);
+    }
+
+    #[test]
+    fn render_snapshot_list_shows_labels() {
+        let snapshot = |timestamp: &str, label: Option<&str>| json_output::Snapshot {
+            timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+            source_snapshot: vec![],
+            converter_snapshots: vec![],
+            total: asset_to_json(&Asset {
+                amount: dec!(1234),
+                denomination: currency("USD"),
+                cost_basis: None,
+            }),
+            unpriced: vec![],
+            allocation: BTreeMap::new(),
+            label: label.map(str::to_string),
+            cfiresim: None,
+            modelling: None,
+        };
+        let list = render_snapshot_list(
+            &[
+                (
+                    "/snapshots/a.json".to_string(),
+                    snapshot("2021-01-01T00:00:00Z", None),
+                ),
+                (
+                    "/snapshots/b.json".to_string(),
+                    snapshot("2021-12-31T00:00:00Z", Some("year-end")),
+                ),
+            ],
+            false,
+        );
+        assert!(list.contains("b.json"), "{}", list);
+        assert!(list.contains("$1,234"), "{}", list);
+        assert!(list.contains("year-end"), "{}", list);
+        assert!(list.find("a.json") < list.find("b.json"), "{}", list);
+    }
+
+    #[test]
+    fn sparkline_of_series() {
+        let series = [
+            dec!(100),
+            dec!(120),
+            dec!(140),
+            dec!(120),
+            dec!(160),
+            dec!(200),
+            dec!(180),
+            dec!(240),
+        ];
+        assert_eq!(sparkline(&series, false), "▁▂▃▂▄▆▅█");
+        assert_eq!(sparkline(&series, true), " .:.-+=#");
+        assert_eq!(sparkline(&[dec!(5), dec!(5)], true), "==");
+        assert_eq!(sparkline(&[], false), "");
+    }
+
+    #[test]
+    fn trend_skips_other_bases() {
+        let snapshot = |timestamp: &str, amount, symbol| {
+            (
+                format!("/snapshots/{}.json", timestamp),
+                json_output::Snapshot {
+                    timestamp: DateTime::parse_from_rfc3339(timestamp).unwrap(),
+                    source_snapshot: vec![],
+                    converter_snapshots: vec![],
+                    total: asset_to_json(&Asset {
+                        amount,
+                        denomination: currency(symbol),
+                        cost_basis: None,
+                    }),
+                    unpriced: vec![],
+                    allocation: BTreeMap::new(),
+                    label: None,
+                    cfiresim: None,
+                    modelling: None,
+                },
+            )
+        };
+        let snapshots = [
+            snapshot("2021-01-01T00:00:00Z", dec!(100), "USD"),
+            snapshot("2021-02-01T00:00:00Z", dec!(2000), "CZK"),
+            snapshot("2021-03-01T00:00:00Z", dec!(120), "USD"),
+            snapshot("2021-04-01T00:00:00Z", dec!(130), "USD"),
+        ];
+        let before = DateTime::parse_from_rfc3339("2021-04-01T00:00:00Z").unwrap();
+        let usd = currency("USD");
+        assert_eq!(
+            comparable_totals(&snapshots, &usd, before, 11),
+            vec![dec!(100), dec!(120)]
+        );
+        assert_eq!(
+            comparable_totals(&snapshots, &usd, before, 1),
+            vec![dec!(120)]
+        );
+
+        let total = Asset {
+            amount: dec!(110),
+            denomination: usd,
+            cost_basis: None,
+        };
+        let trend = render_trend(&[dec!(100), dec!(120)], &total, true);
+        assert!(
+            trend.contains("-10.00 USD since the last snapshot"),
+            "{}",
+            trend
+        );
+        assert!(trend.contains(" #="), "{}", trend);
+        assert_eq!(render_trend(&[], &total, true), "");
+    }
+
+    #[test]
+    fn add_up_amounts_unpriced_denomination() {
+        let goog = Denomination::Stock {
+            stock: "GOOG".to_string(),
+            isin: None,
+        };
+        let snapshot = SourceSnapshot {
+            id: "ib".to_string(),
+            name: "IB".to_string(),
+            source_type: SourceType::IBFlex,
+            snapshot: vec![Asset {
+                amount: dec!(3),
+                denomination: goog.clone(),
+                cost_basis: None,
+            }],
+            base_currency: None,
+            accounts: Vec::new(),
+        };
+        let snapshot_by_id = HashMap::from([("ib".to_string(), &snapshot)]);
+        let in_common_currency = HashMap::from([(currency("USD"), dec!(1))]);
+
+        let error =
+            add_up_amounts(&["ib".to_string()], &snapshot_by_id, &in_common_currency).unwrap_err();
+        assert!(
+            matches!(&error, WorthyError::UnpricedDenomination(d) if *d == goog),
+            "{:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_concurrently_overlaps_and_keeps_order() {
+        // The first item takes longest, but still comes out first.
+        let fetch = |(name, millis): (&'static str, u64)| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(millis)).await;
+            Ok::<_, WorthyError>(name)
+        };
+        let items = || vec![("ib", 300), ("bank", 200)];
+
+        let start = std::time::Instant::now();
+        let names = fetch_concurrently(items(), 2, fetch).await.unwrap();
+        let concurrent = start.elapsed();
+        assert_eq!(names, vec!["ib", "bank"]);
+        assert!(
+            concurrent < std::time::Duration::from_millis(450),
+            "{:?}",
+            concurrent
+        );
+
+        let start = std::time::Instant::now();
+        let names = fetch_concurrently(items(), 1, fetch).await.unwrap();
+        assert_eq!(names, vec!["ib", "bank"]);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+
+        let failing = fetch_concurrently(vec![1, 2], 2, |item| async move {
+            match item {
+                1 => Ok(item),
+                _ => Err(WorthyError::Http("down".to_string())),
+            }
+        })
+        .await;
+        assert!(matches!(failing, Err(WorthyError::Http(_))));
+    }
+
+    #[tokio::test]
+    async fn model_history_over_all_snapshots() {
+        let dir = std::env::temp_dir().join(format!("worthy_model_all_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let snapshot = |timestamp: &str, eur: u32| {
+            format!(
+                r#"{{
+                  "Timestamp": "{}",
+                  "SourceSnapshot": [{{
+                    "Id": "bank",
+                    "Name": "Bank",
+                    "Type": "hardcoded",
+                    "Snapshot": [
+                      {{"Type": "currency", "Symbol": "EUR", "Amount": {}}},
+                      {{"Type": "currency", "Symbol": "USD", "Amount": 10}}
+                    ]
+                  }}],
+                  "ConverterSnapshots": [{{
+                    "Id": "fixer",
+                    "Type": "fixer",
+                    "Params": {{}},
+                    "Snapshot": [{{
+                      "Source": {{"Type": "currency", "Symbol": "EUR"}},
+                      "Target": {{"Type": "currency", "Symbol": "USD"}},
+                      "TargetPerSource": 2
+                    }}]
+                  }}],
+                  "Total": {{"Type": "currency", "Symbol": "USD", "Amount": 1}}
+                }}"#,
+                timestamp, eur
+            )
+        };
+        // File names sort the other way around than the timestamps.
+        let paths: Vec<String> = [
+            ("a.json", snapshot("2021-02-01T00:00:00Z", 200)),
+            ("b.json", snapshot("2021-01-01T00:00:00Z", 100)),
+        ]
+        .iter()
+        .map(|(name, contents)| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_string()
+        })
+        .collect();
+
+        let json_output::ReadSnapshots {
+            mut snapshots,
+            skipped,
+        } = load_snapshots(&paths).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(skipped, 0);
+        json_output::sort_by_timestamp(&mut snapshots);
+
+        let usd = currency("USD");
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(100),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(0.03), dec!(0.06)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(1000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let csv = render_model_history(
+            &usd,
+            &Pricing::default(),
+            &modelling,
+            &snapshots,
+            horizon().now,
+        )
+        .unwrap();
+
+        // Header, then one row per snapshot and yield, oldest first, with the
+        // totals recomputed instead of taken from the files.
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 5, "{}", csv);
+        assert_eq!(rows[0][..2], ["Timestamp", "Total"]);
+        for (row, (timestamp, total)) in rows[1..].iter().zip([
+            ("2021-01-01T00:00:00+00:00", dec!(210)),
+            ("2021-01-01T00:00:00+00:00", dec!(210)),
+            ("2021-02-01T00:00:00+00:00", dec!(410)),
+            ("2021-02-01T00:00:00+00:00", dec!(410)),
+        ]) {
+            assert_eq!(row[0], timestamp, "{}", csv);
+            assert_eq!(Decimal::from_str(row[1]).unwrap(), total, "{}", csv);
+        }
+    }
+
+    #[test]
+    fn check_snapshot_tolerance() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [{
+                "Id": "bank",
+                "Name": "Bank",
+                "Type": "hardcoded",
+                "Snapshot": [
+                  {"Type": "currency", "Symbol": "EUR", "Amount": 100},
+                  {"Type": "currency", "Symbol": "USD", "Amount": 10}
+                ]
+              }],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "currency", "Symbol": "EUR"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 2
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 210}
+            }"#,
+        )
+        .unwrap();
+        let usd = currency("USD");
+
+        let total =
+            check_snapshot(&usd, &Pricing::default(), &snapshot, dec!(209.5), dec!(0.5)).unwrap();
+        assert_eq!(total.amount, dec!(210));
+
+        let error =
+            check_snapshot(&usd, &Pricing::default(), &snapshot, dec!(200), dec!(1)).unwrap_err();
+        assert!(
+            matches!(
+                error,
+                WorthyError::TotalMismatch { expected, actual, .. }
+                    if expected == dec!(200) && actual == dec!(210)
+            ),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.exit_code(), 9);
+    }
+
+    #[test]
+    fn converters_disagreeing_on_rates() {
+        let eur_usd = |id: &str, rate| ConverterSnapshot {
+            id: id.to_string(),
+            converter_type: ConverterType::Fixer,
+            snapshot: vec![ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: currency("EUR"),
+                to: currency("USD"),
+                rate,
+                spread_bps: Decimal::ZERO,
+            }],
+        };
+        let disagreements = common_currency::rate_disagreements(
+            &all_conversions(&[eur_usd("fixer", dec!(1.10)), eur_usd("other", dec!(1.12))]),
+            MAX_RATE_PATH_HOPS,
+        );
+        assert_eq!(disagreements.len(), 1);
+        // 0.02 / 1.10 is about 182 bps.
+        check_rate_spreads(&disagreements, dec!(200)).unwrap();
+        let error = check_rate_spreads(&disagreements, dec!(100)).unwrap_err();
+        assert!(
+            matches!(error, WorthyError::RatesDisagree { pairs: 1, .. }),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.exit_code(), 11);
+
+        let table = render_rate_disagreements(&disagreements, dec!(100), true);
+        assert!(table.contains("181.82"), "{}", table);
+        assert!(table.contains('!'), "{}", table);
+        let table = render_rate_disagreements(&disagreements, dec!(200), true);
+        assert!(!table.contains('!'), "{}", table);
+    }
+
+    #[test]
+    fn first_rate_by_converter_priority() {
+        let eur_usd = |id: &str, rate| ConverterSnapshot {
+            id: id.to_string(),
+            converter_type: ConverterType::Fixer,
+            snapshot: vec![ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: currency("EUR"),
+                to: currency("USD"),
+                rate,
+                spread_bps: Decimal::ZERO,
+            }],
+        };
+        let converter_snapshots = [
+            eur_usd("fixer", dec!(1.10)),
+            eur_usd("ecb", dec!(1.12)),
+            eur_usd("other", dec!(1.14)),
+        ];
+        let rates = |pricing: Pricing| {
+            pricing
+                .conversions(&converter_snapshots)
+                .iter()
+                .map(|conversion| conversion.rate)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            rates(Pricing::default()),
+            vec![dec!(1.10), dec!(1.12), dec!(1.14)]
+        );
+        assert_eq!(
+            rates(Pricing {
+                policy: common_currency::RatePolicy::First,
+                converter_priority: vec!["ecb".to_string()],
+            }),
+            vec![dec!(1.12)]
+        );
+        assert_eq!(
+            rates(Pricing {
+                policy: common_currency::RatePolicy::First,
+                converter_priority: Vec::new(),
+            }),
+            vec![dec!(1.10)]
+        );
+    }
+
+    #[test]
+    fn aliased_crypto_priced_through_canonical_rate() {
+        let xbt = Denomination::Cryptocurrency {
+            symbol: "XBT".to_string(),
+        };
+        let btc = Denomination::Cryptocurrency {
+            symbol: "BTC".to_string(),
+        };
+        let mut source_snapshots = vec![SourceSnapshot {
+            id: "exchange".to_string(),
+            name: "Exchange".to_string(),
+            source_type: SourceType::Hardcoded,
+            snapshot: vec![Asset {
+                amount: dec!(2),
+                denomination: xbt.clone(),
+                cost_basis: None,
+            }],
+            base_currency: None,
+            accounts: Vec::new(),
+        }];
+        let rate = |from: &Denomination, rate| ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
+            from: from.clone(),
+            to: currency("USD"),
+            rate,
+            spread_bps: Decimal::ZERO,
+        };
+        let mut converter_snapshots = vec![ConverterSnapshot {
+            id: "alphavantage".to_string(),
+            converter_type: ConverterType::AlphaVantage,
+            snapshot: vec![rate(&btc, dec!(30000))],
+        }];
+        let usd = currency("USD");
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        assert_eq!(valuation.total.amount, dec!(0));
+
+        let aliases = HashMap::from([("XBT".to_string(), "BTC".to_string())]);
+        canonicalize_assets(&mut source_snapshots, &aliases);
+        canonicalize_rates(&mut converter_snapshots, &aliases);
+        assert_eq!(source_snapshots[0].snapshot[0].denomination, btc);
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        assert_eq!(valuation.total.amount, dec!(60000));
+        assert!(valuation.unpriced.is_empty());
+
+        // A rate between two names of the same thing goes away.
+        let mut converter_snapshots = vec![ConverterSnapshot {
+            id: "alphavantage".to_string(),
+            converter_type: ConverterType::AlphaVantage,
+            snapshot: vec![ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: xbt,
+                to: btc,
+                rate: dec!(1),
+                spread_bps: Decimal::ZERO,
+            }],
+        }];
+        canonicalize_rates(&mut converter_snapshots, &aliases);
+        assert_eq!(converter_snapshots[0].snapshot, vec![]);
+    }
+
+    #[test]
+    fn fiat_portfolio_in_crypto_base() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [{
+                "Id": "bank",
+                "Name": "Bank",
+                "Type": "hardcoded",
+                "Snapshot": [
+                  {"Type": "currency", "Symbol": "USD", "Amount": 30000},
+                  {"Type": "currency", "Symbol": "EUR", "Amount": 10000}
+                ]
+              }],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [
+                  {
+                    "Source": {"Type": "crypto", "Symbol": "BTC"},
+                    "Target": {"Type": "currency", "Symbol": "USD"},
+                    "TargetPerSource": 40000
+                  },
+                  {
+                    "Source": {"Type": "currency", "Symbol": "EUR"},
+                    "Target": {"Type": "currency", "Symbol": "USD"},
+                    "TargetPerSource": 1
+                  }
+                ]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 40000}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+        let btc = Denomination::Cryptocurrency {
+            symbol: "BTC".to_string(),
+        };
+
+        let valuation = value_snapshot(
+            &btc,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+
+        assert_eq!(valuation.total.denomination, btc);
+        assert_eq!(valuation.total.amount.round_dp(6), dec!(1));
+        assert!(valuation.unpriced.is_empty());
+        // Not money, so formatted as a plain amount.
+        assert_eq!(
+            format_asset(&Asset {
+                amount: dec!(0.5),
+                ..valuation.total.clone()
+            }),
+            "0.5 BTC"
+        );
+        assert_eq!(
+            format_asset_ascii(&Asset {
+                amount: dec!(0.5),
+                ..valuation.total
+            }),
+            "0.5 BTC"
+        );
+
+        // Without any conversions to the base, nothing else has a price.
+        assert!(matches!(
+            value_snapshot(&btc, &Pricing::default(), &[], &source_snapshots),
+            Err(WorthyError::BaseNotReachable { .. })
+        ));
+    }
+
+    #[test]
+    fn excluding_a_source() {
+        let usd = currency("USD");
+        let converter_snapshots = [ConverterSnapshot {
+            id: "fixer".to_string(),
+            converter_type: ConverterType::Fixer,
+            snapshot: vec![ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: currency("EUR"),
+                to: usd.clone(),
+                rate: dec!(1.1),
+                spread_bps: Decimal::ZERO,
+            }],
+        }];
+        let mut fund = source_snapshot("fund", dec!(100));
+        fund.snapshot.push(Asset {
+            amount: dec!(5000),
+            denomination: currency("EUR"),
+            cost_basis: None,
+        });
+        let source_snapshots = vec![
+            source_snapshot("bank", dec!(1000)),
+            fund,
+            source_snapshot("pension", dec!(250)),
+        ];
+        let everything = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        let fund_value = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots[1..2],
+        )
+        .unwrap()
+        .total
+        .amount;
+        assert_eq!(fund_value, dec!(5600));
+
+        let excluding = value_excluding(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+            &["fund".to_string()],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(excluding.total.amount, everything.total.amount - fund_value);
+        assert_eq!(
+            excluding.excluded,
+            Some(Excluded {
+                sources: vec!["fund".to_string()],
+                denominations: vec![],
+                unfiltered_total: everything.total.clone(),
+            })
+        );
+        let table = render_excluded(excluding.excluded.as_ref(), &excluding.total, true);
+        for line in ["Sources: fund", "6850.00 USD", "5600.00 USD"] {
+            assert!(table.contains(line), "{}: {}", line, table);
+        }
+
+        // Denominations are left out of every source.
+        let excluding = value_excluding(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+            &[],
+            std::slice::from_ref(&usd),
+        )
+        .unwrap();
+        assert_eq!(excluding.total.amount, dec!(5500));
+    }
+
+    #[test]
+    fn machine_total() {
+        let usd = currency("USD");
+        let converter_snapshots = [ConverterSnapshot {
+            id: "fixer".to_string(),
+            converter_type: ConverterType::Fixer,
+            snapshot: vec![ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: currency("EUR"),
+                to: usd.clone(),
+                rate: dec!(1.1),
+                spread_bps: Decimal::ZERO,
+            }],
+        }];
+        let mut source_snapshots = vec![source_snapshot("bank", dec!(1234567.891))];
+        source_snapshots[0].snapshot.push(Asset {
+            amount: dec!(10),
+            denomination: currency("EUR"),
+            cost_basis: None,
+        });
+        assert_eq!(
+            machine_total_line(
+                &value_snapshot(
+                    &usd,
+                    &Pricing::default(),
+                    &converter_snapshots,
+                    &source_snapshots
+                )
+                .unwrap()
+            ),
+            "TOTAL 1234578.89 USD"
+        );
+
+        // Whole amounts still get the decimal places.
+        source_snapshots[0].snapshot.truncate(1);
+        source_snapshots[0].snapshot[0].amount = dec!(100);
+        assert_eq!(
+            machine_total_line(
+                &value_snapshot(&usd, &Pricing::default(), &[], &source_snapshots).unwrap()
+            ),
+            "TOTAL 100.00 USD"
+        );
+
+        // Only the priced portion, flagged as such.
+        source_snapshots[0].snapshot.push(Asset {
+            amount: dec!(10),
+            denomination: currency("CHF"),
+            cost_basis: None,
+        });
+        assert_eq!(
+            machine_total_line(
+                &value_snapshot(
+                    &usd,
+                    &Pricing::default(),
+                    &converter_snapshots,
+                    &source_snapshots
+                )
+                .unwrap()
+            ),
+            "TOTAL 100.00 USD PARTIAL"
+        );
+    }
+
+    #[test]
+    fn liabilities_exceeding_assets() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [
+                {
+                  "Id": "bank",
+                  "Name": "Bank",
+                  "Type": "hardcoded",
+                  "Snapshot": [
+                    {"Type": "currency", "Symbol": "USD", "Amount": 10000},
+                    {"Type": "currency", "Symbol": "CHF", "Amount": -200000}
+                  ]
+                }
+              ],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "currency", "Symbol": "CHF"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 1.1
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": -210000}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+        let usd = currency("USD");
+
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+
+        assert_eq!(valuation.total.amount, dec!(-210000));
+        assert_eq!(
+            valuation.liabilities,
+            vec![Liability {
+                source: "Bank".to_string(),
+                asset: Asset {
+                    amount: dec!(-200000),
+                    denomination: currency("CHF"),
+                    cost_basis: None,
+                },
+                value: Asset {
+                    amount: dec!(-220000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                },
+            }]
+        );
+        assert_eq!(
+            valuation.allocation,
+            vec![(DenominationType::Currency, dec!(100))]
+        );
+
+        let modelling = config::ModellingConfig {
+            monthly_saving: Asset {
+                amount: dec!(1000),
+                denomination: usd.clone(),
+                cost_basis: None,
+            },
+            yearly_yields: vec![dec!(-0.5), dec!(0), dec!(0.03)],
+            monthly_targets: vec![
+                Asset {
+                    amount: dec!(2000),
+                    denomination: usd.clone(),
+                    cost_basis: None,
+                }
+                .into(),
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let table = render(Format::Table, true, horizon(), &valuation, &modelling, &usd).unwrap();
+        assert!(table.contains("Total -210000.00 USD"), "{}", table);
+        assert!(table.contains("Liabilities"), "{}", table);
+        assert!(table.contains("-200000.00 CHF"), "{}", table);
+        assert!(table.contains("-220000.00 USD"), "{}", table);
+        assert!(!table.contains("ok"), "{}", table);
+        // Nothing to live off.
+        let perpetual = get_perpetual(
+            &valuation.total,
+            dec!(0.03),
+            &valuation.in_common_currency,
+            &usd,
+        )
+        .unwrap();
+        assert_eq!(perpetual.amount, dec!(0));
+    }
+
+    #[test]
+    fn unpriced_amounts_are_summed() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [
+                {
+                  "Id": "bank",
+                  "Name": "Bank",
+                  "Type": "hardcoded",
+                  "Snapshot": [
+                    {"Type": "currency", "Symbol": "USD", "Amount": 10},
+                    {"Type": "stock", "Symbol": "GOOG", "Amount": 2}
+                  ]
+                },
+                {
+                  "Id": "broker",
+                  "Name": "Broker",
+                  "Type": "hardcoded",
+                  "Snapshot": [{"Type": "stock", "Symbol": "GOOG", "Amount": 3}]
+                }
+              ],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "currency", "Symbol": "EUR"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 2
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 10}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+
+        let valuation = value_snapshot(
+            &currency("USD"),
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+
+        assert_eq!(valuation.total.amount, dec!(10));
+        assert_eq!(
+            valuation.unpriced,
+            vec![Asset {
+                amount: dec!(5),
+                denomination: Denomination::Stock {
+                    stock: "GOOG".to_string(),
+                    isin: None,
+                },
+                cost_basis: None,
+            }]
+        );
+        let table = render_unpriced_table(&valuation.unpriced, false);
+        assert!(table.contains("Unpriced"), "{}", table);
+        assert!(table.contains("5 GOOG"), "{}", table);
+        assert_eq!(render_unpriced_table(&[], false), "");
+    }
+
+    #[test]
+    fn allocation_row() {
+        let table = render_allocation(
+            &[
+                (DenominationType::Currency, dec!(37.5)),
+                (DenominationType::Stock, dec!(62.5)),
+            ],
+            false,
+        );
+        assert!(table.contains("Allocation"), "{}", table);
+        assert!(table.contains("currency 37.5%"), "{}", table);
+        assert!(table.contains("stock 62.5%"), "{}", table);
+        assert_eq!(render_allocation(&[], false), "");
+    }
+
+    #[test]
+    fn unrealized_pnl_sums_across_positions() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [
+                {
+                  "Id": "us",
+                  "Name": "US broker",
+                  "Type": "ibflex",
+                  "Snapshot": [
+                    {"Type": "stock", "Symbol": "GOOG", "Amount": 2,
+                     "CostBasis": {"Currency": "USD", "Money": 150, "UnrealizedPnl": 50}},
+                    {"Type": "stock", "Symbol": "VTI", "Amount": 1}
+                  ]
+                },
+                {
+                  "Id": "eu",
+                  "Name": "EU broker",
+                  "Type": "ibflex",
+                  "Snapshot": [
+                    {"Type": "stock", "Symbol": "SAP", "Amount": 1,
+                     "CostBasis": {"Currency": "EUR", "Money": 110, "UnrealizedPnl": -10}},
+                    {"Type": "stock", "Symbol": "7203.T", "Amount": 1,
+                     "CostBasis": {"Currency": "JPY", "Money": 2000, "UnrealizedPnl": 500}}
+                  ]
+                }
+              ],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "currency", "Symbol": "EUR"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 2
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 0}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+
+        // 50 USD, and -10 EUR at 2 USD each. The JPY gain can't be converted.
+        let valuation = value_snapshot(
+            &currency("USD"),
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        let unrealized_pnl = valuation.unrealized_pnl.unwrap();
+        assert_eq!(unrealized_pnl.amount, dec!(30));
+        assert_eq!(unrealized_pnl.denomination, currency("USD"));
+
+        // Cost bases survive the round trip through the JSON snapshot.
+        assert_eq!(
+            asset_to_json(&source_snapshots[0].snapshot[0]),
+            snapshot.source_snapshot[0].snapshot[0]
+        );
+
+        let without_cost_basis = [SourceSnapshot {
+            id: "us".to_string(),
+            name: "US broker".to_string(),
+            source_type: SourceType::IBFlex,
+            snapshot: vec![source_snapshots[0].snapshot[1].clone()],
+            base_currency: None,
+            accounts: Vec::new(),
+        }];
+        assert_eq!(
+            value_snapshot(
+                &currency("USD"),
+                &Pricing::default(),
+                &converter_snapshots,
+                &without_cost_basis
+            )
+            .unwrap()
+            .unrealized_pnl
+            .map(|asset| asset.amount),
+            None
+        );
+    }
+
+    #[test]
+    fn reachability_marks_isolated_denomination() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [{
+                "Id": "bank",
+                "Name": "Bank",
+                "Type": "hardcoded",
+                "Snapshot": [
+                  {"Type": "currency", "Symbol": "EUR", "Amount": 10},
+                  {"Type": "stock", "Symbol": "GOOG", "Amount": 2}
+                ]
+              }],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "currency", "Symbol": "EUR"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 2
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 20}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+        let usd = currency("USD");
+
+        let report = reachability(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+
+        let summary: Vec<(String, Option<(Decimal, usize)>)> = report
+            .iter()
+            .map(|(denomination, price)| {
+                (
+                    denomination.to_string(),
+                    price.as_ref().map(|(price, path)| (*price, path.len() - 1)),
+                )
+            })
+            .collect();
+        let goog = Denomination::Stock {
+            stock: "GOOG".to_string(),
+            isin: None,
+        };
+        assert_eq!(
+            summary,
+            vec![
+                (currency("EUR").to_string(), Some((dec!(2), 1))),
+                (usd.to_string(), Some((dec!(1), 0))),
+                (goog.to_string(), None),
+            ]
+        );
+        let table = render_reachability(&report, &usd, true);
+        assert!(table.contains("Rate in USD"), "{}", table);
+        assert!(table.contains("currency:EUR -> currency:USD"), "{}", table);
+        assert!(
+            table
+                .lines()
+                .any(|line| line.contains(&goog.to_string()) && line.contains(" no ")),
+            "{}",
+            table
+        );
+    }
+
+    #[test]
+    fn table_annotates_disconnected_graph() {
+        // BTC is only converted to ETH, as if the converter pricing crypto in
+        // fiat failed. GOOG has no price, but its source reports its value.
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [{
+                "Id": "bank",
+                "Name": "Bank",
+                "Type": "hardcoded",
+                "Snapshot": [
+                  {"Type": "currency", "Symbol": "EUR", "Amount": 30},
+                  {"Type": "currency", "Symbol": "USD", "Amount": 20},
+                  {"Type": "stock", "Symbol": "GOOG", "Amount": 2,
+                   "CostBasis": {"Currency": "USD", "Money": 15, "UnrealizedPnl": 5}},
+                  {"Type": "crypto", "Symbol": "BTC", "Amount": 1}
+                ]
+              }],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [
+                  {
+                    "Source": {"Type": "currency", "Symbol": "EUR"},
+                    "Target": {"Type": "currency", "Symbol": "USD"},
+                    "TargetPerSource": 2
+                  },
+                  {
+                    "Source": {"Type": "crypto", "Symbol": "BTC"},
+                    "Target": {"Type": "crypto", "Symbol": "ETH"},
+                    "TargetPerSource": 10
+                  }
+                ]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 80}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, mut source_snapshots) = snapshots_from_json(&snapshot);
+        let usd = currency("USD");
+        let modelling: config::ModellingConfig = serde_yaml::from_str(
+            "
+monthly_saving: {currency: USD, amount: 100}
+yearly_yields: [0.04]
+monthly_targets: [{currency: USD, amount: 1000}]
+",
+        )
+        .unwrap();
+
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        assert_eq!(valuation.total.amount, dec!(80));
+        assert_eq!(
+            valuation.coverage,
+            Coverage {
+                held: 4,
+                priced: 2,
+                unpriced_value: dec!(20),
+                unvalued: 1,
+            }
+        );
+        let table = render(Format::Table, true, horizon(), &valuation, &modelling, &usd).unwrap();
+        assert!(
+            table.contains("priced 2 of 4 denominations, at least 20.00% of value unpriced"),
+            "{}",
+            table
+        );
+
+        // Had BTC been valued, the share unpriced would be known.
+        let valued = Coverage {
+            unvalued: 0,
+            ..valuation.coverage
+        };
+        assert_eq!(
+            valued.annotation(dec!(80), 1),
+            Some("priced 2 of 4 denominations, 20.0% of value unpriced".to_string())
+        );
+
+        // With only BTC unpriced, nothing says how much it's worth.
+        source_snapshots[0].snapshot.remove(2);
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        assert_eq!(
+            valuation.coverage.annotation(valuation.total.amount, 2),
+            Some("priced 2 of 3 denominations, unpriced value unknown".to_string())
+        );
+
+        // Everything held is priced.
+        source_snapshots[0].snapshot.pop();
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        assert_eq!(
+            valuation.coverage.annotation(valuation.total.amount, 2),
+            None
+        );
+        let table = render(Format::Table, true, horizon(), &valuation, &modelling, &usd).unwrap();
+        assert!(!table.contains("denominations"), "{}", table);
+    }
+
+    #[test]
+    fn render_metrics_exposition() {
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2021-01-01T00:00:00Z",
+              "SourceSnapshot": [{
+                "Id": "bank",
+                "Name": "Bank",
+                "Type": "hardcoded",
+                "Snapshot": [
+                  {"Type": "currency", "Symbol": "EUR", "Amount": 100},
+                  {"Type": "currency", "Symbol": "USD", "Amount": 10},
+                  {"Type": "stock", "Symbol": "GOOG", "Isin": "US02079K1079", "Amount": 2}
+                ]
+              }],
+              "ConverterSnapshots": [{
+                "Id": "fixer",
+                "Type": "fixer",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "currency", "Symbol": "EUR"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 2
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 210}
+            }"#,
+        )
+        .unwrap();
+        let now = DateTime::parse_from_rfc3339("2021-01-01T01:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let text = render_metrics(&currency("USD"), &Pricing::default(), &snapshot, now).unwrap();
+
+        let value = |series: &str| -> f64 {
+            let line = text
+                .lines()
+                .find(|line| line.starts_with(&format!("{} ", series)))
+                .unwrap_or_else(|| panic!("no {} in {}", series, text));
+            line[series.len() + 1..].parse().unwrap()
+        };
+        assert_eq!(value(r#"worthy_total{currency="USD"}"#), 210.0);
+        assert_eq!(value(r#"worthy_asset_amount{denomination="EUR"}"#), 100.0);
+        assert_eq!(
+            value(r#"worthy_asset_amount{denomination="GOOG",isin="US02079K1079"}"#),
+            2.0
+        );
+        assert_eq!(value("worthy_unpriced_denominations"), 1.0);
+        assert_eq!(value("worthy_snapshot_age_seconds"), 3600.0);
+        assert!(text.contains("# TYPE worthy_total gauge"), "{}", text);
+    }
+
+    fn source_snapshot(id: &str, amount: Decimal) -> SourceSnapshot {
+        SourceSnapshot {
+            id: id.to_string(),
+            name: id.to_uppercase(),
+            source_type: SourceType::Hardcoded,
+            snapshot: vec![Asset {
+                amount,
+                denomination: currency("USD"),
+                cost_basis: None,
+            }],
+            base_currency: None,
+            accounts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn aggregate_assets_across_sources() {
+        let mut bank = source_snapshot("bank", dec!(1.5));
+        bank.snapshot.push(Asset {
+            amount: dec!(3),
+            denomination: currency("CHF"),
+            cost_basis: None,
+        });
+        let snapshots = vec![bank, source_snapshot("ib", dec!(2))];
+        assert_eq!(
+            aggregate_assets(&snapshots).unwrap(),
+            vec![
+                Asset {
+                    amount: dec!(3),
+                    denomination: currency("CHF"),
+                    cost_basis: None,
+                },
+                Asset {
+                    amount: dec!(3.5),
+                    denomination: currency("USD"),
+                    cost_basis: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn denominations_to_price_include_goals() {
+        let asset = |code: &str| Asset {
+            amount: dec!(1),
+            denomination: currency(code),
+            cost_basis: None,
+        };
+        let modelling = config::ModellingConfig {
+            monthly_saving: asset("USD"),
+            yearly_yields: vec![dec!(0)],
+            monthly_targets: vec![
+                asset("EUR").into(),
+                MonthlyTarget {
+                    components: vec![asset("CHF"), asset("USD")],
+                },
+            ],
+            yields_as_percent: false,
+            display_precision: Default::default(),
+            horizon_years: dec!(75),
+        };
+        let held = aggregate_assets(&[source_snapshot("bank", dec!(1))]).unwrap();
+        assert_eq!(
+            denominations_to_price(&held, &modelling),
+            vec![&currency("USD"), &currency("EUR"), &currency("CHF")]
+        );
+    }
+
+    #[test]
+    fn value_snapshot_total_overflow() {
+        let usd = currency("USD");
+        let converter_snapshots = [ConverterSnapshot {
+            id: "fixer".to_string(),
+            converter_type: ConverterType::Fixer,
+            snapshot: vec![ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: currency("EUR"),
+                to: usd.clone(),
+                rate: dec!(2),
+                spread_bps: Decimal::ZERO,
+            }],
+        }];
+        // Each denomination adds up, but their total in USD doesn't.
+        let mut source_snapshots = vec![source_snapshot("bank", Decimal::MAX)];
+        source_snapshots[0].snapshot.push(Asset {
+            amount: dec!(1),
+            denomination: currency("EUR"),
+            cost_basis: None,
+        });
+
+        let error = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .err()
+        .unwrap();
+        assert!(matches!(error, WorthyError::Overflow(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn value_snapshot_prices_futures_at_zero() {
+        // As IBFlex reports an option and a future, with their prices.
+        let snapshot: json_output::Snapshot = serde_json::from_str(
+            r#"{
+              "Timestamp": "2024-06-03T00:00:00Z",
+              "SourceSnapshot": [{
+                "Id": "ib",
+                "Name": "IB",
+                "Type": "ibflex",
+                "Snapshot": [
+                  {"Type": "currency", "Symbol": "USD", "Amount": 1000},
+                  {"Type": "derivative", "Symbol": "AAPL  240621C00190000", "Amount": 300},
+                  {"Type": "derivative", "Symbol": "ESM4", "Amount": 100}
+                ]
+              }],
+              "ConverterSnapshots": [{
+                "Id": "source:ib",
+                "Type": "source",
+                "Params": {},
+                "Snapshot": [{
+                  "Source": {"Type": "derivative", "Symbol": "AAPL  240621C00190000"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 4.35
+                }, {
+                  "Source": {"Type": "derivative", "Symbol": "ESM4"},
+                  "Target": {"Type": "currency", "Symbol": "USD"},
+                  "TargetPerSource": 0
+                }]
+              }],
+              "Total": {"Type": "currency", "Symbol": "USD", "Amount": 2305}
+            }"#,
+        )
+        .unwrap();
+        let (converter_snapshots, source_snapshots) = snapshots_from_json(&snapshot);
+        let usd = currency("USD");
+        let future = Denomination::Derivative {
+            derivative: "ESM4".to_string(),
+        };
+
+        let valuation = value_snapshot(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+
+        assert_eq!(valuation.total.amount, dec!(2305));
+        assert!(valuation.unpriced.is_empty(), "{:?}", valuation.unpriced);
+        assert_eq!(valuation.in_common_currency[&future], dec!(0));
+        assert_eq!(machine_total_line(&valuation), "TOTAL 2305.00 USD");
+        assert_eq!(
+            valuation.coverage.annotation(valuation.total.amount, 2),
+            None
+        );
+        let report = reachability(
+            &usd,
+            &Pricing::default(),
+            &converter_snapshots,
+            &source_snapshots,
+        )
+        .unwrap();
+        assert!(
+            report.contains(&(future.clone(), Some((dec!(0), vec![future, usd])))),
+            "{:?}",
+            report
+        );
+    }
+
+    #[test]
+    fn value_snapshot_base_not_reachable() {
+        let snapshots = [source_snapshot("bank", dec!(10))];
+        // Held in the base, nothing needs converting.
+        let valuation =
+            value_snapshot(&currency("USD"), &Pricing::default(), &[], &snapshots).unwrap();
+        assert_eq!(valuation.total.amount, dec!(10));
+
+        let Err(error) = value_snapshot(&currency("EUR"), &Pricing::default(), &[], &snapshots)
+        else {
+            panic!("valued without rates into EUR");
+        };
+        assert!(
+            matches!(&error, WorthyError::BaseNotReachable { base } if *base == currency("EUR")),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.exit_code(), 14);
+    }
+
+    #[test]
+    fn merge_by_id_carries_over_unmentioned() {
+        let base = vec![
+            source_snapshot("bank", dec!(1)),
+            source_snapshot("ib", dec!(2)),
+        ];
+        let fresh = vec![
+            source_snapshot("ib", dec!(3)),
+            source_snapshot("new", dec!(4)),
+        ];
+        let merged = merge_by_id(base, fresh, |snapshot| &snapshot.id);
+
+        let json: Vec<_> = merged.iter().map(source_snapshot_to_json).collect();
+        assert_eq!(
+            json,
+            vec![
+                source_snapshot_to_json(&source_snapshot("bank", dec!(1))),
+                source_snapshot_to_json(&source_snapshot("ib", dec!(3))),
+                source_snapshot_to_json(&source_snapshot("new", dec!(4))),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_currencies_listed() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sources:
+  bank:
+    name: Bank
+    type: hardcoded
+    assets:
+      - {currency: EURO, amount: 100}
+      - {currency: CHF, amount: 300}
+      - {stock: VT, quantity: 2, unit_price: 100, unit_price_denomination: DOLLAR}
+      - {currency: EURO, amount: 200}
+  ib:
+    name: IB
+    type: ibflex
+    query_id: "123"
+    token: TOKEN
+converters: {}
+common_currency: USD
+dated_json_output: /tmp/worthy-%s.json
+csv_output: /tmp/worthy-%s.csv
+modelling:
+  monthly_saving: {currency: USD, amount: 100}
+  yearly_yields: []
+  monthly_targets:
+    - {currency: BTC, amount: 1}
+    - {currency: EUR, amount: 1000}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            unknown_currencies(&config),
+            vec![
+                "BTC in modelling.monthly_targets",
+                "DOLLAR in source bank",
+                "EURO in source bank",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_config_missing_file() {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix("worthy");
+        let error =
+            load_config(&xdg_dirs, Some(Path::new("/nonexistent/worthy.yaml"))).unwrap_err();
+        assert!(
+            matches!(&error, WorthyError::Config(message) if message.contains("does not exist")),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn common_currency_from_source() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+sources:
+  ib:
+    name: IB
+    type: ibflex
+    query_id: "123"
+    token: TOKEN
+  bank:
+    name: Bank
+    type: hardcoded
+    assets: []
+converters: {}
+common_currency: USD
+dated_json_output: /tmp/worthy-%s.json
+csv_output: /tmp/worthy-%s.csv
+modelling:
+  monthly_saving: {currency: USD, amount: 100}
+  yearly_yields: []
+  monthly_targets: []
+"#,
+        )
+        .unwrap();
+        let snapshots = vec![
+            SourceSnapshot {
+                base_currency: Some("CHF".to_string()),
+                ..source_snapshot("ib", dec!(1))
+            },
+            source_snapshot("bank", dec!(1)),
+        ];
+
+        assert_eq!(
+            common_currency(&config, None, &snapshots).unwrap(),
+            currency("USD")
+        );
+        assert_eq!(
+            common_currency(&config, Some("ib"), &snapshots).unwrap(),
+            currency("CHF")
+        );
+        // Hardcoded sources have no base currency.
+        assert_eq!(
+            common_currency(&config, Some("bank"), &snapshots).unwrap(),
+            currency("USD")
+        );
+        let error = common_currency(&config, Some("nope"), &snapshots).unwrap_err();
+        assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn snapshot_age_at_the_boundary() {
+        let taken = DateTime::parse_from_rfc3339("2021-01-01T00:00:00Z").unwrap();
+        let max_age = chrono::Duration::days(7);
+        let now = taken + max_age;
+        assert!(check_snapshot_age(taken, now, max_age).is_ok());
+        assert!(check_snapshot_age(taken, taken, max_age).is_ok());
+
+        let error =
+            check_snapshot_age(taken, now + chrono::Duration::seconds(1), max_age).unwrap_err();
+        assert!(
+            matches!(&error, WorthyError::StaleSnapshot { age, max_age, .. }
+                if age == "7d 1s" && max_age == "7d"),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.exit_code(), 12);
+    }
+
+    #[test]
+    fn replaying_rates_needs_recordings() {
+        let yaml = r#"
+converters: {}
+common_currency: USD
+dated_json_output: /tmp/worthy-%s.json
+csv_output: /tmp/worthy-%s.csv
+modelling:
+  monthly_saving: {currency: USD, amount: 100}
+  yearly_yields: []
+  monthly_targets: []
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(rate_recordings(&config, false).unwrap().is_none());
+        let error = rate_recordings(&config, true).unwrap_err();
+        assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+
+        let config: Config =
+            serde_yaml::from_str(&format!("rate_recordings: /tmp/rates\n{}", yaml)).unwrap();
+        let recordings = rate_recordings(&config, true).unwrap().unwrap();
+        assert_eq!(recordings.dir, Path::new("/tmp/rates"));
+        assert_eq!(recordings.mode, CacheMode::Replay);
+    }
+
+    #[test]
+    fn select_by_id_validates() {
+        let configs = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert_eq!(select_by_id(&configs, None, "source").unwrap().len(), 2);
+        assert_eq!(
+            select_by_id(&configs, Some(&["b".to_string()]), "source").unwrap(),
+            vec![(&"b".to_string(), &2)]
+        );
+        let error = select_by_id(&configs, Some(&["c".to_string()]), "source").unwrap_err();
+        assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn base_from_source_only_where_a_snapshot_is_valued() {
+        let opt = |command: &str| {
+            Opt::from_iter(&[
+                "worthy",
+                &format!("--command={}", command),
+                "--base-from-source=interactive_brokers",
+            ])
+        };
+        assert!(check_base_from_source(&opt("check")).is_ok());
+        assert!(check_base_from_source(&opt("checkrates")).is_ok());
+        assert!(matches!(
+            check_base_from_source(&opt("modelall")),
+            Err(WorthyError::Config(_))
+        ));
+        assert!(matches!(
+            check_base_from_source(&opt("server")),
+            Err(WorthyError::Config(_))
+        ));
+        assert!(check_base_from_source(&Opt::from_iter(&["worthy", "--command=modelall"])).is_ok());
     }
 }
@@ -0,0 +1,40 @@
+//! Minimal encoder for the Prometheus text exposition format, enough to
+//! expose a few gauges without pulling in a metrics library.
+
+use std::fmt::Write;
+
+#[derive(Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    /// Adds gauge `name` with one sample per `(labels, value)`.
+    pub fn gauge(&mut self, name: &str, help: &str, samples: &[(Vec<(&str, String)>, f64)]) {
+        writeln!(self.text, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.text, "# TYPE {} gauge", name).unwrap();
+        for (labels, value) in samples {
+            self.text.push_str(name);
+            if !labels.is_empty() {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                    .collect();
+                write!(self.text, "{{{}}}", labels.join(",")).unwrap();
+            }
+            writeln!(self.text, " {}", value).unwrap();
+        }
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+/// Escapes a label value: backslashes, double quotes and newlines.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,26 @@
+use metrics::Exposition;
+
+#[test]
+fn gauges() {
+    let mut exposition = Exposition::default();
+    exposition.gauge("worthy_total", "Total.", &[(vec![], 1234.5)]);
+    exposition.gauge(
+        "worthy_asset_amount",
+        "Amount.",
+        &[
+            (vec![("denomination", "USD".to_string())], 10.0),
+            (vec![("denomination", "A\"B\\C".to_string())], 2.0),
+        ],
+    );
+    assert_eq!(
+        exposition.into_text(),
+        r#"# HELP worthy_total Total.
+# TYPE worthy_total gauge
+worthy_total 1234.5
+# HELP worthy_asset_amount Amount.
+# TYPE worthy_asset_amount gauge
+worthy_asset_amount{denomination="USD"} 10
+worthy_asset_amount{denomination="A\"B\\C"} 2
+"#
+    );
+}
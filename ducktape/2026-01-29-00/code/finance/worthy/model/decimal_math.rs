@@ -0,0 +1,113 @@
+//! Logarithms and powers of `Decimal`s, computed by series in `Decimal`
+//! rather than by a round trip through `f64`, which drifts over long
+//! horizons.
+
+use rust_decimal::prelude::*;
+use rust_decimal_macros::*;
+
+/// Digits after the decimal point series are summed to by default.
+pub const DEFAULT_PRECISION: u32 = 24;
+
+/// ln(2), to the 28 significant digits of a `Decimal`.
+const LN_2: Decimal = dec!(0.6931471805599453094172321215);
+
+/// Size below which a term of a series no longer changes its sum at
+/// `precision`.
+fn epsilon(precision: u32) -> Decimal {
+    Decimal::new(1, precision.min(Decimal::MAX_SCALE))
+}
+
+/// Natural logarithm. None if `x` is not positive.
+pub fn ln(x: Decimal) -> Option<Decimal> {
+    ln_with_precision(x, DEFAULT_PRECISION)
+}
+
+/// `ln`, summing its series until terms are below 10^-`precision`.
+pub fn ln_with_precision(x: Decimal, precision: u32) -> Option<Decimal> {
+    if x <= Decimal::ZERO {
+        return None;
+    }
+    // x = m * 2^k with m in [0.5, 2], so that ln(x) = ln(m) + k ln(2).
+    let mut m = x;
+    let mut k: i64 = 0;
+    while m > Decimal::TWO {
+        m /= Decimal::TWO;
+        k += 1;
+    }
+    while m < dec!(0.5) {
+        m *= Decimal::TWO;
+        k -= 1;
+    }
+    // ln(m) = 2 atanh(y) = 2 (y + y^3/3 + y^5/5 + ...) with |y| <= 1/3.
+    let y = (m - Decimal::ONE) / (m + Decimal::ONE);
+    let y_squared = y * y;
+    let epsilon = epsilon(precision);
+    let mut power = y;
+    let mut sum = Decimal::ZERO;
+    let mut n = Decimal::ONE;
+    loop {
+        let term = power / n;
+        sum += term;
+        if term.abs() < epsilon {
+            break;
+        }
+        power *= y_squared;
+        n += Decimal::TWO;
+    }
+    Some(Decimal::TWO * sum + Decimal::from(k) * LN_2)
+}
+
+/// e^`x`. None if it's too large for a `Decimal`; results too small for one
+/// are 0.
+pub fn exp(x: Decimal) -> Option<Decimal> {
+    exp_with_precision(x, DEFAULT_PRECISION)
+}
+
+/// `exp`, summing its series until terms are below 10^-`precision`.
+pub fn exp_with_precision(x: Decimal, precision: u32) -> Option<Decimal> {
+    // x = k ln(2) + r with |r| <= ln(2) / 2, so that e^x = e^r 2^k.
+    let k = x.checked_div(LN_2)?.round();
+    // 2^96 is past the largest `Decimal`, and 2^-96 (times e^r) rounds to 0.
+    if k > dec!(96) {
+        return None;
+    }
+    if k < dec!(-96) {
+        return Some(Decimal::ZERO);
+    }
+    let r = x - k * LN_2;
+    let k = k.to_i64()?;
+    let epsilon = epsilon(precision);
+    let mut term = Decimal::ONE;
+    let mut sum = Decimal::ONE;
+    let mut n = Decimal::ONE;
+    loop {
+        term = term * r / n;
+        sum += term;
+        if term.abs() < epsilon {
+            break;
+        }
+        n += Decimal::ONE;
+    }
+    for _ in 0..k.max(0) {
+        sum = sum.checked_mul(Decimal::TWO)?;
+    }
+    for _ in 0..(-k).max(0) {
+        sum /= Decimal::TWO;
+    }
+    Some(sum)
+}
+
+/// `base`^`exponent`. None for negative bases, for 0 to a power that isn't
+/// positive, and for results too large for a `Decimal`.
+pub fn powf(base: Decimal, exponent: Decimal) -> Option<Decimal> {
+    powf_with_precision(base, exponent, DEFAULT_PRECISION)
+}
+
+/// `powf`, computing its logarithm and exponential to `precision`.
+pub fn powf_with_precision(base: Decimal, exponent: Decimal, precision: u32) -> Option<Decimal> {
+    if base.is_zero() {
+        return (exponent > Decimal::ZERO).then_some(Decimal::ZERO);
+    }
+    let ln_base = ln_with_precision(base, precision)?;
+    exp_with_precision(exponent.checked_mul(ln_base)?, precision)
+}
@@ -0,0 +1,94 @@
+use decimal_math::{exp, ln, ln_with_precision, powf};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+
+const E: Decimal = dec!(2.7182818284590452353602874714);
+
+fn assert_close(actual: Option<Decimal>, expected: Decimal, tolerance: Decimal) {
+    let actual = actual.unwrap();
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{} is not within {} of {}",
+        actual,
+        tolerance,
+        expected
+    );
+}
+
+#[test]
+fn ln_of_known_values() {
+    assert_close(ln(E), dec!(1), dec!(1e-24));
+    assert_close(ln(dec!(1)), dec!(0), dec!(1e-24));
+    assert_close(
+        ln(dec!(2)),
+        dec!(0.6931471805599453094172321215),
+        dec!(1e-24),
+    );
+    assert_close(
+        ln(dec!(10)),
+        dec!(2.3025850929940456840179914547),
+        dec!(1e-24),
+    );
+    assert_close(
+        ln(dec!(1.04)),
+        dec!(0.0392207131532812962692008966),
+        dec!(1e-24),
+    );
+    assert_close(
+        ln(dec!(0.001)),
+        dec!(-6.9077552789821370520539743641),
+        dec!(1e-24),
+    );
+    assert_close(
+        ln(dec!(1e20)),
+        dec!(46.051701859880913680359829093),
+        dec!(1e-22),
+    );
+    assert_eq!(ln(dec!(0)), None);
+    assert_eq!(ln(dec!(-1)), None);
+}
+
+#[test]
+fn ln_to_lower_precision() {
+    assert_close(ln_with_precision(E, 6), dec!(1), dec!(1e-6));
+}
+
+#[test]
+fn exp_of_known_values() {
+    assert_close(exp(dec!(1)), E, dec!(1e-24));
+    assert_close(exp(dec!(0)), dec!(1), dec!(1e-24));
+    assert_close(
+        exp(dec!(-1)),
+        dec!(0.3678794411714423215955237702),
+        dec!(1e-24),
+    );
+    // To the 28 significant digits of a `Decimal`.
+    assert_close(
+        exp(dec!(50)),
+        dec!(5184705528587072464087.4533229),
+        dec!(1e-4),
+    );
+    // Too small for a `Decimal`, and too large.
+    assert_eq!(exp(dec!(-100)), Some(dec!(0)));
+    assert_eq!(exp(dec!(100)), None);
+}
+
+#[test]
+fn powf_of_known_values() {
+    assert_close(powf(dec!(2), dec!(10)), dec!(1024), dec!(1e-18));
+    assert_close(
+        powf(dec!(1.04), dec!(-30)),
+        dec!(0.3083186679734207192729445045),
+        dec!(1e-24),
+    );
+    assert_close(
+        powf(dec!(1.03), dec!(75)),
+        dec!(9.178925667647181997106405813),
+        dec!(1e-22),
+    );
+    assert_close(powf(dec!(9), dec!(0.5)), dec!(3), dec!(1e-24));
+    assert_eq!(powf(dec!(0), dec!(2)), Some(dec!(0)));
+    assert_eq!(powf(dec!(0), dec!(-1)), None);
+    assert_eq!(powf(dec!(-2), dec!(2)), None);
+    assert_eq!(powf(dec!(10), dec!(40)), None);
+}
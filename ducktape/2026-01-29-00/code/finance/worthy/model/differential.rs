@@ -1,49 +1,69 @@
-use log::info;
 use rust_decimal::prelude::*;
 use rust_decimal_macros::*;
 
-// TODO: deduplicate
-fn decimal_log(x: Decimal) -> Decimal {
-    info!("ln({})", x);
-    let x = x.to_f64().unwrap();
-    Decimal::from_f64(x.ln()).unwrap()
-}
-
+/// How many years of saving until `total` grows to `target_number`.
+///
+/// None if the target is never reached, e.g. because there are no savings, or
+/// because a negative yield erodes the portfolio faster than savings add to it.
 pub fn years_until_saved_up_exp(
     total: Decimal,
     yearly_yield: Decimal,
     target_number: Decimal,
     monthly_saving: Decimal,
-) -> Decimal {
+) -> Option<Decimal> {
     // Calculate how much longer do we need to save to get that number.
-    let c = -monthly_saving * dec!(12); // monthly savings (minus - because they're plus, not costs)
+    let s = monthly_saving * dec!(12); // yearly savings
     let f_0 = total; // initial savings
+    if target_number <= f_0 {
+        return Some(Decimal::ZERO);
+    }
+    if yearly_yield.is_zero() {
+        // f_x = f_0 + s*x
+        if s <= Decimal::ZERO {
+            return None;
+        }
+        return Some((target_number - f_0) / s);
+    }
     let i = yearly_yield; // yearly yield, e.g. 0.04 = 4%
-    let i_prime = decimal_log(dec!(1) + i);
-    let c = f_0 - (c / i_prime);
+    let i_prime = decimal_math::ln(dec!(1) + i)?;
+    let c = f_0 + (s / i_prime);
 
-    let time_to_reach = |f_x: Decimal| -> Decimal {
-        // Derivation:
-        // f_x = c*math.Exp(1+i, x) + c/i_prime
-        // 0 = c*math.Exp(1+i, x) + c/i_prime - f_x
-        // -c*math.Exp(1+i, x) = c/i_prime - f_x
-        // math.Exp(1+i, x) = (c/i_prime - f_x) / (-c)
-        // x = math.Log((c/i_prime - f_x) / (-c)) / i_prime
-        decimal_log((c / i_prime - f_x) / (-c)) / i_prime
-    };
-    time_to_reach(target_number)
+    // Derivation:
+    // f_x = c*math.Exp(i_prime*x) - s/i_prime
+    // math.Exp(i_prime*x) = (f_x + s/i_prime) / c
+    // x = math.Log((f_x + s/i_prime) / c) / i_prime
+    if c.is_zero() {
+        return None;
+    }
+    let years = decimal_math::ln((target_number + s / i_prime) / c)? / i_prime;
+    if years < Decimal::ZERO {
+        return None;
+    }
+    Some(years)
 }
 
 // Calculates how long a given amount will last, in years.
+//
+// None if the amount never runs out, i.e. when the yield covers the costs, or
+// if the yield loses everything. Zero if there is nothing to spend, e.g. when
+// liabilities exceed the assets.
 pub fn get_investment_durability(
     total: Decimal,
     yearly_yield: Decimal,
     monthly_costs: Decimal,
-) -> Decimal {
+) -> Option<Decimal> {
+    if total <= Decimal::ZERO {
+        return Some(Decimal::ZERO);
+    }
     let c = monthly_costs * dec!(12); // yearly costs
     let f_0 = total; // initial savings
+    if yearly_yield.is_zero() {
+        // f_x = f_0 - c*x
+        return f_0.checked_div(c);
+    }
     let i = yearly_yield; // yearly yield, e.g. 0.04 = 4%
-    let i_prime = decimal_log(dec!(1) + i);
-    let c = f_0 - (c / i_prime);
-    decimal_log(-c / (c * i_prime)) / i_prime
+    let i_prime = decimal_math::ln(dec!(1) + i)?;
+    // f_x = (f_0 - c/i_prime)*math.Exp(i_prime*x) + c/i_prime
+    // f_x = 0 <=> x = math.Log(c / (c - i_prime*f_0)) / i_prime
+    Some(decimal_math::ln(c.checked_div(c - i_prime * f_0)?)? / i_prime)
 }
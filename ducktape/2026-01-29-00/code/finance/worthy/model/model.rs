@@ -1,22 +1,83 @@
-use chrono::Duration;
 use chrono::prelude::*;
+use chrono::{Duration, Months};
 use log::info;
 use rust_decimal::prelude::*;
 use rust_decimal_macros::*;
 
-fn hack_pow(a: Decimal, b: Decimal) -> Decimal {
-    Decimal::from_f64(a.to_f64().unwrap().powf(b.to_f64().unwrap())).unwrap()
+/// How much money we'd need to get if we want to
+///
+/// None if no amount lasts until the deadline, i.e. for yields of -100% or
+/// worse, or if it's too large for a `Decimal`. Records the steps in
+/// `intermediates`.
+fn deadline_target(
+    yearly_yield: Decimal,
+    monthly_goal: Decimal,
+    deadline: Decimal,
+    intermediates: &mut Intermediates,
+) -> Option<Decimal> {
+    intermediates.yearly_spending = monthly_goal * dec!(12);
+    if yearly_yield <= dec!(-1) {
+        return None;
+    }
+    if yearly_yield.is_zero() {
+        return Some(intermediates.yearly_spending * deadline);
+    }
+    let continuous_yield = decimal_math::ln(dec!(1) + yearly_yield)?;
+    let deadline_share = dec!(1) - decimal_math::powf(dec!(1) + yearly_yield, -deadline)?;
+    intermediates.continuous_yield = Some(continuous_yield);
+    intermediates.deadline_share = Some(deadline_share);
+    Some((intermediates.yearly_spending / continuous_yield) * deadline_share)
 }
 
-// TODO: deduplicate
-fn decimal_log(x: Decimal) -> Decimal {
-    Decimal::from_f64(x.to_f64().unwrap().ln()).unwrap()
+/// How much `total` pays for a month if it's spent down, principal included,
+/// over `deadline` years of `yearly_yield`: the yearly payment of an annuity,
+/// in twelve installments. Never less than the perpetual withdrawal
+/// `total * yearly_yield / 12`, which it approaches for far deadlines.
+///
+/// None for yields of -100% or worse, for a deadline that isn't in the
+/// future, or on overflow.
+pub fn projected_monthly_spend(
+    total: Decimal,
+    yearly_yield: Decimal,
+    deadline: Decimal,
+) -> Option<Decimal> {
+    if yearly_yield <= dec!(-1) || deadline <= Decimal::ZERO {
+        return None;
+    }
+    let yearly = if yearly_yield.is_zero() {
+        total / deadline
+    } else {
+        // Yearly payment per perpetual withdrawal, 1 / (1 - (1 + yield)^-deadline),
+        // at least 1 for positive yields. Written with the power that shrinks,
+        // which goes to 0 for far deadlines rather than overflowing.
+        let ratio = if yearly_yield > Decimal::ZERO {
+            let discount = decimal_math::powf(dec!(1) + yearly_yield, -deadline)?;
+            dec!(1).checked_div(dec!(1) - discount)?
+        } else {
+            let growth = decimal_math::powf(dec!(1) + yearly_yield, deadline)?;
+            growth.checked_div(growth - dec!(1))?
+        };
+        total.checked_mul(yearly_yield)?.checked_mul(ratio)?
+    };
+    Some(yearly / dec!(12))
 }
 
-/// How much money we'd need to get if we want to
-fn deadline_target(yearly_yield: Decimal, monthly_goal: Decimal, deadline: Decimal) -> Decimal {
-    ((monthly_goal * dec!(12)) / decimal_log(dec!(1) + yearly_yield))
-        * (dec!(1) - hack_pow(dec!(1) + yearly_yield, -deadline))
+/// Values the model goes through on the way to a `FiInfo`, to explain it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Intermediates {
+    /// The monthly goal times 12.
+    pub yearly_spending: Decimal,
+    /// ln(1 + yearly yield), the yield compounded continuously. None for a
+    /// zero yield, whose target is just the yearly spending times the years.
+    pub continuous_yield: Option<Decimal>,
+    /// 1 - (1 + yearly yield)^-deadline: how much of a perpetuity the total
+    /// has to be to last until the deadline.
+    pub deadline_share: Option<Decimal>,
+    /// Years the total lasts, if the target isn't reached and the total runs out.
+    pub durability_years: Option<Decimal>,
+    /// Years of saving until the target is reached, if it isn't yet but will
+    /// be.
+    pub years_until_saved: Option<Decimal>,
 }
 
 pub enum State {
@@ -30,24 +91,91 @@ pub enum State {
         lasts_until: DateTime<Utc>,
         projected_until_saved: DateTime<Utc>,
     },
+    /// Savings never grow to the target under this yield, e.g. because a
+    /// negative yield erodes the portfolio faster than savings add to it.
+    NeverReached {
+        durability: Duration,
+
+        lasts_until: DateTime<Utc>,
+    },
+}
+
+/// When the total reaches the target, saving `monthly_saving` a month.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FiDate {
+    AlreadyReached,
+    On(DateTime<Utc>),
+    Never,
+}
+
+impl FiDate {
+    fn of(state: &State) -> FiDate {
+        match *state {
+            State::Reached { .. } => FiDate::AlreadyReached,
+            State::NotReached {
+                projected_until_saved,
+                ..
+            } => FiDate::On(projected_until_saved),
+            State::NeverReached { .. } => FiDate::Never,
+        }
+    }
+
+    /// E.g. "2031-04-17", "already reached" or "never".
+    pub fn short_string(&self) -> String {
+        match self {
+            FiDate::AlreadyReached => "already reached".to_string(),
+            FiDate::On(date) => date.format("%Y-%m-%d").to_string(),
+            FiDate::Never => "never".to_string(),
+        }
+    }
 }
 
 pub struct FiInfo {
     pub deadline: Decimal,
-    pub need_to_last_until_deadline: Decimal,
+    pub yearly_yield: Decimal,
+    pub monthly_goal: Decimal,
+    /// None if no amount lasts until the deadline (yield of -100% or worse).
+    pub need_to_last_until_deadline: Option<Decimal>,
     pub total: Decimal,
     pub monthly_saving: Decimal,
     pub state: State,
+    /// When `state` is reached, the headline of the model.
+    pub fi_date: FiDate,
+    pub intermediates: Intermediates,
 }
 
 impl FiInfo {
-    pub fn lasts_until_short_string(&self) -> String {
+    /// With `ascii`, spells out the check mark and arrows for terminals that
+    /// can't show them.
+    pub fn lasts_until_short_string(&self, ascii: bool) -> String {
+        self.lasts_until_short_string_with_places(ascii, 0)
+    }
+
+    /// Like `lasts_until_short_string`, with `percent_places` decimal places
+    /// of how far the goal is exceeded.
+    pub fn lasts_until_short_string_with_places(
+        &self,
+        ascii: bool,
+        percent_places: usize,
+    ) -> String {
         match self.state {
+            State::Reached {
+                overreach_percentage,
+            } if ascii => format!("{:.*}% ok", percent_places, overreach_percentage),
             State::Reached {
                 overreach_percentage,
             } => {
-                format!("{:.0}% ✓", overreach_percentage)
+                format!("{:.*}% ✓", percent_places, overreach_percentage)
             }
+            State::NotReached {
+                lasts_until,
+                projected_until_saved,
+                ..
+            } if ascii => format!(
+                "saved {}\nlasts {}",
+                projected_until_saved.format("%Y-%m-%d"),
+                lasts_until.format("%Y-%m-%d")
+            ),
             State::NotReached {
                 lasts_until,
                 projected_until_saved,
@@ -61,49 +189,116 @@ impl FiInfo {
                     lasts_until.format("%Y-%m-%d")
                 )
             }
+            State::NeverReached { lasts_until, .. } if ascii => {
+                format!("saved never\nlasts {}", lasts_until.format("%Y-%m-%d"))
+            }
+            State::NeverReached { lasts_until, .. } => {
+                format!(
+                    "\u{2912} never\n\u{2913} {}",
+                    lasts_until.format("%Y-%m-%d")
+                )
+            }
         }
     }
 }
 
-fn years_duration(years: Decimal) -> Duration {
-    Duration::seconds((years.to_f64().unwrap() * 24_f64 * 60_f64 * 60_f64 * 365.24).round() as i64)
+/// `start` moved by `years` on the calendar: whole years keep the month and
+/// day (Feb 29 becomes Feb 28 outside leap years), and the fraction is that
+/// share of the year after them. Past the last representable date, that date.
+fn add_years(start: DateTime<Utc>, years: Decimal) -> DateTime<Utc> {
+    let shift = |years: i64| {
+        let months = u32::try_from(years.unsigned_abs().checked_mul(12)?).ok()?;
+        if years < 0 {
+            start.checked_sub_months(Months::new(months))
+        } else {
+            start.checked_add_months(Months::new(months))
+        }
+    };
+    let whole = years.floor();
+    let (Some(anniversary), Some(next)) = (
+        whole.to_i64().and_then(shift),
+        whole.to_i64().and_then(|whole| shift(whole + 1)),
+    ) else {
+        return if years < Decimal::ZERO {
+            DateTime::<Utc>::MIN_UTC
+        } else {
+            DateTime::<Utc>::MAX_UTC
+        };
+    };
+    let year_seconds = Decimal::from((next - anniversary).num_seconds());
+    let seconds = ((years - whole) * year_seconds)
+        .round()
+        .to_i64()
+        .unwrap_or(0);
+    anniversary + Duration::seconds(seconds)
 }
 
-/// Yearly yield: 0.03 means assumed yearly yield of 3%.
+/// Yearly yield: 0.03 means assumed yearly yield of 3%. Dates are projected
+/// from `now`.
 pub fn model_fi_info(
     total: Decimal,
     yearly_yield: Decimal,
     monthly_goal: Decimal,
     monthly_saving: Decimal,
     deadline: Decimal,
+    now: DateTime<Utc>,
 ) -> FiInfo {
-    let now = Utc::now();
-
-    let target = deadline_target(yearly_yield, monthly_goal, deadline);
+    let mut intermediates = Intermediates::default();
+    let Some(target) = deadline_target(yearly_yield, monthly_goal, deadline, &mut intermediates)
+    else {
+        // Everything is lost right away.
+        return FiInfo {
+            total,
+            deadline,
+            yearly_yield,
+            monthly_goal,
+            need_to_last_until_deadline: None,
+            monthly_saving,
+            state: State::NeverReached {
+                durability: Duration::zero(),
+                lasts_until: now,
+            },
+            fi_date: FiDate::Never,
+            intermediates,
+        };
+    };
+    let state = if target < total {
+        State::Reached {
+            overreach_percentage: (total / target) * dec!(100),
+        }
+    } else {
+        info!("We need {}, we have {}", target, total);
+        let durability = differential::get_investment_durability(total, yearly_yield, monthly_goal);
+        intermediates.durability_years = durability;
+        // Money that never runs out lasts past the last representable date.
+        let lasts_until =
+            durability.map_or(DateTime::<Utc>::MAX_UTC, |years| add_years(now, years));
+        match differential::years_until_saved_up_exp(total, yearly_yield, target, monthly_saving) {
+            Some(need_years) => {
+                intermediates.years_until_saved = Some(need_years);
+                let projected_until_saved = add_years(now, need_years);
+                State::NotReached {
+                    durability: lasts_until - now,
+                    until_saved_up: projected_until_saved - now,
+                    lasts_until,
+                    projected_until_saved,
+                }
+            }
+            None => State::NeverReached {
+                durability: lasts_until - now,
+                lasts_until,
+            },
+        }
+    };
     FiInfo {
         total,
         deadline,
-        need_to_last_until_deadline: target,
+        yearly_yield,
+        monthly_goal,
+        need_to_last_until_deadline: Some(target),
         monthly_saving,
-        state: if target < total {
-            State::Reached {
-                overreach_percentage: (total / target) * dec!(100),
-            }
-        } else {
-            info!("We need {}, we have {}", target, total);
-            let durability =
-                differential::get_investment_durability(total, yearly_yield, monthly_goal);
-            let need_years =
-                differential::years_until_saved_up_exp(total, yearly_yield, target, monthly_saving);
-            let need_years = years_duration(need_years);
-            let durability = years_duration(durability);
-
-            State::NotReached {
-                durability,
-                until_saved_up: need_years,
-                lasts_until: now + durability,
-                projected_until_saved: now + need_years,
-            }
-        },
+        fi_date: FiDate::of(&state),
+        state,
+        intermediates,
     }
 }
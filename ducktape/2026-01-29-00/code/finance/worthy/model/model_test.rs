@@ -0,0 +1,296 @@
+use chrono::prelude::*;
+use model_rs::{FiDate, Intermediates, State, model_fi_info, projected_monthly_spend};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+
+fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap()
+}
+
+#[test]
+fn zero_yield_uses_linear_target() {
+    let info = model_fi_info(dec!(1000), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    assert_eq!(info.need_to_last_until_deadline, Some(dec!(1200)));
+    match info.state {
+        State::NotReached {
+            durability,
+            until_saved_up,
+            ..
+        } => {
+            // 1000 / 120 per year
+            assert_eq!(durability.num_days(), 3043);
+            // (1200 - 1000) / 120 per year
+            assert_eq!(until_saved_up.num_days(), 608);
+        }
+        _ => panic!("expected NotReached"),
+    }
+}
+
+#[test]
+fn zero_yield_reached() {
+    let info = model_fi_info(dec!(2400), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    assert!(matches!(
+        info.state,
+        State::Reached {
+            overreach_percentage
+        } if overreach_percentage == dec!(200)
+    ));
+}
+
+#[test]
+fn small_positive_yield() {
+    let info = model_fi_info(dec!(1000), dec!(0.01), dec!(10), dec!(10), dec!(10), now());
+    let target = info.need_to_last_until_deadline.unwrap();
+    // Yield makes the target smaller than the linear 1200.
+    assert!(target > dec!(1100) && target < dec!(1200), "{}", target);
+    assert!(matches!(info.state, State::NotReached { .. }));
+}
+
+#[test]
+fn negative_yield_reachable_with_savings() {
+    let info = model_fi_info(dec!(1000), dec!(-0.02), dec!(10), dec!(10), dec!(10), now());
+    let target = info.need_to_last_until_deadline.unwrap();
+    // Losses make the target bigger than the linear 1200.
+    assert!(target > dec!(1200) && target < dec!(1400), "{}", target);
+    assert!(matches!(info.state, State::NotReached { .. }));
+}
+
+#[test]
+fn negative_yield_without_savings_never_reached() {
+    let info = model_fi_info(dec!(1000), dec!(-0.02), dec!(10), dec!(0), dec!(10), now());
+    assert!(matches!(info.state, State::NeverReached { .. }));
+}
+
+#[test]
+fn liabilities_exceeding_assets_last_no_time() {
+    // Debt at a negative yield would take the logarithm of a negative number.
+    for yearly_yield in [dec!(-0.5), dec!(0), dec!(0.03)] {
+        let info = model_fi_info(
+            dec!(-100000),
+            yearly_yield,
+            dec!(10),
+            dec!(10),
+            dec!(10),
+            now(),
+        );
+        match info.state {
+            State::NotReached { durability, .. } | State::NeverReached { durability, .. } => {
+                assert!(durability.is_zero(), "{}: {}", yearly_yield, durability)
+            }
+            State::Reached { .. } => panic!("{}: expected not reached", yearly_yield),
+        }
+        assert_eq!(info.intermediates.durability_years, Some(dec!(0)));
+    }
+    // Savings pay the debt off and then save up to the target.
+    let info = model_fi_info(dec!(-1000), dec!(0), dec!(10), dec!(100), dec!(10), now());
+    match info.state {
+        State::NotReached { until_saved_up, .. } => {
+            // (1200 + 1000) / 1200 per year
+            assert_eq!(until_saved_up.num_days(), 669);
+        }
+        _ => panic!("expected NotReached"),
+    }
+}
+
+#[test]
+fn total_loss_yield_never_reached() {
+    let info = model_fi_info(dec!(1000), dec!(-1), dec!(10), dec!(10), dec!(10), now());
+    assert_eq!(info.need_to_last_until_deadline, None);
+    assert!(matches!(info.state, State::NeverReached { .. }));
+}
+
+#[test]
+fn ascii_lasts_until_in_every_state() {
+    let reached = model_fi_info(dec!(2400), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    let not_reached = model_fi_info(dec!(1000), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    let never_reached = model_fi_info(dec!(1000), dec!(-0.02), dec!(10), dec!(0), dec!(10), now());
+    assert!(matches!(reached.state, State::Reached { .. }));
+    assert!(matches!(not_reached.state, State::NotReached { .. }));
+    assert!(matches!(never_reached.state, State::NeverReached { .. }));
+    for info in [reached, not_reached, never_reached] {
+        let ascii = info.lasts_until_short_string(true);
+        assert!(ascii.is_ascii(), "{}", ascii);
+        assert!(!info.lasts_until_short_string(false).is_ascii());
+    }
+}
+
+#[test]
+fn dates_projected_from_now() {
+    let info = model_fi_info(dec!(1000), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    match info.state {
+        State::NotReached {
+            durability,
+            until_saved_up,
+            lasts_until,
+            projected_until_saved,
+        } => {
+            assert_eq!(lasts_until, now() + durability);
+            assert_eq!(projected_until_saved, now() + until_saved_up);
+            // 1000 / 120 per year
+            assert_eq!(
+                lasts_until.date_naive(),
+                NaiveDate::from_ymd_opt(2029, 5, 2).unwrap()
+            );
+        }
+        _ => panic!("expected NotReached"),
+    }
+
+    let lost = model_fi_info(dec!(1000), dec!(-1), dec!(10), dec!(10), dec!(10), now());
+    assert!(matches!(
+        lost.state,
+        State::NeverReached { lasts_until, .. } if lasts_until == now()
+    ));
+}
+
+#[test]
+fn intermediates_of_zero_yield() {
+    let info = model_fi_info(dec!(1000), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    assert_eq!(
+        info.intermediates,
+        Intermediates {
+            yearly_spending: dec!(120),
+            continuous_yield: None,
+            deadline_share: None,
+            durability_years: info.intermediates.durability_years,
+            years_until_saved: info.intermediates.years_until_saved,
+        }
+    );
+    // 1000 / 120 and (1200 - 1000) / 120 per year.
+    let years = |years: Option<rust_decimal::Decimal>| years.unwrap().round_dp(2);
+    assert_eq!(years(info.intermediates.durability_years), dec!(8.33));
+    assert_eq!(years(info.intermediates.years_until_saved), dec!(1.67));
+}
+
+#[test]
+fn intermediates_of_positive_yield() {
+    let info = model_fi_info(dec!(1000), dec!(0.03), dec!(10), dec!(10), dec!(10), now());
+    let intermediates = &info.intermediates;
+    let target = intermediates.yearly_spending / intermediates.continuous_yield.unwrap()
+        * intermediates.deadline_share.unwrap();
+    assert_eq!(info.need_to_last_until_deadline, Some(target));
+}
+
+/// When a zero-yield total of `total` lasts until, spending 10 a month from
+/// `start`, with a deadline too far to reach.
+fn lasts_until(total: Decimal, start: DateTime<Utc>) -> DateTime<Utc> {
+    let deadline = dec!(1_000_000_000_000_000);
+    match model_fi_info(total, dec!(0), dec!(10), dec!(0), deadline, start).state {
+        State::NotReached { lasts_until, .. } | State::NeverReached { lasts_until, .. } => {
+            lasts_until
+        }
+        State::Reached { .. } => panic!("expected not reached"),
+    }
+}
+
+#[test]
+fn years_from_leap_day() {
+    let leap_day = Utc.with_ymd_and_hms(2020, 2, 29, 0, 0, 0).unwrap();
+    // 1 year
+    assert_eq!(
+        lasts_until(dec!(120), leap_day),
+        Utc.with_ymd_and_hms(2021, 2, 28, 0, 0, 0).unwrap()
+    );
+    // 4 years, to the next leap day.
+    assert_eq!(
+        lasts_until(dec!(480), leap_day),
+        Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap()
+    );
+    // 1.5 years: half of the 365 days from 2021-02-28.
+    assert_eq!(
+        lasts_until(dec!(180), leap_day),
+        Utc.with_ymd_and_hms(2021, 8, 29, 12, 0, 0).unwrap()
+    );
+}
+
+#[test]
+fn decades_land_on_the_anniversary() {
+    // 40 years, over 10 leap days, which 365.24 days a year would miss by
+    // most of a day.
+    assert_eq!(
+        lasts_until(dec!(4800), now()),
+        Utc.with_ymd_and_hms(2061, 1, 1, 0, 0, 0).unwrap()
+    );
+    // Too far to represent.
+    assert_eq!(
+        lasts_until(dec!(1000000000000), now()),
+        DateTime::<Utc>::MAX_UTC
+    );
+}
+
+#[test]
+fn fi_date_at_known_saving_and_yield() {
+    // (1200 - 1000) / 120 per year, i.e. 1 year and 8 months.
+    let info = model_fi_info(dec!(1000), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    match info.fi_date {
+        FiDate::On(date) => assert_eq!(
+            date.date_naive(),
+            NaiveDate::from_ymd_opt(2022, 9, 1).unwrap()
+        ),
+        fi_date => panic!("expected a date, got {:?}", fi_date),
+    }
+    assert_eq!(info.fi_date.short_string(), "2022-09-01");
+
+    // Saving up to the target of about 2396 would take over 11 years without
+    // the yield growing the total along with the savings.
+    let info = model_fi_info(dec!(1000), dec!(0.05), dec!(10), dec!(10), dec!(75), now());
+    assert_eq!(info.fi_date.short_string(), "2027-12-13");
+
+    let reached = model_fi_info(dec!(2400), dec!(0), dec!(10), dec!(10), dec!(10), now());
+    assert_eq!(reached.fi_date, FiDate::AlreadyReached);
+    assert_eq!(reached.fi_date.short_string(), "already reached");
+
+    let never = model_fi_info(dec!(1000), dec!(-0.02), dec!(10), dec!(0), dec!(10), now());
+    assert_eq!(never.fi_date, FiDate::Never);
+    assert_eq!(never.fi_date.short_string(), "never");
+}
+
+#[test]
+fn projected_monthly_spend_annuity() {
+    // 100000 paying 8024.26 a year for 20 years at 5%.
+    assert_eq!(
+        projected_monthly_spend(dec!(100000), dec!(0.05), dec!(20)).map(|spend| spend.round_dp(2)),
+        Some(dec!(668.69))
+    );
+    // Without a yield, the total is spread over the years.
+    assert_eq!(
+        projected_monthly_spend(dec!(1200), dec!(0), dec!(10)),
+        Some(dec!(10))
+    );
+    assert_eq!(
+        projected_monthly_spend(dec!(1000), dec!(-1), dec!(10)),
+        None
+    );
+    assert_eq!(
+        projected_monthly_spend(dec!(1000), dec!(0.03), dec!(0)),
+        None
+    );
+}
+
+#[test]
+fn projected_monthly_spend_at_least_perpetual() {
+    let total = dec!(1234567.89);
+    for yearly_yield in [
+        dec!(-0.9),
+        dec!(-0.5),
+        dec!(-0.01),
+        dec!(0),
+        dec!(0.001),
+        dec!(0.03),
+        dec!(0.07),
+        dec!(0.5),
+    ] {
+        for deadline in [dec!(0.5), dec!(1), dec!(10), dec!(40), dec!(75), dec!(1000)] {
+            let spend = projected_monthly_spend(total, yearly_yield, deadline).unwrap();
+            let perpetual = total * yearly_yield / dec!(12);
+            assert!(
+                spend >= perpetual,
+                "{} at {} for {} years: {} < perpetual {}",
+                total,
+                yearly_yield,
+                deadline,
+                spend,
+                perpetual
+            );
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Hand-computed scenarios of the model, each with the closed form its
+//! expectation comes from. They document the formulas and catch regressions,
+//! e.g. in computing logarithms and powers.
+
+use chrono::{TimeZone, Utc};
+use differential::{get_investment_durability, years_until_saved_up_exp};
+use model_rs::{State, model_fi_info};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+
+/// How far a result may be from its expectation, relative to the expectation
+/// (or absolute, below 1).
+const TOLERANCE: Decimal = dec!(0.000000001);
+
+pub struct Scenario {
+    pub name: &'static str,
+    /// The closed form `expected` is computed from.
+    pub formula: &'static str,
+    /// None if there is no result, e.g. the target is never reached.
+    pub expected: Option<Decimal>,
+    compute: fn() -> Option<Decimal>,
+}
+
+pub struct Outcome {
+    pub name: &'static str,
+    pub formula: &'static str,
+    pub expected: Option<Decimal>,
+    pub actual: Option<Decimal>,
+    pub passed: bool,
+}
+
+/// What the model needs to last `deadline` years spending `monthly_goal`.
+fn target(yearly_yield: Decimal, monthly_goal: Decimal, deadline: Decimal) -> Option<Decimal> {
+    let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+    model_fi_info(
+        Decimal::ZERO,
+        yearly_yield,
+        monthly_goal,
+        Decimal::ZERO,
+        deadline,
+        now,
+    )
+    .need_to_last_until_deadline
+}
+
+pub fn scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario {
+            name: "target at 0% yield",
+            formula: "12 * 1000 * 30",
+            expected: Some(dec!(360000)),
+            compute: || target(dec!(0), dec!(1000), dec!(30)),
+        },
+        Scenario {
+            name: "target at 4% yield",
+            formula: "12 * 1000 / ln(1.04) * (1 - 1.04^-30)",
+            expected: Some(dec!(211627.36006049750530)),
+            compute: || target(dec!(0.04), dec!(1000), dec!(30)),
+        },
+        Scenario {
+            name: "target at 4% yield for 75 years",
+            formula: "12 * 1000 / ln(1.04) * (1 - 1.04^-75)",
+            expected: Some(dec!(289811.04856431912842)),
+            compute: || target(dec!(0.04), dec!(1000), dec!(75)),
+        },
+        Scenario {
+            name: "target at -100% yield",
+            formula: "everything is lost right away",
+            expected: None,
+            compute: || target(dec!(-1), dec!(1000), dec!(30)),
+        },
+        Scenario {
+            name: "goal exceeded twice over",
+            formula: "720000 / (12 * 1000 * 30) * 100 %",
+            expected: Some(dec!(200)),
+            compute: || {
+                let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+                match model_fi_info(dec!(720000), dec!(0), dec!(1000), dec!(0), dec!(30), now).state
+                {
+                    State::Reached {
+                        overreach_percentage,
+                    } => Some(overreach_percentage),
+                    _ => None,
+                }
+            },
+        },
+        Scenario {
+            name: "saving up at 0% yield",
+            formula: "(100000 - 10000) / (12 * 1000)",
+            expected: Some(dec!(7.5)),
+            compute: || years_until_saved_up_exp(dec!(10000), dec!(0), dec!(100000), dec!(1000)),
+        },
+        Scenario {
+            name: "saving up at 5% yield",
+            formula: "ln((100000 + s/i') / (10000 + s/i')) / i', s = 12 * 1000, i' = ln(1.05)",
+            expected: Some(dec!(6.1756486477239147842)),
+            compute: || years_until_saved_up_exp(dec!(10000), dec!(0.05), dec!(100000), dec!(1000)),
+        },
+        Scenario {
+            name: "doubling at 7% yield without saving",
+            formula: "ln(2) / ln(1.07)",
+            expected: Some(dec!(10.244768351058720360)),
+            compute: || years_until_saved_up_exp(dec!(10000), dec!(0.07), dec!(20000), dec!(0)),
+        },
+        Scenario {
+            name: "already saved up",
+            formula: "the total is over the target already",
+            expected: Some(dec!(0)),
+            compute: || years_until_saved_up_exp(dec!(20000), dec!(0.05), dec!(10000), dec!(0)),
+        },
+        Scenario {
+            name: "shrinking at -2% yield without saving",
+            formula: "the total only shrinks",
+            expected: None,
+            compute: || years_until_saved_up_exp(dec!(10000), dec!(-0.02), dec!(20000), dec!(0)),
+        },
+        Scenario {
+            name: "lasting at 0% yield",
+            formula: "120000 / (12 * 1000)",
+            expected: Some(dec!(10)),
+            compute: || get_investment_durability(dec!(120000), dec!(0), dec!(1000)),
+        },
+        Scenario {
+            name: "lasting at 3% yield",
+            formula: "ln(c / (c - i' * 200000)) / i', c = 12 * 2000, i' = ln(1.03)",
+            expected: Some(dec!(9.5670944129837571450)),
+            compute: || get_investment_durability(dec!(200000), dec!(0.03), dec!(2000)),
+        },
+        Scenario {
+            name: "lasting forever at 5% yield",
+            formula: "i' * 1000000 > 12 * 2000, i' = ln(1.05)",
+            expected: None,
+            compute: || get_investment_durability(dec!(1000000), dec!(0.05), dec!(2000)),
+        },
+        Scenario {
+            name: "lasting with debts exceeding assets",
+            formula: "there is nothing to spend",
+            expected: Some(dec!(0)),
+            compute: || get_investment_durability(dec!(-1000), dec!(0.03), dec!(2000)),
+        },
+    ]
+}
+
+fn close(expected: Option<Decimal>, actual: Option<Decimal>) -> bool {
+    match (expected, actual) {
+        (Some(expected), Some(actual)) => {
+            (actual - expected).abs() <= TOLERANCE * expected.abs().max(Decimal::ONE)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+impl Scenario {
+    pub fn run(&self) -> Outcome {
+        let actual = (self.compute)();
+        Outcome {
+            name: self.name,
+            formula: self.formula,
+            expected: self.expected,
+            actual,
+            passed: close(self.expected, actual),
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use scenarios::scenarios;
+
+#[test]
+fn all_scenarios_pass() {
+    for scenario in scenarios() {
+        let outcome = scenario.run();
+        assert!(
+            outcome.passed,
+            "{} ({}): expected {:?}, got {:?}",
+            outcome.name, outcome.formula, outcome.expected, outcome.actual
+        );
+    }
+}
@@ -0,0 +1,25 @@
+//! Paths given in the configuration and flags, such as
+//! "~/finance/worthy-%s.json" or "$XDG_DATA_HOME/worthy/worthy-%s.json".
+
+use std::path::{Path, PathBuf};
+use worthy_error::WorthyError;
+
+/// `path` with a leading `~` and environment variables (`$VAR` or `${VAR}`)
+/// expanded.
+pub fn expand(path: &str) -> Result<PathBuf, WorthyError> {
+    shellexpand::full(path)
+        .map(|expanded| PathBuf::from(expanded.into_owned()))
+        .map_err(|error| WorthyError::Config(format!("cannot expand {}: {}", path, error)))
+}
+
+/// Creates the directory `path` is in, unless it already exists.
+pub fn create_parent_dir(path: &Path) -> Result<(), WorthyError> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            std::fs::create_dir_all(parent).map_err(|error| {
+                WorthyError::Config(format!("cannot create {}: {}", parent.display(), error))
+            })
+        }
+        _ => Ok(()),
+    }
+}
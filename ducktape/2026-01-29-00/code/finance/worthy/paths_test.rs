@@ -0,0 +1,45 @@
+use paths::{create_parent_dir, expand};
+use std::path::PathBuf;
+use worthy_error::WorthyError;
+
+fn home() -> String {
+    std::env::var("HOME").unwrap()
+}
+
+#[test]
+fn expands_tilde() {
+    assert_eq!(
+        expand("~/worthy/snapshot-%s.json").unwrap(),
+        PathBuf::from(format!("{}/worthy/snapshot-%s.json", home()))
+    );
+    // Only a leading tilde is expanded.
+    assert_eq!(expand("/tmp/~").unwrap(), PathBuf::from("/tmp/~"));
+}
+
+#[test]
+fn expands_env_vars() {
+    assert_eq!(
+        expand("$HOME/a/${HOME}").unwrap(),
+        PathBuf::from(format!("{}/a/{}", home(), home()))
+    );
+}
+
+#[test]
+fn undefined_env_var() {
+    let error = expand("$WORTHY_SURELY_UNDEFINED_VARIABLE/x").unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
+
+#[test]
+fn creates_missing_parent_dir() {
+    let dir = std::env::temp_dir().join(format!("paths_test_{}", std::process::id()));
+    let path = dir.join("a/b/snapshot.json");
+
+    create_parent_dir(&path).unwrap();
+    assert!(dir.join("a/b").is_dir());
+    // Existing directories and bare file names are fine too.
+    create_parent_dir(&path).unwrap();
+    create_parent_dir(&PathBuf::from("snapshot.json")).unwrap();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,43 @@
+//! Spacing out requests to APIs that only take so many a minute.
+
+use log::trace;
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant, sleep_until};
+
+/// Token bucket holding one token, refilled every `period`. Concurrent
+/// callers each get their own slot, in the order they ask.
+#[derive(Debug)]
+pub struct RateLimiter {
+    period: Duration,
+    /// When the next request may be made.
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Limiter letting `requests` requests through a minute, or any number if
+    /// 0.
+    pub fn per_minute(requests: u32) -> RateLimiter {
+        RateLimiter {
+            period: match requests {
+                0 => Duration::ZERO,
+                requests => Duration::from_secs(60) / requests,
+            },
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Waits until the next request may be made. The first one never waits.
+    pub async fn wait(&self) {
+        let now = Instant::now();
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let slot = next.map_or(now, |next| next.max(now));
+            *next = Some(slot + self.period);
+            slot
+        };
+        if slot > now {
+            trace!("rate limited, waiting {:?}", slot - now);
+            sleep_until(slot).await;
+        }
+    }
+}
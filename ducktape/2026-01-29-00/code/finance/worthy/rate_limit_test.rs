@@ -0,0 +1,42 @@
+use futures::future::join_all;
+use rate_limit::RateLimiter;
+use tokio::time::{Duration, Instant, advance};
+
+// With the clock paused, tokio moves it straight to the next timer whenever
+// everything waits, so elapsed times are exact.
+
+#[tokio::test(start_paused = true)]
+async fn spaces_out_requests() {
+    // 10ms apart.
+    let limiter = RateLimiter::per_minute(6000);
+    let start = Instant::now();
+    limiter.wait().await;
+    assert_eq!(start.elapsed(), Duration::ZERO);
+    limiter.wait().await;
+    limiter.wait().await;
+    assert_eq!(start.elapsed(), Duration::from_millis(20));
+
+    // Once its slot has come, a request goes through at once.
+    advance(Duration::from_millis(50)).await;
+    let later = Instant::now();
+    limiter.wait().await;
+    assert_eq!(later.elapsed(), Duration::ZERO);
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrent_requests_take_turns() {
+    let limiter = RateLimiter::per_minute(6000);
+    let start = Instant::now();
+    join_all((0..4).map(|_| limiter.wait())).await;
+    assert_eq!(start.elapsed(), Duration::from_millis(30));
+}
+
+#[tokio::test(start_paused = true)]
+async fn zero_is_unlimited() {
+    let limiter = RateLimiter::per_minute(0);
+    let start = Instant::now();
+    for _ in 0..100 {
+        limiter.wait().await;
+    }
+    assert_eq!(start.elapsed(), Duration::ZERO);
+}
@@ -0,0 +1,193 @@
+//! Sources and converters by the `type` they are configured with, so that
+//! adding one only takes registering it here.
+
+use alphavantage_converter::AlphaVantageConverter;
+use config::{SourceConfig, TypedConfig};
+use converter::{CallBudget, Converter, DenominationFilter, DynConverter};
+use csv_source::CsvSource;
+use currencylayer_converter::CurrencyLayerConverter;
+use fixer_converter::FixerConverter;
+use hardcoded_source::HardcodedSource;
+use ibflex_source::IBFlexSource;
+use json_output::{ConverterType, SourceType};
+use serde::de::DeserializeOwned;
+use source::{DynSource, Source};
+use std::collections::HashMap;
+use worthy_error::WorthyError;
+use yahoo_finance_converter::YahooFinanceConverter;
+
+/// A source built from its configuration.
+pub struct RegisteredSource {
+    pub name: String,
+    /// What its snapshots are saved as.
+    pub source_type: SourceType,
+    pub source: Box<dyn DynSource>,
+}
+
+/// A converter built from its configuration.
+pub struct RegisteredConverter {
+    /// What its snapshots are saved as.
+    pub converter_type: ConverterType,
+    /// Every converter takes `include` and `exclude`.
+    pub filter: DenominationFilter,
+    /// Every converter takes `daily_call_budget` and `refuse_over_budget`.
+    pub budget: CallBudget,
+    pub converter: Box<dyn DynConverter>,
+}
+
+type Build<T> = Box<dyn Fn(serde_yaml::Value) -> Result<Box<T>, serde_yaml::Error> + Send + Sync>;
+
+struct SourceEntry {
+    source_type: SourceType,
+    build: Build<dyn DynSource>,
+}
+
+struct ConverterEntry {
+    converter_type: ConverterType,
+    build: Build<dyn DynConverter>,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    sources: HashMap<String, SourceEntry>,
+    converters: HashMap<String, ConverterEntry>,
+}
+
+impl Registry {
+    /// Registry of the sources and converters worthy comes with.
+    pub fn builtin() -> Registry {
+        let mut registry = Registry::default();
+        registry.register_source::<HardcodedSource>("hardcoded", SourceType::Hardcoded);
+        registry.register_source::<IBFlexSource>("ibflex", SourceType::IBFlex);
+        registry.register_source::<CsvSource>("csv", SourceType::Csv);
+        registry.register_converter::<CurrencyLayerConverter>(
+            "currency_layer",
+            ConverterType::CurrencyLayer,
+        );
+        registry.register_converter::<AlphaVantageConverter>(
+            "alpha_vantage",
+            ConverterType::AlphaVantage,
+        );
+        registry.register_converter::<FixerConverter>("fixer", ConverterType::Fixer);
+        registry.register_converter::<YahooFinanceConverter>(
+            "yahoo_finance",
+            ConverterType::YahooFinance,
+        );
+        registry
+    }
+
+    /// Makes sources configured with `type: <type_name>` `S`, replacing
+    /// whatever was registered under that type before.
+    pub fn register_source<S>(&mut self, type_name: &str, source_type: SourceType)
+    where
+        S: Source + Send + Sync + 'static,
+        S::Config: DeserializeOwned + Send + Sync,
+    {
+        self.sources.insert(
+            type_name.to_string(),
+            SourceEntry {
+                source_type,
+                build: Box::new(|settings| {
+                    let config: S::Config = serde_yaml::from_value(settings)?;
+                    Ok(Box::new(source::Configured::<S>::new(config)))
+                }),
+            },
+        );
+    }
+
+    /// Makes converters configured with `type: <type_name>` `C`, replacing
+    /// whatever was registered under that type before.
+    pub fn register_converter<C>(&mut self, type_name: &str, converter_type: ConverterType)
+    where
+        C: Converter + 'static,
+        C::Config: DeserializeOwned + Send + Sync,
+    {
+        self.converters.insert(
+            type_name.to_string(),
+            ConverterEntry {
+                converter_type,
+                build: Box::new(|settings| {
+                    let config: C::Config = serde_yaml::from_value(settings)?;
+                    Ok(Box::new(converter::Configured::<C>::new(config)))
+                }),
+            },
+        );
+    }
+
+    pub fn source(&self, config: &SourceConfig) -> Result<RegisteredSource, WorthyError> {
+        let entry = self
+            .sources
+            .get(&config.typed.type_name)
+            .ok_or_else(|| unknown_type("source", &config.typed, self.sources.keys()))?;
+        Ok(RegisteredSource {
+            name: config.name.clone(),
+            source_type: entry.source_type.clone(),
+            source: (entry.build)(config.typed.settings_value())?,
+        })
+    }
+
+    pub fn converter(&self, config: &TypedConfig) -> Result<RegisteredConverter, WorthyError> {
+        let entry = self
+            .converters
+            .get(&config.type_name)
+            .ok_or_else(|| unknown_type("converter", config, self.converters.keys()))?;
+        let settings = config.settings_value();
+        Ok(RegisteredConverter {
+            converter_type: entry.converter_type.clone(),
+            filter: serde_yaml::from_value(settings.clone())?,
+            budget: serde_yaml::from_value(settings.clone())?,
+            converter: (entry.build)(settings)?,
+        })
+    }
+
+    /// Sources built from `configs`, keyed by source ID, and a warning for
+    /// each one left out because it doesn't build.
+    pub fn sources(
+        &self,
+        configs: &HashMap<String, SourceConfig>,
+    ) -> (HashMap<String, RegisteredSource>, Vec<String>) {
+        build_all(configs, |config| self.source(config))
+    }
+
+    /// Converters built from `configs`, keyed by converter ID, and a warning
+    /// for each one left out because it doesn't build.
+    pub fn converters(
+        &self,
+        configs: &HashMap<String, TypedConfig>,
+    ) -> (HashMap<String, RegisteredConverter>, Vec<String>) {
+        build_all(configs, |config| self.converter(config))
+    }
+}
+
+fn unknown_type<'a>(
+    kind: &str,
+    config: &TypedConfig,
+    known: impl Iterator<Item = &'a String>,
+) -> WorthyError {
+    let mut known: Vec<&str> = known.map(String::as_str).collect();
+    known.sort();
+    WorthyError::Config(format!(
+        "unknown {} type {:?}, expected one of {}",
+        kind,
+        config.type_name,
+        known.join(", ")
+    ))
+}
+
+fn build_all<C, T>(
+    configs: &HashMap<String, C>,
+    build: impl Fn(&C) -> Result<T, WorthyError>,
+) -> (HashMap<String, T>, Vec<String>) {
+    let mut built = HashMap::new();
+    let mut warnings = Vec::new();
+    for (id, config) in configs {
+        match build(config) {
+            Ok(entry) => {
+                built.insert(id.clone(), entry);
+            }
+            Err(error) => warnings.push(format!("skipping {}: {}", id, error)),
+        }
+    }
+    warnings.sort();
+    (built, warnings)
+}
@@ -0,0 +1,155 @@
+use async_trait::async_trait;
+use chrono::DateTime;
+use config::{Config, TypedConfig};
+use converter::Converter;
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use json_output::ConverterType;
+use registry::Registry;
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use serde::Deserialize;
+use test_util::currency;
+use worthy_error::WorthyError;
+
+/// Prices currencies at a configured rate, one call per currency.
+struct FlatRateConverter {}
+
+#[derive(Deserialize)]
+struct FlatRateConfig {
+    rate: Decimal,
+}
+
+#[async_trait]
+impl Converter for FlatRateConverter {
+    type Config = FlatRateConfig;
+
+    fn can_handle(denomination: &Denomination) -> bool {
+        matches!(denomination, Denomination::Currency { .. })
+    }
+
+    fn api_calls(_config: &Self::Config, denominations: &[&Denomination]) -> u32 {
+        denominations.len() as u32
+    }
+
+    async fn take_snapshot(
+        config: &Self::Config,
+        denominations: &'life1 [&Denomination],
+        base: &Denomination,
+    ) -> Result<Vec<ExchangeRate>, WorthyError> {
+        Ok(denominations
+            .iter()
+            .map(|denomination| ExchangeRate {
+                timestamp: DateTime::UNIX_EPOCH,
+                from: (*denomination).clone(),
+                to: base.clone(),
+                rate: config.rate,
+                spread_bps: Decimal::ZERO,
+            })
+            .collect())
+    }
+}
+
+fn typed_config(yaml: &str) -> TypedConfig {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[tokio::test]
+async fn custom_converter() {
+    let mut registry = Registry::builtin();
+    registry.register_converter::<FlatRateConverter>(
+        "flat_rate",
+        ConverterType::Other("flatrate".to_string()),
+    );
+    let converter = registry
+        .converter(&typed_config(
+            "{type: flat_rate, rate: 1.5, exclude: [GBP], daily_call_budget: 10}",
+        ))
+        .unwrap();
+    assert_eq!(
+        converter.converter_type,
+        ConverterType::Other("flatrate".to_string())
+    );
+    assert_eq!(converter.budget.daily_call_budget, Some(10));
+
+    let (eur, gbp, usd) = (currency("EUR"), currency("GBP"), currency("USD"));
+    let btc = Denomination::Cryptocurrency {
+        symbol: "BTC".to_string(),
+    };
+    let allowed = converter.filter.apply(&[&eur, &gbp, &btc]);
+    let handled = converter.converter.handled(&allowed);
+    assert_eq!(handled, vec![&eur]);
+    assert_eq!(converter.converter.api_calls(&handled), 1);
+    assert_eq!(
+        converter
+            .converter
+            .take_snapshot(&handled, &usd)
+            .await
+            .unwrap(),
+        vec![ExchangeRate {
+            timestamp: DateTime::UNIX_EPOCH,
+            from: eur.clone(),
+            to: usd.clone(),
+            rate: dec!(1.5),
+            spread_bps: Decimal::ZERO,
+        }]
+    );
+
+    let error = registry
+        .converter(&typed_config("{type: flat_rate, rat: 1.5}"))
+        .err()
+        .unwrap();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
+
+const MALFORMED_CONVERTERS: &str = r#"
+sources:
+  bank:
+    name: Bank
+    type: hardcoded
+    assets: [{currency: USD, amount: 100}]
+  broker:
+    name: Broker
+    type: robinhood
+converters:
+  fixer:
+    type: fixer
+    api_key: FIXER_KEY
+  currencylayer:
+    type: currency_layer
+    # Misspelled api_key.
+    api_kee: CURRENCYLAYER_KEY
+common_currency: USD
+dated_json_output: /tmp/worthy-%s.json
+csv_output: /tmp/worthy.csv
+modelling:
+  monthly_saving: {currency: USD, amount: 100}
+  yearly_yields: []
+  monthly_targets: []
+"#;
+
+#[test]
+fn entries_that_dont_build_are_skipped() {
+    let config: Config = serde_yaml::from_str(MALFORMED_CONVERTERS).unwrap();
+    let registry = Registry::builtin();
+
+    let (converters, warnings) = registry.converters(&config.converter_config);
+    assert_eq!(converters.keys().collect::<Vec<_>>(), vec!["fixer"]);
+    assert_eq!(converters["fixer"].converter_type, ConverterType::Fixer);
+    assert_eq!(warnings.len(), 1);
+    assert!(
+        warnings[0].starts_with("skipping currencylayer: "),
+        "{:?}",
+        warnings
+    );
+
+    let (sources, warnings) = registry.sources(&config.source_config);
+    assert_eq!(sources.keys().collect::<Vec<_>>(), vec!["bank"]);
+    assert_eq!(sources["bank"].name, "Bank");
+    assert_eq!(warnings.len(), 1);
+    assert!(
+        warnings[0].starts_with("skipping broker: ") && warnings[0].contains("robinhood"),
+        "{:?}",
+        warnings
+    );
+}
@@ -0,0 +1,79 @@
+//! Retrying of flaky network requests.
+
+use log::warn;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::fmt::Debug;
+use std::future::Future;
+use tokio::time::{Duration, sleep};
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Total number of attempts, including the first one.
+    pub attempts: u32,
+    /// Delay before the first retry. Doubled on every further retry.
+    pub initial_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: 3,
+            initial_backoff_ms: 1000,
+        }
+    }
+}
+
+/// Runs `attempt` until it returns a result that `is_retriable` rejects, or
+/// until out of attempts. Returns the last result.
+pub async fn retry<T, E, F, Fut>(
+    config: &RetryConfig,
+    is_retriable: impl Fn(&Result<T, E>) -> bool,
+    mut attempt: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Debug,
+{
+    let mut backoff = Duration::from_millis(config.initial_backoff_ms);
+    let mut attempts_left = config.attempts.max(1);
+    loop {
+        let result = attempt().await;
+        attempts_left -= 1;
+        if attempts_left == 0 || !is_retriable(&result) {
+            return result;
+        }
+        match &result {
+            Ok(_) => warn!("retriable result, retrying in {:?}", backoff),
+            Err(error) => warn!("retriable error {:?}, retrying in {:?}", error, backoff),
+        }
+        sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Transport errors, 429 Too Many Requests and 5xx responses are worth
+/// retrying. Other 4xx responses (e.g. auth failures) won't get better.
+pub fn is_retriable_response(result: &Result<reqwest::Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => {
+            let status = response.status();
+            status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+        }
+        Err(_) => true,
+    }
+}
+
+/// Sends a request built by `send`, retrying per `config`.
+pub async fn retry_request<F, Fut>(
+    config: &RetryConfig,
+    send: F,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+{
+    retry(config, is_retriable_response, send).await
+}
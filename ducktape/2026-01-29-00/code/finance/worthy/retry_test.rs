@@ -0,0 +1,52 @@
+use fixture_server::{FixtureServer, Response};
+use retry::{RetryConfig, retry_request};
+
+const FAST: RetryConfig = RetryConfig {
+    attempts: 3,
+    initial_backoff_ms: 1,
+};
+
+#[tokio::test]
+async fn retries_503_then_succeeds() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![
+        Response::status("503 Service Unavailable"),
+        Response::ok("hello"),
+    ]);
+    let url = server.url().to_string();
+    let response = retry_request(&FAST, || reqwest::get(url.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    assert_eq!(response.text().await.unwrap(), "hello");
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn does_not_retry_auth_failure() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::status("401 Unauthorized"), Response::ok("")]);
+    let url = server.url().to_string();
+    let response = retry_request(&FAST, || reqwest::get(url.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn gives_up_after_attempts() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![
+        Response::status("429 Too Many Requests"),
+        Response::status("500 Internal Server Error"),
+        Response::status("502 Bad Gateway"),
+        Response::ok(""),
+    ]);
+    let url = server.url().to_string();
+    let response = retry_request(&FAST, || reqwest::get(url.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::BAD_GATEWAY);
+    assert_eq!(server.requests().len(), 3);
+}
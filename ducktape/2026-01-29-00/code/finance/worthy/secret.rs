@@ -0,0 +1,111 @@
+//! Sensitive settings, such as API keys, kept out of the configuration file:
+//! in the OS keyring, e.g. `token: {keyring: "worthy/ib"}`, or in a file with
+//! stricter permissions, e.g. `api_key: {file: "~/.worthy-secrets.yaml", key:
+//! fixer}`.
+
+use log::warn;
+use serde::Deserialize;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use worthy_error::WorthyError;
+
+/// Settings of sources and converters that may refer to a secret instead of
+/// holding it.
+pub const SECRET_FIELDS: [&str; 4] = ["api_key", "api_secret", "password", "token"];
+
+/// Where a secret is kept.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum SecretRef {
+    /// In the OS keyring, as "<service>/<user>", or "<user>" of service
+    /// "worthy".
+    Keyring { keyring: String },
+    /// The contents of `file`, or with `key`, the value under `key` in the
+    /// YAML mapping in `file`.
+    File {
+        file: String,
+        #[serde(default)]
+        key: Option<String>,
+    },
+}
+
+impl SecretRef {
+    pub fn resolve(&self) -> Result<String, WorthyError> {
+        match self {
+            SecretRef::Keyring { keyring } => {
+                let (service, user) = keyring.split_once('/').unwrap_or(("worthy", keyring));
+                keyring::Entry::new(service, user)
+                    .and_then(|entry| entry.get_password())
+                    .map_err(|error| {
+                        WorthyError::Config(format!(
+                            "cannot read {} from the keyring: {}",
+                            keyring, error
+                        ))
+                    })
+            }
+            SecretRef::File { file, key } => {
+                read_secret_file(&paths::expand(file)?, key.as_deref())
+            }
+        }
+    }
+}
+
+fn read_secret_file(path: &Path, key: Option<&str>) -> Result<String, WorthyError> {
+    warn_if_world_readable(path);
+    let contents = std::fs::read_to_string(path).map_err(|error| {
+        WorthyError::Config(format!("cannot read {}: {}", path.display(), error))
+    })?;
+    let Some(key) = key else {
+        return Ok(contents.trim().to_string());
+    };
+    let secrets: serde_yaml::Mapping = serde_yaml::from_str(&contents).map_err(|error| {
+        WorthyError::Config(format!("cannot parse {}: {}", path.display(), error))
+    })?;
+    match secrets.get(key) {
+        Some(serde_yaml::Value::String(secret)) => Ok(secret.clone()),
+        Some(_) => Err(WorthyError::Config(format!(
+            "{} in {} is not a string",
+            key,
+            path.display()
+        ))),
+        None => Err(WorthyError::Config(format!(
+            "no {} in {}",
+            key,
+            path.display()
+        ))),
+    }
+}
+
+/// Replaces each of `SECRET_FIELDS` in `settings` that refers to a secret
+/// with the secret. Values given literally are kept.
+pub fn resolve_secrets(settings: &mut serde_yaml::Mapping) -> Result<(), WorthyError> {
+    for field in SECRET_FIELDS {
+        let Some(value) = settings.get_mut(field) else {
+            continue;
+        };
+        if !value.is_mapping() {
+            continue;
+        }
+        let secret_ref: SecretRef = serde_yaml::from_value(value.clone()).map_err(|_| {
+            WorthyError::Config(format!(
+                "{} must be a value, {{keyring: ...}} or {{file: ...}}",
+                field
+            ))
+        })?;
+        *value = serde_yaml::Value::String(secret_ref.resolve()?);
+    }
+    Ok(())
+}
+
+/// Warns if anyone may read `path`, e.g. the configuration file holding API
+/// keys.
+pub fn warn_if_world_readable(path: &Path) {
+    if let Ok(metadata) = std::fs::metadata(path)
+        && metadata.permissions().mode() & 0o004 != 0
+    {
+        warn!(
+            "{} is readable by all users; restrict it with chmod o-r",
+            path.display()
+        );
+    }
+}
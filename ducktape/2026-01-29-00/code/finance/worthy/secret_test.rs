@@ -0,0 +1,73 @@
+use secret::{SecretRef, resolve_secrets};
+use test_util::TempDir;
+use worthy_error::WorthyError;
+
+fn settings(yaml: &str) -> serde_yaml::Mapping {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn whole_file() {
+    let dir = TempDir::new("secret_whole_file");
+    let file = dir.write("ib_token", "123456789\n");
+    assert_eq!(
+        SecretRef::File { file, key: None }.resolve().unwrap(),
+        "123456789"
+    );
+}
+
+#[test]
+fn key_in_file() {
+    let dir = TempDir::new("secret_key_in_file");
+    let file = dir.write("secrets.yaml", "fixer: abc\nib: \"123\"\n");
+    let resolve = |key: &str| {
+        SecretRef::File {
+            file: file.clone(),
+            key: Some(key.to_string()),
+        }
+        .resolve()
+    };
+    assert_eq!(resolve("fixer").unwrap(), "abc");
+    assert_eq!(resolve("ib").unwrap(), "123");
+    let error = resolve("alpha_vantage").unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
+
+#[test]
+fn missing_file() {
+    let dir = TempDir::new("secret_missing_file");
+    let error = SecretRef::File {
+        file: dir.file("nope"),
+        key: None,
+    }
+    .resolve()
+    .unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
+
+#[test]
+fn resolves_only_secret_fields() {
+    let dir = TempDir::new("secret_resolves_only_secret_fields");
+    let file = dir.write("secrets.yaml", "fixer: abc\n");
+    let mut resolved = settings(&format!(
+        "api_key: {{file: {:?}, key: fixer}}\n\
+         token: literal\n\
+         include: {{file: {:?}}}\n",
+        file, file
+    ));
+    resolve_secrets(&mut resolved).unwrap();
+    assert_eq!(
+        resolved,
+        settings(&format!(
+            "api_key: abc\ntoken: literal\ninclude: {{file: {:?}}}\n",
+            file
+        ))
+    );
+}
+
+#[test]
+fn malformed_secret_ref() {
+    let mut malformed = settings("api_key: {vault: worthy}");
+    let error = resolve_secrets(&mut malformed).unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
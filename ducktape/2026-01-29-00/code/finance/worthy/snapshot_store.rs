@@ -0,0 +1,257 @@
+//! JSON snapshot files in the output directory.
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset};
+use glob::glob;
+use json_output::{ModellingResult, ReadSnapshots, Snapshot};
+use log::warn;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use worthy_error::WorthyError;
+
+/// Directory of snapshot files, each named after `dated_json_output` with `%s`
+/// replaced by the snapshot's timestamp, `%date` by its date (YYYY-MM-DD) and
+/// `%label` by its label.
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    file_name: String,
+    /// strftime-style format of `%s`; RFC 3339 if unset.
+    timestamp_format: Option<String>,
+}
+
+/// Characters that can't be in file names on some filesystems.
+const UNSAFE_FILE_NAME_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+fn is_safe_file_name_char(c: char) -> bool {
+    !UNSAFE_FILE_NAME_CHARS.contains(&c) && !c.is_control()
+}
+
+/// Ending of the file next to a snapshot file that holds its modelling
+/// results, e.g. "2021-01-01.modelling.json" next to "2021-01-01.json".
+const MODELLING_SUFFIX: &str = ".modelling.json";
+
+/// Path of the modelling results next to the snapshot file at `path`.
+pub fn modelling_path(path: &str) -> String {
+    format!(
+        "{}{}",
+        path.strip_suffix(".json").unwrap_or(path),
+        MODELLING_SUFFIX
+    )
+}
+
+/// A snapshot file, along with what it says about itself.
+#[derive(Debug, PartialEq)]
+pub struct SnapshotMeta {
+    pub path: String,
+    pub timestamp: DateTime<FixedOffset>,
+    pub label: Option<String>,
+}
+
+impl SnapshotStore {
+    /// Store of snapshots written to `dated_json_output`, an already expanded
+    /// path whose file name contains `%s`.
+    pub fn new(dated_json_output: &Path) -> SnapshotStore {
+        SnapshotStore {
+            dir: dated_json_output
+                .parent()
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+            file_name: dated_json_output
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            timestamp_format: None,
+        }
+    }
+
+    /// The store, with `%s` formatted per the strftime-style `format`, e.g.
+    /// "%Y%m%dT%H%M%S". Fails if the format doesn't parse or makes file
+    /// names some filesystems can't hold.
+    pub fn with_timestamp_format(mut self, format: &str) -> Result<SnapshotStore, WorthyError> {
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            return Err(WorthyError::Config(format!(
+                "invalid timestamp_format {:?}",
+                format
+            )));
+        }
+        let example = DateTime::UNIX_EPOCH
+            .fixed_offset()
+            .format(format)
+            .to_string();
+        if example.is_empty() || !example.chars().all(is_safe_file_name_char) {
+            return Err(WorthyError::Config(format!(
+                "timestamp_format {:?} makes file names like {:?}, which not all \
+                 filesystems allow",
+                format, example
+            )));
+        }
+        self.timestamp_format = Some(format.to_string());
+        Ok(self)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Paths of all snapshot files, sorted by name, without the modelling
+    /// results next to them. Files that can't be read are skipped with a
+    /// warning.
+    pub fn paths(&self) -> Result<Vec<String>, WorthyError> {
+        let pattern = self.dir.join("*.json");
+        let entries = glob(&pattern.to_string_lossy()).map_err(|error| {
+            WorthyError::Config(format!("cannot list {}: {}", self.dir.display(), error))
+        })?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            match entry {
+                Ok(path) => {
+                    let path = path.to_string_lossy().into_owned();
+                    if !path.ends_with(MODELLING_SUFFIX) {
+                        paths.push(path);
+                    }
+                }
+                Err(error) => warn!("skipping {}", error),
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Paths of all snapshot files, failing if there are none yet.
+    pub fn existing_paths(&self) -> Result<Vec<String>, WorthyError> {
+        let paths = self.paths()?;
+        if paths.is_empty() {
+            return Err(WorthyError::NoSnapshots(self.dir.display().to_string()));
+        }
+        Ok(paths)
+    }
+
+    /// All snapshots that parse, keyed by path, sorted by name.
+    pub fn read_all(&self) -> Result<ReadSnapshots, WorthyError> {
+        Ok(json_output::read_snapshots(&self.existing_paths()?))
+    }
+
+    /// All snapshots that parse, oldest first.
+    pub fn list(&self) -> Result<Vec<SnapshotMeta>, WorthyError> {
+        let ReadSnapshots { mut snapshots, .. } = json_output::read_snapshots(&self.paths()?);
+        json_output::sort_by_timestamp(&mut snapshots);
+        Ok(snapshots
+            .into_iter()
+            .map(|(path, snapshot)| SnapshotMeta {
+                path,
+                timestamp: snapshot.timestamp,
+                label: snapshot.label,
+            })
+            .collect())
+    }
+
+    pub fn load(&self, path: &str) -> Result<Snapshot, WorthyError> {
+        json_output::read_snapshot(path)
+            .map_err(|error| WorthyError::Parse(format!("{}: {}", path, error)))
+    }
+
+    /// The newest snapshot that parses, going by its timestamp.
+    pub fn latest(&self) -> Result<Option<Snapshot>, WorthyError> {
+        self.list()?
+            .pop()
+            .map(|meta| self.load(&meta.path))
+            .transpose()
+    }
+
+    /// The newest snapshot taken on the same day as `timestamp`.
+    pub fn same_day(
+        &self,
+        timestamp: DateTime<FixedOffset>,
+    ) -> Result<Option<SnapshotMeta>, WorthyError> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .rfind(|meta| meta.timestamp.date_naive() == timestamp.date_naive()))
+    }
+
+    /// Name of the file `snapshot` is written to. Characters of the label
+    /// that can't be in file names are replaced with `_`.
+    pub fn file_name(&self, snapshot: &Snapshot) -> String {
+        let timestamp = match &self.timestamp_format {
+            Some(format) => snapshot.timestamp.format(format).to_string(),
+            None => snapshot.timestamp.to_rfc3339(),
+        };
+        let label: String = snapshot
+            .label
+            .as_deref()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if is_safe_file_name_char(c) { c } else { '_' })
+            .collect();
+        // The label goes last, so that nothing in it is replaced.
+        self.file_name
+            .replace("%s", &timestamp)
+            .replace("%date", &snapshot.timestamp.format("%Y-%m-%d").to_string())
+            .replace("%label", &label)
+    }
+
+    /// Writes `snapshot` into a new file named after it, creating the
+    /// directory if needed. Returns the file's path. Fails if the file exists,
+    /// e.g. with a file name that only has the date, rather than replacing an
+    /// earlier snapshot.
+    pub fn write(&self, snapshot: &Snapshot) -> Result<String, WorthyError> {
+        let path = self.dir.join(self.file_name(snapshot));
+        paths::create_parent_dir(&path)?;
+        let cannot_write =
+            |error| WorthyError::Config(format!("cannot write {}: {}", path.display(), error));
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                return Err(WorthyError::Config(format!(
+                    "{} already exists; put %s in the file name, or pass --overwrite-today",
+                    path.display()
+                )));
+            }
+            Err(error) => return Err(cannot_write(error)),
+        };
+        let json = serde_json::to_string_pretty(snapshot).unwrap();
+        file.write_all(json.as_bytes()).map_err(cannot_write)?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    /// Replaces the snapshot file at `path` with `snapshot`. Modelling results
+    /// of the replaced snapshot are removed if `snapshot` has none, rather than
+    /// left to be taken for its own.
+    pub fn overwrite(&self, path: &str, snapshot: &Snapshot) -> Result<(), WorthyError> {
+        let modelling = modelling_path(path);
+        let path = Path::new(path);
+        paths::create_parent_dir(path)?;
+        let json = serde_json::to_string_pretty(snapshot).unwrap();
+        std::fs::write(path, json).map_err(|error| {
+            WorthyError::Config(format!("cannot write {}: {}", path.display(), error))
+        })?;
+        if snapshot.modelling.is_none() {
+            match std::fs::remove_file(&modelling) {
+                Err(error) if error.kind() != ErrorKind::NotFound => {
+                    return Err(WorthyError::Config(format!(
+                        "cannot remove {}: {}",
+                        modelling, error
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `modelling` next to the snapshot file at `path`, replacing what
+    /// a snapshot overwritten there left. Returns the file's path.
+    pub fn write_modelling(
+        &self,
+        path: &str,
+        modelling: &[ModellingResult],
+    ) -> Result<String, WorthyError> {
+        let path = modelling_path(path);
+        let json = serde_json::to_string_pretty(modelling).unwrap();
+        std::fs::write(&path, json)
+            .map_err(|error| WorthyError::Config(format!("cannot write {}: {}", path, error)))?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::{DateTime, FixedOffset};
+use json_output::{Asset, Denomination, ModellingResult, ModellingState, Snapshot};
+use rust_decimal::Decimal;
+use snapshot_store::{SnapshotMeta, SnapshotStore, modelling_path};
+use std::path::Path;
+use test_util::TempDir;
+use worthy_error::WorthyError;
+
+fn store(dir: &TempDir) -> SnapshotStore {
+    SnapshotStore::new(&dir.path().join("worthy-%s.json"))
+}
+
+fn at(timestamp: &str) -> DateTime<FixedOffset> {
+    DateTime::parse_from_rfc3339(timestamp).unwrap()
+}
+
+fn snapshot(timestamp: &str, total: i64) -> Snapshot {
+    Snapshot {
+        timestamp: at(timestamp),
+        source_snapshot: vec![],
+        converter_snapshots: vec![],
+        total: Asset {
+            denomination: Denomination::Currency {
+                symbol: "USD".to_string(),
+            },
+            amount: Decimal::from(total),
+            cost_basis: None,
+        },
+        unpriced: vec![],
+        allocation: Default::default(),
+        label: None,
+        cfiresim: None,
+        modelling: None,
+    }
+}
+
+#[test]
+fn empty_dir() {
+    let dir = TempDir::new("snapshot_store_empty");
+    let store = store(&dir);
+
+    assert_eq!(store.dir(), dir.path());
+    assert!(store.paths().unwrap().is_empty());
+    assert_eq!(store.list().unwrap(), vec![]);
+    assert_eq!(store.latest().unwrap(), None);
+
+    let error = store.existing_paths().unwrap_err();
+    assert!(
+        matches!(&error, WorthyError::NoSnapshots(location) if *location == dir.path().display().to_string()),
+        "{:?}",
+        error
+    );
+    assert!(
+        error.to_string().contains("run `worthy snapshot` first"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn write_and_read_back() {
+    let dir = TempDir::new("snapshot_store_write");
+    let store = store(&dir);
+
+    let older = snapshot("2021-01-01T09:00:00+00:00", 100);
+    let newer = snapshot("2021-01-02T09:00:00+00:00", 200);
+    let newer_path = store.write(&newer).unwrap();
+    store.write(&older).unwrap();
+    std::fs::write(dir.file("worthy-broken.json"), "{").unwrap();
+
+    assert_eq!(
+        newer_path,
+        dir.file("worthy-2021-01-02T09:00:00+00:00.json")
+    );
+    assert_eq!(store.load(&newer_path).unwrap(), newer);
+    assert!(matches!(
+        store.load(&dir.file("worthy-broken.json")),
+        Err(WorthyError::Parse(_))
+    ));
+    assert_eq!(store.paths().unwrap().len(), 3);
+    assert_eq!(store.latest().unwrap(), Some(newer));
+    assert_eq!(
+        store.list().unwrap(),
+        vec![
+            SnapshotMeta {
+                path: dir.file("worthy-2021-01-01T09:00:00+00:00.json"),
+                timestamp: at("2021-01-01T09:00:00+00:00"),
+                label: None,
+            },
+            SnapshotMeta {
+                path: newer_path,
+                timestamp: at("2021-01-02T09:00:00+00:00"),
+                label: None,
+            },
+        ]
+    );
+    assert_eq!(store.read_all().unwrap().skipped, 1);
+}
+
+#[test]
+fn write_creates_dir() {
+    let dir = TempDir::new("snapshot_store_create");
+    let store = SnapshotStore::new(&dir.path().join("a/b/%s.json"));
+
+    let path = store
+        .write(&snapshot("2021-01-01T09:00:00+00:00", 100))
+        .unwrap();
+    assert!(Path::new(&path).is_file());
+}
+
+#[test]
+fn same_day_overwrite() {
+    let dir = TempDir::new("snapshot_store_same_day");
+    let store = store(&dir);
+    store
+        .write(&snapshot("2021-01-01T09:00:00+00:00", 100))
+        .unwrap();
+    store
+        .write(&snapshot("2021-01-02T09:00:00+00:00", 200))
+        .unwrap();
+
+    let earlier = store
+        .same_day(at("2021-01-02T18:00:00+00:00"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        earlier.path,
+        dir.file("worthy-2021-01-02T09:00:00+00:00.json")
+    );
+    assert_eq!(
+        store.same_day(at("2021-01-03T00:00:00+00:00")).unwrap(),
+        None
+    );
+
+    let later = snapshot("2021-01-02T18:00:00+00:00", 300);
+    store.overwrite(&earlier.path, &later).unwrap();
+    assert_eq!(store.paths().unwrap().len(), 2);
+    assert_eq!(store.latest().unwrap(), Some(later));
+}
+
+#[test]
+fn modelling_next_to_snapshot() {
+    let dir = TempDir::new("snapshot_store_modelling");
+    let store = store(&dir);
+    let path = store
+        .write(&snapshot("2021-01-01T09:00:00+00:00", 100))
+        .unwrap();
+    let modelling = vec![ModellingResult {
+        yearly_yield: Decimal::new(3, 2),
+        monthly_target: vec![],
+        state: ModellingState::Reached {
+            overreach_percentage: Decimal::from(120),
+        },
+    }];
+
+    let modelling_file = store.write_modelling(&path, &modelling).unwrap();
+
+    assert_eq!(
+        modelling_file,
+        dir.file("worthy-2021-01-01T09:00:00+00:00.modelling.json")
+    );
+    assert_eq!(modelling_file, modelling_path(&path));
+    let written: Vec<ModellingResult> =
+        serde_json::from_str(&std::fs::read_to_string(&modelling_file).unwrap()).unwrap();
+    assert_eq!(written, modelling);
+    // Not taken for a snapshot.
+    assert_eq!(store.paths().unwrap(), vec![path.clone()]);
+
+    // Replaced by a snapshot without modelling, it goes too.
+    store
+        .overwrite(&path, &snapshot("2021-01-01T18:00:00+00:00", 200))
+        .unwrap();
+    assert!(!Path::new(&modelling_file).exists());
+    // Nothing to remove the second time around.
+    store
+        .overwrite(&path, &snapshot("2021-01-01T19:00:00+00:00", 300))
+        .unwrap();
+}
+
+#[test]
+fn timestamp_format_and_placeholders() {
+    let dir = TempDir::new("snapshot_store_timestamp_format");
+    let mut labeled = snapshot("2024-06-01T09:30:00+02:00", 100);
+    labeled.label = Some("before buying a house/flat".to_string());
+
+    let compact = SnapshotStore::new(&dir.path().join("worthy-%s.json"))
+        .with_timestamp_format("%Y%m%dT%H%M%S")
+        .unwrap();
+    assert_eq!(compact.file_name(&labeled), "worthy-20240601T093000.json");
+
+    let dated = SnapshotStore::new(&dir.path().join("worthy-%date.json"));
+    assert_eq!(dated.file_name(&labeled), "worthy-2024-06-01.json");
+
+    let with_label = SnapshotStore::new(&dir.path().join("%date-%label.json"))
+        .with_timestamp_format("%Y")
+        .unwrap();
+    let path = with_label.write(&labeled).unwrap();
+    assert_eq!(path, dir.file("2024-06-01-before buying a house_flat.json"));
+    assert_eq!(with_label.load(&path).unwrap(), labeled);
+    // Another snapshot that day would replace it.
+    let mut later = snapshot("2024-06-01T18:00:00+02:00", 200);
+    later.label = labeled.label.clone();
+    let error = with_label.write(&later).unwrap_err();
+    assert!(
+        matches!(&error, WorthyError::Config(message) if message.contains("already exists")),
+        "{:?}",
+        error
+    );
+    assert_eq!(with_label.load(&path).unwrap(), labeled);
+    assert_eq!(
+        with_label.file_name(&snapshot("2024-06-02T09:30:00+02:00", 100)),
+        "2024-06-02-.json"
+    );
+}
+
+#[test]
+fn latest_by_timestamp() {
+    let dir = TempDir::new("snapshot_store_latest");
+    // Day first, so that file names don't sort in the order taken.
+    let store = store(&dir).with_timestamp_format("%d%m%Y").unwrap();
+    let newer = snapshot("2021-02-01T09:00:00+00:00", 200);
+    store.write(&newer).unwrap();
+    store
+        .write(&snapshot("2021-01-02T09:00:00+00:00", 100))
+        .unwrap();
+
+    assert_eq!(
+        store.paths().unwrap().last().unwrap(),
+        &dir.file("worthy-02012021.json")
+    );
+    assert_eq!(store.latest().unwrap(), Some(newer));
+}
+
+#[test]
+fn unsafe_timestamp_format() {
+    let store = || SnapshotStore::new(Path::new("/tmp/worthy-%s.json"));
+    for format in ["%H:%M", "%Y/%m", "", "%Q"] {
+        let error = store().with_timestamp_format(format).unwrap_err();
+        assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+    }
+    assert!(store().with_timestamp_format("%Y-%m-%d_%H%M").is_ok());
+}
@@ -1,10 +1,102 @@
 use asset::Asset;
 use async_trait::async_trait;
-use std::error::Error;
+use chrono::{DateTime, Duration, Utc};
+use exchange_rate::ExchangeRate;
+use rust_decimal::Decimal;
+use std::marker::PhantomData;
+use worthy_error::WorthyError;
+
+/// Assets held in one account of a source with several.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountAssets {
+    pub account_id: String,
+    /// The account's alias, or its ID if it has none.
+    pub name: String,
+    pub assets: Vec<Asset>,
+}
+
+/// Everything a source reports in a snapshot.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceReport {
+    pub assets: Vec<Asset>,
+    /// Base currency of the source's account, if it reports one.
+    pub base_currency: Option<String>,
+    /// `assets` by account, if the source has accounts.
+    pub accounts: Vec<AccountAssets>,
+    /// Prices the source knows, e.g. unit prices entered by hand.
+    pub rates: Vec<ExchangeRate>,
+}
+
+/// How long ago balances entered by hand were last updated, once that's
+/// longer than they are trusted for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Staleness {
+    pub age: Duration,
+    /// Whether the balances are left out of snapshots because of it.
+    pub excluded: bool,
+    /// Factor the balances are counted with, 1 unless they decay.
+    pub weight: Decimal,
+}
 
 #[async_trait]
 pub trait Source {
     type Config;
 
-    async fn take_snapshot(config: &Self::Config) -> Result<Vec<Asset>, Box<dyn Error>>;
+    async fn take_snapshot(config: &Self::Config) -> Result<Vec<Asset>, WorthyError>;
+
+    /// The assets, along with whatever else the source knows about them.
+    async fn report(config: &Self::Config) -> Result<SourceReport, WorthyError>
+    where
+        Self::Config: Sync,
+    {
+        Ok(SourceReport {
+            assets: Self::take_snapshot(config).await?,
+            ..Default::default()
+        })
+    }
+
+    /// Whether the balances are stale at `now`. Sources fetching live
+    /// balances never are.
+    fn staleness(_config: &Self::Config, _now: DateTime<Utc>) -> Option<Staleness> {
+        None
+    }
+}
+
+/// A configured source, callable without knowing its type, e.g. when looked
+/// up by its `type` in the configuration.
+#[async_trait]
+pub trait DynSource: Send + Sync {
+    async fn report(&self) -> Result<SourceReport, WorthyError>;
+
+    fn staleness(&self, now: DateTime<Utc>) -> Option<Staleness>;
+}
+
+/// Source `S` along with its configuration.
+pub struct Configured<S: Source> {
+    config: S::Config,
+    source: PhantomData<fn() -> S>,
+}
+
+impl<S: Source> Configured<S> {
+    pub fn new(config: S::Config) -> Self {
+        Configured {
+            config,
+            source: PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<S> DynSource for Configured<S>
+where
+    S: Source + Send + Sync,
+    S::Config: Send + Sync,
+{
+    async fn report(&self) -> Result<SourceReport, WorthyError> {
+        S::report(&self.config).await
+    }
+
+    fn staleness(&self, now: DateTime<Utc>) -> Option<Staleness> {
+        S::staleness(&self.config, now)
+    }
 }
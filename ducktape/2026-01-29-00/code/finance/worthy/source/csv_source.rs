@@ -0,0 +1,107 @@
+use asset::Asset;
+use async_trait::async_trait;
+use denomination::Denomination;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use source::Source;
+use std::path::PathBuf;
+use std::str::FromStr;
+use worthy_error::WorthyError;
+
+/// Balances tracked by hand in a spreadsheet, exported as CSV with a header
+/// row.
+pub struct CsvSource {}
+
+#[derive(Debug, Deserialize)]
+pub struct CsvSourceConfig {
+    /// `~` and environment variables are expanded.
+    pub path: PathBuf,
+    /// Header of the column with the currency code, ticker or crypto symbol.
+    #[serde(default = "default_symbol_column")]
+    pub symbol_column: String,
+    #[serde(default = "default_amount_column")]
+    pub amount_column: String,
+    /// Header of the column saying what the symbol is: "currency", "stock" or
+    /// "crypto".
+    #[serde(default = "default_type_column")]
+    pub type_column: String,
+}
+
+fn default_symbol_column() -> String {
+    "symbol".to_string()
+}
+
+fn default_amount_column() -> String {
+    "amount".to_string()
+}
+
+fn default_type_column() -> String {
+    "type".to_string()
+}
+
+/// Denomination of `symbol` of type `type_name`, which is case-insensitive.
+pub fn denomination(type_name: &str, symbol: &str) -> Result<Denomination, String> {
+    let kind = match type_name.trim().to_lowercase().as_str() {
+        "currency" => "currency",
+        "stock" => "stock",
+        "crypto" | "cryptocurrency" => "crypto",
+        other => {
+            return Err(format!(
+                "unknown type {:?}, expected currency, stock or crypto",
+                other
+            ));
+        }
+    };
+    format!("{}:{}", kind, symbol.trim()).parse()
+}
+
+/// Index of the column headed `name`.
+fn column(headers: &csv::StringRecord, name: &str) -> Result<usize, String> {
+    headers
+        .iter()
+        .position(|header| header.trim() == name)
+        .ok_or_else(|| format!("no column {:?}", name))
+}
+
+/// The assets in the rows of `input`, read per `config`'s columns.
+pub fn parse_assets(
+    config: &CsvSourceConfig,
+    input: impl std::io::Read,
+) -> Result<Vec<Asset>, String> {
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader.headers().map_err(|error| error.to_string())?.clone();
+    let symbol = column(&headers, &config.symbol_column)?;
+    let amount = column(&headers, &config.amount_column)?;
+    let type_name = column(&headers, &config.type_column)?;
+    let mut assets = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|error| error.to_string())?;
+        // The header is line 1.
+        let line = record.position().map_or(0, |position| position.line());
+        let field = |index: usize| record.get(index).unwrap_or("");
+        let denomination = denomination(field(type_name), field(symbol))
+            .map_err(|error| format!("line {}: {}", line, error))?;
+        let amount = Decimal::from_str(field(amount).trim())
+            .map_err(|error| format!("line {}: amount {:?}: {}", line, field(amount), error))?;
+        assets.push(Asset {
+            amount,
+            denomination,
+            cost_basis: None,
+        });
+    }
+    Ok(assets)
+}
+
+#[async_trait]
+impl Source for CsvSource {
+    type Config = CsvSourceConfig;
+
+    async fn take_snapshot(config: &Self::Config) -> Result<Vec<Asset>, WorthyError> {
+        let path = paths::expand(&config.path.to_string_lossy())?;
+        let file = std::fs::File::open(&path).map_err(|error| {
+            WorthyError::Config(format!("cannot read {}: {}", path.display(), error))
+        })?;
+        parse_assets(config, file)
+            .map_err(|error| WorthyError::Parse(format!("{}: {}", path.display(), error)))
+    }
+}
@@ -0,0 +1,90 @@
+use csv_source::{CsvSource, CsvSourceConfig, denomination, parse_assets};
+use denomination::Denomination;
+use rust_decimal_macros::*;
+use source::Source;
+use test_util::TempDir;
+use worthy_error::WorthyError;
+
+fn config(path: &str) -> CsvSourceConfig {
+    serde_json::from_value(serde_json::json!({ "path": path })).unwrap()
+}
+
+#[test]
+fn denomination_by_type() {
+    assert_eq!(
+        denomination("Currency", " CZK "),
+        Ok(Denomination::Currency {
+            currency: "CZK".to_string()
+        })
+    );
+    assert_eq!(
+        denomination("stock", "VT"),
+        Ok(Denomination::Stock {
+            stock: "VT".to_string(),
+            isin: None,
+        })
+    );
+    assert_eq!(
+        denomination("cryptocurrency", "BTC"),
+        denomination("crypto", "BTC")
+    );
+    assert!(denomination("bond", "X").is_err());
+    assert!(denomination("stock", "").is_err());
+}
+
+#[test]
+fn parse_assets_with_custom_columns() {
+    let config: CsvSourceConfig = serde_json::from_value(serde_json::json!({
+        "path": "unused.csv",
+        "symbol_column": "Ticker",
+        "amount_column": "Units",
+        "type_column": "Kind",
+    }))
+    .unwrap();
+    let csv = "Account,Kind,Ticker,Units\n\
+               Savings,currency,CZK,100000.50\n\
+               Broker,stock,VT,12\n\
+               Wallet,crypto,BTC,0.01\n";
+
+    let assets = parse_assets(&config, csv.as_bytes()).unwrap();
+
+    assert_eq!(
+        assets
+            .iter()
+            .map(|asset| (asset.denomination.to_string(), asset.amount))
+            .collect::<Vec<_>>(),
+        vec![
+            ("currency:CZK".to_string(), dec!(100000.50)),
+            ("stock:VT".to_string(), dec!(12)),
+            ("crypto:BTC".to_string(), dec!(0.01)),
+        ]
+    );
+}
+
+#[test]
+fn parse_assets_errors() {
+    let config = config("unused.csv");
+    let error = parse_assets(&config, "symbol,amount\nUSD,1\n".as_bytes()).unwrap_err();
+    assert_eq!(error, "no column \"type\"");
+    let error = parse_assets(
+        &config,
+        "type,symbol,amount\ncurrency,USD,1\ncurrency,EUR,lots\n".as_bytes(),
+    )
+    .unwrap_err();
+    assert!(error.starts_with("line 3: amount \"lots\""), "{}", error);
+}
+
+#[tokio::test]
+async fn take_snapshot_from_file() {
+    let dir = TempDir::new("csv_source_test");
+    let path = dir.write("accounts.csv", "type,symbol,amount\ncurrency,EUR,250\n");
+
+    let assets = CsvSource::take_snapshot(&config(&path)).await.unwrap();
+    assert_eq!(assets.len(), 1);
+    assert_eq!(assets[0].amount, dec!(250));
+
+    let error = CsvSource::take_snapshot(&config(&dir.file("missing.csv")))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
@@ -0,0 +1,190 @@
+use asset::{Asset, CostBasis};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use log::warn;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use source::{Source, SourceReport, Staleness};
+use worthy_error::WorthyError;
+
+/// Assets entered by hand in the configuration, for institutions without an
+/// API.
+pub struct HardcodedSource {}
+
+/// An asset entered by hand, either as an `amount`, or as a `quantity` along
+/// with the price of one unit, so that updating the price alone revalues it.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(try_from = "HardcodedAssetConfig")]
+pub struct HardcodedAsset {
+    pub asset: Asset,
+    /// Price of one unit of `asset` in another denomination, if entered.
+    pub unit_price: Option<ExchangeRate>,
+}
+
+#[derive(Deserialize)]
+struct HardcodedAssetConfig {
+    #[serde(flatten)]
+    denomination: Denomination,
+    #[serde(default)]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    quantity: Option<Decimal>,
+    #[serde(default)]
+    unit_price: Option<Decimal>,
+    /// E.g. "USD", or "crypto:BTC".
+    #[serde(default)]
+    unit_price_denomination: Option<String>,
+    #[serde(default)]
+    cost_basis: Option<CostBasis>,
+}
+
+impl TryFrom<HardcodedAssetConfig> for HardcodedAsset {
+    type Error = String;
+
+    fn try_from(config: HardcodedAssetConfig) -> Result<Self, Self::Error> {
+        let (amount, unit_price) = match config {
+            HardcodedAssetConfig {
+                amount: Some(amount),
+                quantity: None,
+                unit_price: None,
+                unit_price_denomination: None,
+                ..
+            } => (amount, None),
+            HardcodedAssetConfig {
+                amount: None,
+                quantity: Some(quantity),
+                unit_price: Some(unit_price),
+                unit_price_denomination: Some(ref unit_price_denomination),
+                ..
+            } => (
+                quantity,
+                Some(ExchangeRate {
+                    // Set from the source's `as_of` when reported.
+                    timestamp: DateTime::UNIX_EPOCH,
+                    from: config.denomination.clone(),
+                    to: unit_price_denomination.parse()?,
+                    rate: unit_price,
+                    spread_bps: Decimal::ZERO,
+                }),
+            ),
+            _ => {
+                return Err(format!(
+                    "hardcoded {} needs either amount, or quantity, unit_price and unit_price_denomination",
+                    config.denomination
+                ));
+            }
+        };
+        Ok(HardcodedAsset {
+            asset: Asset {
+                amount,
+                denomination: config.denomination,
+                cost_basis: config.cost_basis,
+            },
+            unit_price,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HardcodedSourceConfig {
+    pub assets: Vec<HardcodedAsset>,
+    /// When the balances were last updated by hand (RFC3339). Without it, the
+    /// balances are never considered stale.
+    #[serde(default)]
+    pub as_of: Option<DateTime<FixedOffset>>,
+    /// Balances older than this are stale.
+    #[serde(default = "default_stale_after_days")]
+    pub stale_after_days: i64,
+    /// Leave stale balances out of snapshots instead of only warning.
+    #[serde(default)]
+    pub exclude_when_stale: bool,
+    /// Fraction of the balances discounted for every day they are stale, down
+    /// to nothing. Without it, stale balances count at full value.
+    #[serde(default)]
+    pub decay: Option<Decimal>,
+}
+
+fn default_stale_after_days() -> i64 {
+    90
+}
+
+impl HardcodedSourceConfig {
+    /// Age of the balances at `now`, if they are older than
+    /// `stale_after_days`.
+    pub fn staleness(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let age = now.signed_duration_since(self.as_of?);
+        (age > Duration::days(self.stale_after_days)).then_some(age)
+    }
+
+    /// Factor the balances are counted with at `now`: 1 until they are stale,
+    /// then less by `decay` for each day past `stale_after_days`.
+    pub fn weight(&self, now: DateTime<Utc>) -> Decimal {
+        let (Some(decay), Some(age)) = (self.decay, self.staleness(now)) else {
+            return Decimal::ONE;
+        };
+        let days_stale = Decimal::from(age.num_days() - self.stale_after_days);
+        decay
+            .checked_mul(days_stale)
+            .map_or(Decimal::ZERO, |discount| {
+                (Decimal::ONE - discount).clamp(Decimal::ZERO, Decimal::ONE)
+            })
+    }
+}
+
+#[async_trait]
+impl Source for HardcodedSource {
+    type Config = HardcodedSourceConfig;
+
+    async fn take_snapshot(config: &Self::Config) -> Result<Vec<Asset>, WorthyError> {
+        Ok(Self::report(config).await?.assets)
+    }
+
+    /// The assets, weighted by their staleness, and the unit prices entered
+    /// along with them as rates, observed at `as_of`, or now without it.
+    async fn report(config: &Self::Config) -> Result<SourceReport, WorthyError> {
+        let now = Utc::now();
+        if config.exclude_when_stale
+            && let Some(age) = config.staleness(now)
+        {
+            warn!(
+                "hardcoded balances are {} days old, excluding them",
+                age.num_days()
+            );
+            return Ok(SourceReport::default());
+        }
+        let observed_at = config
+            .as_of
+            .map_or_else(Utc::now, |as_of| as_of.with_timezone(&Utc));
+        let weight = config.weight(now);
+        Ok(SourceReport {
+            assets: config
+                .assets
+                .iter()
+                .map(|entry| Asset {
+                    amount: entry.asset.amount * weight,
+                    ..entry.asset.clone()
+                })
+                .collect(),
+            rates: config
+                .assets
+                .iter()
+                .filter_map(|entry| entry.unit_price.clone())
+                .map(|rate| ExchangeRate {
+                    timestamp: observed_at,
+                    ..rate
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    fn staleness(config: &Self::Config, now: DateTime<Utc>) -> Option<Staleness> {
+        Some(Staleness {
+            age: config.staleness(now)?,
+            excluded: config.exclude_when_stale,
+            weight: config.weight(now),
+        })
+    }
+}
@@ -0,0 +1,177 @@
+use asset::Asset;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use denomination::Denomination;
+use exchange_rate::ExchangeRate;
+use hardcoded_source::{HardcodedSource, HardcodedSourceConfig};
+use rust_decimal::Decimal;
+use rust_decimal_macros::*;
+use source::Source;
+
+fn config(extra: serde_json::Value) -> HardcodedSourceConfig {
+    let mut value = serde_json::json!({
+        "assets": [{"currency": "USD", "amount": "100"}],
+    });
+    value
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(value).unwrap()
+}
+
+fn now() -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339("2021-07-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+#[test]
+fn without_as_of_never_stale() {
+    assert_eq!(config(serde_json::json!({})).staleness(now()), None);
+}
+
+#[test]
+fn fresh_within_default_threshold() {
+    let config = config(serde_json::json!({"as_of": "2021-05-01T00:00:00Z"}));
+    assert_eq!(config.staleness(now()), None);
+}
+
+#[test]
+fn stale_after_default_threshold() {
+    let config = config(serde_json::json!({"as_of": "2021-01-01T00:00:00Z"}));
+    assert_eq!(config.staleness(now()), Some(Duration::days(181)));
+}
+
+#[test]
+fn custom_threshold() {
+    let config = config(serde_json::json!({
+        "as_of": "2021-06-01T00:00:00+02:00",
+        "stale_after_days": 14,
+    }));
+    assert!(config.staleness(now()).is_some());
+}
+
+#[test]
+fn decay_weight() {
+    let weight = |extra| config(extra).weight(now());
+    // 181 days old, 91 days past the default threshold.
+    assert_eq!(
+        weight(serde_json::json!({"as_of": "2021-01-01T00:00:00Z"})),
+        dec!(1)
+    );
+    assert_eq!(
+        weight(serde_json::json!({"as_of": "2021-06-01T00:00:00Z", "decay": "0.01"})),
+        dec!(1)
+    );
+    assert_eq!(
+        weight(serde_json::json!({"as_of": "2021-01-01T00:00:00Z", "decay": "0.01"})),
+        dec!(0.09)
+    );
+    assert_eq!(
+        weight(serde_json::json!({"as_of": "2021-01-01T00:00:00Z", "decay": "0.02"})),
+        dec!(0)
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_decays_stale() {
+    let decayed = config(serde_json::json!({
+        "as_of": "2000-01-01T00:00:00Z",
+        "decay": "0.5",
+    }));
+    let assets = HardcodedSource::take_snapshot(&decayed).await.unwrap();
+    assert_eq!(assets.len(), 1);
+    assert_eq!(assets[0].amount, Decimal::ZERO);
+}
+
+#[tokio::test]
+async fn take_snapshot_excludes_stale() {
+    let stale = config(serde_json::json!({
+        "as_of": "2000-01-01T00:00:00Z",
+        "exclude_when_stale": true,
+    }));
+    assert!(
+        HardcodedSource::take_snapshot(&stale)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let only_warned = config(serde_json::json!({"as_of": "2000-01-01T00:00:00Z"}));
+    assert_eq!(
+        HardcodedSource::take_snapshot(&only_warned)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn quantity_and_unit_price() {
+    let config: HardcodedSourceConfig = serde_json::from_value(serde_json::json!({
+        "assets": [
+            {"currency": "USD", "amount": "100"},
+            {
+                "stock": "VT",
+                "quantity": "12",
+                "unit_price": "101.5",
+                "unit_price_denomination": "USD",
+            },
+        ],
+        "as_of": "2021-02-20T12:00:00+01:00",
+    }))
+    .unwrap();
+    let vt = Denomination::Stock {
+        stock: "VT".to_string(),
+        isin: None,
+    };
+    let report = HardcodedSource::report(&config).await.unwrap();
+    assert_eq!(
+        report.assets,
+        vec![
+            Asset {
+                amount: dec!(100),
+                denomination: Denomination::Currency {
+                    currency: "USD".to_string()
+                },
+                cost_basis: None,
+            },
+            Asset {
+                amount: dec!(12),
+                denomination: vt.clone(),
+                cost_basis: None,
+            },
+        ]
+    );
+    assert_eq!(
+        report.rates,
+        vec![ExchangeRate {
+            timestamp: Utc.with_ymd_and_hms(2021, 2, 20, 11, 0, 0).unwrap(),
+            from: vt,
+            to: Denomination::Currency {
+                currency: "USD".to_string()
+            },
+            rate: dec!(101.5),
+            spread_bps: Decimal::ZERO,
+        }]
+    );
+}
+
+#[test]
+fn amount_or_quantity_and_unit_price() {
+    for entry in [
+        serde_json::json!({"currency": "USD"}),
+        serde_json::json!({"stock": "VT", "quantity": "12", "unit_price": "101.5"}),
+        serde_json::json!({
+            "stock": "VT",
+            "amount": "12",
+            "quantity": "12",
+            "unit_price": "101.5",
+            "unit_price_denomination": "USD",
+        }),
+    ] {
+        let result: Result<HardcodedSourceConfig, _> =
+            serde_json::from_value(serde_json::json!({"assets": [entry]}));
+        assert!(result.is_err(), "{:?}", result);
+    }
+}
@@ -1,19 +1,20 @@
-use asset::Asset;
+use asset::{Asset, CostBasis};
 use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
 use denomination::Denomination;
+use exchange_rate::ExchangeRate;
 use ibflex::{
-    AssetCategory, FlexQuerySuccess, FlexStatement, LevelOfDetail::Summary, OpenPosition,
-    Side::Long, run_flex_query,
+    AssetCategory, CashLevelOfDetail, CashReportCurrency, DEFAULT_ENDPOINT,
+    DEFAULT_FLEX_API_VERSION, FlexQuerySuccess, FlexRetry, FlexStatement, LevelOfDetail,
+    OpenPosition, ReportDates, Side, run_flex_query,
 };
+use log::warn;
 use rust_decimal::Decimal;
 use serde::Deserialize;
-use source::Source;
-use std::collections::HashMap;
-use std::error::Error;
-use std::{
-    fmt,
-    fmt::{Display, Formatter},
-};
+use source::{AccountAssets, Source, SourceReport};
+use std::collections::{HashMap, HashSet};
+use url::Url;
+use worthy_error::WorthyError;
 
 pub struct IBFlexSource {}
 
@@ -21,112 +22,391 @@ pub struct IBFlexSource {}
 pub struct IBFlexSourceConfig {
     query_id: String,
     token: String,
+    #[serde(default = "default_endpoint")]
+    endpoint: Url,
+    /// Flex web service API version.
+    #[serde(default = "default_version")]
+    version: i32,
+    /// First day of the requested statement, instead of the period saved in
+    /// the Flex query.
+    #[serde(default)]
+    from_date: Option<NaiveDate>,
+    /// Last day of the requested statement. Defaults to `from_date`.
+    #[serde(default)]
+    to_date: Option<NaiveDate>,
+    /// Error codes to retry on (`retriable_codes`), and whether to require
+    /// IB's "try again shortly" message too (`require_try_again_message`).
+    #[serde(flatten)]
+    pub retry: FlexRetry,
+}
+
+impl IBFlexSourceConfig {
+    fn report_dates(&self) -> Result<Option<ReportDates>, WorthyError> {
+        match (self.from_date, self.to_date) {
+            (None, None) => Ok(None),
+            (None, Some(_)) => Err(WorthyError::Config(
+                "ibflex to_date requires from_date".to_string(),
+            )),
+            (Some(from), Some(to)) if to < from => Err(WorthyError::Config(format!(
+                "ibflex to_date {} is before from_date {}",
+                to, from
+            ))),
+            (Some(from), to) => Ok(Some(ReportDates {
+                from,
+                to: to.unwrap_or(from),
+            })),
+        }
+    }
 }
 
-#[derive(Debug)]
-struct UnhandledResponse {
-    message: String,
+fn default_endpoint() -> Url {
+    Url::parse(DEFAULT_ENDPOINT).unwrap()
 }
 
-impl Error for UnhandledResponse {}
-impl Display for UnhandledResponse {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "Unhandled response: {}", self.message)
+fn default_version() -> i32 {
+    DEFAULT_FLEX_API_VERSION
+}
+
+fn unhandled_response(message: String) -> WorthyError {
+    WorthyError::IbFlex {
+        code: None,
+        message: format!("unhandled response: {}", message),
     }
 }
 
-fn get_only_flex_statement(r: &FlexQuerySuccess) -> Result<&FlexStatement, UnhandledResponse> {
+/// The statements of `r`, e.g. one per account for a Flex query covering
+/// several linked accounts.
+fn flex_statements(r: &FlexQuerySuccess) -> Result<&[FlexStatement], WorthyError> {
     let flex_statements = &r.flex_statements;
-    if flex_statements.count != 1 {
-        return Err(UnhandledResponse {
-            message: format!(
-                "expected 1 returned FlexStatements, got {} {:?}",
-                flex_statements.count, r
-            ),
-        });
+    if flex_statements.count < 1 {
+        return Err(unhandled_response(format!(
+            "expected returned FlexStatements, got {} {:?}",
+            flex_statements.count, r
+        )));
     }
-    let flex_statements = &flex_statements.flex_statements;
-    if flex_statements.len() != 1 {
-        return Err(UnhandledResponse {
-            message: format!(
-                "expected 1 returned FlexStatement per FlexStatements, got {} {:?}",
-                flex_statements.len(),
-                r
-            ),
-        });
+    if usize::try_from(flex_statements.count) != Ok(flex_statements.flex_statements.len()) {
+        return Err(unhandled_response(format!(
+            "expected {} returned FlexStatement per FlexStatements, got {} {:?}",
+            flex_statements.count,
+            flex_statements.flex_statements.len(),
+            r
+        )));
     }
-    Ok(&flex_statements[0])
+    Ok(&flex_statements.flex_statements)
 }
 
-fn check_position(position: &OpenPosition) -> Result<(), UnhandledResponse> {
-    if position.multiplier != Decimal::new(1, 0) {
-        return Err(UnhandledResponse {
-            message: "multiplier != 1 not supported".to_string(),
-        });
-    }
-    if position.asset_category != AssetCategory::Stock {
-        return Err(UnhandledResponse {
-            message: "only stocks supported".to_string(),
-        });
+/// Base currency of the statements that include the Account Information
+/// section, if they agree on it.
+fn base_currency(statements: &[FlexStatement]) -> Option<String> {
+    let mut currencies = statements
+        .iter()
+        .filter_map(|statement| statement.account_information.as_ref())
+        .map(|account| &account.currency);
+    let first = currencies.next()?;
+    if let Some(other) = currencies.find(|currency| *currency != first) {
+        warn!(
+            "Flex statements have different base currencies {} and {}",
+            first, other
+        );
+        return None;
     }
-    if !position.put_call.is_empty()
-        || !position.issuer.is_empty()
-        || !position.expiry.is_empty()
-        || position.level_of_detail != Summary
+    Some(first.clone())
+}
+
+fn check_position(position: &OpenPosition) -> Result<(), WorthyError> {
+    // Options and futures expire, and options are puts or calls.
+    let derivative_fields = position.put_call.is_some() || position.expiry.is_some();
+    if !position.issuer.is_empty()
+        || (position.asset_category == AssetCategory::Stock && derivative_fields)
     {
-        return Err(UnhandledResponse {
-            message: "unexpected fields populated".to_string(),
-        });
+        return Err(unhandled_response(
+            "unexpected fields populated".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Cost basis of `position`, if the Flex query reports both its cost and its
+/// unrealized gain.
+fn cost_basis(position: &OpenPosition) -> Option<CostBasis> {
+    Some(CostBasis {
+        currency: position.currency.clone(),
+        money: position.cost_basis_money?,
+        unrealized_pnl: position.fifo_pnl_unrealized?,
+    })
+}
+
+/// How far apart rates to the base currency of positions in one currency may
+/// be, as a fraction of the larger, as IB rounds them.
+const FX_RATE_TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 3);
+
+/// Whether `a` and `b` are within `FX_RATE_TOLERANCE` of each other.
+fn fx_rates_agree(a: Decimal, b: Decimal) -> bool {
+    (a - b).abs() <= FX_RATE_TOLERANCE * a.abs().max(b.abs())
+}
+
+/// Units of the underlying per contract of `position`. A missing multiplier
+/// is taken to be 1, like for any stock.
+fn multiplier(position: &OpenPosition) -> Decimal {
+    position.multiplier.unwrap_or(Decimal::ONE)
+}
+
+/// Stocks by ticker and ISIN. Options and futures are derivatives, so that
+/// converters aren't asked about their contract symbols.
+fn position_denomination(position: &OpenPosition) -> Denomination {
+    match position.asset_category {
+        AssetCategory::Stock => Denomination::Stock {
+            stock: position.symbol.clone(),
+            isin: (!position.isin.is_empty()).then(|| position.isin.clone()),
+        },
+        AssetCategory::Option | AssetCategory::Future => Denomination::Derivative {
+            derivative: position.symbol.clone(),
+        },
+    }
+}
+
+/// The position in units of the underlying, i.e. its contracts times their
+/// multiplier, which IB's mark price is the price of one of. Short positions
+/// are negative, so that they subtract from the total.
+fn position_asset(position: &OpenPosition) -> Asset {
+    let contracts = match position.side {
+        Side::Long => position.position,
+        Side::Short => -position.position.abs(),
+    };
+    Asset {
+        denomination: position_denomination(position),
+        amount: contracts * multiplier(position),
+        cost_basis: cost_basis(position),
     }
-    if position.side != Long {
-        return Err(UnhandledResponse {
-            message: "only long positions supported".to_string(),
+}
+
+/// Prices of the options and futures among `rows`, which no converter
+/// prices. Options are priced at their mark price. Futures are worth nothing
+/// beyond the cash, as IB settles their gains and losses into it every day;
+/// their notional value isn't held. Stocks are left to the converters.
+fn derivative_rates(
+    rows: &[(&OpenPosition, Asset)],
+    fetched_at: DateTime<Utc>,
+) -> Vec<ExchangeRate> {
+    let mut rates: Vec<ExchangeRate> = Vec::new();
+    for (position, asset) in rows {
+        let rate = match position.asset_category {
+            AssetCategory::Stock => continue,
+            AssetCategory::Option => position.mark_price,
+            AssetCategory::Future => Decimal::ZERO,
+        };
+        if rates.iter().any(|rate| rate.from == asset.denomination) {
+            continue;
+        }
+        rates.push(ExchangeRate {
+            timestamp: fetched_at,
+            from: asset.denomination.clone(),
+            to: Denomination::Currency {
+                currency: position.currency.clone(),
+            },
+            rate,
+            spread_bps: Decimal::ZERO,
         });
     }
+    rates
+}
+
+/// Name of an account: its alias, or its ID if it has none.
+fn account_name(account_id: &str, acct_alias: &str) -> String {
+    let alias = acct_alias.trim();
+    if alias.is_empty() {
+        account_id.to_string()
+    } else {
+        alias.to_string()
+    }
+}
+
+/// Cash in the currency of `row`, if it has one and the Flex query includes
+/// ending cash. The BASE_SUMMARY row totals the others, so it's left out.
+fn cash_asset(row: &CashReportCurrency) -> Result<Option<Asset>, WorthyError> {
+    if row.currency == "BASE_SUMMARY" {
+        return Ok(None);
+    }
+    if row.level_of_detail != CashLevelOfDetail::Currency {
+        return Err(unhandled_response(format!(
+            "cash report row for {} has level of detail {:?}",
+            row.currency, row.level_of_detail
+        )));
+    }
+    Ok(row.ending_cash.map(|amount| Asset {
+        denomination: Denomination::Currency {
+            currency: row.currency.clone(),
+        },
+        amount,
+        cost_basis: None,
+    }))
+}
+
+/// Adds the cash of each account to its assets, after its positions.
+fn add_cash(
+    accounts: &mut Vec<AccountAssets>,
+    rows: &[CashReportCurrency],
+) -> Result<(), WorthyError> {
+    for row in rows {
+        let Some(asset) = cash_asset(row)? else {
+            continue;
+        };
+        match accounts
+            .iter_mut()
+            .find(|account| account.account_id == row.account_id)
+        {
+            Some(account) => account.assets.push(asset),
+            None => accounts.push(AccountAssets {
+                account_id: row.account_id.clone(),
+                name: account_name(&row.account_id, &row.acct_alias),
+                assets: vec![asset],
+            }),
+        }
+    }
     Ok(())
 }
 
+/// Positions of `statement` with their assets, checking that positions in
+/// one currency are converted to the base currency at one rate.
+fn statement_rows(statement: &FlexStatement) -> Result<Vec<(&OpenPosition, Asset)>, WorthyError> {
+    let mut seen_fx_rates: HashMap<&str, Decimal> = HashMap::new();
+    let Some(positions) = &statement.open_positions.open_position else {
+        return Ok(Vec::new());
+    };
+    positions
+        .iter()
+        .map(|position| -> Result<(&OpenPosition, Asset), WorthyError> {
+            check_position(position)?;
+            let fx_rate = position.fx_rate_to_base;
+            match seen_fx_rates.get(position.currency.as_str()) {
+                Some(&seen_fx_rate) if !fx_rates_agree(seen_fx_rate, fx_rate) => {
+                    return Err(unhandled_response(format!(
+                        "inconsistent rate for currency {}: {} and {}",
+                        position.currency, seen_fx_rate, fx_rate
+                    )));
+                }
+                _ => {
+                    seen_fx_rates
+                        .entry(position.currency.as_str())
+                        .or_insert(fx_rate);
+                }
+            }
+            Ok((position, position_asset(position)))
+        })
+        .collect()
+}
+
+/// Rows of the positions grouped by account, in the order accounts first
+/// appear, with their lots combined per `combine_lots`.
+fn group_by_account(rows: Vec<(&OpenPosition, Asset)>) -> Vec<AccountAssets> {
+    let mut accounts: Vec<(&OpenPosition, Vec<(&LevelOfDetail, Asset)>)> = Vec::new();
+    for (position, asset) in rows {
+        let row = (&position.level_of_detail, asset);
+        match accounts
+            .iter_mut()
+            .find(|(first, _)| first.account_id == position.account_id)
+        {
+            Some((_, account_rows)) => account_rows.push(row),
+            None => accounts.push((position, vec![row])),
+        }
+    }
+    accounts
+        .into_iter()
+        .map(|(first, rows)| AccountAssets {
+            account_id: first.account_id.clone(),
+            name: account_name(&first.account_id, &first.acct_alias),
+            assets: combine_lots(rows),
+        })
+        .collect()
+}
+
+/// Assets of the positions as their SUMMARY rows have them. A position with
+/// only LOT rows is the sum of its lots, and the LOT rows of a position with a
+/// SUMMARY row are left out, so no position is counted twice.
+fn combine_lots(rows: Vec<(&LevelOfDetail, Asset)>) -> Vec<Asset> {
+    let summarized: HashSet<Denomination> = rows
+        .iter()
+        .filter(|(level, _)| **level == LevelOfDetail::Summary)
+        .map(|(_, asset)| asset.denomination.clone())
+        .collect();
+    let mut assets: Vec<Asset> = Vec::new();
+    let mut lots: Vec<Asset> = Vec::new();
+    for (level, asset) in rows {
+        match level {
+            LevelOfDetail::Summary => assets.push(asset),
+            LevelOfDetail::Lot if summarized.contains(&asset.denomination) => {}
+            LevelOfDetail::Lot => {
+                match lots
+                    .iter_mut()
+                    .find(|sum| sum.denomination == asset.denomination)
+                {
+                    Some(sum) => *sum = sum.checked_add(&asset).unwrap(),
+                    None => lots.push(asset),
+                }
+            }
+        }
+    }
+    assets.extend(lots);
+    assets
+}
+
 #[async_trait]
 impl Source for IBFlexSource {
     type Config = IBFlexSourceConfig;
 
-    async fn take_snapshot(config: &Self::Config) -> Result<Vec<Asset>, Box<dyn Error>> {
-        let IBFlexSourceConfig { query_id, token } = config;
-        let r = run_flex_query(token, query_id).await?;
-        let s = get_only_flex_statement(&r)?;
-
-        let mut seen_exchange_rates: HashMap<String, Decimal> = HashMap::new();
-
-        let empty = Vec::new();
-        let positions: &Vec<OpenPosition> =
-            s.open_positions.open_position.as_ref().unwrap_or(&empty);
-        positions
-            .iter()
-            .map(|position| -> Result<Asset, Box<dyn Error>> {
-                check_position(position)?;
-                match seen_exchange_rates.get(&position.currency) {
-                    Some(seen_exchange_rate)
-                        if (*seen_exchange_rate != position.fx_rate_to_base) =>
-                    {
-                        return Err(UnhandledResponse {
-                            message: "inconsistent rate for currency".to_string(),
-                        }
-                        .into());
-                    }
-                    _ => {
-                        seen_exchange_rates
-                            .entry(position.currency.clone())
-                            .or_insert(position.fx_rate_to_base);
-                    }
-                }
-                Ok(Asset {
-                    denomination: Denomination::Stock {
-                        stock: position.symbol.clone(),
-                    },
-                    amount: position.position,
-                })
-            })
-            .collect()
+    async fn take_snapshot(config: &Self::Config) -> Result<Vec<Asset>, WorthyError> {
+        Ok(Self::report(config).await?.assets)
+    }
+
+    /// Positions and cash in each account of the Flex query, from all its
+    /// statements, along with the base currency if the query includes the
+    /// Account Information section.
+    async fn report(config: &Self::Config) -> Result<SourceReport, WorthyError> {
+        let IBFlexSourceConfig {
+            query_id,
+            token,
+            endpoint,
+            version,
+            ..
+        } = config;
+        let r = run_flex_query(
+            endpoint,
+            *version,
+            token,
+            query_id,
+            config.report_dates()?,
+            &config.retry,
+        )
+        .await?;
+        let statements = flex_statements(&r)?;
+
+        // The statements don't say when their FX rates are from.
+        let fetched_at = Utc::now();
+        let mut rows = Vec::new();
+        for statement in statements {
+            rows.extend(statement_rows(statement)?);
+        }
+        let rates = derivative_rates(&rows, fetched_at);
+        let mut accounts = group_by_account(rows);
+        for statement in statements {
+            if let Some(cash) = statement
+                .cash_report
+                .as_ref()
+                .and_then(|report| report.cash_report_currency.as_ref())
+            {
+                add_cash(&mut accounts, cash)?;
+            }
+        }
+        let base_currency = base_currency(statements);
+        Ok(SourceReport {
+            assets: accounts
+                .iter()
+                .flat_map(|account| account.assets.iter().cloned())
+                .collect(),
+            base_currency,
+            accounts,
+            rates,
+        })
         //		self.logger.Println(openPosition.Symbol, openPosition.Description,
         //			// Position:"6",
         //			openPosition.Position,
@@ -139,15 +419,6 @@ impl Source for IBFlexSource {
         //	//for otherCurrency, rate := range exchangeRates {
         //	//	fmt.Println("1", otherCurrency, "=", rate, statement.AccountInformation.Currency)
         //	//}
-        //	//for _, currency := range statement.CashReport.CashReportCurrency {
-        //	//	if currency.Currency == "BASE_SUMMARY" {
-        //	//		continue
-        //	//	}
-        //	//	if currency.LevelOfDetail != "Currency" {
-        //	//		panic("unexpected fields populated")
-        //	//	}
-        //	//	fmt.Println(currency.Currency, currency.EndingCash)
-        //	//}
         //	return assets, nil
     }
 }
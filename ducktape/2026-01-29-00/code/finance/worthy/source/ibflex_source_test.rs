@@ -0,0 +1,616 @@
+use asset::CostBasis;
+use denomination::Denomination;
+use fixture_server::{FixtureServer, Response};
+use ibflex::FlexRetry;
+use ibflex_source::{IBFlexSource, IBFlexSourceConfig};
+use rust_decimal_macros::*;
+use source::Source;
+use worthy_error::WorthyError;
+
+/// Serves a successful statement response followed by the recorded query
+/// response.
+fn serve_recorded_responses() -> FixtureServer {
+    serve_query_response(include_str!("../testdata/flex_query_response.xml"))
+}
+
+/// Serves a successful statement response followed by `query_response`.
+fn serve_query_response(query_response: &str) -> FixtureServer {
+    let mut server = FixtureServer::bind();
+    let statement_response = format!(
+        "<FlexStatementResponse timestamp='16 February, 2021 04:50 PM EST'>
+<Status>Success</Status>
+<ReferenceCode>4672968268</ReferenceCode>
+<Url>{}GetStatement</Url>
+</FlexStatementResponse>",
+        server.url()
+    );
+    server.serve(vec![
+        Response::ok(statement_response),
+        Response::ok(query_response),
+    ]);
+    server
+}
+
+#[tokio::test]
+async fn take_snapshot_from_recorded_responses() {
+    let server = serve_recorded_responses();
+    let config: IBFlexSourceConfig = serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+        "endpoint": format!("{}SendRequest", server.url()),
+    }))
+    .unwrap();
+
+    let assets = IBFlexSource::take_snapshot(&config).await.unwrap();
+
+    assert_eq!(assets.len(), 2);
+    assert_eq!(
+        assets[0].denomination,
+        Denomination::Stock {
+            stock: "ABCD".to_string(),
+            isin: Some("US12345".to_string()),
+        }
+    );
+    assert_eq!(assets[0].amount, dec!(1111));
+    assert_eq!(
+        assets[0].cost_basis,
+        Some(CostBasis {
+            currency: "USD".to_string(),
+            money: dec!(9999),
+            unrealized_pnl: dec!(111),
+        })
+    );
+    assert_eq!(
+        assets[1].denomination,
+        Denomination::Stock {
+            stock: "EFGH".to_string(),
+            isin: Some("US12346".to_string()),
+        }
+    );
+    assert_eq!(assets[1].amount, dec!(1112));
+    assert_eq!(
+        server.requests(),
+        vec![
+            "GET /SendRequest?t=TOKEN&q=123&v=3 HTTP/1.1",
+            "GET /GetStatement?t=TOKEN&q=4672968268&v=3 HTTP/1.1",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_custom_endpoint_and_version() {
+    let server = serve_recorded_responses();
+    let config: IBFlexSourceConfig = serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+        "endpoint": format!("{}Other/FlexStatementService.SendRequest", server.url()),
+        "version": 4,
+    }))
+    .unwrap();
+
+    IBFlexSource::take_snapshot(&config).await.unwrap();
+
+    assert_eq!(
+        server.requests(),
+        vec![
+            "GET /Other/FlexStatementService.SendRequest?t=TOKEN&q=123&v=4 HTTP/1.1",
+            "GET /GetStatement?t=TOKEN&q=4672968268&v=4 HTTP/1.1",
+        ]
+    );
+}
+
+fn config_for(server: &FixtureServer) -> IBFlexSourceConfig {
+    serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+        "endpoint": server.url(),
+    }))
+    .unwrap()
+}
+
+#[test]
+fn retriable_codes_config() {
+    let config: IBFlexSourceConfig = serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+    }))
+    .unwrap();
+    assert_eq!(config.retry, FlexRetry::default());
+
+    let config: IBFlexSourceConfig = serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+        "retriable_codes": [1004, 1018],
+        "require_try_again_message": true,
+    }))
+    .unwrap();
+    assert_eq!(
+        config.retry,
+        FlexRetry {
+            retriable_codes: [1004, 1018].into_iter().collect(),
+            require_try_again_message: true,
+        }
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_flex_error() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok(
+        "<FlexStatementResponse timestamp='16 February, 2021 05:16 PM EST'>
+<Status>Fail</Status>
+<ErrorCode>1020</ErrorCode>
+<ErrorMessage>Invalid request or unable to validate request.</ErrorMessage>
+</FlexStatementResponse>",
+    )]);
+
+    let error = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            error,
+            WorthyError::IbFlex {
+                code: Some(1020),
+                ..
+            }
+        ),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_garbage_response() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::ok("not xml at all")]);
+
+    let error = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, WorthyError::Parse(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn take_snapshot_http_error() {
+    let mut server = FixtureServer::bind();
+    server.serve(vec![Response::status("503 Service Unavailable")]);
+
+    let error = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, WorthyError::Http(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn take_snapshot_for_report_date() {
+    let server = serve_recorded_responses();
+    let config: IBFlexSourceConfig = serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+        "endpoint": format!("{}SendRequest", server.url()),
+        "from_date": "2021-02-15",
+    }))
+    .unwrap();
+
+    IBFlexSource::take_snapshot(&config).await.unwrap();
+
+    assert_eq!(
+        server.requests()[0],
+        "GET /SendRequest?t=TOKEN&q=123&v=3&fd=20210215&td=20210215 HTTP/1.1"
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_to_date_without_from_date() {
+    let config: IBFlexSourceConfig = serde_json::from_value(serde_json::json!({
+        "query_id": "123",
+        "token": "TOKEN",
+        "to_date": "2021-02-15",
+    }))
+    .unwrap();
+
+    let error = IBFlexSource::take_snapshot(&config).await.unwrap_err();
+    assert!(matches!(error, WorthyError::Config(_)), "{:?}", error);
+}
+
+#[tokio::test]
+async fn same_ticker_different_isin_kept_apart() {
+    // Both positions have ticker ABCD, but different ISINs.
+    let query_response = include_str!("../testdata/flex_query_response.xml")
+        .replace("symbol=\"EFGH\"", "symbol=\"ABCD\"");
+    let server = serve_query_response(&query_response);
+
+    let assets = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap();
+
+    assert_eq!(assets.len(), 2);
+    assert_ne!(assets[0].denomination, assets[1].denomination);
+    assert_eq!(assets[0].denomination.symbol(), "ABCD");
+    assert_eq!(assets[1].denomination.symbol(), "ABCD");
+}
+
+#[tokio::test]
+async fn take_snapshot_tolerates_multiplier_forms() {
+    for multiplier in ["", "1.0"] {
+        let query_response = include_str!("../testdata/flex_query_response.xml").replace(
+            "multiplier=\"1\"",
+            &format!("multiplier=\"{}\"", multiplier),
+        );
+        let server = serve_query_response(&query_response);
+
+        let assets = IBFlexSource::take_snapshot(&config_for(&server))
+            .await
+            .unwrap();
+        assert_eq!(assets.len(), 2, "multiplier={:?}", multiplier);
+    }
+}
+
+#[tokio::test]
+async fn take_snapshot_with_base_currency() {
+    let server = serve_query_response(include_str!("../testdata/flex_query_response.xml"));
+
+    let snapshot = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    assert_eq!(snapshot.assets.len(), 2);
+    assert_eq!(snapshot.base_currency, Some("CHF".to_string()));
+}
+
+#[tokio::test]
+async fn take_snapshot_with_cash() {
+    let mut query_response = include_str!("../testdata/flex_query_response.xml").to_string();
+    for (currency, ending_cash) in [
+        ("BASE_SUMMARY", "1234.9"),
+        ("CHF", "345.6"),
+        ("USD", "998.88"),
+    ] {
+        let row = format!("currency=\"{}\" levelOfDetail", currency);
+        query_response =
+            query_response.replace(&row, &format!("endingCash=\"{}\" {}", ending_cash, row));
+    }
+    let server = serve_query_response(&query_response);
+
+    let snapshot = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    let assets: Vec<_> = snapshot
+        .assets
+        .iter()
+        .map(|asset| (asset.denomination.symbol(), asset.amount))
+        .collect();
+    // The BASE_SUMMARY row only totals the others.
+    assert_eq!(
+        assets,
+        vec![
+            ("ABCD", dec!(1111)),
+            ("EFGH", dec!(1112)),
+            ("CHF", dec!(345.6)),
+            ("USD", dec!(998.88)),
+        ]
+    );
+    assert_eq!(
+        snapshot.assets[3].denomination,
+        Denomination::Currency {
+            currency: "USD".to_string()
+        }
+    );
+    assert_eq!(snapshot.accounts.len(), 1);
+    assert_eq!(snapshot.accounts[0].assets.len(), 4);
+}
+
+#[tokio::test]
+async fn take_snapshot_of_two_statements() {
+    let server = serve_query_response(include_str!(
+        "../testdata/flex_query_response_two_accounts.xml"
+    ));
+
+    let snapshot = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    let accounts: Vec<_> = snapshot
+        .accounts
+        .iter()
+        .map(|account| {
+            (
+                account.account_id.as_str(),
+                account.name.as_str(),
+                account
+                    .assets
+                    .iter()
+                    .map(|asset| (asset.denomination.symbol(), asset.amount))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        accounts,
+        vec![
+            (
+                "U99999",
+                "U99999",
+                vec![("ABCD", dec!(1111)), ("CHF", dec!(100))]
+            ),
+            (
+                "U88888",
+                "Kids",
+                vec![("EFGH", dec!(1112)), ("USD", dec!(100))]
+            ),
+        ]
+    );
+    assert_eq!(snapshot.assets.len(), 4);
+    assert_eq!(snapshot.base_currency, Some("CHF".to_string()));
+}
+
+#[tokio::test]
+async fn take_snapshot_of_two_statements_with_different_base_currencies() {
+    let query_response = include_str!("../testdata/flex_query_response_two_accounts.xml").replace(
+        "currency=\"CHF\" acctAlias=\"Kids\"",
+        "currency=\"EUR\" acctAlias=\"Kids\"",
+    );
+    let server = serve_query_response(&query_response);
+
+    let snapshot = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    assert_eq!(snapshot.assets.len(), 4);
+    assert_eq!(snapshot.base_currency, None);
+}
+
+#[tokio::test]
+async fn take_snapshot_statement_count_mismatch() {
+    let query_response = include_str!("../testdata/flex_query_response.xml").replace(
+        "<FlexStatements count=\"1\">",
+        "<FlexStatements count=\"2\">",
+    );
+    let server = serve_query_response(&query_response);
+
+    let error = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, WorthyError::IbFlex { message, .. } if message.contains("expected 2")),
+        "{:?}",
+        error
+    );
+}
+
+/// Position row of the recorded response for `symbol` at `level`.
+fn position_row(symbol: &str, level: &str, position: &str, cost: &str, pnl: &str) -> String {
+    format!(
+        r#"<OpenPosition accountId="U99999" acctAlias="" currency="USD" fxRateToBase="0.8903" assetCategory="STK" symbol="{symbol}" description="{symbol} Stock" isin="US{symbol}" issuer="" multiplier="1" expiry="" putCall="" position="{position}" markPrice="11.11" costBasisMoney="{cost}" fifoPnlUnrealized="{pnl}" side="Long" levelOfDetail="{level}" />"#
+    )
+}
+
+/// The recorded response, with its positions replaced by `rows`.
+fn with_positions(rows: &[String]) -> String {
+    let recorded = include_str!("../testdata/flex_query_response.xml");
+    let start = recorded.find("<OpenPosition ").unwrap();
+    let end = recorded.find("</OpenPositions>").unwrap();
+    format!(
+        "{}{}\n{}",
+        &recorded[..start],
+        rows.join("\n"),
+        &recorded[end..]
+    )
+}
+
+#[tokio::test]
+async fn take_snapshot_with_lots() {
+    let rows = [
+        // ABCD has both, so only its SUMMARY row counts.
+        position_row("ABCD", "LOT", "100", "1000", "10"),
+        position_row("ABCD", "SUMMARY", "150", "1600", "30"),
+        position_row("ABCD", "LOT", "50", "600", "20"),
+        // EFGH has only lots, which are summed.
+        position_row("EFGH", "LOT", "10", "200", "1"),
+        position_row("EFGH", "LOT", "5", "50", "2"),
+    ];
+    let server = serve_query_response(&with_positions(&rows));
+
+    let assets = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap();
+
+    let summary: Vec<_> = assets
+        .iter()
+        .map(|asset| {
+            (
+                asset.denomination.symbol(),
+                asset.amount,
+                asset.cost_basis.as_ref().unwrap().money,
+                asset.cost_basis.as_ref().unwrap().unrealized_pnl,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("ABCD", dec!(150), dec!(1600), dec!(30)),
+            ("EFGH", dec!(15), dec!(250), dec!(3)),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_with_multiplier() {
+    let rows = [
+        position_row("ABCD", "SUMMARY", "10", "100", "1"),
+        // Two contracts of an index future, at 5000.25 points of $50 each.
+        position_row("ESM4", "SUMMARY", "2", "0", "0")
+            .replace("assetCategory=\"STK\"", "assetCategory=\"FUT\"")
+            .replace("isin=\"USESM4\"", "isin=\"\"")
+            .replace("multiplier=\"1\"", "multiplier=\"50\"")
+            .replace("expiry=\"\"", "expiry=\"20240621\"")
+            .replace("markPrice=\"11.11\"", "markPrice=\"5000.25\""),
+    ];
+    let server = serve_query_response(&with_positions(&rows));
+
+    let report = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    assert_eq!(
+        report
+            .assets
+            .iter()
+            .map(|asset| (asset.denomination.to_string(), asset.amount))
+            .collect::<Vec<_>>(),
+        vec![
+            ("stock:ABCD:USABCD".to_string(), dec!(10)),
+            ("derivative:ESM4".to_string(), dec!(100)),
+        ]
+    );
+    // Not at its notional value, its gains being settled into cash daily.
+    // Stocks are left to converters.
+    assert_eq!(
+        report
+            .rates
+            .iter()
+            .map(|rate| (rate.from.symbol(), rate.to.symbol(), rate.rate))
+            .collect::<Vec<_>>(),
+        vec![("ESM4", "USD", dec!(0))]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_with_option() {
+    let rows = [
+        // Three call contracts on 100 shares each, at $4.35 a share.
+        position_row("AAPL  240621C00190000", "SUMMARY", "3", "1200", "105")
+            .replace("assetCategory=\"STK\"", "assetCategory=\"OPT\"")
+            .replace("isin=\"USAAPL  240621C00190000\"", "isin=\"\"")
+            .replace("multiplier=\"1\"", "multiplier=\"100\"")
+            .replace("expiry=\"\"", "expiry=\"20240621\"")
+            .replace("putCall=\"\"", "putCall=\"C\"")
+            .replace("markPrice=\"11.11\"", "markPrice=\"4.35\""),
+    ];
+    let server = serve_query_response(&with_positions(&rows));
+
+    let report = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    let option = Denomination::Derivative {
+        derivative: "AAPL  240621C00190000".to_string(),
+    };
+    assert_eq!(
+        report
+            .assets
+            .iter()
+            .map(|asset| (asset.denomination.clone(), asset.amount))
+            .collect::<Vec<_>>(),
+        vec![(option.clone(), dec!(300))]
+    );
+    // At the mark price of one share.
+    assert_eq!(
+        report
+            .rates
+            .iter()
+            .map(|rate| (rate.from.clone(), rate.to.symbol(), rate.rate))
+            .collect::<Vec<_>>(),
+        vec![(option, "USD", dec!(4.35))]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_with_short_position() {
+    let rows = [
+        position_row("ABCD", "SUMMARY", "100", "1000", "10"),
+        // A hedge, sold short.
+        position_row("EFGH", "SUMMARY", "-20", "-400", "5")
+            .replace("side=\"Long\"", "side=\"Short\""),
+    ];
+    let server = serve_query_response(&with_positions(&rows));
+
+    let assets = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        assets
+            .iter()
+            .map(|asset| (asset.denomination.symbol(), asset.amount))
+            .collect::<Vec<_>>(),
+        vec![("ABCD", dec!(100)), ("EFGH", dec!(-20))]
+    );
+}
+
+#[tokio::test]
+async fn take_snapshot_by_account() {
+    let in_account = |account: &str, alias: &str, row: String| {
+        row.replace(
+            "accountId=\"U99999\" acctAlias=\"\"",
+            &format!("accountId=\"{}\" acctAlias=\"{}\"", account, alias),
+        )
+    };
+    let rows = [
+        in_account("U1", "", position_row("ABCD", "SUMMARY", "1", "10", "1")),
+        in_account("U2", "Kids", position_row("ABCD", "LOT", "2", "20", "2")),
+        in_account("U2", "Kids", position_row("ABCD", "LOT", "3", "30", "3")),
+        in_account("U1", "", position_row("EFGH", "SUMMARY", "4", "40", "4")),
+    ];
+    let server = serve_query_response(&with_positions(&rows));
+
+    let snapshot = IBFlexSource::report(&config_for(&server)).await.unwrap();
+
+    let accounts: Vec<_> = snapshot
+        .accounts
+        .iter()
+        .map(|account| {
+            (
+                account.account_id.as_str(),
+                account.name.as_str(),
+                account
+                    .assets
+                    .iter()
+                    .map(|asset| (asset.denomination.symbol(), asset.amount))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        accounts,
+        vec![
+            ("U1", "U1", vec![("ABCD", dec!(1)), ("EFGH", dec!(4))]),
+            ("U2", "Kids", vec![("ABCD", dec!(5))]),
+        ]
+    );
+    assert_eq!(snapshot.assets.len(), 3);
+}
+
+/// The recorded response, with the rate to the base currency of its second
+/// USD position replaced by `fx_rate_to_base`.
+fn second_fx_rate_to_base(fx_rate_to_base: &str) -> String {
+    let recorded = include_str!("../testdata/flex_query_response.xml");
+    let second = recorded[..recorded.find("symbol=\"EFGH\"").unwrap()]
+        .rfind('\n')
+        .unwrap();
+    let (first, second) = recorded.split_at(second);
+    format!(
+        "{}{}",
+        first,
+        second.replacen(
+            "fxRateToBase=\"0.8903\"",
+            &format!("fxRateToBase=\"{}\"", fx_rate_to_base),
+            1
+        )
+    )
+}
+
+#[tokio::test]
+async fn take_snapshot_tolerates_rounded_fx_rates() {
+    let server = serve_query_response(&second_fx_rate_to_base("0.89045"));
+
+    let assets = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap();
+    assert_eq!(assets.len(), 2);
+}
+
+#[tokio::test]
+async fn take_snapshot_inconsistent_fx_rates() {
+    let server = serve_query_response(&second_fx_rate_to_base("0.8950"));
+
+    let error = IBFlexSource::take_snapshot(&config_for(&server))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, WorthyError::IbFlex { message, .. } if message.contains("0.8950")),
+        "{:?}",
+        error
+    );
+}
@@ -0,0 +1,46 @@
+//! Helpers shared by worthy's tests.
+
+use denomination::Denomination;
+use std::path::{Path, PathBuf};
+
+pub fn currency(code: &str) -> Denomination {
+    Denomination::Currency {
+        currency: code.to_string(),
+    }
+}
+
+/// Empty directory for one test, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Named after `name` and the process, so tests running at the same time
+    /// don't share one.
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Path of the file `name` in the directory.
+    pub fn file(&self, name: &str) -> String {
+        self.0.join(name).display().to_string()
+    }
+
+    /// Writes `contents` to the file `name` in the directory, returning its path.
+    pub fn write(&self, name: &str, contents: &str) -> String {
+        let path = self.file(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,151 @@
+//! Errors surfaced by worthy's sources, converters and commands.
+
+use denomination::Denomination;
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WorthyError {
+    /// Missing or invalid configuration, including rejected API keys.
+    #[error("config error: {0}")]
+    Config(String),
+
+    /// Network failure or unexpected HTTP status.
+    #[error("HTTP error: {0}")]
+    Http(String),
+
+    /// A response or file could not be parsed.
+    #[error("parse error: {0}")]
+    Parse(String),
+
+    /// A converter's API refused to answer because of rate limits or quotas.
+    #[error("converter {converter} is out of quota: {message}")]
+    ConverterQuota { converter: String, message: String },
+
+    /// The IB Flex web service returned an error, or a statement worthy
+    /// can't handle. `code` is the Flex error code, if any.
+    #[error("IB Flex error: {message}")]
+    IbFlex { code: Option<i32>, message: String },
+
+    /// No conversion path to the common currency.
+    #[error("cannot price {0} in the common currency")]
+    UnpricedDenomination(Denomination),
+
+    /// A computation left the range of `Decimal`, e.g. pricing a huge amount
+    /// in a denomination worth a tiny fraction of the common currency.
+    #[error("arithmetic overflow: {0}")]
+    Overflow(String),
+
+    /// `check` re-valued the snapshot to a total further than `tolerance`
+    /// from the expected one.
+    #[error("total {actual} is more than {tolerance} away from the expected {expected}")]
+    TotalMismatch {
+        expected: Decimal,
+        actual: Decimal,
+        tolerance: Decimal,
+    },
+
+    /// There are no snapshots in the given directory yet.
+    #[error("no snapshots found at {0}; run `worthy snapshot` first")]
+    NoSnapshots(String),
+
+    /// `checkrates` found pairs of denominations whose rates, by different
+    /// converters or paths of conversions, are further apart than allowed.
+    #[error("rates of {pairs} pairs are more than {max_spread_bps} bps apart")]
+    RatesDisagree {
+        pairs: usize,
+        max_spread_bps: Decimal,
+    },
+
+    /// The snapshot to model is older than `--max-age` allows.
+    #[error("snapshot taken at {taken} is {age} old, older than the allowed {max_age}")]
+    StaleSnapshot {
+        taken: String,
+        age: String,
+        max_age: String,
+    },
+
+    /// Scenarios of `selftest` don't come out as computed by hand.
+    #[error("{failed} of {total} model scenarios failed")]
+    SelfTest { failed: usize, total: usize },
+
+    /// No rate converts to or from the common currency `base`, so nothing
+    /// held in anything else can be priced.
+    #[error("no rates convert to or from the common currency {base}")]
+    BaseNotReachable { base: Denomination },
+
+    /// Converting around a cycle of `denominations` (from the first, through
+    /// the rest, back to the first) multiplies money by `rate`, so rates of
+    /// different converters are too inconsistent to price anything.
+    #[error("inconsistent rates: converting around {} multiplies money by {}", cycle(.denominations), .rate.round_dp(6))]
+    Arbitrage {
+        denominations: Vec<Denomination>,
+        rate: Decimal,
+    },
+}
+
+/// E.g. "USD -> EUR -> USD".
+fn cycle(denominations: &[Denomination]) -> String {
+    denominations
+        .iter()
+        .chain(denominations.first())
+        .map(|denomination| denomination.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+impl WorthyError {
+    /// Process exit code for this error, distinct per variant.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            WorthyError::Config(_) => 2,
+            WorthyError::Http(_) => 3,
+            WorthyError::Parse(_) => 4,
+            WorthyError::ConverterQuota { .. } => 5,
+            WorthyError::IbFlex { .. } => 6,
+            WorthyError::UnpricedDenomination(_) => 7,
+            WorthyError::Overflow(_) => 8,
+            WorthyError::TotalMismatch { .. } => 9,
+            WorthyError::NoSnapshots(_) => 10,
+            WorthyError::RatesDisagree { .. } => 11,
+            WorthyError::StaleSnapshot { .. } => 12,
+            WorthyError::SelfTest { .. } => 13,
+            WorthyError::BaseNotReachable { .. } => 14,
+            WorthyError::Arbitrage { .. } => 15,
+        }
+    }
+}
+
+impl From<reqwest::Error> for WorthyError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            WorthyError::Parse(error.to_string())
+        } else {
+            WorthyError::Http(error.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for WorthyError {
+    fn from(error: serde_json::Error) -> Self {
+        WorthyError::Parse(error.to_string())
+    }
+}
+
+impl From<serde_xml_rs::Error> for WorthyError {
+    fn from(error: serde_xml_rs::Error) -> Self {
+        WorthyError::Parse(error.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for WorthyError {
+    fn from(error: serde_yaml::Error) -> Self {
+        WorthyError::Config(error.to_string())
+    }
+}
+
+impl From<url::ParseError> for WorthyError {
+    fn from(error: url::ParseError) -> Self {
+        WorthyError::Config(error.to_string())
+    }
+}